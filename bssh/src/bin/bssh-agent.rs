@@ -0,0 +1,179 @@
+use std::env;
+use std::error;
+use std::ffi::CString;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern crate bsshlib;
+extern crate libc;
+
+use bsshlib::agent;
+use bsshlib::agent::{AgentServer, KeyConfirmation};
+
+const USAGE: &str = "usage: bssh-agent [-d] [-a bind_address] [-t life]";
+
+const ASKPASS_ENV: &str = "SSH_ASKPASS";
+const AGENT_PID_ENV: &str = "SSH_AGENT_PID";
+//expired keys are removed at most this long after their lifetime ends
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+type AgentResult<T> = Result<T, Box<dyn error::Error>>;
+
+#[derive(Default)]
+struct Options {
+    foreground: bool,
+    bind_address: Option<String>,
+    lifetime: Option<u64>,
+}
+
+fn parse_options(args: &[String]) -> AgentResult<Options> {
+    let mut options = Options::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "-d" => options.foreground = true,
+            "-a" | "-t" if i + 1 >= args.len() => return Err(format!("option {} requires an argument", args[i]).into()),
+            "-a" => {
+                options.bind_address = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "-t" => {
+                options.lifetime = Some(args[i + 1].parse()?);
+                i += 1;
+            }
+            arg => return Err(format!("unexpected argument {}", arg).into()),
+        }
+        i += 1;
+    }
+    Ok(options)
+}
+
+//asks through the SSH_ASKPASS program, which exits with 0 when the user agrees
+struct AskpassConfirmation;
+
+impl KeyConfirmation for AskpassConfirmation {
+    fn confirm(&mut self, message: &str) -> bool {
+        match env::var_os(ASKPASS_ENV) {
+            Some(program) => process::Command::new(program)
+                .arg(message)
+                .env("SSH_ASKPASS_PROMPT", "confirm")
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+//private directory for the socket, like /tmp/bssh-XXXXXX
+fn make_socket_directory() -> AgentResult<PathBuf> {
+    let template = env::temp_dir().join("bssh-XXXXXX");
+    let template = CString::new(template.to_string_lossy().into_owned())?;
+    let mut template = template.into_bytes_with_nul();
+    if unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    template.pop();
+    Ok(PathBuf::from(String::from_utf8(template)?))
+}
+
+//only the user running the agent (or root) may use it
+fn is_peer_allowed(stream: &UnixStream) -> bool {
+    let mut credentials: libc::ucred = unsafe { mem::zeroed() };
+    let mut length = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED,
+                         &mut credentials as *mut libc::ucred as *mut libc::c_void, &mut length)
+    };
+    if ret != 0 {
+        return false;
+    }
+    let uid = unsafe { libc::getuid() };
+    credentials.uid == 0 || credentials.uid == uid
+}
+
+//keys are removed when they expire, not only when the next request comes
+fn start_reaper(agent: Arc<Mutex<AgentServer>>) {
+    thread::spawn(move || loop {
+        thread::sleep(REAP_INTERVAL);
+        match agent.lock() {
+            Ok(mut agent) => agent.remove_expired_keys(Instant::now()),
+            Err(_) => return,
+        }
+    });
+}
+
+fn serve(listener: UnixListener, agent: Arc<Mutex<AgentServer>>) {
+    start_reaper(agent.clone());
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("bssh-agent: accept: {}", e);
+                continue;
+            }
+        };
+        if !is_peer_allowed(&stream) {
+            continue;
+        }
+        let agent = agent.clone();
+        thread::spawn(move || {
+            if let Err(e) = agent::serve_connection(&mut stream, &agent) {
+                eprintln!("bssh-agent: {}", e);
+            }
+        });
+    }
+}
+
+fn run() -> AgentResult<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_options(&args)?;
+
+    let socket_path = match options.bind_address {
+        Some(ref address) => PathBuf::from(address),
+        None => make_socket_directory()?.join(format!("agent.{}", process::id())),
+    };
+    //keys and the socket are readable by the user only
+    unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(&socket_path)?;
+
+    let mut agent = AgentServer::new(Box::new(AskpassConfirmation));
+    agent.default_lifetime = options.lifetime.map(Duration::from_secs);
+
+    let pid = if options.foreground {
+        process::id()
+    } else {
+        match unsafe { libc::fork() } {
+            -1 => return Err(std::io::Error::last_os_error().into()),
+            0 => {
+                unsafe { libc::setsid() };
+                serve(listener, Arc::new(Mutex::new(agent)));
+                return Ok(());
+            }
+            child => child as u32,
+        }
+    };
+
+    println!("{}={}; export {};", agent::AUTH_SOCK_ENV, socket_path.display(), agent::AUTH_SOCK_ENV);
+    println!("{}={}; export {};", AGENT_PID_ENV, pid, AGENT_PID_ENV);
+    println!("echo Agent pid {};", pid);
+    if options.foreground {
+        serve(listener, Arc::new(Mutex::new(agent)));
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("bssh-agent: {}", err);
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+}
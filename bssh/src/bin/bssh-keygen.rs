@@ -0,0 +1,356 @@
+use std::env;
+use std::error;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+extern crate bsshlib;
+extern crate libc;
+
+use bsshlib::authorized_keys;
+use bsshlib::fingerprint;
+use bsshlib::fingerprint::FingerprintHash;
+use bsshlib::keys;
+use bsshlib::keys::{PrivateKey, PublicKey};
+use bsshlib::openssh_key;
+use bsshlib::pem;
+use bsshlib::rfc4716;
+use bsshlib::terminal;
+
+const USAGE: &str = "usage: bssh-keygen [-q] [-a rounds] [-b bits] [-C comment] [-f output_keyfile] [-N new_passphrase] [-t ed25519 | rsa | ecdsa]
+       bssh-keygen -p [-a rounds] [-f keyfile] [-N new_passphrase] [-P old_passphrase]
+       bssh-keygen -l [-v] [-E fingerprint_hash] [-f input_keyfile]
+       bssh-keygen -y [-f input_keyfile] [-P passphrase]
+       bssh-keygen -e [-m RFC4716] [-f input_keyfile]
+       bssh-keygen -i [-m RFC4716] [-f input_keyfile]";
+
+const OPTIONS_WITH_ARGUMENT: &str = "abCEfmNPt";
+
+type KeygenResult<T> = Result<T, Box<dyn error::Error>>;
+
+#[derive(Default)]
+struct Options {
+    quiet: bool,
+    verbose: bool,
+    change_passphrase: bool,
+    print_fingerprint: bool,
+    print_public: bool,
+    export: bool,
+    import: bool,
+    rounds: Option<u32>,
+    bits: Option<usize>,
+    comment: Option<String>,
+    fingerprint_hash: Option<String>,
+    file: Option<String>,
+    key_format: Option<String>,
+    new_passphrase: Option<String>,
+    old_passphrase: Option<String>,
+    key_type: Option<String>,
+}
+
+fn parse_options(args: &[String]) -> KeygenResult<Options> {
+    let mut options = Options::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        let arg = &args[i];
+        if !arg.starts_with('-') || arg.len() < 2 {
+            return Err(format!("unexpected argument {}", arg).into());
+        }
+
+        let flags: Vec<char> = arg[1..].chars().collect();
+        for (pos, flag) in flags.iter().enumerate() {
+            if OPTIONS_WITH_ARGUMENT.contains(*flag) {
+                let value: String = if pos + 1 < flags.len() {
+                    flags[pos + 1..].iter().collect()
+                } else {
+                    i += 1;
+                    match args.get(i) {
+                        Some(value) => value.clone(),
+                        None => return Err(format!("option -{} requires an argument", flag).into()),
+                    }
+                };
+
+                match *flag {
+                    'a' => options.rounds = Some(value.parse()?),
+                    'b' => options.bits = Some(value.parse()?),
+                    'C' => options.comment = Some(value),
+                    'E' => options.fingerprint_hash = Some(value),
+                    'f' => options.file = Some(value),
+                    'm' => options.key_format = Some(value),
+                    'N' => options.new_passphrase = Some(value),
+                    'P' => options.old_passphrase = Some(value),
+                    't' => options.key_type = Some(value),
+                    _ => unreachable!(),
+                }
+                break;
+            }
+
+            match *flag {
+                'q' => options.quiet = true,
+                'v' => options.verbose = true,
+                'p' => options.change_passphrase = true,
+                'l' => options.print_fingerprint = true,
+                'y' => options.print_public = true,
+                'e' => options.export = true,
+                'i' => options.import = true,
+                _ => return Err(format!("unknown option -{}", flag).into()),
+            }
+        }
+        i += 1;
+    }
+
+    Ok(options)
+}
+
+fn get_home_dir() -> PathBuf {
+    match env::var("HOME") {
+        Ok(home) => PathBuf::from(home),
+        Err(_) => PathBuf::from("."),
+    }
+}
+
+fn get_user_at_host() -> String {
+    let user = env::var("USER").unwrap_or_else(|_| "user".to_string());
+    let mut buf = [0u8; 256];
+    let host = unsafe {
+        if libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) == 0 {
+            let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        } else {
+            "localhost".to_string()
+        }
+    };
+    format!("{}@{}", user, host)
+}
+
+fn get_algorithm_name(key_type: &str) -> KeygenResult<&'static str> {
+    match key_type {
+        "ed25519" => Ok(keys::SSH_ED25519),
+        "rsa" => Ok(keys::SSH_RSA),
+        "ecdsa" => Ok(keys::ECDSA_SHA2_NISTP256),
+        _ => Err(format!("unknown key type {}", key_type).into()),
+    }
+}
+
+fn get_key_file(options: &Options, prompt: &str) -> KeygenResult<PathBuf> {
+    if let Some(ref file) = options.file {
+        return Ok(PathBuf::from(file));
+    }
+
+    let key_type = options.key_type.clone().unwrap_or_else(|| "ed25519".to_string());
+    let default = get_home_dir().join(".ssh").join(format!("id_{}", key_type));
+    let answer = terminal::read_line_from_tty(&format!("{} ({}): ", prompt, default.display()), true)?;
+    if answer.is_empty() {
+        Ok(default)
+    } else {
+        Ok(PathBuf::from(answer))
+    }
+}
+
+fn get_public_path(path: &Path) -> PathBuf {
+    let mut public = path.as_os_str().to_owned();
+    public.push(".pub");
+    PathBuf::from(public)
+}
+
+fn get_new_passphrase(options: &Options) -> KeygenResult<String> {
+    if let Some(ref passphrase) = options.new_passphrase {
+        return Ok(passphrase.clone());
+    }
+
+    let first = terminal::read_passphrase("Enter passphrase (empty for no passphrase): ")?;
+    let second = terminal::read_passphrase("Enter same passphrase again: ")?;
+    if first != second {
+        return Err("passphrases do not match".into());
+    }
+    Ok(first)
+}
+
+//returns key and comment, asking for passphrase if the key is encrypted and -P was not given
+fn load_private_key(path: &Path, options: &Options) -> KeygenResult<(PrivateKey, String)> {
+    let text = fs::read_to_string(path)?;
+    let block = pem::read_pem_block(&text)?;
+
+    let load = |passphrase: Option<&[u8]>| {
+        if block.label == openssh_key::PEM_LABEL_OPENSSH {
+            openssh_key::read_openssh_private_key(&block.data, passphrase)
+        } else {
+            keys::load_private_key(&text, passphrase).map(|key| (key, String::new()))
+        }
+    };
+
+    match load(options.old_passphrase.as_ref().map(|p| p.as_bytes())) {
+        Err(ref e) if e.kind() == ErrorKind::PermissionDenied => {
+            let passphrase = terminal::read_passphrase(&format!("Enter passphrase for \"{}\": ", path.display()))?;
+            Ok(load(Some(passphrase.as_bytes()))?)
+        }
+        res => Ok(res?),
+    }
+}
+
+//reads public key(s) from .pub, authorized_keys style, RFC 4716 or private key file
+fn load_public_keys(path: &Path, options: &Options) -> KeygenResult<Vec<(PublicKey, String)>> {
+    let text = fs::read_to_string(path)?;
+
+    if text.contains("---- BEGIN SSH2 PUBLIC KEY ----") {
+        let imported = rfc4716::read_rfc4716_public_key(&text)?;
+        let comment = imported.get_comment().unwrap_or_default();
+        return Ok(vec![(imported.key, comment)]);
+    }
+
+    if text.contains("-----BEGIN ") {
+        let public_path = get_public_path(path);
+        if public_path.exists() {
+            return load_public_keys(&public_path, options);
+        }
+        let (key, comment) = load_private_key(path, options)?;
+        return Ok(vec![(key.get_public_key(), comment)]);
+    }
+
+    let entries = authorized_keys::read_authorized_keys(&text);
+    if entries.is_empty() {
+        return Err(format!("{} is not a public key file.", path.display()).into());
+    }
+    Ok(entries.into_iter().map(|entry| (entry.key, entry.comment)).collect())
+}
+
+fn write_key_pair(path: &Path, key: &PrivateKey, comment: &str, passphrase: &str, rounds: u32) -> KeygenResult<()> {
+    let text = openssh_key::write_openssh_private_key_pem(key, comment, Some(passphrase.as_bytes()), rounds)?;
+
+    //remove first, so mode of an existing file is not inherited
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(text.as_bytes())?;
+
+    let public_line = format!("{}\n", bsshlib::public_key_file::write_public_key_line(&key.get_public_key(), comment));
+    fs::write(get_public_path(path), public_line)?;
+    Ok(())
+}
+
+fn generate(options: &Options) -> KeygenResult<()> {
+    let key_type = options.key_type.clone().unwrap_or_else(|| "ed25519".to_string());
+    let algorithm_name = get_algorithm_name(&key_type)?;
+
+    if !options.quiet {
+        println!("Generating public/private {} key pair.", key_type);
+    }
+    let key = keys::generate_private_key(algorithm_name, options.bits)?;
+
+    let path = get_key_file(options, "Enter file in which to save the key")?;
+    if path.exists() {
+        let answer = terminal::read_line_from_tty(&format!("{} already exists.\nOverwrite (y/n)? ", path.display()), true)?;
+        if !answer.starts_with('y') && !answer.starts_with('Y') {
+            return Err("not overwriting existing key".into());
+        }
+    }
+
+    let passphrase = get_new_passphrase(options)?;
+    let comment = options.comment.clone().unwrap_or_else(get_user_at_host);
+    write_key_pair(&path, &key, &comment, &passphrase, options.rounds.unwrap_or(openssh_key::DEFAULT_KDF_ROUNDS))?;
+
+    if !options.quiet {
+        let public_key = key.get_public_key();
+        println!("Your identification has been saved in {}", path.display());
+        println!("Your public key has been saved in {}", get_public_path(&path).display());
+        println!("The key fingerprint is:");
+        println!("{} {}", fingerprint::get_fingerprint(&public_key, FingerprintHash::Sha256), comment);
+        println!("The key's randomart image is:");
+        println!("{}", fingerprint::get_randomart(&public_key, FingerprintHash::Sha256));
+    }
+    Ok(())
+}
+
+fn change_passphrase(options: &Options) -> KeygenResult<()> {
+    let path = get_key_file(options, "Enter file in which the key is")?;
+    let (key, comment) = load_private_key(&path, options)?;
+    let passphrase = get_new_passphrase(options)?;
+    write_key_pair(&path, &key, &comment, &passphrase, options.rounds.unwrap_or(openssh_key::DEFAULT_KDF_ROUNDS))?;
+    println!("Your identification has been saved with the new passphrase.");
+    Ok(())
+}
+
+fn print_fingerprint(options: &Options) -> KeygenResult<()> {
+    let hash = match options.fingerprint_hash {
+        Some(ref name) => match FingerprintHash::from_name(name) {
+            Some(hash) => hash,
+            None => return Err(format!("invalid hash algorithm \"{}\"", name).into()),
+        },
+        None => FingerprintHash::Sha256,
+    };
+
+    let path = get_key_file(options, "Enter file in which the key is")?;
+    for (key, comment) in load_public_keys(&path, options)? {
+        println!("{}", fingerprint::get_fingerprint_line(&key, &comment, hash));
+        if options.verbose {
+            println!("{}", fingerprint::get_randomart(&key, hash));
+        }
+    }
+    Ok(())
+}
+
+fn print_public(options: &Options) -> KeygenResult<()> {
+    let path = get_key_file(options, "Enter file in which the key is")?;
+    let (key, comment) = load_private_key(&path, options)?;
+    println!("{}", bsshlib::public_key_file::write_public_key_line(&key.get_public_key(), &comment));
+    Ok(())
+}
+
+fn check_key_format(options: &Options) -> KeygenResult<()> {
+    match options.key_format {
+        Some(ref format) if format != "RFC4716" => Err(format!("unsupported conversion format \"{}\"", format).into()),
+        _ => Ok(()),
+    }
+}
+
+fn export_public(options: &Options) -> KeygenResult<()> {
+    check_key_format(options)?;
+    let path = get_key_file(options, "Enter file in which the key is")?;
+    let (key, _) = load_public_keys(&path, options)?.remove(0);
+    let comment = format!("{}-bit {}, converted by {} from OpenSSH", key.get_bits(), key.get_type_label(), get_user_at_host());
+    print!("{}", rfc4716::write_rfc4716_public_key(&key, &comment));
+    Ok(())
+}
+
+fn import_public(options: &Options) -> KeygenResult<()> {
+    check_key_format(options)?;
+    let path = get_key_file(options, "Enter file in which the key is")?;
+    let imported = rfc4716::read_rfc4716_public_key(&fs::read_to_string(&path)?)?;
+    println!("{}", bsshlib::public_key_file::write_public_key_line(&imported.key, ""));
+    Ok(())
+}
+
+fn run() -> KeygenResult<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_options(&args)?;
+
+    if options.change_passphrase {
+        change_passphrase(&options)
+    } else if options.print_fingerprint {
+        print_fingerprint(&options)
+    } else if options.print_public {
+        print_public(&options)
+    } else if options.export {
+        export_public(&options)
+    } else if options.import {
+        import_public(&options)
+    } else {
+        generate(&options)
+    }
+}
+
+fn main() {
+    match run() {
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("bssh-keygen: {}", err);
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::error;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+extern crate bsshlib;
+extern crate libc;
+
+use bsshlib::auth_hostbased;
+use bsshlib::config::ClientConfig;
+use bsshlib::errors;
+use bsshlib::io_helpers;
+use bsshlib::keys;
+use bsshlib::passwd;
+use bsshlib::ssh_config;
+use bsshlib::ssh_config::SshConfig;
+
+//as ssh-keysign(8), installed setuid root and run by bsshc for hostbased authentication.
+//The host keys are opened first, then the privileges are dropped for good.
+
+type KeysignResult<T> = Result<T, Box<dyn error::Error>>;
+
+fn drop_privileges() -> KeysignResult<()> {
+    unsafe {
+        if libc::setgid(libc::getgid()) != 0 || libc::setuid(libc::getuid()) != 0 {
+            return Err(From::from(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+fn keysign() -> KeysignResult<()> {
+    let mut host_keys = Vec::new();
+    for file in auth_hostbased::DEFAULT_HOST_KEY_FILES.iter() {
+        if let Ok(key) = keys::load_private_key_file(Path::new(file), None) {
+            host_keys.push(key);
+        }
+    }
+    drop_privileges()?;
+
+    //only the system configuration, the user can not enable it
+    let mut config = SshConfig::new(Path::new("/"));
+    config.read_file(Path::new(ssh_config::SYSTEM_CONFIG_FILE), "")?;
+    if !config.get_enable_ssh_keysign() {
+        return Err(From::from(errors::BSSH_ERR_KEYSIGN_DISABLED));
+    }
+    if host_keys.is_empty() {
+        return Err(From::from(errors::BSSH_ERR_NO_HOST_KEYS));
+    }
+
+    let user = passwd::get_current_user().ok_or("unknown user")?;
+    let host = auth_hostbased::get_local_host_name().ok_or("unknown host name")?;
+    let data = io_helpers::read_string(&mut io::stdin(), None)?;
+    let signature = auth_hostbased::sign_keysign_request(&host_keys, &data, &user.name, &host)?;
+
+    let mut output: Vec<u8> = Vec::new();
+    io_helpers::write_string(&mut output, &signature)?;
+    let mut stdout = io::stdout();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = keysign() {
+        eprintln!("{}: {}", auth_hostbased::KEYSIGN_PROGRAM, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+extern crate bsshlib;
+extern crate libc;
+
+use bsshlib::sftp;
+use bsshlib::sftp::FileAttributes;
+use bsshlib::sftp_client;
+use bsshlib::sftp_client::SftpClient;
+use bsshlib::terminal;
+
+//copies go through the local host, so -3 is the only way remote to remote copies work anyway
+const USAGE: &str = "usage: bssh-scp [-3pqr] [-i identity_file] [-o option] [-P port] [-S program] source ... target";
+
+const OPTIONS_WITH_ARGUMENT: &str = "ioPS";
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+//columns of the progress meter besides the file name
+const PROGRESS_COLUMNS: usize = 36;
+
+type ScpResult<T> = Result<T, Box<dyn error::Error>>;
+
+#[derive(Default)]
+struct Options {
+    recursive: bool,
+    preserve: bool,
+    quiet: bool,
+    program: Option<String>,
+    //passed on to the ssh program
+    ssh_args: Vec<String>,
+    arguments: Vec<String>,
+}
+
+fn parse_options(args: &[String]) -> ScpResult<Options> {
+    let mut options = Options::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "--" {
+            options.arguments.extend_from_slice(&args[i + 1..]);
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            options.arguments.push(arg.clone());
+            i += 1;
+            continue;
+        }
+
+        let flags: Vec<char> = arg[1..].chars().collect();
+        for (pos, flag) in flags.iter().enumerate() {
+            if OPTIONS_WITH_ARGUMENT.contains(*flag) {
+                let value: String = if pos + 1 < flags.len() {
+                    flags[pos + 1..].iter().collect()
+                } else {
+                    i += 1;
+                    match args.get(i) {
+                        Some(value) => value.clone(),
+                        None => return Err(format!("option -{} requires an argument", flag).into()),
+                    }
+                };
+
+                match *flag {
+                    'i' => options.ssh_args.extend_from_slice(&["-i".to_string(), value]),
+                    'o' => options.ssh_args.extend_from_slice(&["-o".to_string(), value]),
+                    'P' => options.ssh_args.extend_from_slice(&["-p".to_string(), value]),
+                    _ => options.program = Some(value),
+                }
+                break;
+            }
+
+            match *flag {
+                '3' => {}
+                'p' => options.preserve = true,
+                'q' => {
+                    options.quiet = true;
+                    options.ssh_args.push("-q".to_string());
+                }
+                'r' => options.recursive = true,
+                flag => return Err(format!("unknown option -{}", flag).into()),
+            }
+        }
+        i += 1;
+    }
+    if options.arguments.len() < 2 {
+        return Err("source and target are needed".into());
+    }
+    Ok(options)
+}
+
+//"[user@]host:path" is remote, as by scp a colon after a slash is part of a local path,
+//IPv6 addresses go in brackets, e.g. "user@[::1]:path"
+fn split_remote(arg: &str) -> Option<(String, &str)> {
+    let host_start = arg.find('@').map(|at| at + 1).unwrap_or(0);
+    if arg[host_start..].starts_with('[') {
+        let end = host_start + arg[host_start..].find("]:")?;
+        let host = format!("{}{}", &arg[..host_start], &arg[host_start + 1..end]);
+        return Some((host, &arg[end + 2..]));
+    }
+    let colon = arg.find(':')?;
+    if colon == 0 || arg[..colon].contains('/') {
+        return None;
+    }
+    Some((arg[..colon].to_string(), &arg[colon + 1..]))
+}
+
+fn get_attributes_error(path: &Path, e: io::Error) -> Box<dyn error::Error> {
+    format!("{}: {}", path.display(), e).into()
+}
+
+//the side of a copy, file names are bytes on both
+trait Endpoint {
+    fn stat(&mut self, path: &Path) -> io::Result<FileAttributes>;
+    //entry names without "." and ".."
+    fn list(&mut self, path: &Path) -> io::Result<Vec<OsString>>;
+    fn open(&mut self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+    //existing files are truncated, new ones get the mode without the bits masked by umask
+    fn create(&mut self, path: &Path, mode: u32) -> io::Result<Box<dyn Write + '_>>;
+    fn mkdir(&mut self, path: &Path, mode: u32) -> io::Result<()>;
+    //permissions and times only
+    fn set_attributes(&mut self, path: &Path, attributes: &FileAttributes) -> io::Result<()>;
+}
+
+struct LocalEndpoint;
+
+impl Endpoint for LocalEndpoint {
+    fn stat(&mut self, path: &Path) -> io::Result<FileAttributes> {
+        Ok(FileAttributes::from_metadata(&fs::metadata(path)?))
+    }
+
+    fn list(&mut self, path: &Path) -> io::Result<Vec<OsString>> {
+        fs::read_dir(path)?.map(|entry| entry.map(|e| e.file_name())).collect()
+    }
+
+    fn open(&mut self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&mut self, path: &Path, mode: u32) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(OpenOptions::new().write(true).create(true).truncate(true).mode(mode).open(path)?))
+    }
+
+    fn mkdir(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        DirBuilder::new().mode(mode).create(path)
+    }
+
+    fn set_attributes(&mut self, path: &Path, attributes: &FileAttributes) -> io::Result<()> {
+        if let Some((atime, mtime)) = attributes.times {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let times = [
+                libc::timeval { tv_sec: atime as libc::time_t, tv_usec: 0 },
+                libc::timeval { tv_sec: mtime as libc::time_t, tv_usec: 0 },
+            ];
+            if unsafe { libc::utimes(c_path.as_ptr(), times.as_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(permissions) = attributes.permissions {
+            fs::set_permissions(path, fs::Permissions::from_mode(permissions & 0o7777))?;
+        }
+        Ok(())
+    }
+}
+
+struct RemoteEndpoint {
+    client: SftpClient,
+    child: Child,
+}
+
+impl Endpoint for RemoteEndpoint {
+    fn stat(&mut self, path: &Path) -> io::Result<FileAttributes> {
+        self.client.stat(path)
+    }
+
+    fn list(&mut self, path: &Path) -> io::Result<Vec<OsString>> {
+        Ok(self.client.read_dir(path)?.into_iter()
+            .filter(|e| e.filename != b"." && e.filename != b"..")
+            .map(|e| OsString::from_vec(e.filename))
+            .collect())
+    }
+
+    fn open(&mut self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(self.client.open(path, sftp::SSH_FXF_READ, &FileAttributes::default())?))
+    }
+
+    fn create(&mut self, path: &Path, mode: u32) -> io::Result<Box<dyn Write + '_>> {
+        let attributes = FileAttributes { permissions: Some(mode), ..FileAttributes::default() };
+        Ok(Box::new(self.client.open(path, sftp::SSH_FXF_WRITE | sftp::SSH_FXF_CREAT | sftp::SSH_FXF_TRUNC, &attributes)?))
+    }
+
+    fn mkdir(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        self.client.mkdir(path, &FileAttributes { permissions: Some(mode), ..FileAttributes::default() })
+    }
+
+    fn set_attributes(&mut self, path: &Path, attributes: &FileAttributes) -> io::Result<()> {
+        self.client.setstat(path, &FileAttributes { permissions: attributes.permissions.map(|p| p & 0o7777), times: attributes.times, ..FileAttributes::default() })
+    }
+}
+
+fn connect(options: &Options, host: &str) -> ScpResult<RemoteEndpoint> {
+    let program = options.program.as_ref().map(PathBuf::from).unwrap_or_else(sftp_client::get_ssh_program);
+    let (child, client) = sftp_client::start_ssh(&program, &options.ssh_args, host)?;
+    Ok(RemoteEndpoint { client, child })
+}
+
+//the ssh client exits once its input is closed
+fn disconnect(endpoint: RemoteEndpoint) {
+    let RemoteEndpoint { client, mut child } = endpoint;
+    drop(client);
+    let _ = child.wait();
+}
+
+//e.g. "977KB" or "12.3MB"
+fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 && unit > 0 {
+        format!("{:.1}{}", value, units[unit])
+    } else {
+        format!("{:.0}{}", value, units[unit])
+    }
+}
+
+fn format_time(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+//meter like the one of scp, redrawn at most once a second on the terminal
+struct ProgressWriter<'a> {
+    output: &'a mut dyn Write,
+    name: String,
+    size: u64,
+    transferred: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+impl<'a> ProgressWriter<'a> {
+    fn new(output: &'a mut dyn Write, name: String, size: u64) -> ProgressWriter<'a> {
+        ProgressWriter { output, name, size, transferred: 0, started: Instant::now(), drawn: None }
+    }
+
+    //the time left while the transfer runs, the time it took once it is done
+    fn draw(&mut self, done: bool) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { self.transferred as f64 / elapsed } else { 0.0 };
+        let percent = (self.transferred * 100).checked_div(self.size).unwrap_or(100);
+        let time = if done {
+            format_time(elapsed as u64)
+        } else if rate > 0.0 {
+            format!("{} ETA", format_time((self.size.saturating_sub(self.transferred) as f64 / rate) as u64))
+        } else {
+            "--:-- ETA".to_string()
+        };
+        let columns = terminal::get_window_size(libc::STDOUT_FILENO).map(|s| s.columns as usize).unwrap_or(80);
+        let width = std::cmp::max(columns.saturating_sub(PROGRESS_COLUMNS), 10);
+        let name: String = self.name.chars().take(width).collect();
+        print!("\r{:<width$} {:>3}% {:>7} {:>8}/s {:>9}", name, percent, format_bytes(self.transferred as f64), format_bytes(rate), time, width = width);
+        if done {
+            println!();
+        }
+        let _ = io::stdout().flush();
+        self.drawn = Some(Instant::now());
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.output.flush()?;
+        self.draw(true);
+        Ok(())
+    }
+}
+
+impl<'a> Write for ProgressWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.output.write(buf)?;
+        self.transferred += length as u64;
+        if self.drawn.map(|d| d.elapsed() >= PROGRESS_INTERVAL).unwrap_or(true) {
+            self.draw(false);
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+struct Copier {
+    recursive: bool,
+    preserve: bool,
+    progress: bool,
+    failed: bool,
+}
+
+impl Copier {
+    fn report(&mut self, e: Box<dyn error::Error>) {
+        eprintln!("bssh-scp: {}", e);
+        self.failed = true;
+    }
+
+    fn copy_file(&mut self, source: &mut dyn Endpoint, source_path: &Path, target: &mut dyn Endpoint, target_path: &Path,
+                 attributes: &FileAttributes) -> ScpResult<()> {
+        let mut input = source.open(source_path).map_err(|e| get_attributes_error(source_path, e))?;
+        let mode = attributes.permissions.unwrap_or(0o644) & 0o777;
+        let mut output = target.create(target_path, mode).map_err(|e| get_attributes_error(target_path, e))?;
+        if self.progress {
+            let name = source_path.file_name().unwrap_or(source_path.as_os_str()).to_string_lossy().into_owned();
+            let mut progress = ProgressWriter::new(&mut output, name, attributes.size.unwrap_or(0));
+            io::copy(&mut input, &mut progress)?;
+            progress.finish()?;
+        } else {
+            io::copy(&mut input, &mut output)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    //directories are created before and get their times after their entries, which would change them
+    fn copy(&mut self, source: &mut dyn Endpoint, source_path: &Path, target: &mut dyn Endpoint, target_path: &Path) -> ScpResult<()> {
+        let attributes = source.stat(source_path).map_err(|e| get_attributes_error(source_path, e))?;
+        let file_type = attributes.permissions.unwrap_or(0) & libc::S_IFMT;
+        if attributes.is_directory() {
+            if !self.recursive {
+                return Err(format!("{}: not a regular file", source_path.display()).into());
+            }
+            match target.stat(target_path) {
+                Ok(ref existing) if existing.is_directory() => {}
+                Ok(_) => return Err(format!("{}: Not a directory", target_path.display()).into()),
+                Err(_) => {
+                    let mode = attributes.permissions.unwrap_or(0o755) & 0o777 | 0o700;
+                    target.mkdir(target_path, mode).map_err(|e| get_attributes_error(target_path, e))?;
+                }
+            }
+            for name in source.list(source_path).map_err(|e| get_attributes_error(source_path, e))? {
+                if let Err(e) = self.copy(source, &source_path.join(&name), target, &target_path.join(&name)) {
+                    self.report(e);
+                }
+            }
+        } else if file_type == libc::S_IFREG {
+            self.copy_file(source, source_path, target, target_path, &attributes)?;
+        } else {
+            return Err(format!("{}: not a regular file", source_path.display()).into());
+        }
+        if self.preserve {
+            target.set_attributes(target_path, &attributes).map_err(|e| get_attributes_error(target_path, e))?;
+        }
+        Ok(())
+    }
+}
+
+//a target directory gets the sources by their names
+fn get_target_path(source_path: &Path, target_path: &Path, target_is_directory: bool) -> ScpResult<PathBuf> {
+    if !target_is_directory {
+        return Ok(target_path.to_path_buf());
+    }
+    let name = source_path.file_name().ok_or_else(|| format!("{}: names no file", source_path.display()))?;
+    Ok(target_path.join(name))
+}
+
+fn get_remote_path(path: &str) -> PathBuf {
+    //an empty path is the initial directory, the home directory of the user
+    PathBuf::from(if path.is_empty() { "." } else { path })
+}
+
+//local copies are left to cp(1), as by scp
+fn copy_locally(options: &Options, sources: &[&String], target: &str) -> ScpResult<bool> {
+    let mut command = Command::new("cp");
+    if options.recursive {
+        command.arg("-r");
+    }
+    if options.preserve {
+        command.arg("-p");
+    }
+    Ok(command.arg("--").args(sources).arg(target).status()?.success())
+}
+
+fn run(options: &Options) -> ScpResult<bool> {
+    let (target_arg, sources) = options.arguments.split_last().unwrap();
+    let mut copier = Copier {
+        recursive: options.recursive,
+        preserve: options.preserve,
+        progress: !options.quiet && terminal::is_terminal(libc::STDOUT_FILENO),
+        failed: false,
+    };
+
+    let (mut remote_target, target_path) = match split_remote(target_arg) {
+        Some((host, path)) => (Some(connect(options, &host)?), get_remote_path(path)),
+        None => {
+            let local: Vec<&String> = sources.iter().filter(|s| split_remote(s).is_none()).collect();
+            if local.len() == sources.len() {
+                return copy_locally(options, &local, target_arg);
+            }
+            (None, PathBuf::from(target_arg))
+        }
+    };
+    let mut local_target = LocalEndpoint;
+    let target: &mut dyn Endpoint = match remote_target {
+        Some(ref mut remote) => remote,
+        None => &mut local_target,
+    };
+    let target_is_directory = target.stat(&target_path).map(|a| a.is_directory()).unwrap_or(false);
+    if sources.len() > 1 && !target_is_directory {
+        return Err(format!("{}: Not a directory", target_path.display()).into());
+    }
+
+    //sources on the same host share the connection
+    let mut remotes: HashMap<String, RemoteEndpoint> = HashMap::new();
+    for source_arg in sources.iter() {
+        let (host, source_path) = match split_remote(source_arg) {
+            Some((host, path)) => (Some(host), get_remote_path(path)),
+            None => (None, PathBuf::from(source_arg)),
+        };
+        let result = get_target_path(&source_path, &target_path, target_is_directory).and_then(|target_path| {
+            match host {
+                Some(host) => {
+                    if !remotes.contains_key(&host) {
+                        let remote = connect(options, &host)?;
+                        remotes.insert(host.clone(), remote);
+                    }
+                    let source = remotes.get_mut(&host).unwrap();
+                    copier.copy(source, &source_path, target, &target_path)
+                }
+                None => copier.copy(&mut LocalEndpoint, &source_path, target, &target_path),
+            }
+        });
+        if let Err(e) = result {
+            copier.report(e);
+        }
+    }
+    for (_, remote) in remotes.drain().chain(remote_target.map(|r| (String::new(), r))) {
+        disconnect(remote);
+    }
+    Ok(!copier.failed)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("bssh-scp: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("bssh-scp: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::env;
+use std::error;
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+extern crate bsshlib;
+
+use bsshlib::sftp;
+use bsshlib::sftp::FileAttributes;
+use bsshlib::sftp_client;
+use bsshlib::sftp_client::SftpClient;
+
+const USAGE: &str = "usage: bssh-sftp [-b batchfile] [-i identity_file] [-o option] [-P port] [-S program] [user@]host[:path]";
+
+const HELP: &str = "Available commands:
+cd path                  Change remote directory to 'path'
+get remote [local]       Download file
+help                     Display this help text
+lcd path                 Change local directory to 'path'
+lpwd                     Print local working directory
+ls [-l] [path]           Display remote directory listing
+mkdir path               Create remote directory
+put local [remote]       Upload file
+pwd                      Display remote working directory
+quit                     Quit bssh-sftp
+rename oldpath newpath   Rename remote file
+rm path                  Delete remote file
+rmdir path               Remove remote directory";
+
+const COMMANDS: [&str; 16] = ["bye", "cd", "exit", "get", "help", "lcd", "lpwd", "ls", "mkdir", "put", "pwd", "quit", "rename", "rm", "rmdir", "?"];
+
+const PROMPT: &str = "sftp> ";
+
+type SftpResult<T> = Result<T, Box<dyn error::Error>>;
+
+#[derive(Default)]
+struct Options {
+    batch_file: Option<String>,
+    program: Option<String>,
+    //passed on to the ssh program
+    ssh_args: Vec<String>,
+    destination: String,
+}
+
+fn parse_options(args: &[String]) -> SftpResult<Options> {
+    let mut options = Options::default();
+    let mut destination: Option<String> = None;
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "-b" | "-i" | "-o" | "-P" | "-S" if i + 1 >= args.len() => return Err(format!("option {} requires an argument", args[i]).into()),
+            "-b" => options.batch_file = Some(args[i + 1].clone()),
+            "-S" => options.program = Some(args[i + 1].clone()),
+            "-i" | "-o" => options.ssh_args.extend_from_slice(&args[i..i + 2]),
+            "-P" => options.ssh_args.extend_from_slice(&["-p".to_string(), args[i + 1].clone()]),
+            arg if arg.starts_with('-') || destination.is_some() => return Err(format!("unexpected argument {}", arg).into()),
+            arg => {
+                destination = Some(arg.to_string());
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+    options.destination = destination.ok_or("no destination given")?;
+    Ok(options)
+}
+
+fn get_file_name(path: &Path) -> SftpResult<&OsStr> {
+    path.file_name().ok_or_else(|| format!("\"{}\" names no file", path.display()).into())
+}
+
+struct Session {
+    client: SftpClient,
+    //remote working directory, absolute
+    directory: PathBuf,
+}
+
+impl Session {
+    fn get_remote_path(&self, path: &str) -> PathBuf {
+        sftp_client::join_path(&self.directory, Path::new(path))
+    }
+
+    fn is_remote_directory(&mut self, path: &Path) -> bool {
+        self.client.stat(path).map(|a| a.is_directory()).unwrap_or(false)
+    }
+
+    fn change_directory(&mut self, path: &str) -> SftpResult<()> {
+        let path = self.get_remote_path(path);
+        let path = self.client.realpath(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if !self.is_remote_directory(&path) {
+            return Err(format!("Can't change directory: \"{}\" is not a directory", path.display()).into());
+        }
+        self.directory = path;
+        Ok(())
+    }
+
+    fn get(&mut self, remote: &str, local: Option<&str>) -> SftpResult<()> {
+        let remote = self.get_remote_path(remote);
+        let mut local = PathBuf::from(local.unwrap_or("."));
+        if local.is_dir() {
+            local.push(get_file_name(&remote)?);
+        }
+        println!("Fetching {} to {}", remote.display(), local.display());
+        let mut file = self.client.open(&remote, sftp::SSH_FXF_READ, &FileAttributes::default())
+            .map_err(|e| format!("{}: {}", remote.display(), e))?;
+        let mode = file.metadata()?.permissions.unwrap_or(0o644) & 0o777;
+        let mut output = File::create(&local).map_err(|e| format!("{}: {}", local.display(), e))?;
+        io::copy(&mut file, &mut output)?;
+        file.close()?;
+        output.set_permissions(fs::Permissions::from_mode(mode))?;
+        Ok(())
+    }
+
+    fn put(&mut self, local: &str, remote: Option<&str>) -> SftpResult<()> {
+        let local = PathBuf::from(local);
+        let mut input = File::open(&local).map_err(|e| format!("{}: {}", local.display(), e))?;
+        let mut remote = match remote {
+            Some(remote) => self.get_remote_path(remote),
+            None => self.directory.clone(),
+        };
+        if self.is_remote_directory(&remote) {
+            remote.push(get_file_name(&local)?);
+        }
+        println!("Uploading {} to {}", local.display(), remote.display());
+        let attributes = FileAttributes {
+            permissions: Some(input.metadata()?.permissions().mode() & 0o777),
+            ..FileAttributes::default()
+        };
+        let mut file = self.client.open(&remote, sftp::SSH_FXF_WRITE | sftp::SSH_FXF_CREAT | sftp::SSH_FXF_TRUNC, &attributes)
+            .map_err(|e| format!("{}: {}", remote.display(), e))?;
+        io::copy(&mut input, &mut file)?;
+        file.close()?;
+        Ok(())
+    }
+
+    //hidden entries are left out, as by ls(1)
+    fn list(&mut self, path: Option<&str>, long: bool) -> SftpResult<()> {
+        let path = match path {
+            Some(path) => self.get_remote_path(path),
+            None => self.directory.clone(),
+        };
+        if !self.is_remote_directory(&path) {
+            self.client.lstat(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            println!("{}", path.display());
+            return Ok(());
+        }
+        let mut entries = self.client.read_dir(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        entries.retain(|e| !e.filename.starts_with(b"."));
+        entries.sort_by(|a, b| a.filename.cmp(&b.filename));
+        for entry in entries.iter() {
+            let name = if long { &entry.longname } else { &entry.filename };
+            println!("{}", OsStr::from_bytes(name).to_string_lossy());
+        }
+        Ok(())
+    }
+
+    //returns false once the session should end
+    fn run_command(&mut self, words: &[String]) -> SftpResult<bool> {
+        let arguments: Vec<&str> = words[1..].iter().map(|w| w.as_str()).collect();
+        match (words[0].as_str(), arguments.as_slice()) {
+            ("bye", []) | ("exit", []) | ("quit", []) => return Ok(false),
+            ("help", []) | ("?", []) => println!("{}", HELP),
+            ("cd", [path]) => self.change_directory(path)?,
+            ("get", [remote]) => self.get(remote, None)?,
+            ("get", [remote, local]) => self.get(remote, Some(local))?,
+            ("put", [local]) => self.put(local, None)?,
+            ("put", [local, remote]) => self.put(local, Some(remote))?,
+            ("ls", []) => self.list(None, false)?,
+            ("ls", ["-l"]) => self.list(None, true)?,
+            ("ls", ["-l", path]) => self.list(Some(path), true)?,
+            ("ls", [path]) => self.list(Some(path), false)?,
+            ("lcd", [path]) => env::set_current_dir(path).map_err(|e| format!("{}: {}", path, e))?,
+            ("lpwd", []) => println!("Local working directory: {}", env::current_dir()?.display()),
+            ("pwd", []) => println!("Remote working directory: {}", self.directory.display()),
+            ("mkdir", [path]) => {
+                let path = self.get_remote_path(path);
+                self.client.mkdir(&path, &FileAttributes::default()).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+            ("rmdir", [path]) => {
+                let path = self.get_remote_path(path);
+                self.client.rmdir(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+            ("rm", [path]) => {
+                let path = self.get_remote_path(path);
+                println!("Removing {}", path.display());
+                self.client.remove(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+            ("rename", [old, new]) => {
+                let old = self.get_remote_path(old);
+                let new = self.get_remote_path(new);
+                self.client.rename(&old, &new).map_err(|e| format!("{}: {}", old.display(), e))?;
+            }
+            (command, _) if COMMANDS.contains(&command) => return Err(format!("Wrong arguments for {}", command).into()),
+            (command, _) => return Err(format!("Invalid command \"{}\"", command).into()),
+        }
+        Ok(true)
+    }
+
+    //batch files echo their commands and stop at the first failure, unless the command starts with "-"
+    fn run_commands(&mut self, input: &mut dyn BufRead, batch: bool) -> SftpResult<()> {
+        let mut line = String::new();
+        loop {
+            if !batch {
+                print!("{}", PROMPT);
+                io::stdout().flush()?;
+            }
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                if !batch {
+                    println!();
+                }
+                return Ok(());
+            }
+            let command = line.trim();
+            if command.is_empty() || command.starts_with('#') {
+                continue;
+            }
+            if batch {
+                println!("{}{}", PROMPT, command);
+            }
+            let (ignore_errors, command) = match command.strip_prefix('-') {
+                Some(command) => (true, command),
+                None => (false, command),
+            };
+            let result = sftp_client::split_command_line(command).map_err(From::from).and_then(|words| {
+                if words.is_empty() { Ok(true) } else { self.run_command(&words) }
+            });
+            match result {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) if batch && !ignore_errors => return Err(e),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+
+    //"-" reads the batch from stdin
+    fn run(&mut self, batch_file: Option<&str>) -> SftpResult<()> {
+        match batch_file {
+            Some("-") => self.run_commands(&mut io::stdin().lock(), true),
+            Some(file) => self.run_commands(&mut BufReader::new(File::open(file)?), true),
+            None => self.run_commands(&mut io::stdin().lock(), false),
+        }
+    }
+}
+
+fn run() -> SftpResult<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("bssh-sftp: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    let (host, path) = match options.destination.find(':') {
+        Some(colon) => (&options.destination[..colon], Some(&options.destination[colon + 1..])),
+        None => (options.destination.as_str(), None),
+    };
+
+    let program = options.program.as_ref().map(PathBuf::from).unwrap_or_else(sftp_client::get_ssh_program);
+    let (mut child, client) = sftp_client::start_ssh(&program, &options.ssh_args, host)?;
+    let mut session = Session { client, directory: PathBuf::new() };
+    session.directory = session.client.realpath(Path::new("."))?;
+    //a file in the destination is fetched, a directory becomes the working directory
+    let result = match path.filter(|p| !p.is_empty()) {
+        Some(path) if !session.is_remote_directory(&session.get_remote_path(path)) => session.get(path, None),
+        Some(path) => session.change_directory(path).and_then(|_| session.run(options.batch_file.as_deref())),
+        None => session.run(options.batch_file.as_deref()),
+    };
+    drop(session);
+    child.wait()?;
+    result
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("bssh-sftp: {}", err);
+        process::exit(1);
+    }
+}
@@ -1,98 +1,330 @@
 use std::error;
-use std::io::prelude::*;
 use std::net::TcpStream;
 use std::net::Shutdown;
-use std::io::Cursor;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 
 extern crate bsshlib;
-extern crate num;
-extern crate rand; //TODO replace with some safe rand someday
+extern crate libc;
 
-use bsshlib::version;
-use bsshlib::msgs;
 use bsshlib::dummy_config;
-use bsshlib::config;
-use bsshlib::packet;
-use bsshlib::diffie_hellman;
-use num::bigint::{BigUint, RandBigInt, ToBigUint};
-
-const HOST: &'static str = "127.0.0.1:22";
-
-fn connect() -> Result<(), Box<error::Error + Send + Sync>> {
-
-	let mut sequence_number : u32 = 0;
-    let hello: Vec<u8> = [version::get_version_byte_string(), b"\r\n".to_vec()].concat();
-
-    let mut stream = try!(TcpStream::connect(HOST));
-    try!(stream.write_all(&hello));
-
-    let welcome : Vec<String> = msgs::read_welcome_string(&mut stream, true)?;
-
-    for i in welcome.iter() { println!("{}",i); };
-
-	let config = dummy_config::DummyCommonConfig{};
-	let kex = msgs::create_kex_init_message(&config, false);
-	
-	let mut kex_payload : Vec<u8> = Vec::new();
-	msgs::write_kex_init_message(&mut kex_payload, &kex)?;
-	let mut kex_message : Vec<u8> = packet::get_packet_from_payload(&mut kex_payload, None, false, sequence_number)?;
-	stream.write(&mut kex_message)?;
-	
-	sequence_number += 1;
-	
-	let ret_kex_payload : Vec<u8> = packet::read_packet_from_stream(&mut stream, None)?;
-	let mut x = Cursor::new(ret_kex_payload);
-	let ret_kex_message = msgs::read_kex_init_message(&mut x)?;
-	
-	println!("{}", &ret_kex_message.available_algorithm_set as &config::AvailableAlgorithms);
-
-	//diffie-hellman
-	let mut rng = rand::thread_rng();
-	let p : BigUint = diffie_hellman::get_oakley_group14_prime();
-	let x : BigUint = rng.gen_biguint_range(&1.to_biguint().unwrap(), &p);
-	let e : BigUint = num::pow(x, 2) % p;
-
-	let mut kexdh_payload : Vec<u8> = Vec::new();	
-	msgs::write_kexdh_init_message(&mut kexdh_payload, e)?;
-	let kexdh_payload_len = kexdh_payload.len();
-	let mut kexdh_message : Vec<u8> = packet::get_packet_from_payload(&mut kexdh_payload, None, true, sequence_number)?;
-	println!("sending kexdh len {} payload {}", kexdh_message.len(), kexdh_payload_len);
-	stream.write(&mut kexdh_message)?;
-	
-	sequence_number += 1;
-	
-	let mut buf;
-    loop {
-    	let mut done = false;
-        // clear out the buffer so we don't send garbage
-        buf = [0; 4*1024];
-        let num = match stream.read(&mut buf) {
-            Err(e) => panic!("Got an error: {}", e),
-            Ok(m) => {
-                if m == 0 {
-                    // we've got an EOF
-                    println!("eof");
-                    done = true;
-                }
-                m
-            }
-        };
-
-        let mut vbuf = buf.to_vec();
-        vbuf.truncate(num);
-        println!("got {:?}: {:?}", num, vbuf);
-        if done { break; };
-    }
+use bsshlib::kex;
+use bsshlib::certificate;
+use bsshlib::certificate::Certificate;
+use bsshlib::errors;
+use bsshlib::keys::PublicKey;
+use bsshlib::known_hosts;
+use bsshlib::known_hosts::{HostKeyAction, HostKeyStatus, KnownHosts};
+use bsshlib::ssh_config;
+use bsshlib::ssh_config::SshConfig;
+use bsshlib::terminal;
+use bsshlib::transport::TransportStream;
+use bsshlib::config::{ClientConfig, LogLevel};
+use bsshlib::userauth;
+use bsshlib::userauth::{ClientAuthMethod, UserauthClient};
+use bsshlib::auth_password::{ClientPasswordMethod, TtyPasswordPrompt};
+use bsshlib::auth_publickey::{ClientPublicKeyMethod, FileIdentity, Identity};
+use bsshlib::agent;
+use bsshlib::agent::AgentClient;
+use bsshlib::auth_hostbased;
+use bsshlib::auth_hostbased::ClientHostbasedMethod;
+use bsshlib::passwd;
+use bsshlib::auth_keyboard_interactive::{ClientKeyboardInteractiveMethod, TtyKeyboardInteractivePrompt};
+use bsshlib::client_session;
+use bsshlib::client_session::{ClientEvent, ClientSessionOptions, SessionIo};
+use bsshlib::session;
+use bsshlib::session::PtyRequest;
+use bsshlib::signals;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+
+struct Destination {
+	host: String,
+	port: u16,
+	command: Option<String>,
+	//the command names a subsystem
+	subsystem: bool,
+}
+
+fn usage() -> String {
+	"usage: bsshc [-qs] [-i identity_file] [-L [bind_address:]port:host:hostport] [-p port] [-R [bind_address:]port:host:hostport] [-o option] [user@]hostname [command]".to_string()
+}
+
+fn parse_args(args: &[String], config: &mut SshConfig) -> Result<Destination, Box<dyn error::Error + Send + Sync>> {
+	let mut host: Option<String> = None;
+	let mut port: Option<u16> = None;
+	let mut command: Option<String> = None;
+	let mut subsystem = false;
+	let mut i = 0;
+
+	while i < args.len() {
+		match args[i].as_str() {
+			"-p" | "-o" | "-i" | "-L" | "-R" if i + 1 >= args.len() => return Err(From::from(usage())),
+			"-q" => config.apply_option("LogLevel QUIET")?,
+			"-s" => subsystem = true,
+			"-i" => {
+				config.apply_option(&format!("IdentityFile {}", args[i + 1]))?;
+				i += 1;
+			}
+			"-L" => {
+				config.apply_option(&format!("LocalForward {}", args[i + 1]))?;
+				i += 1;
+			}
+			"-R" => {
+				config.apply_option(&format!("RemoteForward {}", args[i + 1]))?;
+				i += 1;
+			}
+			"-p" => {
+				port = Some(args[i + 1].parse().map_err(|_| usage())?);
+				i += 1;
+			}
+			"-o" => {
+				config.apply_option(&args[i + 1])?;
+				i += 1;
+			}
+			//everything after the host is the remote command, as by ssh
+			_ if host.is_some() => {
+				command = Some(args[i..].join(" "));
+				break;
+			}
+			arg if arg.starts_with('-') => return Err(From::from(usage())),
+			arg => {
+				let destination = match arg.rfind('@') {
+					Some(at) => {
+						config.apply_option(&format!("User {}", &arg[..at]))?;
+						&arg[at + 1..]
+					}
+					None => arg,
+				};
+				host = Some(destination.to_string());
+			}
+		}
+		i += 1;
+	}
+
+	if subsystem && command.is_none() {
+		return Err(From::from(usage()));
+	}
+	let host = host.unwrap_or_else(|| DEFAULT_HOST.to_string());
+	let user_config = config.home.join(ssh_config::USER_CONFIG_FILE);
+	config.read_file(&user_config, &host)?;
+	config.read_file(Path::new(ssh_config::SYSTEM_CONFIG_FILE), &host)?;
+
+	Ok(Destination {
+		port: port.or(config.port).unwrap_or(known_hosts::DEFAULT_PORT),
+		host,
+		command,
+		subsystem,
+	})
+}
+
+//host certificates are trusted through @cert-authority entries, if there is none
+//for the host (or certificate is not valid) the certified key is checked as a plain key
+//returns false when a changed key was accepted only because checking is disabled, then
+//the connection may be intercepted and nothing secret should be typed into it
+fn verify_host_key(config: &SshConfig, destination: &Destination, host_key_blob: &[u8]) -> Result<bool, Box<dyn error::Error + Send + Sync>> {
+	let mut known = KnownHosts::new();
+	for path in config.get_user_known_hosts_files().iter().chain(config.get_global_known_hosts_files().iter()) {
+		known.read_file(path)?;
+	}
+
+	let (key, certificate_status) = if certificate::is_certificate_blob(host_key_blob) {
+		let certificate = Certificate::from_blob(host_key_blob)?;
+		let status = match known.check_host_certificate(&destination.host, destination.port, &certificate, certificate::get_current_time()) {
+			Ok(status) => status,
+			Err(e) => {
+				eprintln!("{}", e);
+				HostKeyStatus::NotFound
+			}
+		};
+		(certificate.key, status)
+	} else {
+		(PublicKey::from_blob(host_key_blob)?, HostKeyStatus::NotFound)
+	};
+	let key = &key;
+
+	let status = match certificate_status {
+		HostKeyStatus::NotFound => known.check_host_key(&destination.host, destination.port, key),
+		status => status,
+	};
+	let host_display = known_hosts::get_host_key_name(&destination.host, destination.port);
+
+	let mut trusted = true;
+	let add = match known_hosts::get_host_key_action(&status, config.get_strict_host_key_checking(), &host_display, key) {
+		HostKeyAction::Accept => false,
+		HostKeyAction::Add(warning) => {
+			if let Some(warning) = warning {
+				eprintln!("{}", warning);
+			}
+			true
+		}
+		HostKeyAction::Ask(prompt) => {
+			let answer = terminal::read_line_from_tty(&prompt, true)?;
+			if !known_hosts::is_confirmation(&answer, key) {
+				return Err(From::from(errors::BSSH_ERR_HOST_KEY_VERIFICATION_FAILED));
+			}
+			true
+		}
+		HostKeyAction::Warn(warning) => {
+			eprintln!("{}", warning);
+			trusted = false;
+			false
+		}
+		HostKeyAction::Reject(message) => {
+			eprintln!("{}", message);
+			return Err(From::from(errors::BSSH_ERR_HOST_KEY_VERIFICATION_FAILED));
+		}
+	};
+
+	if add {
+		if let Some(path) = config.get_user_known_hosts_files().first() {
+			if let Err(e) = known_hosts::append_known_host(path, &destination.host, destination.port, key, config.get_hash_known_hosts()) {
+				eprintln!("Failed to add the host to the list of known hosts ({}): {}", path.display(), e);
+			}
+		}
+	}
+
+	Ok(trusted)
+}
+
+//identity files which do not exist are skipped silently, as by ssh
+//keys in the agent are tried first, key files the agent already holds are skipped
+fn load_identities(config: &SshConfig) -> Vec<Box<dyn Identity>> {
+	let mut identities: Vec<Box<dyn Identity>> = Vec::new();
+	match AgentClient::connect_from_env().and_then(|agent| agent.map(agent::get_agent_identities).unwrap_or_else(|| Ok(Vec::new()))) {
+		Ok(agent_identities) => identities.extend(agent_identities),
+		Err(e) => eprintln!("Error connecting to agent: {}", e),
+	}
+	for path in config.get_identity_files() {
+		if !path.exists() {
+			continue;
+		}
+		match FileIdentity::load(&path) {
+			Ok(ref identity) if identities.iter().any(|i| i.get_public_key_blob() == identity.get_public_key_blob()) => {}
+			Ok(identity) => identities.push(Box::new(identity)),
+			Err(e) => eprintln!("Load key \"{}\": {}", path.display(), e),
+		}
+	}
+	identities
+}
+
+//the host keys are signed with by bssh-keysign, installed next to bsshc
+fn get_keysign_path() -> Option<PathBuf> {
+	let path = env::current_exe().ok()?.with_file_name(auth_hostbased::KEYSIGN_PROGRAM);
+	if path.exists() { Some(path) } else { None }
+}
+
+//interactive sessions get a pty and the local terminal goes to raw mode until the session ends,
+//otherwise SIGINT, SIGTERM and SIGHUP go to the remote process, unless it is a subsystem
+//returns the exit status of the remote command, None if it did not report one
+fn run_session(payload_stream: TransportStream<TcpStream>, config: &SshConfig, command: Option<String>, subsystem: bool) -> Result<Option<u32>, Box<dyn error::Error + Send + Sync>> {
+	let (events, received) = mpsc::channel();
+	let interactive = command.is_none() && terminal::is_terminal(libc::STDIN_FILENO);
+	let mut pty = None;
+	let mut _raw_mode = None;
+	if interactive {
+		pty = Some(PtyRequest {
+			term: env::var("TERM").unwrap_or_default(),
+			size: terminal::get_window_size(libc::STDIN_FILENO).unwrap_or_default(),
+			modes: session::get_terminal_modes(&terminal::get_attributes(libc::STDIN_FILENO)?),
+		});
+		let window_events = events.clone();
+		signals::spawn_signal_handler(&[libc::SIGWINCH], move |_| match terminal::get_window_size(libc::STDIN_FILENO) {
+			Ok(size) => window_events.send(ClientEvent::WindowChanged(size)).is_ok(),
+			Err(_) => true,
+		})?;
+		_raw_mode = Some(terminal::RawModeGuard::enter(libc::STDIN_FILENO)?);
+	} else if !subsystem {
+		//with a pty the terminal sends e.g. ^C as input, without one the signals are forwarded
+		let signal_events = events.clone();
+		signals::spawn_signal_handler(&[libc::SIGINT, libc::SIGTERM, libc::SIGHUP], move |signal| {
+			signal_events.send(ClientEvent::Signal(signal)).is_ok()
+		})?;
+	}
+
+	let environment = client_session::get_environment(env::vars(), &config.get_send_env(), &config.get_set_env());
+	let local_forwards = config.get_local_forwards();
+	let remote_forwards = config.get_remote_forwards();
+	let (receiver, mut payload_stream) = payload_stream.split()?;
+	let io = SessionIo {
+		stdin: Box::new(io::stdin()),
+		stdout: Box::new(io::stdout()),
+		stderr: Box::new(io::stderr()),
+	};
+	let session = client_session::run_client_session(receiver, &mut payload_stream, ClientSessionOptions { pty, command, subsystem, environment, local_forwards, remote_forwards }, io, events, received)?;
+	drop(_raw_mode);
+	payload_stream.stream.shutdown(Shutdown::Both)?;
+	if let Some(ref signal) = session.exit_signal {
+		if !signal.error_message.is_empty() {
+			eprintln!("{}", terminal::sanitize(&signal.error_message));
+		}
+	}
+	Ok(session.exit_status)
+}
+
+fn connect() -> Result<Option<u32>, Box<dyn error::Error + Send + Sync>> {
+
+	let mut client_config = SshConfig::from_env();
+	let args: Vec<String> = env::args().skip(1).collect();
+	let destination = parse_args(&args, &mut client_config)?;
+
+	let stream = TcpStream::connect((destination.host.as_str(), destination.port))?;
+	let kex_result = kex::run_client(stream, &dummy_config::DummyCommonConfig{})?;
+
+	//stdout belongs to the remote command, debug output goes to stderr
+	let debug = client_config.get_log_level() >= LogLevel::Debug1;
+	if debug {
+		for line in kex_result.welcome.iter() {
+			eprintln!("{}", terminal::sanitize(line));
+		}
+		eprintln!("{:?}", kex_result.algorithms);
+	}
+	let host_key_trusted = verify_host_key(&client_config, &destination, &kex_result.host_key)?;
 
-    stream.shutdown(Shutdown::Both)?;
+	let user = client_config.user.clone().or_else(|| env::var("USER").ok()).unwrap_or_default();
+	let mut payload_stream = kex_result.stream;
+	let mut methods: Vec<Box<dyn ClientAuthMethod>> = Vec::new();
+	if client_config.get_hostbased_authentication() {
+		if let (Some(host), Some(local_user), Some(helper)) = (auth_hostbased::get_local_host_name(), passwd::get_current_user(), get_keysign_path()) {
+			methods.push(Box::new(ClientHostbasedMethod::new(auth_hostbased::load_keysign_identities(&helper), &host, &local_user.name)));
+		}
+	}
+	if client_config.get_pubkey_authentication() {
+		methods.push(Box::new(ClientPublicKeyMethod::new(load_identities(&client_config))));
+	}
+	//as ssh, passwords are not sent to a host whose key changed
+	if client_config.get_kbd_interactive_authentication() && !host_key_trusted {
+		eprintln!("Keyboard-interactive authentication is disabled to avoid man-in-the-middle attacks.");
+	} else if client_config.get_kbd_interactive_authentication() {
+		methods.push(Box::new(ClientKeyboardInteractiveMethod::new(Box::new(TtyKeyboardInteractivePrompt), client_config.get_number_of_password_prompts())));
+	}
+	if client_config.get_password_authentication() && !host_key_trusted {
+		eprintln!("Password authentication is disabled to avoid man-in-the-middle attacks.");
+	} else if client_config.get_password_authentication() {
+		methods.push(Box::new(ClientPasswordMethod::new(Box::new(TtyPasswordPrompt), &destination.host, client_config.get_number_of_password_prompts())));
+	}
+	let mut client = UserauthClient::new(&user, &payload_stream.session_id, methods);
+	client.confidential = payload_stream.is_encrypted();
+	let show_banners = client_config.get_log_level() >= LogLevel::Info;
+	userauth::run_client(&mut payload_stream, &mut client, &mut |banner| {
+		if show_banners {
+			eprint!("{}", terminal::sanitize(banner));
+		}
+	})?;
 
-    Ok(())
+	run_session(payload_stream, &client_config, destination.command, destination.subsystem)
 }
 
+//the exit status is the one of the remote command, 255 on errors or if it was killed by a signal
 fn main() {
 
     match connect() {
-        Ok(_) => println!("ok"),
-        Err(err) => println!("An error occurred: {}", err),
+        Ok(status) => std::process::exit(status.map(|s| s as i32).unwrap_or(255)),
+        Err(err) => {
+            eprintln!("An error occurred: {}", err);
+            std::process::exit(255);
+        }
     }
 }
@@ -1,80 +1,229 @@
 use std::error;
-use std::io::Write;
-use std::io::Read;
-use std::io::Cursor;
+use std::io;
 use std::net::TcpListener;
 use std::net::TcpStream;
-// use std::net::Shutdown;
+use std::net::Shutdown;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
 use std::thread;
+use std::env;
+use std::fs;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
 extern crate bsshlib;
-use bsshlib::version;
-use bsshlib::msgs;
 use bsshlib::dummy_config;
-use bsshlib::config;
-use bsshlib::packet;
-
-const HOST: &'static str = "127.0.0.1:5555";
-
-fn handle_client(mut stream: TcpStream) -> Result<(), Box<error::Error + Send + Sync>> {
-	let mut sequence_number : u32 = 0;
-    let hello: Vec<u8> = [version::get_version_byte_string(), b"\r\n".to_vec()].concat();
-    try!(stream.write_all(&hello));
-
-    let welcome : Vec<String> = msgs::read_welcome_string(&mut stream, false)?;
-    
-    for i in welcome.iter() { println!("{}",i); };
-    
-	let config = dummy_config::DummyCommonConfig{};
-	let kex = msgs::create_kex_init_message(&config, false);
-	
-	let mut kex_payload : Vec<u8> = Vec::new();
-	msgs::write_kex_init_message(&mut kex_payload, &kex)?;
-	let mut kex_message : Vec<u8> = packet::get_packet_from_payload(&mut kex_payload, None, false, sequence_number)?;
-	stream.write(&mut kex_message)?;
-	
-	sequence_number += 1;
-	
-	let ret_kex_payload : Vec<u8> = packet::read_packet_from_stream(&mut stream, None)?;
-	let mut x = Cursor::new(ret_kex_payload);
-	let ret_kex_message = msgs::read_kex_init_message(&mut x)?;
-	
-	println!("{}", &ret_kex_message.available_algorithm_set as &config::AvailableAlgorithms);
-
-    let mut buf;
-    loop {
-        // clear out the buffer so we don't send garbage
-        buf = [0; 4*1024];
-        let num = match stream.read(&mut buf) {
-            Err(e) => panic!("Got an error: {}", e),
-            Ok(m) => {
-                if m == 0 {
-                    // we've got an EOF
-                    println!("eof");
-                    break;
-                }
-                m
-            }
-        };
+use bsshlib::certificate;
+use bsshlib::diffie_hellman;
+use bsshlib::diffie_hellman::HostKey;
+use bsshlib::errors;
+use bsshlib::kex;
+use bsshlib::keys;
+use bsshlib::sshd_config::SshdConfig;
+use bsshlib::config::ServerConfig;
+use bsshlib::userauth;
+use bsshlib::userauth::{ServerAuthMethod, UserauthServer};
+use bsshlib::auth_password;
+use bsshlib::auth_password::{ServerPasswordMethod, ShadowPasswordVerifier};
+use bsshlib::auth_publickey::ServerPublicKeyMethod;
+use bsshlib::auth_hostbased::ServerHostbasedMethod;
+use bsshlib::auth_keyboard_interactive::{ChallengeKind, ChallengeProvider, PasswordChallengeProvider, ServerKeyboardInteractiveMethod, TotpChallengeProvider};
+use bsshlib::passwd;
+use bsshlib::dns;
+use bsshlib::auth_options::AuthOptions;
+use bsshlib::server_session;
+use bsshlib::server_session::SessionConfig;
+use bsshlib::sftp_server;
+
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 5555;
+//bsshd runs itself with this option as the user to serve internal-sftp
+const INTERNAL_SFTP_OPTION: &str = "--internal-sftp";
+
+//one provider for all connections so a used code is refused on every connection
+type SharedTotp = Arc<Mutex<TotpChallengeProvider>>;
+
+fn load_config() -> Result<(SshdConfig, Option<SharedTotp>), Box<dyn error::Error + Send + Sync>> {
+	let args: Vec<String> = env::args().skip(1).collect();
+	let mut config = SshdConfig::new();
+
+	match args.len() {
+		0 => {}
+		2 if args[0] == "-f" => config.read_file(Path::new(&args[1]))?,
+		_ => return Err(From::from("usage: bsshd [-f config_file]")),
+	}
+	let totp = load_totp_secrets(&config)?;
+	let methods = get_auth_methods(&config, totp.clone());
+	let enabled: Vec<&str> = methods.iter().map(|m| m.get_name()).collect();
+	userauth::check_authentication_methods(&config.get_authentication_methods(), &enabled)?;
+	Ok((config, totp))
+}
+
+//the secrets are needed only for keyboard-interactive with KbdInteractiveChallenge totp
+fn load_totp_secrets(server_config: &SshdConfig) -> Result<Option<SharedTotp>, Box<dyn error::Error + Send + Sync>> {
+	if !server_config.get_kbd_interactive_authentication() || server_config.get_kbd_interactive_challenge() != ChallengeKind::Totp {
+		return Ok(None);
+	}
+	let path = server_config.get_totp_secrets_file().ok_or(errors::BSSH_ERR_TOTP_SECRETS_MISSING)?;
+	let mut provider = TotpChallengeProvider::new();
+	provider.read_file(&path).map_err(|e| format!("Unable to load TOTP secrets \"{}\": {}", path.display(), e))?;
+	Ok(Some(Arc::new(Mutex::new(provider))))
+}
+
+//unreadable host keys and certificates are skipped, as in OpenSSH, but at least one key is needed
+fn load_host_keys(server_config: &SshdConfig) -> Result<Vec<HostKey>, Box<dyn error::Error + Send + Sync>> {
+	let mut host_keys = Vec::new();
+	for path in server_config.get_host_key_files().iter() {
+		match keys::load_private_key_file(path, None) {
+			Ok(key) => host_keys.push(HostKey::new(key)),
+			Err(e) => eprintln!("Unable to load host key \"{}\": {}", path.display(), e),
+		}
+	}
+	if host_keys.is_empty() {
+		return Err(From::from(errors::BSSH_ERR_NO_HOST_KEYS));
+	}
+	for path in server_config.get_host_certificate_files().iter() {
+		let loaded = fs::read_to_string(path)
+			.and_then(|text| certificate::read_certificate_line(text.trim()))
+			.and_then(|(host_certificate, _)| diffie_hellman::attach_host_certificate(&mut host_keys, host_certificate));
+		if let Err(e) = loaded {
+			eprintln!("Unable to load host certificate \"{}\": {}", path.display(), e);
+		}
+	}
+	Ok(host_keys)
+}
+
+//authentication methods enabled in the configuration, in the order they are offered to the client
+fn get_auth_methods(server_config: &SshdConfig, totp: Option<SharedTotp>) -> Vec<Box<dyn ServerAuthMethod>> {
+	let mut methods: Vec<Box<dyn ServerAuthMethod>> = Vec::new();
+	if server_config.get_hostbased_authentication() {
+		let mut method = ServerHostbasedMethod::new(Box::new(|user| passwd::get_user_by_name(user).map(|p| p.home)));
+		method.ignore_rhosts = server_config.get_ignore_rhosts();
+		method.ignore_user_known_hosts = server_config.get_ignore_user_known_hosts();
+		method.uses_name_from_packet_only = server_config.get_hostbased_uses_name_from_packet_only();
+		methods.push(Box::new(method));
+	}
+	if server_config.get_pubkey_authentication() {
+		methods.push(Box::new(ServerPublicKeyMethod::new(server_config.get_authorized_keys_files(),
+		                                                 server_config.get_trusted_user_ca_keys(),
+		                                                 Box::new(|user| passwd::get_user_by_name(user).map(|p| p.home)))));
+	}
+	if server_config.get_kbd_interactive_authentication() {
+		let provider: Box<dyn ChallengeProvider> = match totp {
+			Some(totp) => Box::new(totp),
+			None => Box::new(PasswordChallengeProvider::new(Box::new(ShadowPasswordVerifier::new(PathBuf::from(auth_password::SHADOW_FILE))))),
+		};
+		methods.push(Box::new(ServerKeyboardInteractiveMethod::new(provider)));
+	}
+	if server_config.get_password_authentication() {
+		methods.push(Box::new(ServerPasswordMethod::new(Box::new(ShadowPasswordVerifier::new(PathBuf::from(auth_password::SHADOW_FILE))))));
+	}
+	methods
+}
+
+//LoginGraceTime is counted from the connection so a slow client can't extend it, the
+//connection is shut down unless the returned sender is dropped before the deadline
+fn start_login_grace_timer(stream: &TcpStream, grace: Duration) -> Result<mpsc::Sender<()>, io::Error> {
+	let watched = stream.try_clone()?;
+	let (authenticated, deadline) = mpsc::channel::<()>();
+	thread::spawn(move || {
+		if let Err(mpsc::RecvTimeoutError::Timeout) = deadline.recv_timeout(grace) {
+			let _ = watched.shutdown(Shutdown::Both);
+		}
+	});
+	Ok(authenticated)
+}
+
+fn handle_client(stream: TcpStream, server_config: Arc<SshdConfig>, host_keys: Arc<Vec<HostKey>>, totp: Option<SharedTotp>) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let client_address = stream.peer_addr().ok().map(|a| a.ip());
+	let started = Instant::now();
+	let login_grace_time = server_config.get_login_grace_time();
+	let grace_timer = match login_grace_time {
+		Some(grace) => Some(start_login_grace_timer(&stream, grace)?),
+		None => None,
+	};
+
+	let kex_result = kex::run_server(stream, &dummy_config::DummyCommonConfig{}, &host_keys)?;
+	let mut payload_stream = kex_result.stream;
+	let session_id = payload_stream.session_id.clone();
+	let methods = get_auth_methods(&server_config, totp);
+	let mut server = UserauthServer::new(&session_id, client_address, methods, server_config.get_max_auth_tries(), login_grace_time, started);
+	server.confidential = payload_stream.is_encrypted();
+	if server_config.get_use_dns() {
+		server.context.client_host_name = client_address.as_ref().and_then(dns::get_host_name_by_address);
+	}
+	server.set_authentication_methods(server_config.get_authentication_methods())?;
+	//missing banner file is not an error, as in OpenSSH
+	server.banner = server_config.get_banner_file().and_then(|path| fs::read_to_string(path).ok());
+	userauth::run_server(&mut payload_stream, &mut server)?;
+	drop(grace_timer);
+
+	let user = passwd::get_user_by_name(&server.context.user).ok_or("unknown user")?;
+	let auth_options = server.auth_options.take().unwrap_or_else(AuthOptions::unrestricted);
+	let (receiver, mut payload_stream) = payload_stream.split()?;
+	let session_config = SessionConfig {
+		accept_env: server_config.get_accept_env(),
+		internal_sftp_command: env::current_exe().ok().map(|exe| vec![exe.to_string_lossy().into_owned(), INTERNAL_SFTP_OPTION.to_string()]),
+		permit_user_environment: server_config.get_permit_user_environment(),
+		subsystems: server_config.get_subsystems(),
+		allow_local_forwarding: server_config.get_allow_local_forwarding(),
+		permit_open: server_config.get_permit_open(),
+		allow_remote_forwarding: server_config.get_allow_remote_forwarding(),
+		gateway_ports: server_config.get_gateway_ports(),
+	};
+	server_session::run_server_connection(receiver, &mut payload_stream, user, auth_options, session_config)?;
 
-        let mut vbuf = buf.to_vec();
-        vbuf.truncate(num);
-        println!("got {:?}: {:?}", num, vbuf);
-    }
     Ok(())
 }
 
+//standard input and output are the session channel, a fresh process has no threads of the daemon
+fn serve_internal_sftp() -> ! {
+    let mut input = unsafe { fs::File::from_raw_fd(0) };
+    let mut output = unsafe { fs::File::from_raw_fd(1) };
+    match sftp_server::serve(&mut input, &mut output) {
+        Ok(()) => process::exit(0),
+        Err(e) => {
+            eprintln!("internal-sftp: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
-    let listener = TcpListener::bind(HOST).unwrap();
+    if env::args().nth(1).as_deref() == Some(INTERNAL_SFTP_OPTION) {
+        serve_internal_sftp();
+    }
+    let (config, totp) = match load_config() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let host_keys = match load_host_keys(&config) {
+        Ok(host_keys) => Arc::new(host_keys),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let config = Arc::new(config);
+
+    let address = config.listen_address.clone().unwrap_or_else(|| DEFAULT_LISTEN_ADDRESS.to_string());
+    let listener = TcpListener::bind((address.as_str(), config.port.unwrap_or(DEFAULT_PORT))).unwrap();
     for stream in listener.incoming() {
         match stream {
-            Err(e) => println!("failed: {}", e),
+            Err(e) => eprintln!("accept failed: {}", e),
             Ok(stream) => {
+                let config = config.clone();
+                let host_keys = host_keys.clone();
+                let totp = totp.clone();
                 thread::spawn(move || {
-                		match handle_client(stream) {
-                			Err(e) => println!("failed2: {}", e),
-                			_ => {}
-                		}
+                    if let Err(e) = handle_client(stream, config, host_keys, totp) {
+                        eprintln!("connection failed: {}", e);
+                    }
                 });
             }
         }
@@ -0,0 +1,659 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::{Cursor, Error, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha512};
+use auth_publickey::Identity;
+use errors;
+use io_helpers;
+use mac;
+use keys::{PrivateKey, PublicKey};
+use msgs;
+use openssh_key;
+use signature;
+
+//draft-miller-ssh-agent, SSH Agent Protocol
+
+pub const AUTH_SOCK_ENV: &str = "SSH_AUTH_SOCK";
+
+pub const SSH_AGENT_FAILURE                : u8 =  5;
+pub const SSH_AGENT_SUCCESS                : u8 =  6;
+pub const SSH_AGENTC_REQUEST_IDENTITIES    : u8 = 11;
+pub const SSH_AGENT_IDENTITIES_ANSWER      : u8 = 12;
+pub const SSH_AGENTC_SIGN_REQUEST          : u8 = 13;
+pub const SSH_AGENT_SIGN_RESPONSE          : u8 = 14;
+pub const SSH_AGENTC_ADD_IDENTITY          : u8 = 17;
+pub const SSH_AGENTC_REMOVE_IDENTITY       : u8 = 18;
+pub const SSH_AGENTC_REMOVE_ALL_IDENTITIES : u8 = 19;
+pub const SSH_AGENTC_LOCK                  : u8 = 22;
+pub const SSH_AGENTC_UNLOCK                : u8 = 23;
+pub const SSH_AGENTC_ADD_ID_CONSTRAINED    : u8 = 25;
+
+//key constraints
+pub const SSH_AGENT_CONSTRAIN_LIFETIME     : u8 =  1;
+pub const SSH_AGENT_CONSTRAIN_CONFIRM      : u8 =  2;
+
+//signature flags, RFC 8332 page 6
+pub const SSH_AGENT_RSA_SHA2_256           : u32 = 2;
+pub const SSH_AGENT_RSA_SHA2_512           : u32 = 4;
+
+//same limit as OpenSSH
+const MAX_MESSAGE_LENGTH: u32 = 256 * 1024;
+
+//messages are "uint32 length, byte type, contents"
+pub fn write_agent_message(stream: &mut dyn Write, message: &[u8]) -> Result<(), Error> {
+    let mut framed: Vec<u8> = Vec::new();
+    framed.write_u32::<BigEndian>(message.len() as u32)?;
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+    stream.flush()
+}
+
+pub fn read_agent_message(stream: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    let length = stream.read_u32::<BigEndian>()?;
+    if length == 0 || length > MAX_MESSAGE_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_AGENT_MESSAGE_LENGTH));
+    }
+    let mut message = vec![0u8; length as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+//key as listed by the agent, key_blob may be a certificate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AgentKey {
+    pub key_blob: Vec<u8>,
+    pub comment: String,
+}
+
+pub fn write_identities_answer(stream: &mut dyn Write, keys: &[AgentKey]) -> Result<(), Error> {
+    stream.write_all(&[SSH_AGENT_IDENTITIES_ANSWER])?;
+    stream.write_u32::<BigEndian>(keys.len() as u32)?;
+    for key in keys.iter() {
+        io_helpers::write_string(stream, &key.key_blob)?;
+        io_helpers::write_string(stream, &key.comment.as_bytes().to_vec())?;
+    }
+    Ok(())
+}
+
+pub fn read_identities_answer(stream: &mut dyn Read) -> Result<Vec<AgentKey>, Error> {
+    msgs::read_message_number(stream, SSH_AGENT_IDENTITIES_ANSWER)?;
+    let count = stream.read_u32::<BigEndian>()?;
+    let mut keys: Vec<AgentKey> = Vec::new();
+    for _ in 0..count {
+        let key_blob = io_helpers::read_string(stream, None)?;
+        //comments are not required to be utf8
+        let comment = String::from_utf8_lossy(&io_helpers::read_string(stream, None)?).into_owned();
+        keys.push(AgentKey { key_blob, comment });
+    }
+    Ok(keys)
+}
+
+pub struct SignRequest {
+    pub key_blob: Vec<u8>,
+    pub data: Vec<u8>,
+    pub flags: u32,
+}
+
+pub fn write_sign_request(stream: &mut dyn Write, request: &SignRequest) -> Result<(), Error> {
+    stream.write_all(&[SSH_AGENTC_SIGN_REQUEST])?;
+    io_helpers::write_string(stream, &request.key_blob)?;
+    io_helpers::write_string(stream, &request.data)?;
+    stream.write_u32::<BigEndian>(request.flags)?;
+    Ok(())
+}
+
+pub fn read_sign_request(stream: &mut dyn Read) -> Result<SignRequest, Error> {
+    msgs::read_message_number(stream, SSH_AGENTC_SIGN_REQUEST)?;
+    let key_blob = io_helpers::read_string(stream, None)?;
+    let data = io_helpers::read_string(stream, None)?;
+    let flags = stream.read_u32::<BigEndian>()?;
+    Ok(SignRequest { key_blob, data, flags })
+}
+
+pub fn write_sign_response(stream: &mut dyn Write, signature: &[u8]) -> Result<(), Error> {
+    stream.write_all(&[SSH_AGENT_SIGN_RESPONSE])?;
+    io_helpers::write_string(stream, &signature.to_vec())?;
+    Ok(())
+}
+
+pub fn read_sign_response(stream: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    msgs::read_message_number(stream, SSH_AGENT_SIGN_RESPONSE)?;
+    io_helpers::read_string(stream, None)
+}
+
+pub struct AddIdentityRequest {
+    pub key: PrivateKey,
+    pub comment: String,
+    //seconds
+    pub lifetime: Option<u32>,
+    //every use has to be confirmed
+    pub confirm: bool,
+}
+
+//SSH_AGENTC_ADD_ID_CONSTRAINED is used only when there are constraints
+pub fn write_add_identity_request(stream: &mut dyn Write, request: &AddIdentityRequest) -> Result<(), Error> {
+    let constrained = request.lifetime.is_some() || request.confirm;
+    stream.write_all(&[if constrained { SSH_AGENTC_ADD_ID_CONSTRAINED } else { SSH_AGENTC_ADD_IDENTITY }])?;
+    let mut fields: Vec<u8> = Vec::new();
+    openssh_key::write_private_fields(&mut fields, &request.key);
+    stream.write_all(&fields)?;
+    io_helpers::write_string(stream, &request.comment.as_bytes().to_vec())?;
+    if let Some(lifetime) = request.lifetime {
+        stream.write_all(&[SSH_AGENT_CONSTRAIN_LIFETIME])?;
+        stream.write_u32::<BigEndian>(lifetime)?;
+    }
+    if request.confirm {
+        stream.write_all(&[SSH_AGENT_CONSTRAIN_CONFIRM])?;
+    }
+    Ok(())
+}
+
+//unknown constraints are an error, the key must not be added without them
+pub fn read_add_identity_request(message: &[u8]) -> Result<AddIdentityRequest, Error> {
+    let mut stream = Cursor::new(message);
+    let number = stream.read_u8()?;
+    if number != SSH_AGENTC_ADD_IDENTITY && number != SSH_AGENTC_ADD_ID_CONSTRAINED {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+    }
+    let key = openssh_key::read_private_fields(&mut stream)?;
+    let comment = String::from_utf8_lossy(&io_helpers::read_string(&mut stream, None)?).into_owned();
+    let mut request = AddIdentityRequest {
+        key,
+        comment,
+        lifetime: None,
+        confirm: false,
+    };
+    while number == SSH_AGENTC_ADD_ID_CONSTRAINED && (stream.position() as usize) < message.len() {
+        match stream.read_u8()? {
+            SSH_AGENT_CONSTRAIN_LIFETIME => request.lifetime = Some(stream.read_u32::<BigEndian>()?),
+            SSH_AGENT_CONSTRAIN_CONFIRM => request.confirm = true,
+            _ => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_AGENT_UNKNOWN_CONSTRAINT)),
+        }
+    }
+    Ok(request)
+}
+
+//flags selecting the signature algorithm, only RSA keys have a choice
+pub fn get_sign_flags(signature_algorithm: &str) -> u32 {
+    match signature_algorithm {
+        signature::RSA_SHA2_256 => SSH_AGENT_RSA_SHA2_256,
+        signature::RSA_SHA2_512 => SSH_AGENT_RSA_SHA2_512,
+        _ => 0,
+    }
+}
+
+fn agent_failure() -> Error {
+    Error::new(ErrorKind::PermissionDenied, errors::BSSH_ERR_AGENT_FAILURE)
+}
+
+pub struct AgentClient<S: Read + Write> {
+    pub stream: S,
+}
+
+impl AgentClient<UnixStream> {
+    //None when SSH_AUTH_SOCK is not set
+    pub fn connect_from_env() -> Result<Option<AgentClient<UnixStream>>, Error> {
+        match env::var_os(AUTH_SOCK_ENV) {
+            Some(ref path) if !path.is_empty() => Ok(Some(AgentClient::new(UnixStream::connect(path)?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<S: Read + Write> AgentClient<S> {
+    pub fn new(stream: S) -> AgentClient<S> {
+        AgentClient { stream }
+    }
+
+    //sends request and returns the reply, SSH_AGENT_FAILURE is turned into an error
+    fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, Error> {
+        write_agent_message(&mut self.stream, request)?;
+        let reply = read_agent_message(&mut self.stream)?;
+        if msgs::get_message_number(&reply)? == SSH_AGENT_FAILURE {
+            return Err(agent_failure());
+        }
+        Ok(reply)
+    }
+
+    //for requests answered with SSH_AGENT_SUCCESS
+    fn simple_request(&mut self, request: &[u8]) -> Result<(), Error> {
+        let reply = self.request(request)?;
+        match msgs::get_message_number(&reply)? {
+            SSH_AGENT_SUCCESS => Ok(()),
+            _ => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        }
+    }
+
+    pub fn add_identity(&mut self, request: &AddIdentityRequest) -> Result<(), Error> {
+        let mut message: Vec<u8> = Vec::new();
+        write_add_identity_request(&mut message, request)?;
+        self.simple_request(&message)
+    }
+
+    pub fn remove_identity(&mut self, key_blob: &[u8]) -> Result<(), Error> {
+        let mut message: Vec<u8> = vec![SSH_AGENTC_REMOVE_IDENTITY];
+        io_helpers::write_string(&mut message, &key_blob.to_vec())?;
+        self.simple_request(&message)
+    }
+
+    pub fn remove_all_identities(&mut self) -> Result<(), Error> {
+        self.simple_request(&[SSH_AGENTC_REMOVE_ALL_IDENTITIES])
+    }
+
+    pub fn lock(&mut self, passphrase: &str) -> Result<(), Error> {
+        let mut message: Vec<u8> = vec![SSH_AGENTC_LOCK];
+        io_helpers::write_string(&mut message, &passphrase.as_bytes().to_vec())?;
+        self.simple_request(&message)
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), Error> {
+        let mut message: Vec<u8> = vec![SSH_AGENTC_UNLOCK];
+        io_helpers::write_string(&mut message, &passphrase.as_bytes().to_vec())?;
+        self.simple_request(&message)
+    }
+
+    pub fn request_identities(&mut self) -> Result<Vec<AgentKey>, Error> {
+        let reply = self.request(&[SSH_AGENTC_REQUEST_IDENTITIES])?;
+        read_identities_answer(&mut Cursor::new(reply))
+    }
+
+    //returns signature encoded as signature::sign does
+    pub fn sign(&mut self, key_blob: &[u8], data: &[u8], flags: u32) -> Result<Vec<u8>, Error> {
+        let mut request: Vec<u8> = Vec::new();
+        write_sign_request(&mut request, &SignRequest {
+            key_blob: key_blob.to_vec(),
+            data: data.to_vec(),
+            flags,
+        })?;
+        let reply = self.request(&request)?;
+        read_sign_response(&mut Cursor::new(reply))
+    }
+}
+
+//key held by the agent, several identities share one connection
+pub struct AgentIdentity<S: Read + Write> {
+    agent: Rc<RefCell<AgentClient<S>>>,
+    pub key: AgentKey,
+}
+
+impl<S: Read + Write> Identity for AgentIdentity<S> {
+    fn get_public_key_blob(&self) -> Vec<u8> {
+        self.key.key_blob.clone()
+    }
+
+    fn get_description(&self) -> String {
+        self.key.comment.clone()
+    }
+
+    fn sign(&mut self, algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let signature = self.agent.borrow_mut().sign(&self.key.key_blob, data, get_sign_flags(algorithm))?;
+        //agent may ignore the flags, e.g. answer with ssh-rsa
+        match signature::read_signature(&signature) {
+            Ok((ref signed_with, _)) if signed_with == algorithm => Ok(signature),
+            _ => Err(agent_failure()),
+        }
+    }
+}
+
+pub fn get_agent_identities<S: Read + Write + 'static>(agent: AgentClient<S>) -> Result<Vec<Box<dyn Identity>>, Error> {
+    let agent = Rc::new(RefCell::new(agent));
+    let keys = agent.borrow_mut().request_identities()?;
+    Ok(keys.into_iter()
+        .map(|key| Box::new(AgentIdentity { agent: agent.clone(), key }) as Box<dyn Identity>)
+        .collect())
+}
+
+//asks the user whether a key with the confirm constraint may be used
+pub trait KeyConfirmation {
+    fn confirm(&mut self, message: &str) -> bool;
+}
+
+//refuses every use of confirm constrained keys
+pub struct NoConfirmation;
+
+impl KeyConfirmation for NoConfirmation {
+    fn confirm(&mut self, _message: &str) -> bool {
+        false
+    }
+}
+
+pub struct HeldKey {
+    pub key: PrivateKey,
+    pub public_key: PublicKey,
+    pub comment: String,
+    pub expires: Option<Instant>,
+    pub confirm: bool,
+}
+
+const LOCK_SALT_LENGTH: usize = 16;
+
+fn hash_lock_passphrase(salt: &[u8], passphrase: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update(salt);
+    hasher.update(passphrase);
+    hasher.finalize().to_vec()
+}
+
+//keys held in memory and the lock state, answers one request at a time
+pub struct AgentServer {
+    pub keys: Vec<HeldKey>,
+    //lifetime of keys added without one
+    pub default_lifetime: Option<Duration>,
+    //salt and hash of the lock passphrase while locked
+    lock: Option<(Vec<u8>, Vec<u8>)>,
+    confirmation: Box<dyn KeyConfirmation + Send>,
+}
+
+impl AgentServer {
+    pub fn new(confirmation: Box<dyn KeyConfirmation + Send>) -> AgentServer {
+        AgentServer {
+            keys: Vec::new(),
+            default_lifetime: None,
+            lock: None,
+            confirmation,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    pub fn remove_expired_keys(&mut self, now: Instant) {
+        self.keys.retain(|k| k.expires.map(|e| now < e).unwrap_or(true));
+    }
+
+    fn find_key(&self, key_blob: &[u8]) -> Option<usize> {
+        let key = PublicKey::from_blob(key_blob).ok()?;
+        self.keys.iter().position(|k| k.public_key == key)
+    }
+
+    fn add_identity(&mut self, message: &[u8], now: Instant) -> Result<(), Error> {
+        let request = read_add_identity_request(message)?;
+        let lifetime = request.lifetime.map(|l| Duration::from_secs(l as u64)).or(self.default_lifetime);
+        let public_key = request.key.get_public_key();
+        //adding a held key again replaces its comment and constraints
+        self.keys.retain(|k| k.public_key != public_key);
+        self.keys.push(HeldKey {
+            key: request.key,
+            public_key,
+            comment: request.comment,
+            expires: lifetime.map(|l| now + l),
+            confirm: request.confirm,
+        });
+        Ok(())
+    }
+
+    fn sign(&mut self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let request = read_sign_request(&mut Cursor::new(message))?;
+        let index = self.find_key(&request.key_blob).ok_or_else(agent_failure)?;
+        let algorithm = match self.keys[index].public_key {
+            PublicKey::Rsa(_) if request.flags & SSH_AGENT_RSA_SHA2_512 != 0 => signature::RSA_SHA2_512,
+            PublicKey::Rsa(_) if request.flags & SSH_AGENT_RSA_SHA2_256 != 0 => signature::RSA_SHA2_256,
+            _ => self.keys[index].key.get_algorithm_name(),
+        };
+        if self.keys[index].confirm {
+            let message = format!("Allow use of key {}?", self.keys[index].comment);
+            if !self.confirmation.confirm(&message) {
+                return Err(agent_failure());
+            }
+        }
+        let signature = signature::sign(&self.keys[index].key, algorithm, &request.data)?;
+        let mut reply: Vec<u8> = Vec::new();
+        write_sign_response(&mut reply, &signature)?;
+        Ok(reply)
+    }
+
+    fn remove_identity(&mut self, message: &[u8]) -> Result<(), Error> {
+        let mut stream = Cursor::new(message);
+        msgs::read_message_number(&mut stream, SSH_AGENTC_REMOVE_IDENTITY)?;
+        let key_blob = io_helpers::read_string(&mut stream, None)?;
+        let index = self.find_key(&key_blob).ok_or_else(agent_failure)?;
+        self.keys.remove(index);
+        Ok(())
+    }
+
+    fn read_passphrase(message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut stream = Cursor::new(&message[1..]);
+        io_helpers::read_string(&mut stream, None)
+    }
+
+    fn lock(&mut self, message: &[u8]) -> Result<(), Error> {
+        if self.lock.is_some() {
+            return Err(agent_failure());
+        }
+        let passphrase = AgentServer::read_passphrase(message)?;
+        let mut salt = vec![0u8; LOCK_SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        let hash = hash_lock_passphrase(&salt, &passphrase);
+        self.lock = Some((salt, hash));
+        Ok(())
+    }
+
+    fn unlock(&mut self, message: &[u8]) -> Result<(), Error> {
+        let passphrase = AgentServer::read_passphrase(message)?;
+        let unlocked = match self.lock {
+            Some((ref salt, ref hash)) => mac::constant_time_eq(&hash_lock_passphrase(salt, &passphrase), hash),
+            None => false,
+        };
+        if !unlocked {
+            return Err(agent_failure());
+        }
+        self.lock = None;
+        Ok(())
+    }
+
+    fn handle(&mut self, message: &[u8], now: Instant) -> Result<Vec<u8>, Error> {
+        let number = msgs::get_message_number(message)?;
+        let success = vec![SSH_AGENT_SUCCESS];
+        //locked agent lists no keys and accepts only unlocking
+        if self.is_locked() {
+            return match number {
+                SSH_AGENTC_REQUEST_IDENTITIES => {
+                    let mut reply: Vec<u8> = Vec::new();
+                    write_identities_answer(&mut reply, &[])?;
+                    Ok(reply)
+                }
+                SSH_AGENTC_UNLOCK => self.unlock(message).map(|_| success),
+                _ => Err(agent_failure()),
+            };
+        }
+        match number {
+            SSH_AGENTC_REQUEST_IDENTITIES => {
+                let keys: Vec<AgentKey> = self.keys
+                    .iter()
+                    .map(|k| AgentKey { key_blob: k.public_key.to_blob(), comment: k.comment.clone() })
+                    .collect();
+                let mut reply: Vec<u8> = Vec::new();
+                write_identities_answer(&mut reply, &keys)?;
+                Ok(reply)
+            }
+            SSH_AGENTC_SIGN_REQUEST => self.sign(message),
+            SSH_AGENTC_ADD_IDENTITY | SSH_AGENTC_ADD_ID_CONSTRAINED => self.add_identity(message, now).map(|_| success),
+            SSH_AGENTC_REMOVE_IDENTITY => self.remove_identity(message).map(|_| success),
+            SSH_AGENTC_REMOVE_ALL_IDENTITIES => {
+                self.keys.clear();
+                Ok(success)
+            }
+            SSH_AGENTC_LOCK => self.lock(message).map(|_| success),
+            _ => Err(agent_failure()),
+        }
+    }
+
+    //reply to one request, any problem is reported as SSH_AGENT_FAILURE
+    pub fn handle_request(&mut self, message: &[u8], now: Instant) -> Vec<u8> {
+        self.remove_expired_keys(now);
+        self.handle(message, now).unwrap_or_else(|_| vec![SSH_AGENT_FAILURE])
+    }
+}
+
+//serves requests of one client until it disconnects
+pub fn serve_connection<S: Read + Write>(stream: &mut S, agent: &Mutex<AgentServer>) -> Result<(), Error> {
+    loop {
+        let message = match read_agent_message(stream) {
+            Ok(message) => message,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let reply = match agent.lock() {
+            Ok(mut agent) => agent.handle_request(&message, Instant::now()),
+            Err(_) => vec![SSH_AGENT_FAILURE],
+        };
+        write_agent_message(stream, &reply)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::thread;
+    use keys;
+    use std::sync::Arc;
+
+    //asks nobody, answers are given in advance
+    struct ScriptedConfirmation {
+        answers: Vec<bool>,
+    }
+
+    impl KeyConfirmation for ScriptedConfirmation {
+        fn confirm(&mut self, _message: &str) -> bool {
+            !self.answers.is_empty() && self.answers.remove(0)
+        }
+    }
+
+    //agent holding the keys, serving on the other end of the returned stream
+    fn start_agent(private_keys: Vec<PrivateKey>, confirmations: Vec<bool>) -> (UnixStream, Arc<Mutex<AgentServer>>, thread::JoinHandle<()>) {
+        let mut agent = AgentServer::new(Box::new(ScriptedConfirmation { answers: confirmations }));
+        for key in private_keys {
+            let request = AddIdentityRequest { key, comment: "test".to_string(), lifetime: None, confirm: false };
+            let mut message: Vec<u8> = Vec::new();
+            write_add_identity_request(&mut message, &request).unwrap();
+            assert_eq!(agent.handle_request(&message, Instant::now()), vec![SSH_AGENT_SUCCESS]);
+        }
+        let agent = Arc::new(Mutex::new(agent));
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let server_agent = agent.clone();
+        let agent_thread = thread::spawn(move || serve_connection(&mut server, &server_agent).unwrap());
+        (client, agent, agent_thread)
+    }
+
+    #[test]
+    fn agent_identities_sign() {
+        let ed25519 = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let rsa = keys::generate_private_key(keys::SSH_RSA, Some(2048)).unwrap();
+        let (client, _, agent_thread) = start_agent(vec![ed25519.clone(), rsa.clone()], Vec::new());
+
+        let mut identities = get_agent_identities(AgentClient::new(client)).unwrap();
+        assert_eq!(identities.len(), 2);
+        assert_eq!(identities[0].get_public_key_blob(), ed25519.get_public_key().to_blob());
+        assert_eq!(identities[1].get_description(), "test");
+
+        let signature = identities[0].sign(keys::SSH_ED25519, b"data").unwrap();
+        assert!(signature::verify(&ed25519.get_public_key(), b"data", &signature));
+        let signature = identities[1].sign(signature::RSA_SHA2_512, b"data").unwrap();
+        assert_eq!(signature::read_signature(&signature).unwrap().0, signature::RSA_SHA2_512);
+        assert!(signature::verify(&rsa.get_public_key(), b"data", &signature));
+        //agent signs with the key algorithm, not the requested one
+        assert!(identities[0].sign(keys::ECDSA_SHA2_NISTP256, b"data").is_err());
+
+        drop(identities);
+        agent_thread.join().unwrap();
+    }
+
+    #[test]
+    fn agent_failure_is_an_error() {
+        let (client, _, agent_thread) = start_agent(Vec::new(), Vec::new());
+        let mut agent = AgentClient::new(client);
+        assert!(agent.request_identities().unwrap().is_empty());
+        assert_eq!(agent.sign(b"unknown", b"data", 0).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        drop(agent);
+        agent_thread.join().unwrap();
+    }
+
+    #[test]
+    fn agent_adds_and_removes_keys() {
+        let (client, server_agent, agent_thread) = start_agent(Vec::new(), vec![false, true]);
+        let mut agent = AgentClient::new(client);
+        let first = keys::generate_private_key(keys::ECDSA_SHA2_NISTP256, None).unwrap();
+        let second = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+
+        agent.add_identity(&AddIdentityRequest { key: first.clone(), comment: "first".to_string(), lifetime: Some(60), confirm: false }).unwrap();
+        agent.add_identity(&AddIdentityRequest { key: second.clone(), comment: "second".to_string(), lifetime: None, confirm: true }).unwrap();
+        let listed = agent.request_identities().unwrap();
+        assert_eq!(listed.iter().map(|k| k.comment.as_str()).collect::<Vec<&str>>(), vec!["first", "second"]);
+
+        //confirmation is refused once, then given
+        let second_blob = second.get_public_key().to_blob();
+        assert!(agent.sign(&second_blob, b"data", 0).is_err());
+        assert!(signature::verify(&second.get_public_key(), b"data", &agent.sign(&second_blob, b"data", 0).unwrap()));
+
+        //lifetime constraint
+        {
+            let mut server = server_agent.lock().unwrap();
+            server.remove_expired_keys(Instant::now() + Duration::from_secs(61));
+            assert_eq!(server.keys.len(), 1);
+        }
+
+        agent.remove_identity(&second_blob).unwrap();
+        assert!(agent.remove_identity(&second_blob).is_err());
+        assert!(agent.request_identities().unwrap().is_empty());
+
+        agent.add_identity(&AddIdentityRequest { key: first, comment: "again".to_string(), lifetime: None, confirm: false }).unwrap();
+        agent.remove_all_identities().unwrap();
+        assert!(agent.request_identities().unwrap().is_empty());
+
+        drop(agent);
+        agent_thread.join().unwrap();
+    }
+
+    #[test]
+    fn locked_agent_hides_keys() {
+        let key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let (client, _, agent_thread) = start_agent(vec![key.clone()], Vec::new());
+        let mut agent = AgentClient::new(client);
+
+        agent.lock("secret").unwrap();
+        assert!(agent.lock("again").is_err());
+        assert!(agent.request_identities().unwrap().is_empty());
+        assert!(agent.sign(&key.get_public_key().to_blob(), b"data", 0).is_err());
+        assert!(agent.remove_all_identities().is_err());
+        assert!(agent.unlock("wrong").is_err());
+        agent.unlock("secret").unwrap();
+        assert_eq!(agent.request_identities().unwrap().len(), 1);
+        assert!(agent.unlock("secret").is_err());
+
+        drop(agent);
+        agent_thread.join().unwrap();
+    }
+
+    #[test]
+    fn add_identity_request_roundtrips() {
+        let key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let mut message: Vec<u8> = Vec::new();
+        write_add_identity_request(&mut message, &AddIdentityRequest { key: key.clone(), comment: "c".to_string(), lifetime: Some(5), confirm: true }).unwrap();
+        assert_eq!(message[0], SSH_AGENTC_ADD_ID_CONSTRAINED);
+        let read = read_add_identity_request(&message).unwrap();
+        assert_eq!(read.key.get_public_key(), key.get_public_key());
+        assert_eq!((read.comment.as_str(), read.lifetime, read.confirm), ("c", Some(5), true));
+
+        //unknown constraint
+        message.push(0xff);
+        assert!(read_add_identity_request(&message).is_err());
+    }
+
+    #[test]
+    fn agent_message_length_is_checked() {
+        let mut framed: Vec<u8> = Vec::new();
+        write_agent_message(&mut framed, &[SSH_AGENTC_REQUEST_IDENTITIES]).unwrap();
+        assert_eq!(framed, vec![0, 0, 0, 1, SSH_AGENTC_REQUEST_IDENTITIES]);
+        assert_eq!(read_agent_message(&mut Cursor::new(framed)).unwrap(), vec![SSH_AGENTC_REQUEST_IDENTITIES]);
+        assert!(read_agent_message(&mut Cursor::new(vec![0xff, 0, 0, 0])).is_err());
+        assert!(read_agent_message(&mut Cursor::new(vec![0, 0, 0, 0])).is_err());
+    }
+}
@@ -0,0 +1,603 @@
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use byteorder::ReadBytesExt;
+use libc;
+use auth_options::AuthOptions;
+use auth_publickey::{get_publickey_algorithms, get_signature_algorithm, HomeLookup, Identity};
+use certificate;
+use certificate::Certificate;
+use dns;
+use errors;
+use io_helpers;
+use keys;
+use keys::{PrivateKey, PublicKey};
+use known_hosts;
+use known_hosts::{HostKeyStatus, KnownHosts};
+use numbers;
+use public_key_file;
+use signature;
+use userauth;
+use userauth::{ClientAuthContext, ClientAuthMethod, ServerAuthContext, ServerAuthMethod, ServerMethodResult};
+
+//RFC 4252 page 12, Host-Based Authentication: "hostbased"
+
+pub const METHOD_HOSTBASED: &str = "hostbased";
+
+//ssh_config(5) HostbasedKeyTypes are taken from these, private keys are readable by root only
+//so bsshc uses the public keys and has KEYSIGN_PROGRAM sign
+pub const DEFAULT_HOST_KEY_FILES: [&str; 3] = ["/etc/ssh/ssh_host_ecdsa_key", "/etc/ssh/ssh_host_ed25519_key", "/etc/ssh/ssh_host_rsa_key"];
+pub const SYSTEM_KNOWN_HOSTS_FILES: [&str; 2] = ["/etc/ssh/ssh_known_hosts", "/etc/ssh/ssh_known_hosts2"];
+pub const USER_KNOWN_HOSTS_FILE: &str = ".ssh/known_hosts";
+//not used for root, as in OpenSSH
+pub const SYSTEM_EQUIV_FILES: [&str; 2] = ["/etc/hosts.equiv", "/etc/ssh/shosts.equiv"];
+//used only without IgnoreRhosts
+pub const USER_EQUIV_FILES: [&str; 2] = [".shosts", ".rhosts"];
+
+const MAX_EQUIV_FILE_LENGTH: u64 = 1024 * 1024; //TODO arbitrary value
+
+//method specific part of the USERAUTH_REQUEST
+pub struct HostbasedRequest {
+    pub algorithm: String,
+    pub key_blob: Vec<u8>,
+    //fully qualified, OpenSSH adds a trailing dot
+    pub client_host: String,
+    pub client_user: String,
+    pub signature: Vec<u8>,
+}
+
+pub fn write_hostbased_request(stream: &mut dyn Write, request: &HostbasedRequest) -> Result<(), Error> {
+    io_helpers::write_string(stream, &request.algorithm.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.key_blob)?;
+    io_helpers::write_string(stream, &request.client_host.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.client_user.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.signature)?;
+    Ok(())
+}
+
+pub fn read_hostbased_request(stream: &mut dyn Read) -> Result<HostbasedRequest, Error> {
+    let algorithm = io_helpers::read_utf8_string(stream)?;
+    let key_blob = io_helpers::read_string(stream, None)?;
+    let client_host = io_helpers::read_utf8_string(stream)?;
+    let client_user = io_helpers::read_utf8_string(stream)?;
+    let signature = io_helpers::read_string(stream, None)?;
+    Ok(HostbasedRequest {
+        algorithm,
+        key_blob,
+        client_host,
+        client_user,
+        signature,
+    })
+}
+
+//RFC 4252 page 13, data covered by the signature
+pub fn get_signed_data(session_id: &[u8], user: &str, service: &str, request: &HostbasedRequest) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    //writing to Vec can not fail
+    io_helpers::write_string(&mut data, &session_id.to_vec()).unwrap();
+    data.push(numbers::SSH_MSG_USERAUTH_REQUEST);
+    io_helpers::write_string(&mut data, &user.as_bytes().to_vec()).unwrap();
+    io_helpers::write_string(&mut data, &service.as_bytes().to_vec()).unwrap();
+    io_helpers::write_string(&mut data, &METHOD_HOSTBASED.as_bytes().to_vec()).unwrap();
+    io_helpers::write_string(&mut data, &request.algorithm.as_bytes().to_vec()).unwrap();
+    io_helpers::write_string(&mut data, &request.key_blob).unwrap();
+    io_helpers::write_string(&mut data, &request.client_host.as_bytes().to_vec()).unwrap();
+    io_helpers::write_string(&mut data, &request.client_user.as_bytes().to_vec()).unwrap();
+    data
+}
+
+//gethostname(2), None if it fails
+pub fn get_local_host_name() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return None;
+    }
+    let length = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..length]).into_owned())
+}
+
+pub type HostNameLookup = Box<dyn Fn(&IpAddr) -> Option<String>>;
+
+//signs with each host key in turn, there is no query step as for publickey
+pub struct ClientHostbasedMethod {
+    identities: Vec<Box<dyn Identity>>,
+    client_host: String,
+    client_user: String,
+    next: usize,
+}
+
+impl ClientHostbasedMethod {
+    pub fn new(identities: Vec<Box<dyn Identity>>, client_host: &str, client_user: &str) -> ClientHostbasedMethod {
+        let client_host = if client_host.ends_with('.') { client_host.to_string() } else { format!("{}.", client_host) };
+        ClientHostbasedMethod {
+            identities,
+            client_host,
+            client_user: client_user.to_string(),
+            next: 0,
+        }
+    }
+}
+
+impl ClientAuthMethod for ClientHostbasedMethod {
+    fn get_name(&self) -> &'static str {
+        METHOD_HOSTBASED
+    }
+
+    fn next_request(&mut self, context: &ClientAuthContext) -> Result<Option<Vec<u8>>, Error> {
+        while self.next < self.identities.len() {
+            let index = self.next;
+            self.next += 1;
+
+            let key_blob = self.identities[index].get_public_key_blob();
+            let algorithm = match get_publickey_algorithms(&key_blob) {
+                Ok(ref algorithms) if !algorithms.is_empty() => algorithms[0].clone(),
+                _ => continue,
+            };
+            let mut request = HostbasedRequest {
+                algorithm,
+                key_blob,
+                client_host: self.client_host.clone(),
+                client_user: self.client_user.clone(),
+                signature: Vec::new(),
+            };
+            let data = get_signed_data(&context.session_id, &context.user, &context.service, &request);
+            request.signature = match self.identities[index].sign(get_signature_algorithm(&request.algorithm), &data) {
+                Ok(signature) => signature,
+                Err(_) => continue,
+            };
+            let mut method_data: Vec<u8> = Vec::new();
+            write_hostbased_request(&mut method_data, &request)?;
+            return Ok(Some(context.get_request_payload(METHOD_HOSTBASED, method_data)));
+        }
+        Ok(None)
+    }
+}
+
+//ssh-keysign(8) equivalent, installed next to bsshc, setuid root to read the host keys
+pub const KEYSIGN_PROGRAM: &str = "bssh-keysign";
+
+fn bad_keysign_request() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_KEYSIGN_BAD_REQUEST)
+}
+
+//the helper signs only data of get_signed_data for the real user on this host, otherwise
+//any local user could have the host keys sign whatever they like
+pub fn check_keysign_request(data: &[u8], local_user: &str, local_host: &str) -> Result<HostbasedRequest, Error> {
+    let mut stream = Cursor::new(data);
+    let session_id = io_helpers::read_string(&mut stream, None)?;
+    //length of the exchange hash of the supported key exchanges, as OpenSSH checks
+    if ![20, 32, 48, 64].contains(&session_id.len()) || stream.read_u8()? != numbers::SSH_MSG_USERAUTH_REQUEST {
+        return Err(bad_keysign_request());
+    }
+    let _server_user = io_helpers::read_utf8_string(&mut stream)?;
+    let service = io_helpers::read_utf8_string(&mut stream)?;
+    let method = io_helpers::read_utf8_string(&mut stream)?;
+    let request = HostbasedRequest {
+        algorithm: io_helpers::read_utf8_string(&mut stream)?,
+        key_blob: io_helpers::read_string(&mut stream, None)?,
+        client_host: io_helpers::read_utf8_string(&mut stream)?,
+        client_user: io_helpers::read_utf8_string(&mut stream)?,
+        signature: Vec::new(),
+    };
+    let algorithm_matches = get_publickey_algorithms(&request.key_blob).map(|a| a.contains(&request.algorithm)).unwrap_or(false);
+    if stream.position() != data.len() as u64 || service != userauth::SERVICE_CONNECTION || method != METHOD_HOSTBASED || !algorithm_matches ||
+        request.client_user != local_user || !request.client_host.trim_end_matches('.').eq_ignore_ascii_case(local_host) {
+        return Err(bad_keysign_request());
+    }
+    Ok(request)
+}
+
+//signs with the host key named by the request, after check_keysign_request
+pub fn sign_keysign_request(host_keys: &[PrivateKey], data: &[u8], local_user: &str, local_host: &str) -> Result<Vec<u8>, Error> {
+    let request = check_keysign_request(data, local_user, local_host)?;
+    let key = host_keys.iter().find(|k| k.get_public_key().to_blob() == request.key_blob)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, errors::BSSH_ERR_KEYSIGN_NO_HOST_KEY))?;
+    signature::sign(key, get_signature_algorithm(&request.algorithm), data)
+}
+
+//host key whose public part is readable, signing is done by the helper program which gets the
+//data to sign as an SSH string on its standard input and writes the signature the same way
+pub struct KeysignIdentity {
+    pub helper: PathBuf,
+    pub blob: Vec<u8>,
+    pub description: String,
+}
+
+impl KeysignIdentity {
+    pub fn load(helper: &Path, key_file: &str) -> Result<KeysignIdentity, Error> {
+        let public_key = public_key_file::load_public_key_file(Path::new(&format!("{}.pub", key_file)))?;
+        Ok(KeysignIdentity {
+            helper: helper.to_path_buf(),
+            blob: public_key.key.to_blob(),
+            description: key_file.to_string(),
+        })
+    }
+}
+
+impl Identity for KeysignIdentity {
+    fn get_public_key_blob(&self) -> Vec<u8> {
+        self.blob.clone()
+    }
+
+    fn get_description(&self) -> String {
+        self.description.clone()
+    }
+
+    //the helper takes the algorithm from the request in data, a helper exiting early may
+    //leave the request unwritten, so its exit status is checked first
+    fn sign(&mut self, _algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut child = Command::new(&self.helper).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let written = match child.stdin.take() {
+            Some(mut input) => io_helpers::write_string(&mut input, &data.to_vec()),
+            None => Ok(()),
+        };
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::other(errors::BSSH_ERR_KEYSIGN_FAILED));
+        }
+        written?;
+        io_helpers::read_string(&mut Cursor::new(output.stdout.as_slice()), None)
+    }
+}
+
+//host keys with a readable public key file, unless there are none
+pub fn load_keysign_identities(helper: &Path) -> Vec<Box<dyn Identity>> {
+    let mut identities: Vec<Box<dyn Identity>> = Vec::new();
+    for file in DEFAULT_HOST_KEY_FILES.iter() {
+        if let Ok(identity) = KeysignIdentity::load(helper, file) {
+            identities.push(Box::new(identity));
+        }
+    }
+    identities
+}
+
+//one line of hosts.equiv(5) or .shosts: "host [user]", where "+" matches anything and "-" negates.
+//None if the line does not apply, netgroups ("@group") never match.
+pub fn check_equiv_line(line: &str, client_host: &str, client_user: &str, server_user: &str) -> Option<bool> {
+    let mut fields = line.split_whitespace();
+    let host = fields.next()?;
+    if host.starts_with('#') {
+        return None;
+    }
+    let (host_negated, host) = match host.strip_prefix('-') {
+        Some(host) => (true, host),
+        None => (false, host),
+    };
+    let host_matches = match host {
+        "+" => true,
+        _ if host.starts_with('@') || host.starts_with("+@") => false,
+        _ => host.trim_end_matches('.').eq_ignore_ascii_case(client_host),
+    };
+    if !host_matches {
+        return None;
+    }
+
+    let (user_negated, user_matches) = match fields.next() {
+        None => (false, client_user == server_user),
+        Some("+") => (false, true),
+        Some(user) if user.starts_with('@') || user.starts_with("+@") => (false, false),
+        Some(user) => match user.strip_prefix('-') {
+            Some(user) => (true, user == client_user),
+            None => (false, user == client_user),
+        },
+    };
+    if !user_matches {
+        return None;
+    }
+    Some(!host_negated && !user_negated)
+}
+
+//first line that applies decides
+pub fn check_equiv_file(text: &str, client_host: &str, client_user: &str, server_user: &str) -> Option<bool> {
+    text.lines().filter_map(|line| check_equiv_line(line, client_host, client_user, server_user)).next()
+}
+
+pub struct ServerHostbasedMethod {
+    pub known_hosts_files: Vec<PathBuf>,
+    pub equiv_files: Vec<PathBuf>,
+    //sshd_config(5) IgnoreRhosts, skips .shosts and .rhosts of the user
+    pub ignore_rhosts: bool,
+    //sshd_config(5) IgnoreUserKnownHosts
+    pub ignore_user_known_hosts: bool,
+    //sshd_config(5) HostbasedUsesNameFromPacketOnly, otherwise the host name of the
+    //request has to be the name of the client address
+    pub uses_name_from_packet_only: bool,
+    pub host_name_lookup: HostNameLookup,
+    home_lookup: HomeLookup,
+}
+
+impl ServerHostbasedMethod {
+    pub fn new(home_lookup: HomeLookup) -> ServerHostbasedMethod {
+        ServerHostbasedMethod {
+            known_hosts_files: SYSTEM_KNOWN_HOSTS_FILES.iter().map(PathBuf::from).collect(),
+            equiv_files: SYSTEM_EQUIV_FILES.iter().map(PathBuf::from).collect(),
+            ignore_rhosts: true,
+            ignore_user_known_hosts: false,
+            uses_name_from_packet_only: false,
+            host_name_lookup: Box::new(dns::get_host_name_by_address),
+            home_lookup,
+        }
+    }
+
+    fn read_file(path: &Path) -> Option<String> {
+        let mut text = String::new();
+        let file = fs::File::open(path).ok()?;
+        file.take(MAX_EQUIV_FILE_LENGTH).read_to_string(&mut text).ok()?;
+        Some(text)
+    }
+
+    //whether client_user on client_host may log in as user without further authentication
+    pub fn is_user_allowed(&self, user: &str, client_host: &str, client_user: &str) -> bool {
+        let mut files: Vec<PathBuf> = Vec::new();
+        if user != "root" {
+            files.extend(self.equiv_files.iter().cloned());
+        }
+        if !self.ignore_rhosts {
+            if let Some(home) = (self.home_lookup)(user) {
+                files.extend(USER_EQUIV_FILES.iter().map(|f| home.join(f)));
+            }
+        }
+        for path in files.iter() {
+            if let Some(text) = ServerHostbasedMethod::read_file(path) {
+                if let Some(allowed) = check_equiv_file(&text, client_host, client_user, user) {
+                    return allowed;
+                }
+            }
+        }
+        false
+    }
+
+    fn read_known_hosts(&self, user: &str) -> KnownHosts {
+        let mut known = KnownHosts::new();
+        let mut files = self.known_hosts_files.clone();
+        if !self.ignore_user_known_hosts {
+            if let Some(home) = (self.home_lookup)(user) {
+                files.push(home.join(USER_KNOWN_HOSTS_FILE));
+            }
+        }
+        for path in files.iter() {
+            //unreadable files are skipped
+            let _ = known.read_file(path);
+        }
+        known
+    }
+
+    //returns key to verify the signature with, None if it is not a known key of the host
+    fn check_host_key(&self, user: &str, client_host: &str, key_blob: &[u8]) -> Option<PublicKey> {
+        let known = self.read_known_hosts(user);
+        let port = known_hosts::DEFAULT_PORT;
+        if certificate::is_certificate_blob(key_blob) {
+            let certificate = Certificate::from_blob(key_blob).ok()?;
+            match known.check_host_certificate(client_host, port, &certificate, certificate::get_current_time()) {
+                Ok(HostKeyStatus::Known) => Some(certificate.key),
+                _ => None,
+            }
+        } else {
+            let key = PublicKey::from_blob(key_blob).ok()?;
+            match known.check_host_key(client_host, port, &key) {
+                HostKeyStatus::Known => Some(key),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl ServerAuthMethod for ServerHostbasedMethod {
+    fn get_name(&self) -> &'static str {
+        METHOD_HOSTBASED
+    }
+
+    fn handle_request(&mut self, context: &ServerAuthContext, method_data: &[u8]) -> Result<ServerMethodResult, Error> {
+        let request = read_hostbased_request(&mut Cursor::new(method_data))?;
+        match get_publickey_algorithms(&request.key_blob) {
+            Ok(ref algorithms) if algorithms.contains(&request.algorithm) => {}
+            _ => return Ok(ServerMethodResult::Failure),
+        }
+        //SHA-1 signatures are not accepted any more
+        if get_signature_algorithm(&request.algorithm) == keys::SSH_RSA {
+            return Ok(ServerMethodResult::Failure);
+        }
+
+        let client_host = request.client_host.trim_end_matches('.');
+        if !self.uses_name_from_packet_only {
+            let resolved = context.client_address.as_ref().and_then(|a| (self.host_name_lookup)(a));
+            if !resolved.map(|name| name.trim_end_matches('.').eq_ignore_ascii_case(client_host)).unwrap_or(false) {
+                return Ok(ServerMethodResult::Failure);
+            }
+        }
+        if !self.is_user_allowed(&context.user, client_host, &request.client_user) {
+            return Ok(ServerMethodResult::Failure);
+        }
+        let key = match self.check_host_key(&context.user, client_host, &request.key_blob) {
+            Some(key) => key,
+            None => return Ok(ServerMethodResult::Failure),
+        };
+
+        match signature::read_signature(&request.signature) {
+            Ok((ref algorithm, _)) if algorithm == get_signature_algorithm(&request.algorithm) => {}
+            _ => return Ok(ServerMethodResult::Failure),
+        }
+        let data = get_signed_data(&context.session_id, &context.user, &context.service, &request);
+        if signature::verify(&key, &data, &request.signature) {
+            Ok(ServerMethodResult::Success(AuthOptions::unrestricted()))
+        } else {
+            Ok(ServerMethodResult::Failure)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+    use std::io::ErrorKind;
+    use std::time::Instant;
+    use auth_publickey::PrivateKeyIdentity;
+    use keys::PrivateKey;
+    use userauth::{ClientAuthState, UserauthClient, UserauthServer};
+
+    fn make_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bssh_hostbased_{}_{}", name, std::process::id()));
+        fs::create_dir_all(dir.join(".ssh")).unwrap();
+        dir
+    }
+
+    //known hosts and .shosts are both in the home directory of the user,
+    //the host name of the request is trusted when there is no resolved name
+    fn authenticate_from(dir: &Path, host_key: &PrivateKey, client_host: &str, resolved_host: Option<&str>) -> (UserauthClient, UserauthServer, Result<(), Error>) {
+        let identities: Vec<Box<dyn Identity>> = vec![Box::new(PrivateKeyIdentity::new(host_key.clone(), "host key"))];
+        let mut client = UserauthClient::new("bob", b"session", vec![Box::new(ClientHostbasedMethod::new(identities, client_host, "alice"))]);
+        let lookup_home = dir.to_path_buf();
+        let mut method = ServerHostbasedMethod::new(Box::new(move |_| Some(lookup_home.clone())));
+        method.known_hosts_files = Vec::new();
+        method.equiv_files = Vec::new();
+        method.ignore_rhosts = false;
+        method.uses_name_from_packet_only = resolved_host.is_none();
+        let resolved_host = resolved_host.map(|h| h.to_string());
+        method.host_name_lookup = Box::new(move |_| resolved_host.clone());
+        let client_address = "192.0.2.1".parse().ok();
+        let mut server = UserauthServer::new(b"session", client_address, vec![Box::new(method)], 6, None, Instant::now());
+
+        let mut to_server = client.start();
+        let mut res = Ok(());
+        while !to_server.is_empty() && res.is_ok() {
+            let mut to_client: Vec<Vec<u8>> = Vec::new();
+            for payload in to_server.iter() {
+                to_client.extend(server.handle_payload(payload, Instant::now()).unwrap());
+            }
+            to_server = Vec::new();
+            for payload in to_client.iter() {
+                match client.handle_payload(payload) {
+                    Ok(payloads) => to_server.extend(payloads),
+                    Err(e) => res = Err(e),
+                }
+            }
+        }
+        (client, server, res)
+    }
+
+    fn authenticate(dir: &Path, host_key: &PrivateKey, client_host: &str) -> (UserauthClient, UserauthServer, Result<(), Error>) {
+        authenticate_from(dir, host_key, client_host, None)
+    }
+
+    #[test]
+    fn hostbased_name_has_to_match_client_address() {
+        let dir = make_dir("address");
+        let host_key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let known_host = known_hosts::write_known_host_line("node1.cluster", known_hosts::DEFAULT_PORT, &host_key.get_public_key(), true);
+        fs::write(dir.join(USER_KNOWN_HOSTS_FILE), format!("{}\n", known_host)).unwrap();
+        fs::write(dir.join(".shosts"), "+ alice\n").unwrap();
+
+        let (client, _, res) = authenticate_from(&dir, &host_key, "node1.cluster", Some("NODE1.cluster."));
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        let (_, _, res) = authenticate_from(&dir, &host_key, "node1.cluster", Some("node2.cluster"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn hostbased_authentication_works() {
+        let dir = make_dir("works");
+        let host_key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let known_host = known_hosts::write_known_host_line("node1.cluster", known_hosts::DEFAULT_PORT, &host_key.get_public_key(), true);
+        fs::write(dir.join(USER_KNOWN_HOSTS_FILE), format!("{}\n", known_host)).unwrap();
+        fs::write(dir.join(".shosts"), "-node2.cluster alice\nnode1.cluster alice\nnode2.cluster +\n").unwrap();
+
+        let (client, server, res) = authenticate(&dir, &host_key, "node1.cluster");
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.get_authenticated_user(), Some("bob"));
+
+        //known key, but the host is denied
+        let (_, _, res) = authenticate(&dir, &host_key, "node2.cluster");
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+        //allowed host, but the key is not known for it
+        fs::write(dir.join(".shosts"), "+ alice\n").unwrap();
+        let (_, _, res) = authenticate(&dir, &host_key, "node3.cluster");
+        assert!(res.is_err());
+        let other_key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let (_, _, res) = authenticate(&dir, &other_key, "node1.cluster");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn equiv_lines_are_checked() {
+        assert_eq!(check_equiv_line("node1", "node1", "bob", "bob"), Some(true));
+        assert_eq!(check_equiv_line("node1", "node1", "alice", "bob"), None);
+        assert_eq!(check_equiv_line("NODE1. alice", "node1", "alice", "bob"), Some(true));
+        assert_eq!(check_equiv_line("+ +", "any", "alice", "bob"), Some(true));
+        assert_eq!(check_equiv_line("-node1", "node1", "bob", "bob"), Some(false));
+        assert_eq!(check_equiv_line("node1 -alice", "node1", "alice", "bob"), Some(false));
+        assert_eq!(check_equiv_line("@cluster alice", "node1", "alice", "bob"), None);
+        assert_eq!(check_equiv_line("# node1", "node1", "bob", "bob"), None);
+        assert_eq!(check_equiv_file("node1 -alice\n+ +\n", "node1", "alice", "bob"), Some(false));
+        assert_eq!(check_equiv_file("\nnode2\n", "node1", "bob", "bob"), None);
+    }
+
+    #[test]
+    fn reading_writing_hostbased_request_works() {
+        let request = HostbasedRequest {
+            algorithm: keys::SSH_ED25519.to_string(),
+            key_blob: vec![1, 2],
+            client_host: "node1.".to_string(),
+            client_user: "alice".to_string(),
+            signature: vec![3],
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_hostbased_request(&mut data, &request).unwrap();
+        let read = read_hostbased_request(&mut Cursor::new(data)).unwrap();
+        assert_eq!((read.algorithm.as_str(), read.client_host.as_str(), read.client_user.as_str()), (keys::SSH_ED25519, "node1.", "alice"));
+        assert_eq!((read.key_blob, read.signature), (vec![1, 2], vec![3]));
+    }
+
+    fn get_keysign_data(session_id: &[u8], key: &PrivateKey, client_host: &str, client_user: &str) -> Vec<u8> {
+        let request = HostbasedRequest {
+            algorithm: keys::SSH_ED25519.to_string(),
+            key_blob: key.get_public_key().to_blob(),
+            client_host: client_host.to_string(),
+            client_user: client_user.to_string(),
+            signature: Vec::new(),
+        };
+        get_signed_data(session_id, "bob", userauth::SERVICE_CONNECTION, &request)
+    }
+
+    #[test]
+    fn keysign_signs_only_requests_of_local_user() {
+        let host_key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let data = get_keysign_data(&[7; 32], &host_key, "node1.", "alice");
+        let signature = sign_keysign_request(std::slice::from_ref(&host_key), &data, "alice", "node1").unwrap();
+        assert!(signature::verify(&host_key.get_public_key(), &data, &signature));
+
+        for data in [get_keysign_data(&[7; 32], &host_key, "node1.", "mallory"),
+                     get_keysign_data(&[7; 32], &host_key, "node2.", "alice"),
+                     get_keysign_data(&[7; 5], &host_key, "node1.", "alice"),
+                     [data.clone(), vec![0]].concat()].iter() {
+            let err = sign_keysign_request(std::slice::from_ref(&host_key), data, "alice", "node1").unwrap_err();
+            assert_eq!(err.to_string(), errors::BSSH_ERR_KEYSIGN_BAD_REQUEST);
+        }
+
+        let other_key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        let err = sign_keysign_request(&[other_key], &data, "alice", "node1").unwrap_err();
+        assert_eq!(err.to_string(), errors::BSSH_ERR_KEYSIGN_NO_HOST_KEY);
+    }
+
+    #[test]
+    fn keysign_identity_runs_helper() {
+        //cat writes the SSH string back, as if it was the signature
+        let mut identity = KeysignIdentity {
+            helper: PathBuf::from("/bin/cat"),
+            blob: vec![1],
+            description: "host key".to_string(),
+        };
+        assert_eq!(identity.sign(keys::SSH_ED25519, b"data").unwrap(), b"data".to_vec());
+
+        identity.helper = PathBuf::from("/bin/false");
+        assert_eq!(identity.sign(keys::SSH_ED25519, b"data").unwrap_err().to_string(), errors::BSSH_ERR_KEYSIGN_FAILED);
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use auth_options::AuthOptions;
+use auth_password::{PasswordCheck, PasswordVerifier};
+use errors;
+use io_helpers;
+use mac;
+use msgs;
+use numbers;
+use terminal;
+use userauth::{ClientAuthContext, ClientAuthMethod, ServerAuthContext, ServerAuthMethod, ServerMethodResult};
+
+//RFC 4256, Generic Message Exchange Authentication: "keyboard-interactive"
+
+pub const METHOD_KEYBOARD_INTERACTIVE: &str = "keyboard-interactive";
+
+//arbitrary, OpenSSH allows 100 prompts
+const MAX_PROMPTS: u32 = 100;
+
+//method specific part of the USERAUTH_REQUEST
+pub struct KeyboardInteractiveRequest {
+    pub language: String,
+    //comma separated hints, RFC 4256 page 4
+    pub submethods: String,
+}
+
+pub fn write_keyboard_interactive_request(stream: &mut dyn Write, request: &KeyboardInteractiveRequest) -> Result<(), Error> {
+    io_helpers::write_string(stream, &request.language.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.submethods.as_bytes().to_vec())?;
+    Ok(())
+}
+
+pub fn read_keyboard_interactive_request(stream: &mut dyn Read) -> Result<KeyboardInteractiveRequest, Error> {
+    let language = io_helpers::read_utf8_string(stream)?;
+    let submethods = io_helpers::read_utf8_string(stream)?;
+    Ok(KeyboardInteractiveRequest {
+        language,
+        submethods,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prompt {
+    pub prompt: String,
+    //false for secrets like passwords and one time codes
+    pub echo: bool,
+}
+
+impl Prompt {
+    pub fn new(prompt: &str, echo: bool) -> Prompt {
+        Prompt {
+            prompt: prompt.to_string(),
+            echo,
+        }
+    }
+}
+
+//RFC 4256 page 5, SSH_MSG_USERAUTH_INFO_REQUEST
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InfoRequest {
+    pub name: String,
+    pub instruction: String,
+    pub prompts: Vec<Prompt>,
+}
+
+impl InfoRequest {
+    pub fn new(name: &str, instruction: &str, prompts: Vec<Prompt>) -> InfoRequest {
+        InfoRequest {
+            name: name.to_string(),
+            instruction: instruction.to_string(),
+            prompts,
+        }
+    }
+}
+
+pub fn write_info_request_message(stream: &mut dyn Write, request: &InfoRequest) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_USERAUTH_INFO_REQUEST])?;
+    io_helpers::write_string(stream, &request.name.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.instruction.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &Vec::new())?;
+    stream.write_u32::<BigEndian>(request.prompts.len() as u32)?;
+    for prompt in request.prompts.iter() {
+        io_helpers::write_string(stream, &prompt.prompt.as_bytes().to_vec())?;
+        io_helpers::write_boolean(stream, prompt.echo)?;
+    }
+    Ok(())
+}
+
+pub fn read_info_request_message(stream: &mut dyn Read) -> Result<InfoRequest, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_USERAUTH_INFO_REQUEST)?;
+    let name = io_helpers::read_utf8_string(stream)?;
+    let instruction = io_helpers::read_utf8_string(stream)?;
+    let _language_tag = io_helpers::read_string(stream, None)?;
+    let count = stream.read_u32::<BigEndian>()?;
+    if count > MAX_PROMPTS {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_TOO_MANY_PROMPTS));
+    }
+    let mut prompts: Vec<Prompt> = Vec::new();
+    for _ in 0..count {
+        let prompt = io_helpers::read_utf8_string(stream)?;
+        let echo = io_helpers::read_boolean(stream)?;
+        prompts.push(Prompt { prompt, echo });
+    }
+    Ok(InfoRequest {
+        name,
+        instruction,
+        prompts,
+    })
+}
+
+//RFC 4256 page 6, SSH_MSG_USERAUTH_INFO_RESPONSE
+pub fn write_info_response_message(stream: &mut dyn Write, responses: &[String]) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_USERAUTH_INFO_RESPONSE])?;
+    stream.write_u32::<BigEndian>(responses.len() as u32)?;
+    for response in responses.iter() {
+        io_helpers::write_string(stream, &response.as_bytes().to_vec())?;
+    }
+    Ok(())
+}
+
+pub fn read_info_response_message(stream: &mut dyn Read) -> Result<Vec<String>, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_USERAUTH_INFO_RESPONSE)?;
+    let count = stream.read_u32::<BigEndian>()?;
+    if count > MAX_PROMPTS {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_TOO_MANY_PROMPTS));
+    }
+    let mut responses: Vec<String> = Vec::new();
+    for _ in 0..count {
+        responses.push(io_helpers::read_utf8_string(stream)?);
+    }
+    Ok(responses)
+}
+
+pub trait KeyboardInteractivePrompt {
+    //one response for each prompt, in the same order
+    fn respond(&mut self, request: &InfoRequest) -> Result<Vec<String>, Error>;
+}
+
+//shows sanitized name and instruction on stderr and reads the responses from the controlling terminal
+pub struct TtyKeyboardInteractivePrompt;
+
+impl KeyboardInteractivePrompt for TtyKeyboardInteractivePrompt {
+    fn respond(&mut self, request: &InfoRequest) -> Result<Vec<String>, Error> {
+        if !request.name.is_empty() {
+            eprintln!("{}", terminal::sanitize(&request.name));
+        }
+        if !request.instruction.is_empty() {
+            eprintln!("{}", terminal::sanitize(&request.instruction));
+        }
+        request.prompts.iter().map(|p| terminal::read_line_from_tty(&terminal::sanitize(&p.prompt), p.echo)).collect()
+    }
+}
+
+pub struct ClientKeyboardInteractiveMethod {
+    prompt: Box<dyn KeyboardInteractivePrompt>,
+    attempts_left: u32,
+}
+
+impl ClientKeyboardInteractiveMethod {
+    //as OpenSSH, number of attempts is NumberOfPasswordPrompts
+    pub fn new(prompt: Box<dyn KeyboardInteractivePrompt>, number_of_attempts: u32) -> ClientKeyboardInteractiveMethod {
+        ClientKeyboardInteractiveMethod {
+            prompt,
+            attempts_left: number_of_attempts,
+        }
+    }
+}
+
+impl ClientAuthMethod for ClientKeyboardInteractiveMethod {
+    fn get_name(&self) -> &'static str {
+        METHOD_KEYBOARD_INTERACTIVE
+    }
+
+    fn requires_confidentiality(&self) -> bool {
+        true
+    }
+
+    fn next_request(&mut self, context: &ClientAuthContext) -> Result<Option<Vec<u8>>, Error> {
+        if self.attempts_left == 0 {
+            return Ok(None);
+        }
+        self.attempts_left -= 1;
+        let request = KeyboardInteractiveRequest {
+            language: String::new(),
+            submethods: String::new(),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_keyboard_interactive_request(&mut data, &request)?;
+        Ok(Some(context.get_request_payload(METHOD_KEYBOARD_INTERACTIVE, data)))
+    }
+
+    fn handle_message(&mut self, _context: &ClientAuthContext, payload: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let request = read_info_request_message(&mut Cursor::new(payload))?;
+        //RFC 4256 page 6, a request without prompts is answered without asking the user
+        let responses = if request.prompts.is_empty() && request.name.is_empty() && request.instruction.is_empty() {
+            Vec::new()
+        } else {
+            self.prompt.respond(&request)?
+        };
+        if responses.len() != request.prompts.len() {
+            return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_WRONG_NUMBER_OF_RESPONSES));
+        }
+        let mut response: Vec<u8> = Vec::new();
+        write_info_response_message(&mut response, &responses)?;
+        Ok(vec![response])
+    }
+}
+
+pub enum ChallengeResult {
+    Success,
+    Failure,
+    //another round of prompts
+    Challenge(InfoRequest),
+}
+
+//server side backend asking the questions, e.g. password or one time code
+pub trait ChallengeProvider {
+    //first prompts for the user, None if the user can not use this provider
+    fn start(&mut self, user: &str, submethods: &str) -> Result<Option<InfoRequest>, Error>;
+    fn check_responses(&mut self, user: &str, responses: &[String]) -> Result<ChallengeResult, Error>;
+}
+
+//one provider for all connections, e.g. so a one time code is accepted only once
+impl<P: ChallengeProvider> ChallengeProvider for Arc<Mutex<P>> {
+    fn start(&mut self, user: &str, submethods: &str) -> Result<Option<InfoRequest>, Error> {
+        self.lock().unwrap_or_else(|e| e.into_inner()).start(user, submethods)
+    }
+
+    fn check_responses(&mut self, user: &str, responses: &[String]) -> Result<ChallengeResult, Error> {
+        self.lock().unwrap_or_else(|e| e.into_inner()).check_responses(user, responses)
+    }
+}
+
+//KbdInteractiveChallenge, what the keyboard-interactive method asks for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChallengeKind {
+    Password,
+    Totp,
+}
+
+impl ChallengeKind {
+    pub fn from_name(name: &str) -> Option<ChallengeKind> {
+        match name.to_lowercase().as_str() {
+            "password" => Some(ChallengeKind::Password),
+            "totp" => Some(ChallengeKind::Totp),
+            _ => None,
+        }
+    }
+}
+
+//asks for the password checked by a password verifier, expired passwords are rejected
+pub struct PasswordChallengeProvider {
+    verifier: Box<dyn PasswordVerifier>,
+}
+
+impl PasswordChallengeProvider {
+    pub fn new(verifier: Box<dyn PasswordVerifier>) -> PasswordChallengeProvider {
+        PasswordChallengeProvider { verifier }
+    }
+}
+
+impl ChallengeProvider for PasswordChallengeProvider {
+    fn start(&mut self, _user: &str, _submethods: &str) -> Result<Option<InfoRequest>, Error> {
+        Ok(Some(InfoRequest::new("", "", vec![Prompt::new("Password: ", false)])))
+    }
+
+    fn check_responses(&mut self, user: &str, responses: &[String]) -> Result<ChallengeResult, Error> {
+        match self.verifier.verify_password(user, &responses[0])? {
+            PasswordCheck::Valid => Ok(ChallengeResult::Success),
+            _ => Ok(ChallengeResult::Failure),
+        }
+    }
+}
+
+//RFC 6238 TOTP with HMAC-SHA1
+pub const TOTP_TIME_STEP: u64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+
+//RFC 4226 page 7, HOTP with dynamic truncation
+pub fn get_hotp_code(secret: &[u8], counter: u64, digits: u32) -> String {
+    let mut message: Vec<u8> = Vec::new();
+    message.write_u64::<BigEndian>(counter).unwrap();
+    let hash = mac::hmac_sha1_with_key(secret, &message);
+    let offset = (hash[19] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+    format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+}
+
+pub fn get_totp_code(secret: &[u8], unix_time: u64) -> String {
+    get_hotp_code(secret, unix_time / TOTP_TIME_STEP, TOTP_DIGITS)
+}
+
+//RFC 4648 base32, as authenticator apps show the secret, padding and spaces are optional
+pub fn decode_base32(text: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut result: Vec<u8> = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes().filter(|c| *c != b' ') {
+        let value = ALPHABET.iter().position(|a| *a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Some(result)
+}
+
+//one time codes of the current time step, the previous and the next one are accepted,
+//but never a time step at or before the last one accepted for the user
+pub struct TotpChallengeProvider {
+    pub secrets: HashMap<String, Vec<u8>>,
+    pub last_counters: HashMap<String, u64>,
+    pub now: Box<dyn Fn() -> u64 + Send>,
+}
+
+impl TotpChallengeProvider {
+    pub fn new() -> TotpChallengeProvider {
+        TotpChallengeProvider {
+            secrets: HashMap::new(),
+            last_counters: HashMap::new(),
+            now: Box::new(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)),
+        }
+    }
+
+    pub fn add_user(&mut self, user: &str, secret: &[u8]) {
+        self.secrets.insert(user.to_string(), secret.to_vec());
+    }
+
+    //TotpSecretsFile, "user BASE32SECRET" lines, empty lines and comments starting with # are skipped
+    pub fn read(&mut self, text: &str) -> Result<(), Error> {
+        for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let mut fields = line.split_whitespace();
+            let secret = match (fields.next(), fields.next().and_then(decode_base32), fields.next()) {
+                (Some(user), Some(secret), None) if !secret.is_empty() => (user, secret),
+                _ => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_TOTP_SECRETS_MALFORMED)),
+            };
+            self.add_user(secret.0, &secret.1);
+        }
+        Ok(())
+    }
+
+    pub fn read_file(&mut self, path: &Path) -> Result<(), Error> {
+        let text = fs::read_to_string(path)?;
+        self.read(&text)
+    }
+}
+
+impl Default for TotpChallengeProvider {
+    fn default() -> TotpChallengeProvider {
+        TotpChallengeProvider::new()
+    }
+}
+
+impl ChallengeProvider for TotpChallengeProvider {
+    fn start(&mut self, user: &str, _submethods: &str) -> Result<Option<InfoRequest>, Error> {
+        if !self.secrets.contains_key(user) {
+            return Ok(None);
+        }
+        Ok(Some(InfoRequest::new("", "", vec![Prompt::new("Verification code: ", false)])))
+    }
+
+    fn check_responses(&mut self, user: &str, responses: &[String]) -> Result<ChallengeResult, Error> {
+        let secret = match self.secrets.get(user) {
+            Some(secret) => secret,
+            None => return Ok(ChallengeResult::Failure),
+        };
+        let now = (self.now)();
+        let counter = now / TOTP_TIME_STEP;
+        let code = responses[0].trim();
+        let last = self.last_counters.get(user).cloned();
+        //every candidate is compared so the time taken doesn't depend on which one matches
+        let mut accepted: Option<u64> = None;
+        for c in &[counter.saturating_sub(1), counter, counter + 1] {
+            if mac::constant_time_eq(get_hotp_code(secret, *c, TOTP_DIGITS).as_bytes(), code.as_bytes()) && last.is_none_or(|l| *c > l) {
+                accepted = Some(*c);
+            }
+        }
+        match accepted {
+            Some(c) => {
+                self.last_counters.insert(user.to_string(), c);
+                Ok(ChallengeResult::Success)
+            }
+            None => Ok(ChallengeResult::Failure),
+        }
+    }
+}
+
+pub struct ServerKeyboardInteractiveMethod {
+    provider: Box<dyn ChallengeProvider>,
+    //number of prompts in the last INFO_REQUEST, None when no exchange is in progress
+    expected_responses: Option<usize>,
+}
+
+impl ServerKeyboardInteractiveMethod {
+    pub fn new(provider: Box<dyn ChallengeProvider>) -> ServerKeyboardInteractiveMethod {
+        ServerKeyboardInteractiveMethod {
+            provider,
+            expected_responses: None,
+        }
+    }
+
+    fn send_info_request(&mut self, request: &InfoRequest) -> Result<ServerMethodResult, Error> {
+        self.expected_responses = Some(request.prompts.len());
+        let mut payload: Vec<u8> = Vec::new();
+        write_info_request_message(&mut payload, request)?;
+        Ok(ServerMethodResult::Continue(vec![payload]))
+    }
+}
+
+impl ServerAuthMethod for ServerKeyboardInteractiveMethod {
+    fn get_name(&self) -> &'static str {
+        METHOD_KEYBOARD_INTERACTIVE
+    }
+
+    fn requires_confidentiality(&self) -> bool {
+        true
+    }
+
+    fn handle_request(&mut self, context: &ServerAuthContext, method_data: &[u8]) -> Result<ServerMethodResult, Error> {
+        let request = read_keyboard_interactive_request(&mut Cursor::new(method_data))?;
+        self.expected_responses = None;
+        match self.provider.start(&context.user, &request.submethods)? {
+            Some(info_request) => self.send_info_request(&info_request),
+            None => Ok(ServerMethodResult::Failure),
+        }
+    }
+
+    fn handle_message(&mut self, context: &ServerAuthContext, payload: &[u8]) -> Result<ServerMethodResult, Error> {
+        let responses = read_info_response_message(&mut Cursor::new(payload))?;
+        let expected = match self.expected_responses.take() {
+            Some(expected) => expected,
+            None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        };
+        if responses.len() != expected {
+            return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_WRONG_NUMBER_OF_RESPONSES));
+        }
+        match self.provider.check_responses(&context.user, &responses)? {
+            ChallengeResult::Success => Ok(ServerMethodResult::Success(AuthOptions::unrestricted())),
+            ChallengeResult::Failure => Ok(ServerMethodResult::Failure),
+            ChallengeResult::Challenge(info_request) => self.send_info_request(&info_request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::{Duration, Instant};
+    use auth_password::InMemoryPasswordVerifier;
+    use userauth::{ClientAuthState, UserauthClient, UserauthServer};
+
+    //answers prompts from a list, remembers what was asked
+    struct ScriptedPrompt {
+        answers: Vec<String>,
+        asked: Vec<Prompt>,
+    }
+
+    impl KeyboardInteractivePrompt for ScriptedPrompt {
+        fn respond(&mut self, request: &InfoRequest) -> Result<Vec<String>, Error> {
+            self.asked.extend(request.prompts.iter().cloned());
+            let mut responses: Vec<String> = Vec::new();
+            for _ in request.prompts.iter() {
+                if self.answers.is_empty() {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "no more answers"));
+                }
+                responses.push(self.answers.remove(0));
+            }
+            Ok(responses)
+        }
+    }
+
+    //asks for a name first, then for a code depending on it
+    struct TwoRoundProvider;
+
+    impl ChallengeProvider for TwoRoundProvider {
+        fn start(&mut self, _user: &str, _submethods: &str) -> Result<Option<InfoRequest>, Error> {
+            Ok(Some(InfoRequest::new("Login", "Two rounds", vec![Prompt::new("Name: ", true), Prompt::new("PIN: ", false)])))
+        }
+
+        fn check_responses(&mut self, _user: &str, responses: &[String]) -> Result<ChallengeResult, Error> {
+            match responses {
+                [name, pin] if name == "alice" && pin == "1234" => Ok(ChallengeResult::Challenge(InfoRequest::new("", "", vec![Prompt::new("Code: ", false)]))),
+                [code] if code == "42" => Ok(ChallengeResult::Success),
+                _ => Ok(ChallengeResult::Failure),
+            }
+        }
+    }
+
+    fn authenticate(provider: Box<dyn ChallengeProvider>, answers: &[&str]) -> (UserauthClient, UserauthServer, Result<(), Error>) {
+        let prompt = ScriptedPrompt { answers: answers.iter().map(|a| a.to_string()).collect(), asked: Vec::new() };
+        let mut client = UserauthClient::new("user", b"session", vec![Box::new(ClientKeyboardInteractiveMethod::new(Box::new(prompt), 3))]);
+        let mut server = UserauthServer::new(b"session", None, vec![Box::new(ServerKeyboardInteractiveMethod::new(provider))], 6, Some(Duration::from_secs(120)), Instant::now());
+        //as over an encrypted transport
+        client.confidential = true;
+        server.confidential = true;
+
+        let mut to_server = client.start();
+        let mut res = Ok(());
+        while !to_server.is_empty() && res.is_ok() {
+            let mut to_client: Vec<Vec<u8>> = Vec::new();
+            for payload in to_server.iter() {
+                to_client.extend(server.handle_payload(payload, Instant::now()).unwrap());
+            }
+            to_server = Vec::new();
+            for payload in to_client.iter() {
+                match client.handle_payload(payload) {
+                    Ok(payloads) => to_server.extend(payloads),
+                    Err(e) => res = Err(e),
+                }
+            }
+        }
+        (client, server, res)
+    }
+
+    #[test]
+    fn keyboard_interactive_password_works() {
+        let mut verifier = InMemoryPasswordVerifier::new();
+        verifier.add_user("user", "secret");
+
+        let (client, server, res) = authenticate(Box::new(PasswordChallengeProvider::new(Box::new(verifier))), &["wrong", "secret"]);
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+        assert_eq!(server.failures, 1);
+
+        let verifier = InMemoryPasswordVerifier::new();
+        let (client, _, res) = authenticate(Box::new(PasswordChallengeProvider::new(Box::new(verifier))), &["a", "b", "c", "d"]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(client.state, ClientAuthState::Failed);
+    }
+
+    #[test]
+    fn keyboard_interactive_supports_several_rounds() {
+        let (client, server, res) = authenticate(Box::new(TwoRoundProvider), &["alice", "1234", "42"]);
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+
+        let (client, _, res) = authenticate(Box::new(TwoRoundProvider), &["alice", "1234", "41", "bob", "1234", "alice", "0000"]);
+        assert!(res.is_err());
+        assert_eq!(client.state, ClientAuthState::Failed);
+    }
+
+    #[test]
+    fn totp_codes_are_checked() {
+        //RFC 6238 Appendix B, SHA1 test vectors truncated to 6 digits
+        let secret = b"12345678901234567890";
+        assert_eq!(get_hotp_code(secret, 59 / 30, 8), "94287082");
+        assert_eq!(get_totp_code(secret, 1111111109), "081804");
+        assert_eq!(get_totp_code(secret, 2000000000), "279037");
+        //RFC 4226 Appendix D
+        assert_eq!(get_hotp_code(secret, 0, 6), "755224");
+
+        let mut provider = TotpChallengeProvider::new();
+        provider.add_user("user", secret);
+        provider.now = Box::new(|| 1111111109);
+        assert!(provider.start("other", "").unwrap().is_none());
+        assert_eq!(provider.start("user", "").unwrap().unwrap().prompts, vec![Prompt::new("Verification code: ", false)]);
+        let code = get_totp_code(secret, 1111111109 - 30);
+        assert!(matches!(provider.check_responses("user", std::slice::from_ref(&code)).unwrap(), ChallengeResult::Success));
+        //a code can't be used twice
+        assert!(matches!(provider.check_responses("user", &[code]).unwrap(), ChallengeResult::Failure));
+        let code = get_totp_code(secret, 1111111109 - 60);
+        assert!(matches!(provider.check_responses("user", &[code]).unwrap(), ChallengeResult::Failure));
+
+        let (client, server, res) = authenticate(Box::new(provider), &["000000", "081804"]);
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.failures, 1);
+    }
+
+    #[test]
+    fn totp_secrets_are_read_as_base32() {
+        //RFC 4648 test vectors
+        assert_eq!(decode_base32("MZXW6YTBOI======").unwrap(), b"foobar".to_vec());
+        assert_eq!(decode_base32("mzxw 6ytb oi").unwrap(), b"foobar".to_vec());
+        assert_eq!(decode_base32("MY").unwrap(), b"f".to_vec());
+        assert!(decode_base32("MZ1W").is_none());
+
+        let mut provider = TotpChallengeProvider::new();
+        provider.read("# user secret\n\nalice GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n").unwrap();
+        assert_eq!(provider.secrets.get("alice").unwrap(), b"12345678901234567890");
+        assert!(provider.read("bob\n").is_err());
+        assert!(provider.read("bob GEZ1\n").is_err());
+        assert!(provider.read("bob GEZDGNBV extra\n").is_err());
+
+        assert_eq!(ChallengeKind::from_name("TOTP"), Some(ChallengeKind::Totp));
+        assert_eq!(ChallengeKind::from_name("otp"), None);
+    }
+
+    #[test]
+    fn shared_provider_remembers_used_codes() {
+        let secret = b"12345678901234567890";
+        let mut provider = TotpChallengeProvider::new();
+        provider.add_user("user", secret);
+        provider.now = Box::new(|| 1111111109);
+        let shared = Arc::new(Mutex::new(provider));
+
+        let code = get_totp_code(secret, 1111111109);
+        let (client, _, res) = authenticate(Box::new(shared.clone()), &[&code]);
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        //another connection can't reuse the code
+        let (client, _, res) = authenticate(Box::new(shared), &[&code, &code, &code]);
+        assert!(res.is_err());
+        assert_eq!(client.state, ClientAuthState::Failed);
+    }
+
+    #[test]
+    fn reading_writing_info_messages_works() {
+        let request = InfoRequest::new("name", "instruction", vec![Prompt::new("Code: ", false), Prompt::new("User: ", true)]);
+        let mut payload: Vec<u8> = Vec::new();
+        write_info_request_message(&mut payload, &request).unwrap();
+        assert_eq!(payload[0], numbers::SSH_MSG_USERAUTH_INFO_REQUEST);
+        assert_eq!(read_info_request_message(&mut Cursor::new(payload)).unwrap(), request);
+
+        let mut payload: Vec<u8> = Vec::new();
+        write_info_response_message(&mut payload, &["a".to_string(), "bc".to_string()]).unwrap();
+        assert_eq!(payload, b"\x3d\x00\x00\x00\x02\x00\x00\x00\x01a\x00\x00\x00\x02bc".to_vec());
+        assert_eq!(read_info_response_message(&mut Cursor::new(payload)).unwrap(), vec!["a", "bc"]);
+
+        let mut data: Vec<u8> = Vec::new();
+        write_keyboard_interactive_request(&mut data, &KeyboardInteractiveRequest { language: String::new(), submethods: "pam".to_string() }).unwrap();
+        assert_eq!(read_keyboard_interactive_request(&mut Cursor::new(data)).unwrap().submethods, "pam");
+
+        let too_many = b"\x3d\xff\xff\xff\xff".to_vec();
+        assert!(read_info_response_message(&mut Cursor::new(too_many)).is_err());
+    }
+}
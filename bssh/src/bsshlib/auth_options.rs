@@ -0,0 +1,462 @@
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::net::IpAddr;
+use libc;
+use authorized_keys::KeyOption;
+use errors;
+use patterns;
+
+//restrictions applied to a session authenticated with a key, coming from
+//certificate critical options and extensions (PROTOCOL.certkeys) and authorized_keys options
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthOptions {
+    pub permit_pty: bool,
+    pub permit_port_forwarding: bool,
+    pub permit_agent_forwarding: bool,
+    pub permit_x11_forwarding: bool,
+    pub permit_user_rc: bool,
+    pub force_command: Option<String>,
+    //comma separated list of "address[/bits]"
+    pub source_addresses: Option<String>,
+    //comma separated host name or address patterns of authorized_keys "from"
+    pub from_patterns: Option<String>,
+    //"host:port" destinations allowed for local forwarding, None allows any
+    pub permit_open: Option<Vec<String>>,
+    //"[host:]port" listen addresses allowed for remote forwarding, None allows any
+    pub permit_listen: Option<Vec<String>>,
+    pub environment: Vec<(String, String)>,
+    //seconds since epoch
+    pub expiry_time: Option<u64>,
+}
+
+fn bad_options() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_AUTHORIZED_KEYS_BAD_OPTIONS)
+}
+
+fn not_permitted(reason: &'static str) -> Error {
+    Error::new(ErrorKind::PermissionDenied, reason)
+}
+
+//None allows anything, so only entries in both lists are kept
+fn intersect(a: &Option<Vec<String>>, b: &Option<Vec<String>>) -> Option<Vec<String>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.iter().filter(|e| b.contains(e)).cloned().collect()),
+        (a, b) => a.clone().or_else(|| b.clone()),
+    }
+}
+
+//days since epoch of a proleptic Gregorian date
+fn get_days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+//seconds since epoch of a local time, mktime(3) takes care of the time zone and DST
+fn get_local_time(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> Option<i64> {
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_year = (year - 1900) as libc::c_int;
+    tm.tm_mon = (month - 1) as libc::c_int;
+    tm.tm_mday = day as libc::c_int;
+    tm.tm_hour = hour as libc::c_int;
+    tm.tm_min = minute as libc::c_int;
+    tm.tm_sec = second as libc::c_int;
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => None,
+        time => Some(time),
+    }
+}
+
+//"YYYYMMDD[HHMM[SS]]" as in sshd(8) expiry-time, local time unless it ends with "Z" for UTC
+pub fn parse_absolute_time(value: &str) -> Result<u64, Error> {
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    if !value.chars().all(|c| c.is_ascii_digit()) || ![8, 12, 14].contains(&value.len()) {
+        return Err(bad_options());
+    }
+    let field = |from: usize, to: usize| -> i64 { value.get(from..to).and_then(|f| f.parse().ok()).unwrap_or(0) };
+    let (year, month, day) = (field(0, 4), field(4, 6), field(6, 8));
+    let (hour, minute, second) = (field(8, 10), field(10, 12), field(12, 14));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return Err(bad_options());
+    }
+    let time = if utc {
+        get_days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+    } else {
+        get_local_time(year, month, day, hour, minute, second).ok_or_else(bad_options)?
+    };
+    if time < 0 {
+        return Err(bad_options());
+    }
+    Ok(time as u64)
+}
+
+//splits "host:port" at the last colon, brackets around IPv6 addresses are removed
+fn split_host_port(value: &str) -> Option<(String, String)> {
+    let colon = value.rfind(':')?;
+    let host = &value[..colon];
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    Some((host.to_string(), value[colon + 1..].to_string()))
+}
+
+//"host:port" entries of permitopen and sshd_config PermitOpen, "*" matches any port
+pub fn match_permit_open(list: &[String], host: &str, port: u16) -> bool {
+    list.iter().any(|entry| match split_host_port(entry) {
+        Some((ref h, ref p)) => h.eq_ignore_ascii_case(host) && (p == "*" || *p == port.to_string()),
+        None => false,
+    })
+}
+
+fn is_valid_port(port: &str) -> bool {
+    port == "*" || port.parse::<u16>().is_ok()
+}
+
+impl AuthOptions {
+    //plain key without any options
+    pub fn unrestricted() -> AuthOptions {
+        AuthOptions {
+            permit_pty: true,
+            permit_port_forwarding: true,
+            permit_agent_forwarding: true,
+            permit_x11_forwarding: true,
+            permit_user_rc: true,
+            force_command: None,
+            source_addresses: None,
+            from_patterns: None,
+            permit_open: None,
+            permit_listen: None,
+            environment: Vec::new(),
+            expiry_time: None,
+        }
+    }
+
+    //certificates grant only what their extensions list
+    pub fn restricted() -> AuthOptions {
+        AuthOptions {
+            permit_pty: false,
+            permit_port_forwarding: false,
+            permit_agent_forwarding: false,
+            permit_x11_forwarding: false,
+            permit_user_rc: false,
+            ..AuthOptions::unrestricted()
+        }
+    }
+
+    //sshd(8) AUTHORIZED_KEYS FILE FORMAT. "cert-authority" and "principals" describe the key
+    //rather than the session and are only checked for validity here.
+    pub fn from_key_options(options: &[KeyOption]) -> Result<AuthOptions, Error> {
+        let mut res = AuthOptions::unrestricted();
+
+        for option in options.iter() {
+            let name = option.name.to_lowercase();
+            match (name.as_str(), option.value.as_ref()) {
+                ("restrict", None) => {
+                    res.permit_pty = false;
+                    res.permit_port_forwarding = false;
+                    res.permit_agent_forwarding = false;
+                    res.permit_x11_forwarding = false;
+                    res.permit_user_rc = false;
+                }
+                ("cert-authority", None) | ("no-touch-required", None) | ("verify-required", None) => {}
+                ("agent-forwarding", None) => res.permit_agent_forwarding = true,
+                ("no-agent-forwarding", None) => res.permit_agent_forwarding = false,
+                ("port-forwarding", None) => res.permit_port_forwarding = true,
+                ("no-port-forwarding", None) => res.permit_port_forwarding = false,
+                ("pty", None) => res.permit_pty = true,
+                ("no-pty", None) => res.permit_pty = false,
+                ("user-rc", None) => res.permit_user_rc = true,
+                ("no-user-rc", None) => res.permit_user_rc = false,
+                ("x11-forwarding", None) => res.permit_x11_forwarding = true,
+                ("no-x11-forwarding", None) => res.permit_x11_forwarding = false,
+                ("command", Some(value)) => res.force_command = Some(value.clone()),
+                ("from", Some(value)) => res.from_patterns = Some(value.clone()),
+                ("principals", Some(_)) => {}
+                ("expiry-time", Some(value)) => {
+                    let time = parse_absolute_time(value)?;
+                    //as sshd, the earliest of repeated options wins
+                    res.expiry_time = Some(res.expiry_time.map_or(time, |t| t.min(time)));
+                }
+                ("environment", Some(value)) => {
+                    let equals = match value.find('=') {
+                        Some(equals) if equals > 0 => equals,
+                        _ => return Err(bad_options()),
+                    };
+                    let name = &value[..equals];
+                    //first value of a variable is used
+                    if !res.environment.iter().any(|(n, _)| n == name) {
+                        res.environment.push((name.to_string(), value[equals + 1..].to_string()));
+                    }
+                }
+                ("permitopen", Some(value)) => {
+                    match split_host_port(value) {
+                        Some((ref host, ref port)) if !host.is_empty() && is_valid_port(port) => {}
+                        _ => return Err(bad_options()),
+                    }
+                    res.permit_open.get_or_insert_with(Vec::new).push(value.clone());
+                }
+                ("permitlisten", Some(value)) => {
+                    let port = split_host_port(value).map(|(_, port)| port).unwrap_or_else(|| value.clone());
+                    if !is_valid_port(&port) {
+                        return Err(bad_options());
+                    }
+                    res.permit_listen.get_or_insert_with(Vec::new).push(value.clone());
+                }
+                _ => return Err(bad_options()),
+            }
+        }
+        Ok(res)
+    }
+
+    //options of the authorized_keys line (self) combined with those of the certificate it
+    //authorized, or of an earlier method with those of the next one in AuthenticationMethods;
+    //anything not allowed by both is not allowed; two different forced commands can't both be
+    //honoured, so they fail the authentication; environment variables of both are set, for a
+    //name set by both the value of self is used, as the first environment option wins
+    pub fn merge(&self, certificate: &AuthOptions) -> Result<AuthOptions, Error> {
+        let force_command = match (&self.force_command, &certificate.force_command) {
+            (Some(a), Some(b)) if a != b => return Err(not_permitted(errors::BSSH_ERR_CONFLICTING_FORCE_COMMANDS)),
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        Ok(AuthOptions {
+            permit_pty: self.permit_pty && certificate.permit_pty,
+            permit_port_forwarding: self.permit_port_forwarding && certificate.permit_port_forwarding,
+            permit_agent_forwarding: self.permit_agent_forwarding && certificate.permit_agent_forwarding,
+            permit_x11_forwarding: self.permit_x11_forwarding && certificate.permit_x11_forwarding,
+            permit_user_rc: self.permit_user_rc && certificate.permit_user_rc,
+            force_command,
+            source_addresses: self.source_addresses.clone().or_else(|| certificate.source_addresses.clone()),
+            from_patterns: self.from_patterns.clone().or_else(|| certificate.from_patterns.clone()),
+            permit_open: intersect(&self.permit_open, &certificate.permit_open),
+            permit_listen: intersect(&self.permit_listen, &certificate.permit_listen),
+            environment: self.environment.iter()
+                .chain(certificate.environment.iter().filter(|(name, _)| !self.environment.iter().any(|(n, _)| n == name)))
+                .cloned()
+                .collect(),
+            expiry_time: match (self.expiry_time, certificate.expiry_time) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        })
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry_time.map(|t| now >= t).unwrap_or(false)
+    }
+
+    //"from" patterns with letters other than in IPv6 addresses, they match only the host name
+    pub fn has_host_name_patterns(&self) -> bool {
+        self.from_patterns.as_ref().is_some_and(|patterns| {
+            patterns.split(',').any(|p| !p.contains(':') && p.chars().any(|c| c.is_ascii_alphabetic()))
+        })
+    }
+
+    //checks "from" patterns against the client address and its host name, as OpenSSH a negated
+    //match of either denies; the host name is known only with UseDNS, without it host name
+    //patterns never match. Certificate source addresses are checked against the address only.
+    pub fn check_source_address(&self, client_address: Option<&IpAddr>, client_host_name: Option<&str>) -> Result<(), Error> {
+        if let Some(ref patterns) = self.from_patterns {
+            let by_address = client_address.and_then(|address| patterns::match_pattern_list(&address.to_string(), patterns, true));
+            let by_name = client_host_name.and_then(|name| patterns::match_pattern_list(name, patterns, true));
+            let allowed = client_address.is_some() && by_address != Some(false) && by_name != Some(false) &&
+                (by_address == Some(true) || by_name == Some(true));
+            if !allowed {
+                return Err(not_permitted(errors::BSSH_ERR_KEY_SOURCE_ADDRESS));
+            }
+        }
+        if let Some(ref source_addresses) = self.source_addresses {
+            let allowed = match client_address {
+                Some(address) => patterns::match_cidr_list(address, source_addresses)?,
+                None => false,
+            };
+            if !allowed {
+                return Err(not_permitted(errors::BSSH_ERR_CERT_SOURCE_ADDRESS));
+            }
+        }
+        Ok(())
+    }
+
+    //direct-tcpip destination, RFC 4254 page 16
+    pub fn is_open_permitted(&self, host: &str, port: u16) -> bool {
+        if !self.permit_port_forwarding {
+            return false;
+        }
+        match self.permit_open {
+            None => true,
+            Some(ref list) => match_permit_open(list, host, port),
+        }
+    }
+
+    //tcpip-forward listen address, RFC 4254 page 18
+    pub fn is_listen_permitted(&self, host: &str, port: u16) -> bool {
+        if !self.permit_port_forwarding {
+            return false;
+        }
+        match self.permit_listen {
+            None => true,
+            Some(ref list) => list.iter().any(|entry| {
+                let (h, p) = split_host_port(entry).unwrap_or_else(|| (String::new(), entry.clone()));
+                let host_matches = if h.is_empty() {
+                    //only the port given means loopback, as with GatewayPorts no
+                    host.is_empty() || host == "localhost" || host == "127.0.0.1" || host == "::1"
+                } else {
+                    h == "*" || h.eq_ignore_ascii_case(host)
+                };
+                host_matches && (p == "*" || p == port.to_string())
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn option(name: &str, value: Option<&str>) -> KeyOption {
+        KeyOption {
+            name: name.to_string(),
+            value: value.map(|v| v.to_string()),
+        }
+    }
+
+    #[test]
+    fn from_key_options_handles_restrict_and_overrides() {
+        let options = AuthOptions::from_key_options(&[option("command", Some("/usr/bin/deploy")), option("restrict", None), option("pty", None)]).unwrap();
+        assert_eq!(options.force_command, Some("/usr/bin/deploy".to_string()));
+        assert!(options.permit_pty);
+        assert!(!options.permit_port_forwarding);
+        assert!(!options.permit_agent_forwarding);
+        assert!(!options.permit_user_rc);
+
+        let options = AuthOptions::from_key_options(&[option("no-pty", None), option("environment", Some("A=1")), option("environment", Some("A=2"))]).unwrap();
+        assert!(!options.permit_pty);
+        assert!(options.permit_port_forwarding);
+        assert_eq!(options.environment, vec![("A".to_string(), "1".to_string())]);
+
+        assert!(AuthOptions::from_key_options(&[option("no-such-option", None)]).is_err());
+        assert!(AuthOptions::from_key_options(&[option("command", None)]).is_err());
+        assert!(AuthOptions::from_key_options(&[option("environment", Some("=x"))]).is_err());
+        assert!(AuthOptions::from_key_options(&[option("permitopen", Some("host"))]).is_err());
+    }
+
+    #[test]
+    fn forwarding_permissions_work() {
+        let options = AuthOptions::from_key_options(&[option("permitopen", Some("db.local:5432")),
+                                                      option("permitopen", Some("[::1]:*")),
+                                                      option("permitlisten", Some("8080")),
+                                                      option("permitlisten", Some("*:9000"))])
+            .unwrap();
+        assert!(options.is_open_permitted("db.local", 5432));
+        assert!(!options.is_open_permitted("db.local", 5433));
+        assert!(options.is_open_permitted("::1", 22));
+        assert!(options.is_listen_permitted("localhost", 8080));
+        assert!(!options.is_listen_permitted("0.0.0.0", 8080));
+        assert!(options.is_listen_permitted("0.0.0.0", 9000));
+
+        assert!(AuthOptions::unrestricted().is_open_permitted("anything", 1));
+        let options = AuthOptions::from_key_options(&[option("permitopen", Some("db.local:5432")), option("no-port-forwarding", None)]).unwrap();
+        assert!(!options.is_open_permitted("db.local", 5432));
+    }
+
+    #[test]
+    fn expiry_time_and_source_address_are_checked() {
+        assert_eq!(parse_absolute_time("20200101Z").unwrap(), 1577836800);
+        assert_eq!(parse_absolute_time("202001010130Z").unwrap(), 1577836800 + 5400);
+        assert_eq!(parse_absolute_time("20200101013001Z").unwrap(), 1577836800 + 5401);
+        //without "Z" the time is local, midnight there is UTC midnight shifted by the offset of the zone
+        let mut tm: libc::tm = unsafe { mem::zeroed() };
+        unsafe { libc::localtime_r(&1577836800, &mut tm) };
+        assert_eq!(parse_absolute_time("20200101").unwrap() as i64, 1577836800 - tm.tm_gmtoff);
+        assert_eq!(parse_absolute_time("202001010130").unwrap() as i64, 1577836800 + 5400 - tm.tm_gmtoff);
+        assert!(parse_absolute_time("2020011").is_err());
+        assert!(parse_absolute_time("20201301").is_err());
+
+        let options = AuthOptions::from_key_options(&[option("expiry-time", Some("20300101")), option("expiry-time", Some("20250101")), option("from", Some("10.0.0.*,!10.0.0.5"))]).unwrap();
+        assert!(!options.is_expired(1577836800));
+        assert!(options.is_expired(parse_absolute_time("20250101").unwrap()));
+        assert!(!options.is_expired(parse_absolute_time("20250101").unwrap() - 1));
+
+        let inside: IpAddr = "10.0.0.1".parse().unwrap();
+        let denied: IpAddr = "10.0.0.5".parse().unwrap();
+        assert!(options.check_source_address(Some(&inside), None).is_ok());
+        assert!(options.check_source_address(Some(&denied), None).is_err());
+        assert!(options.check_source_address(None, None).is_err());
+        assert!(!options.has_host_name_patterns());
+    }
+
+    #[test]
+    fn from_host_name_patterns_need_host_name() {
+        let options = AuthOptions::from_key_options(&[option("from", Some("*.example.com,!bad.example.com,fe80::*"))]).unwrap();
+        assert!(options.has_host_name_patterns());
+        let address: IpAddr = "192.0.2.1".parse().unwrap();
+        //without UseDNS there is no name to match
+        assert!(options.check_source_address(Some(&address), None).is_err());
+        assert!(options.check_source_address(Some(&address), Some("good.example.com")).is_ok());
+        assert!(options.check_source_address(Some(&address), Some("bad.example.com")).is_err());
+        assert!(options.check_source_address(Some(&address), Some("host.example.org")).is_err());
+        let link_local: IpAddr = "fe80::1".parse().unwrap();
+        assert!(options.check_source_address(Some(&link_local), None).is_ok());
+
+        //a negated address wins over a matching name
+        let options = AuthOptions::from_key_options(&[option("from", Some("*.example.com,!192.0.2.1"))]).unwrap();
+        assert!(options.check_source_address(Some(&address), Some("good.example.com")).is_err());
+    }
+
+    #[test]
+    fn merge_keeps_the_most_restrictive_options() {
+        let key = AuthOptions::from_key_options(&[option("no-pty", None), option("expiry-time", Some("20300101"))]).unwrap();
+        let mut certificate = AuthOptions::restricted();
+        certificate.permit_pty = true;
+        certificate.permit_port_forwarding = true;
+        certificate.force_command = Some("/bin/date".to_string());
+
+        let merged = key.merge(&certificate).unwrap();
+        assert!(!merged.permit_pty);
+        assert!(merged.permit_port_forwarding);
+        assert!(!merged.permit_agent_forwarding);
+        assert_eq!(merged.force_command, Some("/bin/date".to_string()));
+        assert_eq!(merged.expiry_time, key.expiry_time);
+    }
+
+    #[test]
+    fn merge_intersects_permitted_destinations() {
+        let key = AuthOptions::from_key_options(&[option("permitopen", Some("a:22")), option("permitopen", Some("b:22")), option("permitlisten", Some("8080"))]).unwrap();
+        let mut certificate = AuthOptions::unrestricted();
+        certificate.permit_open = Some(vec!["b:22".to_string(), "c:22".to_string()]);
+        let merged = key.merge(&certificate).unwrap();
+        assert_eq!(merged.permit_open, Some(vec!["b:22".to_string()]));
+        assert_eq!(merged.permit_listen, Some(vec!["8080".to_string()]));
+
+        certificate.permit_listen = Some(vec!["9090".to_string()]);
+        assert_eq!(key.merge(&certificate).unwrap().permit_listen, Some(Vec::new()));
+    }
+
+    #[test]
+    fn merge_sets_environment_of_both() {
+        let first = AuthOptions::from_key_options(&[option("environment", Some("A=1")), option("environment", Some("B=1"))]).unwrap();
+        let second = AuthOptions::from_key_options(&[option("environment", Some("B=2")), option("environment", Some("C=2"))]).unwrap();
+        assert_eq!(first.merge(&second).unwrap().environment, vec![("A".to_string(), "1".to_string()),
+                                                                   ("B".to_string(), "1".to_string()),
+                                                                   ("C".to_string(), "2".to_string())]);
+        //e.g. password accepted first, then a key with environment options
+        assert_eq!(AuthOptions::unrestricted().merge(&second).unwrap().environment, second.environment);
+        assert_eq!(first.merge(&AuthOptions::restricted()).unwrap().environment, first.environment);
+    }
+
+    #[test]
+    fn merge_rejects_different_forced_commands() {
+        let key = AuthOptions::from_key_options(&[option("command", Some("/bin/date"))]).unwrap();
+        let mut certificate = AuthOptions::unrestricted();
+        certificate.force_command = Some("/bin/date".to_string());
+        assert_eq!(key.merge(&certificate).unwrap().force_command, Some("/bin/date".to_string()));
+        certificate.force_command = Some("/bin/true".to_string());
+        assert_eq!(key.merge(&certificate).unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use libc;
+use auth_options::AuthOptions;
+use errors;
+use io_helpers;
+use mac;
+use msgs;
+use numbers;
+use terminal;
+use userauth::{ClientAuthContext, ClientAuthMethod, ServerAuthContext, ServerAuthMethod, ServerMethodResult};
+
+//RFC 4252 page 10, Password Authentication Method: "password"
+
+pub const METHOD_PASSWORD: &str = "password";
+pub const DEFAULT_NUMBER_OF_PASSWORD_PROMPTS: u32 = 3; //same as OpenSSH
+pub const SHADOW_FILE: &str = "/etc/shadow";
+
+const CHANGE_REQUIRED_PROMPT: &str = "You are required to change your password immediately.";
+//hashed for unknown and locked accounts, so they take as long to reject as wrong passwords
+const DUMMY_HASH_SETTING: &str = "$6$bsshdummysalt$";
+
+//method specific part of the USERAUTH_REQUEST
+pub struct PasswordRequest {
+    pub password: String,
+    //set when changing the password
+    pub new_password: Option<String>,
+}
+
+pub fn write_password_request(stream: &mut dyn Write, request: &PasswordRequest) -> Result<(), Error> {
+    io_helpers::write_boolean(stream, request.new_password.is_some())?;
+    io_helpers::write_string(stream, &request.password.as_bytes().to_vec())?;
+    if let Some(ref new_password) = request.new_password {
+        io_helpers::write_string(stream, &new_password.as_bytes().to_vec())?;
+    }
+    Ok(())
+}
+
+pub fn read_password_request(stream: &mut dyn Read) -> Result<PasswordRequest, Error> {
+    let change = io_helpers::read_boolean(stream)?;
+    let password = io_helpers::read_utf8_string(stream)?;
+    let new_password = if change { Some(io_helpers::read_utf8_string(stream)?) } else { None };
+    Ok(PasswordRequest {
+        password,
+        new_password,
+    })
+}
+
+pub fn write_passwd_changereq_message(stream: &mut dyn Write, prompt: &str) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_USERAUTH_PASSWD_CHANGEREQ])?;
+    io_helpers::write_string(stream, &prompt.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &Vec::new())?;
+    Ok(())
+}
+
+pub fn read_passwd_changereq_message(stream: &mut dyn Read) -> Result<String, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_USERAUTH_PASSWD_CHANGEREQ)?;
+    let prompt = io_helpers::read_utf8_string(stream)?;
+    let _language_tag = io_helpers::read_string(stream, None)?;
+    Ok(prompt)
+}
+
+pub trait PasswordPrompt {
+    fn read_password(&mut self, prompt: &str) -> Result<String, Error>;
+    //informational text from the server, like the password change prompt
+    fn show_message(&mut self, message: &str);
+}
+
+//reads from the controlling terminal with echo disabled
+pub struct TtyPasswordPrompt;
+
+impl PasswordPrompt for TtyPasswordPrompt {
+    fn read_password(&mut self, prompt: &str) -> Result<String, Error> {
+        terminal::read_passphrase(prompt)
+    }
+
+    fn show_message(&mut self, message: &str) {
+        eprintln!("{}", terminal::sanitize(message));
+    }
+}
+
+pub struct ClientPasswordMethod {
+    prompt: Box<dyn PasswordPrompt>,
+    host: String,
+    prompts_left: u32,
+    last_password: Option<String>,
+}
+
+impl ClientPasswordMethod {
+    pub fn new(prompt: Box<dyn PasswordPrompt>, host: &str, number_of_prompts: u32) -> ClientPasswordMethod {
+        ClientPasswordMethod {
+            prompt,
+            host: host.to_string(),
+            prompts_left: number_of_prompts,
+            last_password: None,
+        }
+    }
+
+    //asks twice for the new password, until both entries match
+    fn read_new_password(&mut self, user: &str) -> Result<String, Error> {
+        loop {
+            let new_password = self.prompt.read_password(&format!("Enter {}'s new password: ", user))?;
+            let retyped = self.prompt.read_password(&format!("Retype {}'s new password: ", user))?;
+            if new_password == retyped {
+                return Ok(new_password);
+            }
+            self.prompt.show_message("Mismatch; try again, EOF to quit.");
+        }
+    }
+}
+
+impl ClientAuthMethod for ClientPasswordMethod {
+    fn get_name(&self) -> &'static str {
+        METHOD_PASSWORD
+    }
+
+    fn requires_confidentiality(&self) -> bool {
+        true
+    }
+
+    fn next_request(&mut self, context: &ClientAuthContext) -> Result<Option<Vec<u8>>, Error> {
+        if self.prompts_left == 0 {
+            return Ok(None);
+        }
+        self.prompts_left -= 1;
+        let request = PasswordRequest {
+            password: self.prompt.read_password(&format!("{}@{}'s password: ", context.user, self.host))?,
+            new_password: None,
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_password_request(&mut data, &request)?;
+        self.last_password = Some(request.password);
+        Ok(Some(context.get_request_payload(METHOD_PASSWORD, data)))
+    }
+
+    //RFC 4252 page 11, the old password is the one just rejected as expired
+    fn handle_message(&mut self, context: &ClientAuthContext, payload: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let message = read_passwd_changereq_message(&mut Cursor::new(payload))?;
+        let password = match self.last_password {
+            Some(ref password) => password.clone(),
+            None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        };
+        self.prompt.show_message(&message);
+        let request = PasswordRequest {
+            password,
+            new_password: Some(self.read_new_password(&context.user)?),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_password_request(&mut data, &request)?;
+        Ok(vec![context.get_request_payload(METHOD_PASSWORD, data)])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Valid,
+    Invalid,
+    //password is correct but expired
+    ChangeRequired,
+}
+
+pub trait PasswordVerifier {
+    fn verify_password(&self, user: &str, password: &str) -> Result<PasswordCheck, Error>;
+    //returns false when the change is not possible
+    fn change_password(&mut self, _user: &str, _old_password: &str, _new_password: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+//passwords kept in memory, mostly useful for testing
+pub struct InMemoryPasswordVerifier {
+    pub passwords: HashMap<String, String>,
+    pub expired: HashSet<String>,
+}
+
+impl InMemoryPasswordVerifier {
+    pub fn new() -> InMemoryPasswordVerifier {
+        InMemoryPasswordVerifier {
+            passwords: HashMap::new(),
+            expired: HashSet::new(),
+        }
+    }
+
+    pub fn add_user(&mut self, user: &str, password: &str) {
+        self.passwords.insert(user.to_string(), password.to_string());
+    }
+}
+
+impl Default for InMemoryPasswordVerifier {
+    fn default() -> InMemoryPasswordVerifier {
+        InMemoryPasswordVerifier::new()
+    }
+}
+
+impl PasswordVerifier for InMemoryPasswordVerifier {
+    fn verify_password(&self, user: &str, password: &str) -> Result<PasswordCheck, Error> {
+        match self.passwords.get(user) {
+            Some(stored) if mac::constant_time_eq(stored.as_bytes(), password.as_bytes()) => {
+                if self.expired.contains(user) { Ok(PasswordCheck::ChangeRequired) } else { Ok(PasswordCheck::Valid) }
+            }
+            _ => Ok(PasswordCheck::Invalid),
+        }
+    }
+
+    fn change_password(&mut self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error> {
+        if self.verify_password(user, old_password)? == PasswordCheck::Invalid {
+            return Ok(false);
+        }
+        self.add_user(user, new_password);
+        self.expired.remove(user);
+        Ok(true)
+    }
+}
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt(key: *const libc::c_char, salt: *const libc::c_char) -> *mut libc::c_char;
+    fn crypt_gensalt(prefix: *const libc::c_char, count: libc::c_ulong, rbytes: *const libc::c_char, nrbytes: libc::c_int) -> *mut libc::c_char;
+}
+
+//crypt(3) and crypt_gensalt(3) return a static buffer
+static CRYPT_LOCK: Mutex<()> = Mutex::new(());
+
+//hashes password with the algorithm and salt of setting, as crypt(3)
+pub fn crypt_password(password: &str, setting: &str) -> Result<String, Error> {
+    let key = CString::new(password).map_err(|_| Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_STRING_NOT_UTF8))?;
+    let salt = CString::new(setting).map_err(|_| Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_STRING_NOT_UTF8))?;
+    let _lock = CRYPT_LOCK.lock().map_err(|_| Error::other(errors::BSSH_ERR_CRYPT_FAILED))?;
+    let hash = unsafe { crypt(key.as_ptr(), salt.as_ptr()) };
+    if hash.is_null() {
+        return Err(Error::other(errors::BSSH_ERR_CRYPT_FAILED));
+    }
+    let hash = unsafe { CStr::from_ptr(hash) }.to_string_lossy().into_owned();
+    //libxcrypt returns "*0" or "*1" for unsupported settings
+    if hash.starts_with('*') {
+        return Err(Error::other(errors::BSSH_ERR_CRYPT_FAILED));
+    }
+    Ok(hash)
+}
+
+//new setting with a random salt for the algorithm of hash, the default algorithm for DES hashes
+pub fn generate_setting(hash: &str) -> Result<String, Error> {
+    let prefix = match hash.split('$').nth(1) {
+        Some(id) if hash.starts_with('$') => Some(CString::new(format!("${}$", id)).map_err(|_| Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_STRING_NOT_UTF8))?),
+        _ => None,
+    };
+    let _lock = CRYPT_LOCK.lock().map_err(|_| Error::other(errors::BSSH_ERR_CRYPT_FAILED))?;
+    let prefix_ptr = prefix.as_ref().map(|p| p.as_ptr()).unwrap_or(std::ptr::null());
+    //no random bytes given, libxcrypt reads them from the system
+    let setting = unsafe { crypt_gensalt(prefix_ptr, 0, std::ptr::null(), 0) };
+    if setting.is_null() {
+        return Err(Error::other(errors::BSSH_ERR_CRYPT_FAILED));
+    }
+    Ok(unsafe { CStr::from_ptr(setting) }.to_string_lossy().into_owned())
+}
+
+//shadow(5) entry, dates are in days since epoch
+pub struct ShadowEntry {
+    pub user: String,
+    pub hash: String,
+    pub last_change: Option<u64>,
+    pub max_days: Option<u64>,
+    pub expire_date: Option<u64>,
+}
+
+pub fn read_shadow_line(line: &str) -> Option<ShadowEntry> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 2 || fields[0].is_empty() {
+        return None;
+    }
+    let field = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+    Some(ShadowEntry {
+        user: fields[0].to_string(),
+        hash: fields[1].to_string(),
+        last_change: field(2),
+        max_days: field(4),
+        expire_date: field(7),
+    })
+}
+
+fn get_current_day() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / (24 * 60 * 60)).unwrap_or(0)
+}
+
+//checks against crypt hashes from shadow file, changed passwords are hashed with the algorithm of the old one
+pub struct ShadowPasswordVerifier {
+    pub path: PathBuf,
+}
+
+impl ShadowPasswordVerifier {
+    pub fn new(path: PathBuf) -> ShadowPasswordVerifier {
+        ShadowPasswordVerifier { path }
+    }
+
+    pub fn check_entry(entry: &ShadowEntry, password: &str, today: u64) -> Result<PasswordCheck, Error> {
+        //locked ("!", "*") and empty passwords never match
+        let locked = entry.hash.is_empty() || entry.hash.starts_with('!') || entry.hash.starts_with('*');
+        let hash = crypt_password(password, if locked { DUMMY_HASH_SETTING } else { &entry.hash })?;
+        if locked || !mac::constant_time_eq(hash.as_bytes(), entry.hash.as_bytes()) {
+            return Ok(PasswordCheck::Invalid);
+        }
+        if entry.expire_date.map(|d| d <= today).unwrap_or(false) {
+            return Ok(PasswordCheck::Invalid);
+        }
+        //last change 0 forces the change on next login
+        let change_required = match (entry.last_change, entry.max_days) {
+            (Some(0), _) => true,
+            (Some(last_change), Some(max_days)) => last_change + max_days < today,
+            _ => false,
+        };
+        if change_required { Ok(PasswordCheck::ChangeRequired) } else { Ok(PasswordCheck::Valid) }
+    }
+}
+
+impl PasswordVerifier for ShadowPasswordVerifier {
+    fn verify_password(&self, user: &str, password: &str) -> Result<PasswordCheck, Error> {
+        let text = fs::read_to_string(&self.path)?;
+        match text.lines().filter_map(read_shadow_line).find(|e| e.user == user) {
+            Some(entry) => ShadowPasswordVerifier::check_entry(&entry, password, get_current_day()),
+            None => {
+                crypt_password(password, DUMMY_HASH_SETTING)?;
+                Ok(PasswordCheck::Invalid)
+            }
+        }
+    }
+
+    //the new file is written next to the old one with the same owner and mode, then renamed over it
+    fn change_password(&mut self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error> {
+        let text = fs::read_to_string(&self.path)?;
+        let today = get_current_day();
+        let entry = match text.lines().filter_map(read_shadow_line).find(|e| e.user == user) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        if ShadowPasswordVerifier::check_entry(&entry, old_password, today)? == PasswordCheck::Invalid {
+            return Ok(false);
+        }
+        let hash = crypt_password(new_password, &generate_setting(&entry.hash)?)?;
+        let mut updated = String::new();
+        for line in text.lines() {
+            let mut fields: Vec<String> = line.split(':').map(|f| f.to_string()).collect();
+            if fields.len() >= 2 && fields[0] == user {
+                fields.resize(fields.len().max(3), String::new());
+                fields[1] = hash.clone();
+                fields[2] = today.to_string();
+            }
+            updated.push_str(&fields.join(":"));
+            updated.push('\n');
+        }
+
+        let metadata = fs::metadata(&self.path)?;
+        let mut temp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push("+");
+        let temp_path = self.path.with_file_name(temp_name);
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp_path)?;
+        let written = file.write_all(updated.as_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid())))
+            .and_then(|_| fs::set_permissions(&temp_path, metadata.permissions()))
+            .and_then(|_| fs::rename(&temp_path, &self.path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        Ok(true)
+    }
+}
+
+pub struct ServerPasswordMethod {
+    verifier: Box<dyn PasswordVerifier>,
+}
+
+impl ServerPasswordMethod {
+    pub fn new(verifier: Box<dyn PasswordVerifier>) -> ServerPasswordMethod {
+        ServerPasswordMethod { verifier }
+    }
+}
+
+impl ServerAuthMethod for ServerPasswordMethod {
+    fn get_name(&self) -> &'static str {
+        METHOD_PASSWORD
+    }
+
+    fn requires_confidentiality(&self) -> bool {
+        true
+    }
+
+    fn handle_request(&mut self, context: &ServerAuthContext, method_data: &[u8]) -> Result<ServerMethodResult, Error> {
+        let request = read_password_request(&mut Cursor::new(method_data))?;
+        if let Some(ref new_password) = request.new_password {
+            if self.verifier.change_password(&context.user, &request.password, new_password)? {
+                return Ok(ServerMethodResult::Success(AuthOptions::unrestricted()));
+            }
+            return Ok(ServerMethodResult::Failure);
+        }
+
+        match self.verifier.verify_password(&context.user, &request.password)? {
+            PasswordCheck::Valid => Ok(ServerMethodResult::Success(AuthOptions::unrestricted())),
+            PasswordCheck::Invalid => Ok(ServerMethodResult::Failure),
+            PasswordCheck::ChangeRequired => {
+                let mut payload: Vec<u8> = Vec::new();
+                write_passwd_changereq_message(&mut payload, CHANGE_REQUIRED_PROMPT)?;
+                Ok(ServerMethodResult::Continue(vec![payload]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::{Duration, Instant};
+    use userauth::{ClientAuthState, ServerAuthState, UserauthClient, UserauthServer};
+
+    //answers prompts from a list
+    struct ScriptedPrompt {
+        answers: Vec<String>,
+    }
+
+    impl PasswordPrompt for ScriptedPrompt {
+        fn read_password(&mut self, _prompt: &str) -> Result<String, Error> {
+            if self.answers.is_empty() {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "no more answers"));
+            }
+            Ok(self.answers.remove(0))
+        }
+        fn show_message(&mut self, _message: &str) {}
+    }
+
+    fn authenticate(verifier: InMemoryPasswordVerifier, answers: &[&str]) -> (UserauthClient, UserauthServer, Result<(), Error>) {
+        let prompt = ScriptedPrompt { answers: answers.iter().map(|a| a.to_string()).collect() };
+        let mut client = UserauthClient::new("user", b"session", vec![Box::new(ClientPasswordMethod::new(Box::new(prompt), "host", DEFAULT_NUMBER_OF_PASSWORD_PROMPTS))]);
+        let mut server = UserauthServer::new(b"session", None, vec![Box::new(ServerPasswordMethod::new(Box::new(verifier)))], 6, Some(Duration::from_secs(120)), Instant::now());
+        //as over an encrypted transport
+        client.confidential = true;
+        server.confidential = true;
+
+        let mut to_server = client.start();
+        let mut res = Ok(());
+        while !to_server.is_empty() && res.is_ok() {
+            let mut to_client: Vec<Vec<u8>> = Vec::new();
+            for payload in to_server.iter() {
+                to_client.extend(server.handle_payload(payload, Instant::now()).unwrap());
+            }
+            to_server = Vec::new();
+            for payload in to_client.iter() {
+                match client.handle_payload(payload) {
+                    Ok(payloads) => to_server.extend(payloads),
+                    Err(e) => res = Err(e),
+                }
+            }
+        }
+        (client, server, res)
+    }
+
+    #[test]
+    fn password_authentication_works() {
+        let mut verifier = InMemoryPasswordVerifier::new();
+        verifier.add_user("user", "secret");
+
+        let (client, server, res) = authenticate(verifier, &["wrong", "secret"]);
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+        assert_eq!(server.failures, 1);
+    }
+
+    #[test]
+    fn password_prompts_are_limited() {
+        let mut verifier = InMemoryPasswordVerifier::new();
+        verifier.add_user("user", "secret");
+
+        let (client, server, res) = authenticate(verifier, &["a", "b", "c", "secret"]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(client.state, ClientAuthState::Failed);
+        assert_eq!(server.state, ServerAuthState::Authenticating);
+    }
+
+    #[test]
+    fn expired_password_is_changed() {
+        let mut verifier = InMemoryPasswordVerifier::new();
+        verifier.add_user("user", "old");
+        verifier.expired.insert("user".to_string());
+
+        //mismatched retype is asked again
+        let (client, server, res) = authenticate(verifier, &["old", "new", "typo", "new", "new"]);
+        res.unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+    }
+
+    #[test]
+    fn reading_writing_password_messages_works() {
+        let request = PasswordRequest {
+            password: "old".to_string(),
+            new_password: Some("new".to_string()),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_password_request(&mut data, &request).unwrap();
+        assert_eq!(data, b"\x01\x00\x00\x00\x03old\x00\x00\x00\x03new".to_vec());
+        let read = read_password_request(&mut Cursor::new(data)).unwrap();
+        assert_eq!((read.password.as_str(), read.new_password), ("old", Some("new".to_string())));
+
+        let mut payload: Vec<u8> = Vec::new();
+        write_passwd_changereq_message(&mut payload, "change it").unwrap();
+        assert_eq!(read_passwd_changereq_message(&mut Cursor::new(payload)).unwrap(), "change it");
+    }
+
+    #[test]
+    fn shadow_entries_are_checked() {
+        let sha512 = "user:$6$saltsalt$TVLlQcbpFVof5W3Yz4DTP6gRstiNuHwwTt6GLc1E5n0U0aDehy0S5knV8wiOQSpT0Y77vwPZN.Pq.H91p5hVO1:19000:0:99999:7:::";
+        let entry = read_shadow_line(sha512).unwrap();
+        assert_eq!(entry.user, "user");
+        assert_eq!(ShadowPasswordVerifier::check_entry(&entry, "secret", 19001).unwrap(), PasswordCheck::Valid);
+        assert_eq!(ShadowPasswordVerifier::check_entry(&entry, "Secret", 19001).unwrap(), PasswordCheck::Invalid);
+        assert_eq!(ShadowPasswordVerifier::check_entry(&entry, "secret", 19000 + 100000).unwrap(), PasswordCheck::ChangeRequired);
+
+        let md5 = read_shadow_line("user:$1$abc$iCQ2D3nhptRYi27fDYv2s1:0::::::").unwrap();
+        assert_eq!(ShadowPasswordVerifier::check_entry(&md5, "secret", 19001).unwrap(), PasswordCheck::ChangeRequired);
+
+        let locked = read_shadow_line("user:!$1$abc$iCQ2D3nhptRYi27fDYv2s1:19000::::::").unwrap();
+        assert_eq!(ShadowPasswordVerifier::check_entry(&locked, "secret", 19001).unwrap(), PasswordCheck::Invalid);
+
+        let expired = read_shadow_line("user:$1$abc$iCQ2D3nhptRYi27fDYv2s1:19000:::::19001:").unwrap();
+        assert_eq!(ShadowPasswordVerifier::check_entry(&expired, "secret", 19001).unwrap(), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn shadow_passwords_are_changed() {
+        let path = std::env::temp_dir().join(format!("bssh_shadow_{}", std::process::id()));
+        fs::write(&path, "root:*:19000:0:99999:7:::\nuser:$1$abc$iCQ2D3nhptRYi27fDYv2s1:0::::::\n").unwrap();
+        let mut verifier = ShadowPasswordVerifier::new(path.clone());
+        assert_eq!(verifier.verify_password("user", "secret").unwrap(), PasswordCheck::ChangeRequired);
+        assert_eq!(verifier.verify_password("nobody", "secret").unwrap(), PasswordCheck::Invalid);
+        assert!(!verifier.change_password("user", "wrong", "new").unwrap());
+        assert!(!verifier.change_password("nobody", "secret", "new").unwrap());
+
+        assert!(verifier.change_password("user", "secret", "new").unwrap());
+        assert_eq!(verifier.verify_password("user", "new").unwrap(), PasswordCheck::Valid);
+        assert_eq!(verifier.verify_password("user", "secret").unwrap(), PasswordCheck::Invalid);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("root:*:19000:0:99999:7:::\nuser:$1$"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use std::io::{Error, ErrorKind};
+use errors;
+use keys::PublicKey;
+use public_key_file;
+
+//sshd(8) AUTHORIZED_KEYS FILE FORMAT: "[options] <type> <base64 blob> [comment]"
+pub struct KeyOption {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+pub struct AuthorizedKey {
+    pub options: Vec<KeyOption>,
+    pub key: PublicKey,
+    pub comment: String,
+}
+
+impl AuthorizedKey {
+    //option names are case insensitive
+    pub fn get_option(&self, name: &str) -> Option<&KeyOption> {
+        self.options.iter().find(|o| o.name.eq_ignore_ascii_case(name))
+    }
+
+    //key of a CA whose user certificates are accepted
+    pub fn is_cert_authority(&self) -> bool {
+        self.get_option("cert-authority").is_some()
+    }
+}
+
+fn bad_options() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_AUTHORIZED_KEYS_BAD_OPTIONS)
+}
+
+//parses comma separated options, values have to be double quoted and may contain \" escapes.
+//returns options and the remainder of the line.
+pub fn read_key_options(text: &str) -> Result<(Vec<KeyOption>, &str), Error> {
+    let mut options: Vec<KeyOption> = Vec::new();
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+
+    loop {
+        let name_start = pos;
+        while pos < bytes.len() && bytes[pos] != b'=' && bytes[pos] != b',' && !(bytes[pos] as char).is_whitespace() {
+            pos += 1;
+        }
+        if pos == name_start {
+            return Err(bad_options());
+        }
+        let name = text[name_start..pos].to_string();
+
+        let mut value: Option<String> = None;
+        if pos < bytes.len() && bytes[pos] == b'=' {
+            pos += 1;
+            if pos >= bytes.len() || bytes[pos] != b'"' {
+                return Err(bad_options());
+            }
+            pos += 1;
+
+            let mut unquoted: Vec<u8> = Vec::new();
+            loop {
+                if pos >= bytes.len() {
+                    return Err(bad_options());
+                }
+                if bytes[pos] == b'"' {
+                    pos += 1;
+                    break;
+                }
+                if bytes[pos] == b'\\' && pos + 1 < bytes.len() && bytes[pos + 1] == b'"' {
+                    pos += 1;
+                }
+                unquoted.push(bytes[pos]);
+                pos += 1;
+            }
+
+            match String::from_utf8(unquoted) {
+                Ok(s) => value = Some(s),
+                Err(_) => return Err(bad_options()),
+            }
+        }
+
+        options.push(KeyOption { name, value });
+
+        if pos >= bytes.len() {
+            return Err(bad_options()); //options without a key
+        }
+        if bytes[pos] == b',' {
+            pos += 1;
+            continue;
+        }
+        if (bytes[pos] as char).is_whitespace() {
+            return Ok((options, text[pos..].trim_start()));
+        }
+        return Err(bad_options());
+    }
+}
+
+//returns None for empty and comment lines
+pub fn read_authorized_key_line(line: &str) -> Result<Option<AuthorizedKey>, Error> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    //like sshd, first try to read the line as a bare key and only then look for options
+    if let Ok(key_line) = public_key_file::read_public_key_line(line) {
+        return Ok(Some(AuthorizedKey {
+            options: Vec::new(),
+            key: key_line.key,
+            comment: key_line.comment,
+        }));
+    }
+
+    let (options, rest) = read_key_options(line)?;
+    let key_line = public_key_file::read_public_key_line(rest)?;
+
+    Ok(Some(AuthorizedKey {
+        options,
+        key: key_line.key,
+        comment: key_line.comment,
+    }))
+}
+
+//malformed lines are skipped, same as sshd does
+pub fn read_authorized_keys(text: &str) -> Vec<AuthorizedKey> {
+    text.lines()
+        .filter_map(|line| read_authorized_key_line(line).unwrap_or(None))
+        .collect()
+}
+
+pub fn write_authorized_key_line(authorized_key: &AuthorizedKey) -> String {
+    let options: Vec<String> = authorized_key.options
+        .iter()
+        .map(|option| match option.value {
+            Some(ref value) => format!("{}=\"{}\"", option.name, value.replace('"', "\\\"")),
+            None => option.name.clone(),
+        })
+        .collect();
+
+    let key_line = public_key_file::write_public_key_line(&authorized_key.key, &authorized_key.comment);
+
+    if options.is_empty() {
+        key_line
+    } else {
+        format!("{} {}", options.join(","), key_line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK3FM+xl4coRYITfMC07c5t0WngvwhzU5Te9htYbXReo user@example.com";
+
+    #[test]
+    fn read_authorized_key_line_without_options() {
+        let entry = read_authorized_key_line(KEY).unwrap().unwrap();
+        assert!(entry.options.is_empty());
+        assert_eq!(entry.comment, "user@example.com");
+    }
+
+    #[test]
+    fn read_authorized_key_line_with_options() {
+        let line = format!("restrict,command=\"echo \\\"a, b\\\"\",from=\"10.0.0.*\" {}", KEY);
+        let entry = read_authorized_key_line(&line).unwrap().unwrap();
+        assert_eq!(entry.options.len(), 3);
+        assert_eq!(entry.options[0].name, "restrict");
+        assert!(entry.options[0].value.is_none());
+        assert_eq!(entry.options[1].name, "command");
+        assert_eq!(entry.options[1].value, Some("echo \"a, b\"".to_string()));
+        assert_eq!(entry.options[2].value, Some("10.0.0.*".to_string()));
+        assert_eq!(write_authorized_key_line(&entry), line);
+    }
+
+    #[test]
+    fn read_authorized_key_line_skips_comments() {
+        assert!(read_authorized_key_line("# comment").unwrap().is_none());
+        assert!(read_authorized_key_line("   ").unwrap().is_none());
+    }
+
+    #[test]
+    fn read_authorized_key_line_rejects_bad_options() {
+        assert!(read_authorized_key_line(&format!("command=unquoted {}", KEY)).is_err());
+        assert!(read_authorized_key_line(&format!("command=\"unterminated {}", KEY)).is_err());
+        assert!(read_authorized_key_line("no-pty").is_err());
+    }
+
+    #[test]
+    fn read_authorized_keys_skips_malformed_lines() {
+        let text = format!("# header\ngarbage line\n{}\nno-pty {}\n", KEY, KEY);
+        let entries = read_authorized_keys(&text);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].options[0].name, "no-pty");
+    }
+}
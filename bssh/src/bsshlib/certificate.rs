@@ -0,0 +1,381 @@
+use std::io::{Cursor, Error, ErrorKind};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use byteorder::{BigEndian, ReadBytesExt};
+use auth_options::AuthOptions;
+use errors;
+use io_helpers;
+use keys;
+use keys::PublicKey;
+use patterns;
+use public_key_file;
+use signature;
+
+//OpenSSH PROTOCOL.certkeys
+
+pub const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+
+pub const SSH2_CERT_TYPE_USER: u32 = 1;
+pub const SSH2_CERT_TYPE_HOST: u32 = 2;
+
+pub const OPTION_FORCE_COMMAND: &str = "force-command";
+pub const OPTION_SOURCE_ADDRESS: &str = "source-address";
+
+pub const EXTENSION_PERMIT_X11_FORWARDING: &str = "permit-X11-forwarding";
+pub const EXTENSION_PERMIT_AGENT_FORWARDING: &str = "permit-agent-forwarding";
+pub const EXTENSION_PERMIT_PORT_FORWARDING: &str = "permit-port-forwarding";
+pub const EXTENSION_PERMIT_PTY: &str = "permit-pty";
+pub const EXTENSION_PERMIT_USER_RC: &str = "permit-user-rc";
+
+pub struct Certificate {
+    pub nonce: Vec<u8>,
+    pub key: PublicKey,
+    pub serial: u64,
+    pub cert_type: u32,
+    pub key_id: String,
+    pub principals: Vec<String>,
+    pub valid_after: u64,
+    pub valid_before: u64,
+    pub critical_options: Vec<(String, Vec<u8>)>,
+    pub extensions: Vec<(String, Vec<u8>)>,
+    pub signature_key: PublicKey,
+    pub signature: Vec<u8>,
+    //whole certificate blob, the signature covers all of it up to the signature field
+    pub blob: Vec<u8>,
+    pub signed_length: usize,
+}
+
+fn malformed_certificate() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_CERT_MALFORMED)
+}
+
+fn invalid_certificate(reason: &'static str) -> Error {
+    Error::new(ErrorKind::PermissionDenied, reason)
+}
+
+pub fn is_certificate_algorithm(name: &str) -> bool {
+    name.ends_with(CERT_SUFFIX)
+}
+
+//host keys and user keys on the wire are certificates if their algorithm name says so
+pub fn is_certificate_blob(blob: &[u8]) -> bool {
+    match io_helpers::read_string(&mut Cursor::new(blob), None) {
+        Ok(name) => name.ends_with(CERT_SUFFIX.as_bytes()),
+        Err(_) => false,
+    }
+}
+
+pub fn get_certificate_algorithm_name(key: &PublicKey) -> String {
+    format!("{}{}", key.get_algorithm_name(), CERT_SUFFIX)
+}
+
+pub fn get_current_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn read_utf8_string(stream: &mut Cursor<&[u8]>) -> Result<String, Error> {
+    match String::from_utf8(io_helpers::read_string(stream, None)?) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(malformed_certificate()),
+    }
+}
+
+//principals are packed as a sequence of strings inside a string
+fn read_packed_strings(data: &[u8]) -> Result<Vec<String>, Error> {
+    let mut stream = Cursor::new(data);
+    let mut res: Vec<String> = Vec::new();
+    while (stream.position() as usize) < data.len() {
+        res.push(read_utf8_string(&mut stream)?);
+    }
+    Ok(res)
+}
+
+//options and extensions are packed as name/data pairs, names have to be sorted and unique
+fn read_packed_options(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut stream = Cursor::new(data);
+    let mut res: Vec<(String, Vec<u8>)> = Vec::new();
+    while (stream.position() as usize) < data.len() {
+        let name = read_utf8_string(&mut stream)?;
+        let value = io_helpers::read_string(&mut stream, None)?;
+        if let Some(previous) = res.last() {
+            if previous.0 >= name {
+                return Err(malformed_certificate());
+            }
+        }
+        res.push((name, value));
+    }
+    Ok(res)
+}
+
+//non-flag option data is itself a string
+fn read_option_value(data: &[u8]) -> Result<String, Error> {
+    let mut stream = Cursor::new(data);
+    let value = read_utf8_string(&mut stream)?;
+    if stream.position() as usize != data.len() {
+        return Err(malformed_certificate());
+    }
+    Ok(value)
+}
+
+impl Certificate {
+    pub fn from_blob(blob: &[u8]) -> Result<Certificate, Error> {
+        let mut stream = Cursor::new(blob);
+
+        let name = read_utf8_string(&mut stream)?;
+        if !is_certificate_algorithm(&name) {
+            return Err(keys::unsupported_key_type());
+        }
+        let nonce = io_helpers::read_string(&mut stream, None)?;
+        let key = keys::read_public_key_fields(&name.as_bytes()[..name.len() - CERT_SUFFIX.len()], &mut stream)?;
+        let serial = stream.read_u64::<BigEndian>()?;
+        let cert_type = stream.read_u32::<BigEndian>()?;
+        let key_id = read_utf8_string(&mut stream)?;
+        let principals = read_packed_strings(&io_helpers::read_string(&mut stream, None)?)?;
+        let valid_after = stream.read_u64::<BigEndian>()?;
+        let valid_before = stream.read_u64::<BigEndian>()?;
+        let critical_options = read_packed_options(&io_helpers::read_string(&mut stream, None)?)?;
+        let extensions = read_packed_options(&io_helpers::read_string(&mut stream, None)?)?;
+        let _reserved = io_helpers::read_string(&mut stream, None)?;
+        let signature_key = PublicKey::from_blob(&io_helpers::read_string(&mut stream, None)?)?;
+        let signed_length = stream.position() as usize;
+        let signature = io_helpers::read_string(&mut stream, None)?;
+
+        if stream.position() as usize != blob.len() || (cert_type != SSH2_CERT_TYPE_USER && cert_type != SSH2_CERT_TYPE_HOST) {
+            return Err(malformed_certificate());
+        }
+
+        Ok(Certificate {
+            nonce,
+            key,
+            serial,
+            cert_type,
+            key_id,
+            principals,
+            valid_after,
+            valid_before,
+            critical_options,
+            extensions,
+            signature_key,
+            signature,
+            blob: blob.to_vec(),
+            signed_length,
+        })
+    }
+
+    pub fn get_algorithm_name(&self) -> String {
+        get_certificate_algorithm_name(&self.key)
+    }
+
+    pub fn is_host_certificate(&self) -> bool {
+        self.cert_type == SSH2_CERT_TYPE_HOST
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|e| e.0 == name)
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        //same as OpenSSH default CASignatureAlgorithms, SHA-1 RSA signatures are not accepted
+        match signature::read_signature(&self.signature) {
+            Ok((ref algorithm, _)) if algorithm == keys::SSH_RSA => return false,
+            Err(_) => return false,
+            _ => {}
+        }
+        signature::verify(&self.signature_key, &self.blob[..self.signed_length], &self.signature)
+    }
+
+    //checks type, validity window and principals, does not check the signature.
+    //Host certificate principals may contain wildcards.
+    pub fn check_authority(&self, want_host: bool, require_principal: bool, name: Option<&str>, now: u64) -> Result<(), Error> {
+        if self.is_host_certificate() != want_host {
+            return Err(invalid_certificate(errors::BSSH_ERR_CERT_WRONG_TYPE));
+        }
+        if now < self.valid_after {
+            return Err(invalid_certificate(errors::BSSH_ERR_CERT_NOT_YET_VALID));
+        }
+        if now >= self.valid_before {
+            return Err(invalid_certificate(errors::BSSH_ERR_CERT_EXPIRED));
+        }
+        if self.principals.is_empty() {
+            if require_principal {
+                return Err(invalid_certificate(errors::BSSH_ERR_CERT_NO_PRINCIPALS));
+            }
+            return Ok(());
+        }
+        if let Some(name) = name {
+            let matched = self.principals.iter().any(|p| {
+                if want_host {
+                    patterns::match_pattern(&name.to_lowercase(), &p.to_lowercase())
+                } else {
+                    p == name
+                }
+            });
+            if !matched {
+                return Err(invalid_certificate(errors::BSSH_ERR_CERT_PRINCIPAL_MISMATCH));
+            }
+        }
+        Ok(())
+    }
+
+    //unknown critical options make the certificate unusable, unknown extensions are ignored
+    pub fn get_auth_options(&self) -> Result<AuthOptions, Error> {
+        let mut options = AuthOptions::restricted();
+
+        for (name, value) in self.critical_options.iter() {
+            match name.as_str() {
+                OPTION_FORCE_COMMAND => options.force_command = Some(read_option_value(value)?),
+                OPTION_SOURCE_ADDRESS => options.source_addresses = Some(read_option_value(value)?),
+                _ => return Err(invalid_certificate(errors::BSSH_ERR_CERT_UNKNOWN_CRITICAL_OPTION)),
+            }
+        }
+
+        for (name, _) in self.extensions.iter() {
+            match name.as_str() {
+                EXTENSION_PERMIT_X11_FORWARDING => options.permit_x11_forwarding = true,
+                EXTENSION_PERMIT_AGENT_FORWARDING => options.permit_agent_forwarding = true,
+                EXTENSION_PERMIT_PORT_FORWARDING => options.permit_port_forwarding = true,
+                EXTENSION_PERMIT_PTY => options.permit_pty = true,
+                EXTENSION_PERMIT_USER_RC => options.permit_user_rc = true,
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+//"<type>-cert-v01@openssh.com <base64 certificate> [comment]", as written by ssh-keygen -s
+pub fn read_certificate_line(line: &str) -> Result<(Certificate, String), Error> {
+    let (key_type, rest) = public_key_file::split_token(line);
+    let (encoded, comment) = public_key_file::split_token(rest);
+
+    let blob = match STANDARD.decode(encoded) {
+        Ok(blob) => blob,
+        Err(_) => return Err(malformed_certificate()),
+    };
+    let certificate = Certificate::from_blob(&blob)?;
+    if certificate.get_algorithm_name() != key_type {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PUBLIC_KEY_TYPE_MISMATCH));
+    }
+    Ok((certificate, comment.to_string()))
+}
+
+//sshd side of TrustedUserCAKeys: certificate has to be signed by one of the trusted CAs,
+//valid now, list user as a principal and, if restricted, be used from an allowed address
+pub fn authorize_user_certificate(certificate: &Certificate,
+                                  trusted_ca_keys: &[PublicKey],
+                                  user: &str,
+                                  client_address: Option<&IpAddr>,
+                                  now: u64)
+                                  -> Result<AuthOptions, Error> {
+    if !trusted_ca_keys.contains(&certificate.signature_key) {
+        return Err(invalid_certificate(errors::BSSH_ERR_CERT_UNTRUSTED_CA));
+    }
+    if !certificate.verify_signature() {
+        return Err(invalid_certificate(errors::BSSH_ERR_CERT_BAD_SIGNATURE));
+    }
+    certificate.check_authority(false, true, Some(user), now)?;
+
+    let options = certificate.get_auth_options()?;
+    options.check_source_address(client_address, None)?;
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //ssh-keygen -s ca -I alice@example -n alice,bob -V 20200101:20400101 -z 42 -O force-command=/bin/date
+    //  -O source-address=10.0.0.0/8,127.0.0.1 -O no-port-forwarding user.pub
+    const USER_CERT: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIHL39h4vQS6PYtLyTvwj/0Kk2/TUua98cCbOjPT0K6vJAAAAIAdny23U0IL1qdTMqZ/CIwgHYL0tpoMDTU5zE1UJIPj9AAAAAAAAACoAAAABAAAADWFsaWNlQGV4YW1wbGUAAAAQAAAABWFsaWNlAAAAA2JvYgAAAABeC+EAAAAAAIOqfoAAAABQAAAADWZvcmNlLWNvbW1hbmQAAAANAAAACS9iaW4vZGF0ZQAAAA5zb3VyY2UtYWRkcmVzcwAAABgAAAAUMTAuMC4wLjAvOCwxMjcuMC4wLjEAAABkAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAII7fJlUP11Kv8cdL/3YQfA8WsCeHVNiWdLBcc+GbB/xMAAAAUwAAAAtzc2gtZWQyNTUxOQAAAED3rQVKzN9DiW4OwSgOBxe0SZcUUcTTyPQxJU+PnL0LSSssDJN1pygBlpeCSgk6iW1IAbWHTGhRUJSXsYvD52MF user";
+    //ssh-keygen -s rsaca -h -I host.example.com -n host.example.com,*.example.org -V 20200101:20400101 host.pub
+    const HOST_CERT: &str = "ecdsa-sha2-nistp256-cert-v01@openssh.com AAAAKGVjZHNhLXNoYTItbmlzdHAyNTYtY2VydC12MDFAb3BlbnNzaC5jb20AAAAgOd9WynFTK2khfKExuJBw4lNUCJhAj5fRcmATr1FOUa8AAAAIbmlzdHAyNTYAAABBBEciOXh2btC38Hh5xv7Jjw/SENSeS5/eA6dlOSx+8Be7btFCBzXmjCWKYvuW247bYLCfa7XvDmnwPamtqSG2pGQAAAAAAAAAAAAAAAIAAAAQaG9zdC5leGFtcGxlLmNvbQAAACUAAAAQaG9zdC5leGFtcGxlLmNvbQAAAA0qLmV4YW1wbGUub3JnAAAAAF4L4QAAAAAAg6p+gAAAAAAAAAAAAAAAAAAAARcAAAAHc3NoLXJzYQAAAAMBAAEAAAEBALC7OKc1KSrNfC8K69YqxkVnCjFCvWCzmVT4dKANUY9CqAFrWu/CSyRitG94JIzElgeTlIdK6FObvuEPN1BQ8wD9FU9/5lYEp+Uu82aAKgtCRpY2z8a1nt1B76bWtOcTvXAkiR8ciQVTv+lY60LVCLFNgyvlm5wwOi6pRQ5ZaOexFfjrmuMogdmJ+QFWbKoYzwCpbuupT+84JyEjHlZ3pcO46fMph8VbOV6thYET79+UD9J4e6zwdSdhhG1MuVU/aWKiCtb59MqgYAuCooN3wUpVXBn17ICoyYE8lEZkrfqNNCURYRbHDP5QcfZFbx/Zd9NvWqN+/1roLoSG2GNOD0EAAAEUAAAADHJzYS1zaGEyLTUxMgAAAQAHBU4LOOZ3ZUkppzXzAU7JuMgekVcSjFBJvlKxmmKPoVNk6ARnhUEtGbEI0kEB6xu4YPQL2D1F4wNRFbdCEeDnPybSKIVYzKwYVu8Z14KN+A/ls/FAsPolq0WtwCW051BJ5cK45nhnC7BR5FKNOydriBpT80GYOvTwbT7Y//yebRUD2aG0iHJxKS7pQA9o5iEhhpOK5CzAY9QByZvfunQlCU1pjXVOXfXS+8SvWpswbc/cAX+F65PgjFwNu931twJSJH1z3/uWHXkZlU2c+TH5LyeUJUcTll6rGDpXaGwBrzVcjbDJO4Sd0JZQhLgUaaetHQh2Em6Cbo1Qno6lBmnZ host";
+    //ssh-keygen -s ca -I expired -n alice -V 20000101:20010101 user.pub
+    const EXPIRED_CERT: &str = "ssh-ed25519-cert-v01@openssh.com AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIEBfcZrL/D/S9AL7UlpTjPSarqoqIjBZxsq49g0dzjfaAAAAIAdny23U0IL1qdTMqZ/CIwgHYL0tpoMDTU5zE1UJIPj9AAAAAAAAAAAAAAABAAAAB2V4cGlyZWQAAAAJAAAABWFsaWNlAAAAADhtQ4AAAAAAOk/IgAAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAII7fJlUP11Kv8cdL/3YQfA8WsCeHVNiWdLBcc+GbB/xMAAAAUwAAAAtzc2gtZWQyNTUxOQAAAECQKfnt1dO3evNP1gOI5uFxo7V3gIIa6l4caP0dxCdi2UPpscC+u3/WkRadpcKtIMiPRsbqVPVsJUcpSZ529/EK user";
+    const CA: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAII7fJlUP11Kv8cdL/3YQfA8WsCeHVNiWdLBcc+GbB/xM ca";
+    const USER_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAdny23U0IL1qdTMqZ/CIwgHYL0tpoMDTU5zE1UJIPj9 user";
+    const NOW: u64 = 1700000000; //2023-11-14
+
+    fn key(line: &str) -> PublicKey {
+        public_key_file::read_public_key_line(line).unwrap().key
+    }
+
+    #[test]
+    fn read_certificate_line_reads_ssh_keygen_certificates() {
+        let (cert, comment) = read_certificate_line(USER_CERT).unwrap();
+        assert_eq!(comment, "user");
+        assert!(cert.key == key(USER_KEY));
+        assert!(cert.signature_key == key(CA));
+        assert_eq!(cert.serial, 42);
+        assert_eq!(cert.cert_type, SSH2_CERT_TYPE_USER);
+        assert_eq!(cert.key_id, "alice@example");
+        assert_eq!(cert.principals, vec!["alice".to_string(), "bob".to_string()]);
+        assert_eq!((cert.valid_after, cert.valid_before), (1577836800, 2208988800));
+        assert!(cert.verify_signature());
+        assert!(is_certificate_blob(&cert.blob));
+        assert!(!is_certificate_blob(&cert.key.to_blob()));
+
+        let (cert, _) = read_certificate_line(HOST_CERT).unwrap();
+        assert!(cert.is_host_certificate());
+        assert_eq!(cert.get_algorithm_name(), "ecdsa-sha2-nistp256-cert-v01@openssh.com");
+        assert!(cert.verify_signature());
+    }
+
+    #[test]
+    fn verify_signature_detects_tampering() {
+        let (cert, _) = read_certificate_line(USER_CERT).unwrap();
+        let mut blob = cert.blob.clone();
+        //serial 42 as uint64
+        let position = blob.windows(8).position(|w| w == [0, 0, 0, 0, 0, 0, 0, 42]).unwrap();
+        blob[position + 7] = 43;
+        let tampered = Certificate::from_blob(&blob).unwrap();
+        assert_eq!(tampered.serial, 43);
+        assert!(!tampered.verify_signature());
+    }
+
+    #[test]
+    fn check_authority_checks_type_time_and_principals() {
+        let (cert, _) = read_certificate_line(USER_CERT).unwrap();
+        assert!(cert.check_authority(false, true, Some("alice"), NOW).is_ok());
+        assert!(cert.check_authority(false, true, Some("mallory"), NOW).is_err());
+        assert!(cert.check_authority(true, false, Some("alice"), NOW).is_err());
+        assert!(cert.check_authority(false, true, Some("alice"), 1000).is_err());
+
+        let (expired, _) = read_certificate_line(EXPIRED_CERT).unwrap();
+        assert!(expired.verify_signature());
+        assert!(expired.check_authority(false, true, Some("alice"), NOW).is_err());
+
+        let (host, _) = read_certificate_line(HOST_CERT).unwrap();
+        assert!(host.check_authority(true, false, Some("host.example.com"), NOW).is_ok());
+        assert!(host.check_authority(true, false, Some("www.Example.org"), NOW).is_ok());
+        assert!(host.check_authority(true, false, Some("www.example.com"), NOW).is_err());
+    }
+
+    #[test]
+    fn get_auth_options_follows_options_and_extensions() {
+        let (cert, _) = read_certificate_line(USER_CERT).unwrap();
+        let options = cert.get_auth_options().unwrap();
+        assert_eq!(options.force_command, Some("/bin/date".to_string()));
+        assert_eq!(options.source_addresses, Some("10.0.0.0/8,127.0.0.1".to_string()));
+        assert!(options.permit_pty);
+        assert!(!options.permit_port_forwarding);
+        assert!(cert.has_extension(EXTENSION_PERMIT_PTY));
+    }
+
+    #[test]
+    fn authorize_user_certificate_works() {
+        let (cert, _) = read_certificate_line(USER_CERT).unwrap();
+        let ca = vec![key(CA)];
+        let inside: IpAddr = "10.1.2.3".parse().unwrap();
+        let outside: IpAddr = "192.168.1.1".parse().unwrap();
+
+        assert!(authorize_user_certificate(&cert, &ca, "bob", Some(&inside), NOW).is_ok());
+        assert!(authorize_user_certificate(&cert, &ca, "bob", Some(&outside), NOW).is_err());
+        assert!(authorize_user_certificate(&cert, &ca, "bob", None, NOW).is_err());
+        assert!(authorize_user_certificate(&cert, &ca, "carol", Some(&inside), NOW).is_err());
+        assert!(authorize_user_certificate(&cert, &[key(USER_KEY)], "bob", Some(&inside), NOW).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{Error, ErrorKind};
+use aes::{Aes128, Aes256};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use errors;
+
+pub const AES128_CTR: &str = "aes128-ctr";
+pub const AES256_CTR: &str = "aes256-ctr";
+
+pub const AES_BLOCK_SIZE: usize = 16;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+pub fn get_key_length(algorithm: &str) -> Option<usize> {
+    match algorithm {
+        AES128_CTR => Some(16),
+        AES256_CTR => Some(32),
+        _ => None,
+    }
+}
+
+//RFC 4344, the counter runs on across packets of one direction
+pub struct PacketCipher {
+    cipher: Box<dyn StreamCipher + Send>,
+}
+
+impl PacketCipher {
+    //key and iv may be longer than needed, as derived by the key exchange
+    pub fn new(algorithm: &str, key: &[u8], iv: &[u8]) -> Result<PacketCipher, Error> {
+        let key_length = get_key_length(algorithm).ok_or_else(|| Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNSUPPORTED_CIPHER))?;
+        if key.len() < key_length || iv.len() < AES_BLOCK_SIZE {
+            return Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNSUPPORTED_CIPHER));
+        }
+        let iv = &iv[..AES_BLOCK_SIZE];
+        let cipher: Box<dyn StreamCipher + Send> = match algorithm {
+            AES128_CTR => Box::new(Aes128Ctr::new(key[..key_length].into(), iv.into())),
+            _ => Box::new(Aes256Ctr::new(key[..key_length].into(), iv.into())),
+        };
+        Ok(PacketCipher { cipher })
+    }
+
+    pub fn get_block_size(&self) -> usize {
+        AES_BLOCK_SIZE
+    }
+
+    //encrypts or decrypts in place
+    pub fn apply(&mut self, data: &mut [u8]) {
+        self.cipher.apply_keystream(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn aes_ctr_continues_across_calls() {
+        let key = [1u8; 32];
+        let iv = [2u8; 16];
+        let mut whole = [0u8; 48];
+        PacketCipher::new(AES256_CTR, &key, &iv).unwrap().apply(&mut whole);
+
+        let mut parts = [0u8; 48];
+        let mut cipher = PacketCipher::new(AES256_CTR, &key, &iv).unwrap();
+        cipher.apply(&mut parts[..16]);
+        cipher.apply(&mut parts[16..]);
+        assert_eq!(whole, parts);
+
+        let mut decrypted = whole;
+        PacketCipher::new(AES256_CTR, &key, &iv).unwrap().apply(&mut decrypted);
+        assert_eq!(decrypted, [0u8; 48]);
+    }
+
+    #[test]
+    fn aes128_ctr_matches_nist_vector() {
+        //NIST SP 800-38A F.5.1, first block
+        let key = [0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c];
+        let iv = [0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff];
+        let mut data = [0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a];
+        PacketCipher::new(AES128_CTR, &key, &iv).unwrap().apply(&mut data);
+        assert_eq!(data, [0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6, 0xce]);
+        assert!(PacketCipher::new("3des-cbc", &key, &iv).is_err());
+    }
+}
@@ -0,0 +1,694 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use byteorder::{BigEndian, ReadBytesExt};
+use libc;
+use connection;
+use connection::{ChannelEvent, ChannelInput, ChannelManager, ReaderThrottle};
+use errors;
+use forwarding;
+use forwarding::{Forward, TcpChannels, TcpipChannel};
+use numbers;
+use patterns;
+use session;
+use session::PtyRequest;
+use terminal::WindowSize;
+use transport;
+use transport::PayloadStream;
+
+//Client side of an RFC 4254 session, the terminal handling is left to the caller.
+
+pub enum ClientEvent {
+    Payload(Result<Vec<u8>, Error>),
+    Input(ChannelInput),
+    WindowChanged(WindowSize),
+    //local signal to forward to the remote process
+    Signal(libc::c_int),
+    //connection to a local forwarding, by its index in the options
+    Accepted { forward: usize, stream: TcpStream },
+    //channel data taken by a forwarded connection
+    Written { channel: u32, length: usize },
+    //connection to the destination of a forwarded-tcpip channel
+    Connected { channel: u32, stream: Result<TcpStream, Error> },
+    //stops a remote forwarding, by its index in the options
+    CancelRemoteForward(usize),
+}
+
+pub struct ClientSessionOptions {
+    pub pty: Option<PtyRequest>,
+    //remote command, the login shell runs when there is none
+    pub command: Option<String>,
+    //the command is the name of a subsystem, like "sftp"
+    pub subsystem: bool,
+    //variables sent with env requests, the server may ignore them
+    pub environment: Vec<(String, String)>,
+    //connections to these local ports go through direct-tcpip channels
+    pub local_forwards: Vec<Forward>,
+    //the server listens on these ports and opens forwarded-tcpip channels
+    pub remote_forwards: Vec<Forward>,
+}
+
+//local variables matching SendEnv patterns, followed by those of SetEnv
+pub fn get_environment<I>(variables: I, send_env: &[String], set_env: &[(String, String)]) -> Vec<(String, String)>
+    where I: Iterator<Item = (String, String)>
+{
+    let mut environment: Vec<(String, String)> = variables.filter(|v| send_env.iter().any(|p| patterns::match_pattern(&v.0, p))).collect();
+    environment.extend(set_env.iter().cloned());
+    environment
+}
+
+pub struct SessionIo {
+    pub stdin: Box<dyn Read + Send>,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+}
+
+pub struct ClientSession {
+    pub manager: ChannelManager,
+    pub channel: u32,
+    pub options: ClientSessionOptions,
+    //stdin is read once the channel is open
+    stdin: Option<Box<dyn Read + Send>>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    //requests in the order their replies come
+    requests_awaiting_reply: VecDeque<&'static str>,
+    throttle: ReaderThrottle,
+    //accepted connections waiting for their channel to open
+    connecting: HashMap<u32, TcpStream>,
+    //remote forwardings by index, with the port the server listens on
+    remote_ports: HashMap<usize, u16>,
+    //global requests in the order their replies come, with the index of the remote forwarding
+    global_requests_awaiting_reply: VecDeque<(&'static str, usize)>,
+    forwards: TcpChannels,
+    events: Sender<ClientEvent>,
+    pub closed: bool,
+    pub exit_status: Option<u32>,
+    pub exit_signal: Option<session::ExitSignal>,
+}
+
+impl ClientSession {
+    pub fn new(options: ClientSessionOptions, io: SessionIo, events: Sender<ClientEvent>) -> ClientSession {
+        let mut manager = ChannelManager::new();
+        let channel = manager.open(session::CHANNEL_SESSION, &[]);
+        ClientSession {
+            manager,
+            channel,
+            options,
+            stdin: Some(io.stdin),
+            stdout: io.stdout,
+            stderr: io.stderr,
+            requests_awaiting_reply: VecDeque::new(),
+            throttle: ReaderThrottle::default(),
+            connecting: HashMap::new(),
+            remote_ports: HashMap::new(),
+            global_requests_awaiting_reply: VecDeque::new(),
+            forwards: TcpChannels::default(),
+            events,
+            closed: false,
+            exit_status: None,
+            exit_signal: None,
+        }
+    }
+
+    //a forwarding which can not listen is reported and skipped, as by ssh without ExitOnForwardFailure
+    pub fn listen_local_forwards(&mut self) -> Result<(), Error> {
+        for (index, forward) in self.options.local_forwards.iter().enumerate() {
+            let address = forwarding::get_listen_address(forward.listen_host.as_deref());
+            match TcpListener::bind((address, forward.listen_port)) {
+                Ok(listener) => {
+                    forwarding::spawn_listener(listener, self.events.clone(), move |stream| ClientEvent::Accepted { forward: index, stream });
+                }
+                Err(e) => {
+                    writeln!(self.stderr, "bind [{}]:{}: {}", address, forward.listen_port, e)?;
+                    writeln!(self.stderr, "Could not request local forwarding.")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //"localhost" stands for the default of the server, as by ssh
+    fn get_remote_listen_host(forward: &Forward) -> &str {
+        forward.listen_host.as_deref().unwrap_or("localhost")
+    }
+
+    pub fn request_remote_forwards(&mut self) -> Result<(), Error> {
+        for (index, forward) in self.options.remote_forwards.iter().enumerate() {
+            let mut data: Vec<u8> = Vec::new();
+            forwarding::write_forward_request(&mut data, ClientSession::get_remote_listen_host(forward), forward.listen_port)?;
+            self.manager.send_global_request(forwarding::REQUEST_TCPIP_FORWARD, true, &data);
+            self.global_requests_awaiting_reply.push_back((forwarding::REQUEST_TCPIP_FORWARD, index));
+        }
+        Ok(())
+    }
+
+    fn cancel_remote_forward(&mut self, index: usize) -> Result<(), Error> {
+        let (forward, port) = match (self.options.remote_forwards.get(index), self.remote_ports.get(&index)) {
+            (Some(forward), Some(&port)) => (forward, port),
+            _ => return Ok(()),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        forwarding::write_forward_request(&mut data, ClientSession::get_remote_listen_host(forward), port)?;
+        self.manager.send_global_request(forwarding::REQUEST_CANCEL_TCPIP_FORWARD, true, &data);
+        self.global_requests_awaiting_reply.push_back((forwarding::REQUEST_CANCEL_TCPIP_FORWARD, index));
+        Ok(())
+    }
+
+    //a forwarding asking for any port gets the port in the reply
+    fn handle_global_request_reply(&mut self, success: bool, data: &[u8]) -> Result<(), Error> {
+        let (request, index) = match self.global_requests_awaiting_reply.pop_front() {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let forward = &self.options.remote_forwards[index];
+        match request {
+            forwarding::REQUEST_TCPIP_FORWARD if success => {
+                let mut port = forward.listen_port;
+                if port == 0 {
+                    port = u16::try_from((&data[..]).read_u32::<BigEndian>()?).map_err(|_| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE))?;
+                    writeln!(self.stderr, "Allocated port {} for remote forward to {}:{}", port, forward.connect_host, forward.connect_port)?;
+                }
+                self.remote_ports.insert(index, port);
+            }
+            forwarding::REQUEST_TCPIP_FORWARD => {
+                writeln!(self.stderr, "Warning: remote port forwarding failed for listen port {}", forward.listen_port)?;
+            }
+            _ if success => {
+                self.remote_ports.remove(&index);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    //connections come from forwardings we asked for only, the destination is connected in a thread
+    fn handle_forwarded_tcpip(&mut self, channel: u32, type_data: &[u8]) -> Result<(), Error> {
+        let request = forwarding::read_tcpip_channel(&mut &type_data[..])?;
+        let remote_forwards = &self.options.remote_forwards;
+        let forward = self.remote_ports.iter()
+            .find(|&(&index, &port)| port == request.port && ClientSession::get_remote_listen_host(&remote_forwards[index]) == request.host)
+            .map(|(&index, _)| &remote_forwards[index]);
+        let (host, port) = match forward {
+            Some(forward) => (forward.connect_host.clone(), forward.connect_port),
+            None => return self.manager.reject(channel, numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED, "open failed"),
+        };
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let stream = TcpStream::connect((host.as_str(), port));
+            let _ = events.send(ClientEvent::Connected { channel, stream });
+        });
+        Ok(())
+    }
+
+    fn open_direct_tcpip(&mut self, forward: usize, stream: TcpStream) -> Result<(), Error> {
+        let forward = &self.options.local_forwards[forward];
+        let originator = stream.peer_addr()?;
+        let request = TcpipChannel {
+            host: forward.connect_host.clone(),
+            port: forward.connect_port,
+            originator_address: originator.ip().to_string(),
+            originator_port: originator.port(),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        forwarding::write_tcpip_channel(&mut data, &request)?;
+        let channel = self.manager.open(forwarding::CHANNEL_DIRECT_TCPIP, &data);
+        self.connecting.insert(channel, stream);
+        Ok(())
+    }
+
+    fn send_request(&mut self, request_type: &'static str, data: &[u8]) -> Result<(), Error> {
+        self.manager.send_request(self.channel, request_type, true, data)?;
+        self.requests_awaiting_reply.push_back(request_type);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), Error> {
+        if let Some(request) = self.options.pty.clone() {
+            let mut data: Vec<u8> = Vec::new();
+            session::write_pty_request(&mut data, &request)?;
+            self.send_request(session::REQUEST_PTY, &data)?;
+        }
+        //no reply is wanted, as by OpenSSH, a refused variable is not worth failing the session
+        for (name, value) in self.options.environment.iter() {
+            let mut data: Vec<u8> = Vec::new();
+            session::write_env_request(&mut data, name, value)?;
+            self.manager.send_request(self.channel, session::REQUEST_ENV, false, &data)?;
+        }
+        match self.options.command.clone() {
+            Some(name) if self.options.subsystem => {
+                let mut data: Vec<u8> = Vec::new();
+                session::write_subsystem_request(&mut data, &name)?;
+                self.send_request(session::REQUEST_SUBSYSTEM, &data)?;
+            }
+            Some(command) => {
+                let mut data: Vec<u8> = Vec::new();
+                session::write_exec_request(&mut data, &command)?;
+                self.send_request(session::REQUEST_EXEC, &data)?;
+            }
+            None => self.send_request(session::REQUEST_SHELL, &[])?,
+        }
+        if let Some(stdin) = self.stdin.take() {
+            connection::spawn_channel_reader(stdin, self.channel, None, self.events.clone(), ClientEvent::Input);
+        }
+        Ok(())
+    }
+
+    fn handle_channel_event(&mut self, event: ChannelEvent) -> Result<(), Error> {
+        match event {
+            ChannelEvent::Opened { channel, .. } if channel == self.channel => self.start()?,
+            ChannelEvent::Opened { channel, .. } => {
+                if let Some(stream) = self.connecting.remove(&channel) {
+                    self.forwards.start(stream, channel, &self.events, ClientEvent::Input, |channel, length| ClientEvent::Written { channel, length })?;
+                }
+            }
+            ChannelEvent::OpenFailed { channel, description, .. } if channel == self.channel => {
+                return Err(Error::new(ErrorKind::ConnectionRefused, format!("Channel open failed: {}", description)));
+            }
+            //the accepted connection is dropped
+            ChannelEvent::OpenFailed { channel, description, .. } => {
+                self.connecting.remove(&channel);
+                writeln!(self.stderr, "channel {}: open failed: {}", channel, description)?;
+            }
+            ChannelEvent::Data { channel, data } if self.forwards.contains(channel) => self.forwards.handle_data(&mut self.manager, channel, data)?,
+            ChannelEvent::Eof { channel } if self.forwards.contains(channel) => self.forwards.handle_eof(&mut self.manager, channel)?,
+            ChannelEvent::RequestReply { success, .. } => {
+                match self.requests_awaiting_reply.pop_front() {
+                    Some(session::REQUEST_PTY) if !success => {
+                        writeln!(self.stderr, "PTY allocation request failed on channel {}", self.channel)?;
+                    }
+                    Some(request) if !success && request != session::REQUEST_PTY => {
+                        writeln!(self.stderr, "{} request failed on channel {}", request, self.channel)?;
+                        self.manager.close(self.channel)?;
+                    }
+                    _ => {}
+                }
+            }
+            ChannelEvent::Data { channel, data } => {
+                self.stdout.write_all(&data)?;
+                self.stdout.flush()?;
+                self.manager.consume(channel, data.len())?;
+            }
+            ChannelEvent::ExtendedData { channel, data, .. } => {
+                self.stderr.write_all(&data)?;
+                self.stderr.flush()?;
+                self.manager.consume(channel, data.len())?;
+            }
+            ChannelEvent::Request { channel, request_type, want_reply, data } => {
+                match request_type.as_str() {
+                    session::REQUEST_EXIT_STATUS => self.exit_status = Some(session::read_exit_status_request(&mut &data[..])?),
+                    session::REQUEST_EXIT_SIGNAL => self.exit_signal = Some(session::read_exit_signal_request(&mut &data[..])?),
+                    _ if want_reply => self.manager.reply_request(channel, false)?,
+                    _ => {}
+                }
+            }
+            ChannelEvent::WindowAdjusted { .. } => self.throttle.resume_readers(&self.manager),
+            ChannelEvent::Closed { channel } if channel == self.channel => self.closed = true,
+            ChannelEvent::Closed { channel } => self.forwards.handle_closed(channel),
+            ChannelEvent::OpenRequested { channel, channel_type, type_data } => {
+                if channel_type == forwarding::CHANNEL_FORWARDED_TCPIP {
+                    self.handle_forwarded_tcpip(channel, &type_data)?;
+                } else {
+                    self.manager.reject(channel, numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED, "open failed")?;
+                }
+            }
+            ChannelEvent::GlobalRequest { want_reply: true, .. } => self.manager.reply_global_request(false, &[]),
+            ChannelEvent::GlobalRequestReply { success, data } => self.handle_global_request_reply(success, &data)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.manager.get_channel(self.channel).map(|c| !c.sent_close).unwrap_or(false)
+    }
+
+    pub fn handle_event(&mut self, event: ClientEvent) -> Result<(), Error> {
+        match event {
+            ClientEvent::Payload(payload) => {
+                let payload = payload?;
+                if !connection::is_connection_message(payload[0]) {
+                    return Ok(());
+                }
+                if let Some(event) = self.manager.handle_payload(&payload)? {
+                    self.handle_channel_event(event)?;
+                }
+            }
+            //input of a closed forwarding is dropped
+            ClientEvent::Input(input) if input.channel != self.channel => {
+                if !self.forwards.contains(input.channel) {
+                    return Ok(());
+                }
+                match input.data {
+                    Some(_) => self.throttle.send_input(&mut self.manager, input)?,
+                    None => self.forwards.handle_input_end(&mut self.manager, input.channel)?,
+                }
+            }
+            ClientEvent::Input(input) => {
+                if !self.is_open() {
+                    return Ok(());
+                }
+                match input.data {
+                    Some(_) => self.throttle.send_input(&mut self.manager, input)?,
+                    None => self.manager.send_eof(self.channel)?,
+                }
+            }
+            ClientEvent::Signal(signal) => {
+                //there is nothing to forward to yet, so the signal ends the session
+                if !self.is_open() || self.stdin.is_some() {
+                    return Err(Error::new(ErrorKind::Interrupted, errors::BSSH_ERR_INTERRUPTED));
+                }
+                let mut data: Vec<u8> = Vec::new();
+                session::write_signal_request(&mut data, &session::get_signal_name(signal))?;
+                self.manager.send_request(self.channel, session::REQUEST_SIGNAL, false, &data)?;
+            }
+            ClientEvent::WindowChanged(size) => {
+                if self.is_open() && self.options.pty.is_some() {
+                    let mut data: Vec<u8> = Vec::new();
+                    session::write_window_change_request(&mut data, &size)?;
+                    self.manager.send_request(self.channel, session::REQUEST_WINDOW_CHANGE, false, &data)?;
+                }
+            }
+            ClientEvent::Accepted { forward, stream } => self.open_direct_tcpip(forward, stream)?,
+            ClientEvent::Written { channel, length } => {
+                if self.manager.get_channel(channel).is_some() {
+                    self.manager.consume(channel, length)?;
+                }
+            }
+            ClientEvent::Connected { channel, stream: Ok(stream) } => {
+                self.manager.accept(channel, &[])?;
+                self.forwards.start(stream, channel, &self.events, ClientEvent::Input, |channel, length| ClientEvent::Written { channel, length })?;
+            }
+            ClientEvent::Connected { channel, stream: Err(e) } => {
+                self.manager.reject(channel, numbers::SSH_OPEN_CONNECT_FAILED, &e.to_string())?;
+            }
+            ClientEvent::CancelRemoteForward(index) => self.cancel_remote_forward(index)?,
+        }
+        Ok(())
+    }
+}
+
+//runs the session until its channel is closed, events are also sent by the caller (window changes)
+//the finished session holds exit status or signal of the remote process
+pub fn run_client_session<R, W>(receiver: R, sender: &mut W, options: ClientSessionOptions, io: SessionIo,
+                                events: Sender<ClientEvent>, received: Receiver<ClientEvent>) -> Result<ClientSession, Error>
+    where R: PayloadStream + Send + 'static, W: PayloadStream
+{
+    transport::spawn_payload_receiver(receiver, events.clone(), ClientEvent::Payload);
+    let mut session = ClientSession::new(options, io, events);
+    session.listen_local_forwards()?;
+    session.request_remote_forwards()?;
+    session.manager.send_outgoing(sender)?;
+    while !session.closed {
+        let event = received.recv().map_err(|_| Error::new(ErrorKind::UnexpectedEof, errors::BSSH_ERR_UNEXPECTED_MESSAGE))?;
+        session.handle_event(event)?;
+        session.manager.send_outgoing(sender)?;
+    }
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io;
+    use std::io::Cursor;
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+    use std::thread;
+    use auth_options::AuthOptions;
+    use mocks::ChannelPayloadStream;
+    use passwd;
+    use server_session;
+    use server_session::SessionConfig;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn client_session_runs_command() {
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        let config = SessionConfig { accept_env: vec!["BUILD_ID".to_string()], ..SessionConfig::default() };
+        let server = thread::spawn(move || server_session::run_server_connection(server_in, &mut server_out, user, AuthOptions::unrestricted(), config).unwrap());
+
+        let stdout = SharedBuffer::default();
+        let stderr = SharedBuffer::default();
+        let io = SessionIo {
+            stdin: Box::new(Cursor::new(b"echo out; echo err >&2\n".to_vec())),
+            stdout: Box::new(stdout.clone()),
+            stderr: Box::new(stderr.clone()),
+        };
+        let client_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: client_receiver };
+        let mut client_out = ChannelPayloadStream { sender: to_server, receiver: mpsc::channel().1 };
+        let (events, received) = mpsc::channel();
+        let options = ClientSessionOptions { pty: None, command: Some("cat; exit $BUILD_ID".to_string()), subsystem: false, environment: vec![("BUILD_ID".to_string(), "7".to_string())], local_forwards: Vec::new(), remote_forwards: Vec::new() };
+        let session = run_client_session(client_in, &mut client_out, options, io, events, received).unwrap();
+        assert_eq!(*stdout.0.lock().unwrap(), b"echo out; echo err >&2\n");
+        assert_eq!(session.exit_status, Some(7));
+        drop(session);
+        drop(client_out);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn client_session_starts_subsystem() {
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        let config = SessionConfig { subsystems: vec![("echo".to_string(), "cat".to_string())], ..SessionConfig::default() };
+        let server = thread::spawn(move || server_session::run_server_connection(server_in, &mut server_out, user, AuthOptions::unrestricted(), config).unwrap());
+
+        let stdout = SharedBuffer::default();
+        let io = SessionIo {
+            stdin: Box::new(Cursor::new(b"hello".to_vec())),
+            stdout: Box::new(stdout.clone()),
+            stderr: Box::new(SharedBuffer::default()),
+        };
+        let client_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: client_receiver };
+        let mut client_out = ChannelPayloadStream { sender: to_server, receiver: mpsc::channel().1 };
+        let (events, received) = mpsc::channel();
+        let options = ClientSessionOptions { pty: None, command: Some("echo".to_string()), subsystem: true, environment: Vec::new(), local_forwards: Vec::new(), remote_forwards: Vec::new() };
+        let session = run_client_session(client_in, &mut client_out, options, io, events, received).unwrap();
+        assert_eq!(*stdout.0.lock().unwrap(), b"hello");
+        assert_eq!(session.exit_status, Some(0));
+        drop(session);
+        drop(client_out);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn client_session_runs_shell() {
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        let server = thread::spawn(move || server_session::run_server_connection(server_in, &mut server_out, user, AuthOptions::unrestricted(), SessionConfig::default()).unwrap());
+
+        let stdout = SharedBuffer::default();
+        let stderr = SharedBuffer::default();
+        let io = SessionIo {
+            stdin: Box::new(Cursor::new(b"echo out; echo err >&2\n".to_vec())),
+            stdout: Box::new(stdout.clone()),
+            stderr: Box::new(stderr.clone()),
+        };
+        let client_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: client_receiver };
+        let mut client_out = ChannelPayloadStream { sender: to_server, receiver: mpsc::channel().1 };
+        let (events, received) = mpsc::channel();
+        let session = run_client_session(client_in, &mut client_out, ClientSessionOptions { pty: None, command: None, subsystem: false, environment: Vec::new(), local_forwards: Vec::new(), remote_forwards: Vec::new() }, io, events, received).unwrap();
+        assert_eq!(*stdout.0.lock().unwrap(), b"out\n");
+        assert_eq!(*stderr.0.lock().unwrap(), b"err\n");
+        assert_eq!(session.exit_status, Some(0));
+
+        drop(client_out);
+        server.join().unwrap();
+    }
+
+    //interrupts the session as soon as there is some output
+    struct InterruptingOutput(Sender<ClientEvent>);
+
+    impl Write for InterruptingOutput {
+        fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+            let _ = self.0.send(ClientEvent::Signal(libc::SIGINT));
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn client_session_forwards_signals() {
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        let server = thread::spawn(move || server_session::run_server_connection(server_in, &mut server_out, user, AuthOptions::unrestricted(), SessionConfig::default()).unwrap());
+
+        let (events, received) = mpsc::channel();
+        let io = SessionIo {
+            stdin: Box::new(Cursor::new(Vec::new())),
+            stdout: Box::new(InterruptingOutput(events.clone())),
+            stderr: Box::new(SharedBuffer::default()),
+        };
+        let client_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: client_receiver };
+        let mut client_out = ChannelPayloadStream { sender: to_server, receiver: mpsc::channel().1 };
+        let options = ClientSessionOptions { pty: None, command: Some("echo started; sleep 30".to_string()), subsystem: false, environment: Vec::new(), local_forwards: Vec::new(), remote_forwards: Vec::new() };
+        let session = run_client_session(client_in, &mut client_out, options, io, events, received).unwrap();
+        assert_eq!(session.exit_status, None);
+        assert_eq!(session.exit_signal.as_ref().map(|s| s.signal_name.as_str()), Some("INT"));
+
+        drop(client_out);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn local_forwards_open_direct_tcpip_channels() {
+        let echo = TcpListener::bind("127.0.0.1:0").unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut stream = echo.accept().unwrap().0;
+            let mut reader = stream.try_clone().unwrap();
+            io::copy(&mut reader, &mut stream).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+        });
+        let listen_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        let config = SessionConfig { allow_local_forwarding: true, ..SessionConfig::default() };
+        let server = thread::spawn(move || server_session::run_server_connection(server_in, &mut server_out, user, AuthOptions::unrestricted(), config).unwrap());
+
+        //the session lasts until its input is closed
+        let (input, stdin) = UnixStream::pair().unwrap();
+        let stderr = SharedBuffer::default();
+        let client_stderr = stderr.clone();
+        let client = thread::spawn(move || {
+            let io = SessionIo { stdin: Box::new(stdin), stdout: Box::new(SharedBuffer::default()), stderr: Box::new(client_stderr) };
+            let client_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: client_receiver };
+            let mut client_out = ChannelPayloadStream { sender: to_server, receiver: mpsc::channel().1 };
+            let (events, received) = mpsc::channel();
+            let forward = Forward { listen_host: None, listen_port, connect_host: "127.0.0.1".to_string(), connect_port: echo_port };
+            let options = ClientSessionOptions { pty: None, command: Some("cat".to_string()), subsystem: false, environment: Vec::new(), local_forwards: vec![forward.clone(), forward], remote_forwards: Vec::new() };
+            run_client_session(client_in, &mut client_out, options, io, events, received).unwrap().exit_status
+        });
+
+        let mut connection = (0..100).filter_map(|_| {
+            TcpStream::connect(("127.0.0.1", listen_port)).map_err(|_| thread::sleep(std::time::Duration::from_millis(20))).ok()
+        }).next().unwrap();
+        connection.write_all(b"ping").unwrap();
+        connection.shutdown(Shutdown::Write).unwrap();
+        let mut echoed: Vec<u8> = Vec::new();
+        connection.read_to_end(&mut echoed).unwrap();
+        assert_eq!(echoed, b"ping");
+
+        drop(input);
+        assert_eq!(client.join().unwrap(), Some(0));
+        assert!(String::from_utf8_lossy(&stderr.0.lock().unwrap()).contains("Could not request local forwarding."));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn remote_forwards_accept_forwarded_tcpip_channels() {
+        let echo = TcpListener::bind("127.0.0.1:0").unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut stream = echo.accept().unwrap().0;
+            let mut reader = stream.try_clone().unwrap();
+            io::copy(&mut reader, &mut stream).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+        });
+
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        let config = SessionConfig { allow_remote_forwarding: true, ..SessionConfig::default() };
+        let server = thread::spawn(move || server_session::run_server_connection(server_in, &mut server_out, user, AuthOptions::unrestricted(), config).unwrap());
+
+        let (input, stdin) = UnixStream::pair().unwrap();
+        let stderr = SharedBuffer::default();
+        let client_stderr = stderr.clone();
+        let (events, received) = mpsc::channel();
+        let cancel = events.clone();
+        let client = thread::spawn(move || {
+            let io = SessionIo { stdin: Box::new(stdin), stdout: Box::new(SharedBuffer::default()), stderr: Box::new(client_stderr) };
+            let client_in = ChannelPayloadStream { sender: mpsc::channel().0, receiver: client_receiver };
+            let mut client_out = ChannelPayloadStream { sender: to_server, receiver: mpsc::channel().1 };
+            let forward = Forward { listen_host: None, listen_port: 0, connect_host: "127.0.0.1".to_string(), connect_port: echo_port };
+            let options = ClientSessionOptions { pty: None, command: Some("cat".to_string()), subsystem: false, environment: Vec::new(), local_forwards: Vec::new(), remote_forwards: vec![forward] };
+            run_client_session(client_in, &mut client_out, options, io, events, received).unwrap().exit_status
+        });
+
+        //"Allocated port N for remote forward to 127.0.0.1:echo_port"
+        let port: u16 = (0..100).filter_map(|_| {
+            let text = String::from_utf8_lossy(&stderr.0.lock().unwrap()).into_owned();
+            let port = text.strip_prefix("Allocated port ").and_then(|t| t.split(' ').next()).and_then(|p| p.parse().ok());
+            if port.is_none() {
+                thread::sleep(std::time::Duration::from_millis(20));
+            }
+            port
+        }).next().unwrap();
+        let mut connection = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        connection.write_all(b"ping").unwrap();
+        connection.shutdown(Shutdown::Write).unwrap();
+        let mut echoed: Vec<u8> = Vec::new();
+        connection.read_to_end(&mut echoed).unwrap();
+        assert_eq!(echoed, b"ping");
+
+        cancel.send(ClientEvent::CancelRemoteForward(0)).unwrap();
+        let refused = (0..100).any(|_| {
+            thread::sleep(std::time::Duration::from_millis(20));
+            TcpStream::connect(("127.0.0.1", port)).is_err()
+        });
+        assert!(refused);
+
+        drop(input);
+        assert_eq!(client.join().unwrap(), Some(0));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn environment_follows_send_env_and_set_env() {
+        let variables = vec![("LANG".to_string(), "C".to_string()), ("HOME".to_string(), "/".to_string()), ("LC_ALL".to_string(), "C".to_string())];
+        let send_env = vec!["LANG".to_string(), "LC_*".to_string()];
+        let set_env = vec![("BUILD_ID".to_string(), "42".to_string())];
+        let environment = get_environment(variables.into_iter(), &send_env, &set_env);
+        assert_eq!(environment, vec![("LANG".to_string(), "C".to_string()), ("LC_ALL".to_string(), "C".to_string()), ("BUILD_ID".to_string(), "42".to_string())]);
+    }
+}
@@ -1,11 +1,106 @@
 use std;
 use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use auth_keyboard_interactive::ChallengeKind;
+use forwarding::{Forward, GatewayPorts};
+use keys::PublicKey;
+use known_hosts::StrictHostKeyChecking;
 
 pub trait CommonConfig {}
 
-pub trait ClientConfig {}
+//ssh_config(5) and sshd_config(5) LogLevel, in order of verbosity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Quiet,
+    Fatal,
+    Error,
+    Info,
+    Verbose,
+    Debug1,
+    Debug2,
+    Debug3,
+}
+
+impl LogLevel {
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name.to_uppercase().as_str() {
+            "QUIET" => Some(LogLevel::Quiet),
+            "FATAL" => Some(LogLevel::Fatal),
+            "ERROR" => Some(LogLevel::Error),
+            "INFO" => Some(LogLevel::Info),
+            "VERBOSE" => Some(LogLevel::Verbose),
+            "DEBUG" | "DEBUG1" => Some(LogLevel::Debug1),
+            "DEBUG2" => Some(LogLevel::Debug2),
+            "DEBUG3" => Some(LogLevel::Debug3),
+            _ => None,
+        }
+    }
+}
+
+pub trait ClientConfig {
+    fn get_strict_host_key_checking(&self) -> StrictHostKeyChecking;
+    fn get_user_known_hosts_files(&self) -> Vec<PathBuf>;
+    fn get_global_known_hosts_files(&self) -> Vec<PathBuf>;
+    fn get_hash_known_hosts(&self) -> bool;
+    fn get_password_authentication(&self) -> bool;
+    fn get_number_of_password_prompts(&self) -> u32;
+    fn get_pubkey_authentication(&self) -> bool;
+    fn get_kbd_interactive_authentication(&self) -> bool;
+    fn get_hostbased_authentication(&self) -> bool;
+    fn get_enable_ssh_keysign(&self) -> bool;
+    fn get_log_level(&self) -> LogLevel;
+    fn get_identity_files(&self) -> Vec<PathBuf>;
+    //SendEnv patterns of local variables passed to the server
+    fn get_send_env(&self) -> Vec<String>;
+    //SetEnv variables, sent after those of SendEnv
+    fn get_set_env(&self) -> Vec<(String, String)>;
+    //LocalForward and -L, in the order given
+    fn get_local_forwards(&self) -> Vec<Forward>;
+    //RemoteForward and -R, in the order given
+    fn get_remote_forwards(&self) -> Vec<Forward>;
+}
 
-pub trait ServerConfig {}
+pub trait ServerConfig {
+    //HostKey files, the default ones when none are given
+    fn get_host_key_files(&self) -> Vec<PathBuf>;
+    //HostCertificate files, public certificates of the host keys
+    fn get_host_certificate_files(&self) -> Vec<PathBuf>;
+    fn get_trusted_user_ca_keys(&self) -> Vec<PublicKey>;
+    fn get_max_auth_tries(&self) -> u32;
+    //None when unlimited
+    fn get_login_grace_time(&self) -> Option<Duration>;
+    fn get_password_authentication(&self) -> bool;
+    fn get_pubkey_authentication(&self) -> bool;
+    fn get_kbd_interactive_authentication(&self) -> bool;
+    //what keyboard-interactive asks for
+    fn get_kbd_interactive_challenge(&self) -> ChallengeKind;
+    fn get_totp_secrets_file(&self) -> Option<PathBuf>;
+    fn get_hostbased_authentication(&self) -> bool;
+    fn get_ignore_rhosts(&self) -> bool;
+    fn get_ignore_user_known_hosts(&self) -> bool;
+    fn get_hostbased_uses_name_from_packet_only(&self) -> bool;
+    fn get_use_dns(&self) -> bool;
+    //AuthorizedKeysFile patterns, tokens are not expanded yet
+    fn get_authorized_keys_files(&self) -> Vec<String>;
+    //AuthenticationMethods, empty when any single method is enough
+    fn get_authentication_methods(&self) -> Vec<Vec<String>>;
+    //file sent to the client before authentication
+    fn get_banner_file(&self) -> Option<PathBuf>;
+    //AcceptEnv patterns of variables clients may set
+    fn get_accept_env(&self) -> Vec<String>;
+    //PermitUserEnvironment patterns of variables from authorized_keys environment= options
+    fn get_permit_user_environment(&self) -> Vec<String>;
+    //Subsystem names and their commands
+    fn get_subsystems(&self) -> Vec<(String, String)>;
+    //AllowTcpForwarding for direct-tcpip channels
+    fn get_allow_local_forwarding(&self) -> bool;
+    //PermitOpen "host:port" destinations, None when any is allowed
+    fn get_permit_open(&self) -> Option<Vec<String>>;
+    //AllowTcpForwarding for tcpip-forward requests
+    fn get_allow_remote_forwarding(&self) -> bool;
+    fn get_gateway_ports(&self) -> GatewayPorts;
+}
 
 pub trait AvailableAlgorithms {
     fn get_available_kex_algorithms(&self) -> Vec<String>;
@@ -163,6 +258,14 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn log_level_from_name_works() {
+        assert_eq!(LogLevel::from_name("quiet"), Some(LogLevel::Quiet));
+        assert_eq!(LogLevel::from_name("DEBUG"), Some(LogLevel::Debug1));
+        assert!(LogLevel::from_name("ERROR").unwrap() < LogLevel::Info);
+        assert_eq!(LogLevel::from_name("loud"), None);
+    }
+
     #[test]
     fn vector_intersection_works() {
         assert_eq!(vector_intersection(vec![1, 2, 3, 4, 5], vec![13, 11, 7, 5, 3]),
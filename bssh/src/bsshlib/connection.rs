@@ -0,0 +1,1016 @@
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use errors;
+use io_helpers;
+use msgs;
+use numbers;
+use transport::PayloadStream;
+
+//RFC 4254, The Secure Shell (SSH) Connection Protocol
+
+//same as OpenSSH
+pub const DEFAULT_WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+pub const DEFAULT_MAX_PACKET_SIZE: u32 = 32 * 1024;
+
+//data queued on a channel before readers feeding it are paused
+pub const MAX_PENDING_DATA: usize = 64 * 1024;
+const READ_BUFFER_LENGTH: usize = 16 * 1024;
+
+fn unexpected_message() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)
+}
+
+fn unknown_channel() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNKNOWN_CHANNEL)
+}
+
+fn not_open() -> Error {
+    Error::new(ErrorKind::NotConnected, errors::BSSH_ERR_CHANNEL_NOT_OPEN)
+}
+
+pub fn is_connection_message(number: u8) -> bool {
+    (numbers::SSH_MSG_GLOBAL_REQUEST..=numbers::SSH_MSG_CHANNEL_FAILURE).contains(&number)
+}
+
+fn read_rest(stream: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    let mut rest: Vec<u8> = Vec::new();
+    stream.read_to_end(&mut rest)?;
+    Ok(rest)
+}
+
+//RFC 4254 page 4
+pub struct GlobalRequest {
+    pub name: String,
+    pub want_reply: bool,
+    //request specific data
+    pub data: Vec<u8>,
+}
+
+pub fn write_global_request_message(stream: &mut dyn Write, request: &GlobalRequest) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_GLOBAL_REQUEST])?;
+    io_helpers::write_string(stream, &request.name.as_bytes().to_vec())?;
+    io_helpers::write_boolean(stream, request.want_reply)?;
+    stream.write_all(&request.data)?;
+    Ok(())
+}
+
+pub fn read_global_request_message(stream: &mut dyn Read) -> Result<GlobalRequest, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_GLOBAL_REQUEST)?;
+    let name = io_helpers::read_utf8_string(stream)?;
+    let want_reply = io_helpers::read_boolean(stream)?;
+    let data = read_rest(stream)?;
+    Ok(GlobalRequest { name, want_reply, data })
+}
+
+//RFC 4254 page 5
+pub struct ChannelOpen {
+    pub channel_type: String,
+    pub sender_channel: u32,
+    pub initial_window_size: u32,
+    pub maximum_packet_size: u32,
+    //channel type specific data
+    pub type_data: Vec<u8>,
+}
+
+pub fn write_channel_open_message(stream: &mut dyn Write, open: &ChannelOpen) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_CHANNEL_OPEN])?;
+    io_helpers::write_string(stream, &open.channel_type.as_bytes().to_vec())?;
+    stream.write_u32::<BigEndian>(open.sender_channel)?;
+    stream.write_u32::<BigEndian>(open.initial_window_size)?;
+    stream.write_u32::<BigEndian>(open.maximum_packet_size)?;
+    stream.write_all(&open.type_data)?;
+    Ok(())
+}
+
+pub fn read_channel_open_message(stream: &mut dyn Read) -> Result<ChannelOpen, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_CHANNEL_OPEN)?;
+    let channel_type = io_helpers::read_utf8_string(stream)?;
+    let sender_channel = stream.read_u32::<BigEndian>()?;
+    let initial_window_size = stream.read_u32::<BigEndian>()?;
+    let maximum_packet_size = stream.read_u32::<BigEndian>()?;
+    let type_data = read_rest(stream)?;
+    Ok(ChannelOpen {
+        channel_type,
+        sender_channel,
+        initial_window_size,
+        maximum_packet_size,
+        type_data,
+    })
+}
+
+//RFC 4254 page 6
+pub struct ChannelOpenConfirmation {
+    pub recipient_channel: u32,
+    pub sender_channel: u32,
+    pub initial_window_size: u32,
+    pub maximum_packet_size: u32,
+    pub type_data: Vec<u8>,
+}
+
+pub fn write_channel_open_confirmation_message(stream: &mut dyn Write, confirmation: &ChannelOpenConfirmation) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_CHANNEL_OPEN_CONFIRMATION])?;
+    stream.write_u32::<BigEndian>(confirmation.recipient_channel)?;
+    stream.write_u32::<BigEndian>(confirmation.sender_channel)?;
+    stream.write_u32::<BigEndian>(confirmation.initial_window_size)?;
+    stream.write_u32::<BigEndian>(confirmation.maximum_packet_size)?;
+    stream.write_all(&confirmation.type_data)?;
+    Ok(())
+}
+
+pub fn read_channel_open_confirmation_message(stream: &mut dyn Read) -> Result<ChannelOpenConfirmation, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_CHANNEL_OPEN_CONFIRMATION)?;
+    let recipient_channel = stream.read_u32::<BigEndian>()?;
+    let sender_channel = stream.read_u32::<BigEndian>()?;
+    let initial_window_size = stream.read_u32::<BigEndian>()?;
+    let maximum_packet_size = stream.read_u32::<BigEndian>()?;
+    let type_data = read_rest(stream)?;
+    Ok(ChannelOpenConfirmation {
+        recipient_channel,
+        sender_channel,
+        initial_window_size,
+        maximum_packet_size,
+        type_data,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelOpenFailure {
+    pub recipient_channel: u32,
+    pub reason_code: u32,
+    pub description: String,
+}
+
+pub fn write_channel_open_failure_message(stream: &mut dyn Write, failure: &ChannelOpenFailure) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_CHANNEL_OPEN_FAILURE])?;
+    stream.write_u32::<BigEndian>(failure.recipient_channel)?;
+    stream.write_u32::<BigEndian>(failure.reason_code)?;
+    io_helpers::write_string(stream, &failure.description.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &Vec::new())?;
+    Ok(())
+}
+
+pub fn read_channel_open_failure_message(stream: &mut dyn Read) -> Result<ChannelOpenFailure, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_CHANNEL_OPEN_FAILURE)?;
+    let recipient_channel = stream.read_u32::<BigEndian>()?;
+    let reason_code = stream.read_u32::<BigEndian>()?;
+    //description is not required to be utf8 by every implementation
+    let description = String::from_utf8_lossy(&io_helpers::read_string(stream, None)?).into_owned();
+    let _language_tag = io_helpers::read_string(stream, None)?;
+    Ok(ChannelOpenFailure {
+        recipient_channel,
+        reason_code,
+        description,
+    })
+}
+
+//RFC 4254 page 7
+pub fn write_channel_window_adjust_message(stream: &mut dyn Write, recipient_channel: u32, bytes_to_add: u32) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_CHANNEL_WINDOW_ADJUST])?;
+    stream.write_u32::<BigEndian>(recipient_channel)?;
+    stream.write_u32::<BigEndian>(bytes_to_add)?;
+    Ok(())
+}
+
+//data_type is None for SSH_MSG_CHANNEL_DATA, data type code of SSH_MSG_CHANNEL_EXTENDED_DATA otherwise
+pub fn write_channel_data_message(stream: &mut dyn Write, recipient_channel: u32, data_type: Option<u32>, data: &[u8]) -> Result<(), Error> {
+    match data_type {
+        None => {
+            stream.write_all(&[numbers::SSH_MSG_CHANNEL_DATA])?;
+            stream.write_u32::<BigEndian>(recipient_channel)?;
+        }
+        Some(data_type) => {
+            stream.write_all(&[numbers::SSH_MSG_CHANNEL_EXTENDED_DATA])?;
+            stream.write_u32::<BigEndian>(recipient_channel)?;
+            stream.write_u32::<BigEndian>(data_type)?;
+        }
+    }
+    io_helpers::write_string(stream, &data.to_vec())?;
+    Ok(())
+}
+
+//SSH_MSG_CHANNEL_EOF, SSH_MSG_CHANNEL_CLOSE, SSH_MSG_CHANNEL_SUCCESS and SSH_MSG_CHANNEL_FAILURE
+//carry the recipient channel only
+pub fn write_channel_message(stream: &mut dyn Write, number: u8, recipient_channel: u32) -> Result<(), Error> {
+    stream.write_all(&[number])?;
+    stream.write_u32::<BigEndian>(recipient_channel)?;
+    Ok(())
+}
+
+//RFC 4254 page 10
+pub struct ChannelRequest {
+    pub recipient_channel: u32,
+    pub request_type: String,
+    pub want_reply: bool,
+    //request type specific data
+    pub data: Vec<u8>,
+}
+
+pub fn write_channel_request_message(stream: &mut dyn Write, request: &ChannelRequest) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_CHANNEL_REQUEST])?;
+    stream.write_u32::<BigEndian>(request.recipient_channel)?;
+    io_helpers::write_string(stream, &request.request_type.as_bytes().to_vec())?;
+    io_helpers::write_boolean(stream, request.want_reply)?;
+    stream.write_all(&request.data)?;
+    Ok(())
+}
+
+pub fn read_channel_request_message(stream: &mut dyn Read) -> Result<ChannelRequest, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_CHANNEL_REQUEST)?;
+    let recipient_channel = stream.read_u32::<BigEndian>()?;
+    let request_type = io_helpers::read_utf8_string(stream)?;
+    let want_reply = io_helpers::read_boolean(stream)?;
+    let data = read_rest(stream)?;
+    Ok(ChannelRequest {
+        recipient_channel,
+        request_type,
+        want_reply,
+        data,
+    })
+}
+
+fn get_payload<F: FnOnce(&mut Vec<u8>) -> Result<(), Error>>(write: F) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    //writing to Vec can not fail
+    write(&mut payload).unwrap();
+    payload
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelState {
+    //we sent SSH_MSG_CHANNEL_OPEN
+    Opening,
+    //the other side sent SSH_MSG_CHANNEL_OPEN, waiting for accept or reject
+    Requested,
+    Open,
+}
+
+pub struct Channel {
+    pub local_id: u32,
+    pub remote_id: u32,
+    pub channel_type: String,
+    pub state: ChannelState,
+    //what the other side may still send
+    pub local_window: u32,
+    pub local_window_size: u32,
+    pub local_max_packet_size: u32,
+    //received data the application has handled, not yet returned to the window
+    consumed: u32,
+    //what we may still send
+    pub remote_window: u32,
+    pub remote_max_packet_size: u32,
+    //data waiting for window space, with data type code for extended data
+    pending: VecDeque<(Option<u32>, Vec<u8>)>,
+    eof_queued: bool,
+    close_queued: bool,
+    pub sent_eof: bool,
+    pub received_eof: bool,
+    pub sent_close: bool,
+    pub received_close: bool,
+    //our requests waiting for SSH_MSG_CHANNEL_SUCCESS or SSH_MSG_CHANNEL_FAILURE
+    pub requests_awaiting_reply: u32,
+}
+
+impl Channel {
+    pub fn get_pending_length(&self) -> usize {
+        self.pending.iter().map(|(_, data)| data.len()).sum()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChannelEvent {
+    //the application has to call accept or reject
+    OpenRequested { channel: u32, channel_type: String, type_data: Vec<u8> },
+    Opened { channel: u32, type_data: Vec<u8> },
+    OpenFailed { channel: u32, reason_code: u32, description: String },
+    //the application has to call consume once the data is handled
+    Data { channel: u32, data: Vec<u8> },
+    ExtendedData { channel: u32, data_type: u32, data: Vec<u8> },
+    WindowAdjusted { channel: u32 },
+    Eof { channel: u32 },
+    //both sides sent SSH_MSG_CHANNEL_CLOSE, the channel id is free again
+    Closed { channel: u32 },
+    //the application has to call reply_request if want_reply is set
+    Request { channel: u32, request_type: String, want_reply: bool, data: Vec<u8> },
+    RequestReply { channel: u32, success: bool },
+    //the application has to call reply_global_request if want_reply is set
+    GlobalRequest { name: String, want_reply: bool, data: Vec<u8> },
+    GlobalRequestReply { success: bool, data: Vec<u8> },
+}
+
+//Channel multiplexing without IO, incoming payloads are turned into events and
+//everything to send is queued until take_outgoing is called.
+pub struct ChannelManager {
+    pub channels: BTreeMap<u32, Channel>,
+    pub window_size: u32,
+    pub max_packet_size: u32,
+    next_id: u32,
+    outgoing: Vec<Vec<u8>>,
+    pub global_requests_awaiting_reply: u32,
+}
+
+impl Default for ChannelManager {
+    fn default() -> ChannelManager {
+        ChannelManager::new()
+    }
+}
+
+impl ChannelManager {
+    pub fn new() -> ChannelManager {
+        ChannelManager {
+            channels: BTreeMap::new(),
+            window_size: DEFAULT_WINDOW_SIZE,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            next_id: 0,
+            outgoing: Vec::new(),
+            global_requests_awaiting_reply: 0,
+        }
+    }
+
+    pub fn get_channel(&self, id: u32) -> Option<&Channel> {
+        self.channels.get(&id)
+    }
+
+    pub fn take_outgoing(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    pub fn send_outgoing(&mut self, stream: &mut dyn PayloadStream) -> Result<(), Error> {
+        for payload in self.take_outgoing() {
+            stream.send_payload(&payload)?;
+        }
+        Ok(())
+    }
+
+    //lowest id not in use
+    fn allocate_id(&mut self) -> u32 {
+        while self.channels.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn new_channel(&mut self, channel_type: &str, state: ChannelState, remote_id: u32) -> u32 {
+        let local_id = self.allocate_id();
+        self.channels.insert(local_id, Channel {
+            local_id,
+            remote_id,
+            channel_type: channel_type.to_string(),
+            state,
+            local_window: self.window_size,
+            local_window_size: self.window_size,
+            local_max_packet_size: self.max_packet_size,
+            consumed: 0,
+            remote_window: 0,
+            remote_max_packet_size: 0,
+            pending: VecDeque::new(),
+            eof_queued: false,
+            close_queued: false,
+            sent_eof: false,
+            received_eof: false,
+            sent_close: false,
+            received_close: false,
+            requests_awaiting_reply: 0,
+        });
+        local_id
+    }
+
+    fn get_open_channel(&mut self, id: u32) -> Result<&mut Channel, Error> {
+        match self.channels.get_mut(&id) {
+            Some(channel) if channel.state == ChannelState::Open && !channel.sent_close && !channel.close_queued => Ok(channel),
+            Some(_) => Err(not_open()),
+            None => Err(unknown_channel()),
+        }
+    }
+
+    //returns local id of the channel, Opened or OpenFailed event follows
+    pub fn open(&mut self, channel_type: &str, type_data: &[u8]) -> u32 {
+        let local_id = self.new_channel(channel_type, ChannelState::Opening, 0);
+        let open = ChannelOpen {
+            channel_type: channel_type.to_string(),
+            sender_channel: local_id,
+            initial_window_size: self.window_size,
+            maximum_packet_size: self.max_packet_size,
+            type_data: type_data.to_vec(),
+        };
+        self.outgoing.push(get_payload(|p| write_channel_open_message(p, &open)));
+        local_id
+    }
+
+    pub fn accept(&mut self, id: u32, type_data: &[u8]) -> Result<(), Error> {
+        let payload = match self.channels.get_mut(&id) {
+            Some(channel) if channel.state == ChannelState::Requested => {
+                channel.state = ChannelState::Open;
+                let confirmation = ChannelOpenConfirmation {
+                    recipient_channel: channel.remote_id,
+                    sender_channel: channel.local_id,
+                    initial_window_size: channel.local_window_size,
+                    maximum_packet_size: channel.local_max_packet_size,
+                    type_data: type_data.to_vec(),
+                };
+                get_payload(|p| write_channel_open_confirmation_message(p, &confirmation))
+            }
+            Some(_) => return Err(unexpected_message()),
+            None => return Err(unknown_channel()),
+        };
+        self.outgoing.push(payload);
+        Ok(())
+    }
+
+    pub fn reject(&mut self, id: u32, reason_code: u32, description: &str) -> Result<(), Error> {
+        match self.channels.get(&id) {
+            Some(channel) if channel.state == ChannelState::Requested => {}
+            Some(_) => return Err(unexpected_message()),
+            None => return Err(unknown_channel()),
+        }
+        let channel = self.channels.remove(&id).unwrap();
+        let failure = ChannelOpenFailure {
+            recipient_channel: channel.remote_id,
+            reason_code,
+            description: description.to_string(),
+        };
+        self.outgoing.push(get_payload(|p| write_channel_open_failure_message(p, &failure)));
+        Ok(())
+    }
+
+    //queued data is sent as the window allows, in packets of at most the maximum packet size
+    pub fn send_data(&mut self, id: u32, data: &[u8]) -> Result<(), Error> {
+        self.queue_data(id, None, data)
+    }
+
+    pub fn send_extended_data(&mut self, id: u32, data_type: u32, data: &[u8]) -> Result<(), Error> {
+        self.queue_data(id, Some(data_type), data)
+    }
+
+    fn queue_data(&mut self, id: u32, data_type: Option<u32>, data: &[u8]) -> Result<(), Error> {
+        {
+            let channel = self.get_open_channel(id)?;
+            if channel.eof_queued {
+                return Err(Error::new(ErrorKind::BrokenPipe, errors::BSSH_ERR_CHANNEL_DATA_AFTER_EOF));
+            }
+            if !data.is_empty() {
+                channel.pending.push_back((data_type, data.to_vec()));
+            }
+        }
+        self.flush(id);
+        Ok(())
+    }
+
+    //sent after the queued data
+    pub fn send_eof(&mut self, id: u32) -> Result<(), Error> {
+        self.get_open_channel(id)?.eof_queued = true;
+        self.flush(id);
+        Ok(())
+    }
+
+    //sent after the queued data and EOF, Closed event follows once the other side closes too
+    pub fn close(&mut self, id: u32) -> Result<(), Error> {
+        match self.channels.get_mut(&id) {
+            Some(channel) if channel.state == ChannelState::Open => channel.close_queued = true,
+            Some(_) => return Err(not_open()),
+            None => return Err(unknown_channel()),
+        }
+        self.flush(id);
+        Ok(())
+    }
+
+    pub fn send_request(&mut self, id: u32, request_type: &str, want_reply: bool, data: &[u8]) -> Result<(), Error> {
+        let payload = {
+            let channel = self.get_open_channel(id)?;
+            if want_reply {
+                channel.requests_awaiting_reply += 1;
+            }
+            let request = ChannelRequest {
+                recipient_channel: channel.remote_id,
+                request_type: request_type.to_string(),
+                want_reply,
+                data: data.to_vec(),
+            };
+            get_payload(|p| write_channel_request_message(p, &request))
+        };
+        self.outgoing.push(payload);
+        Ok(())
+    }
+
+    //replies are sent in the order the requests came
+    pub fn reply_request(&mut self, id: u32, success: bool) -> Result<(), Error> {
+        let remote_id = match self.channels.get(&id) {
+            //a closing channel needs no reply
+            Some(channel) if channel.sent_close || channel.close_queued => return Ok(()),
+            Some(channel) => channel.remote_id,
+            None => return Err(unknown_channel()),
+        };
+        let number = if success { numbers::SSH_MSG_CHANNEL_SUCCESS } else { numbers::SSH_MSG_CHANNEL_FAILURE };
+        self.outgoing.push(get_payload(|p| write_channel_message(p, number, remote_id)));
+        Ok(())
+    }
+
+    pub fn send_global_request(&mut self, name: &str, want_reply: bool, data: &[u8]) {
+        if want_reply {
+            self.global_requests_awaiting_reply += 1;
+        }
+        let request = GlobalRequest {
+            name: name.to_string(),
+            want_reply,
+            data: data.to_vec(),
+        };
+        self.outgoing.push(get_payload(|p| write_global_request_message(p, &request)));
+    }
+
+    pub fn reply_global_request(&mut self, success: bool, data: &[u8]) {
+        let mut payload = vec![if success { numbers::SSH_MSG_REQUEST_SUCCESS } else { numbers::SSH_MSG_REQUEST_FAILURE }];
+        if success {
+            payload.extend_from_slice(data);
+        }
+        self.outgoing.push(payload);
+    }
+
+    //returns the received data to the window, WINDOW_ADJUST is sent once half of the window is used
+    pub fn consume(&mut self, id: u32, length: usize) -> Result<(), Error> {
+        let payload = match self.channels.get_mut(&id) {
+            Some(channel) => {
+                channel.consumed = channel.consumed.saturating_add(length as u32);
+                if channel.sent_close || channel.received_eof || channel.consumed < channel.local_window_size / 2 {
+                    return Ok(());
+                }
+                let bytes_to_add = cmp::min(channel.consumed, channel.local_window_size - channel.local_window);
+                channel.consumed = 0;
+                channel.local_window += bytes_to_add;
+                get_payload(|p| write_channel_window_adjust_message(p, channel.remote_id, bytes_to_add))
+            }
+            None => return Err(unknown_channel()),
+        };
+        self.outgoing.push(payload);
+        Ok(())
+    }
+
+    //sends what the window allows, then EOF and CLOSE if queued and nothing is pending
+    fn flush(&mut self, id: u32) {
+        let outgoing = &mut self.outgoing;
+        let channel = match self.channels.get_mut(&id) {
+            Some(channel) if channel.state == ChannelState::Open && !channel.sent_close => channel,
+            _ => return,
+        };
+        while channel.remote_window > 0 {
+            let (data_type, mut data) = match channel.pending.pop_front() {
+                Some(pending) => pending,
+                None => break,
+            };
+            let length = cmp::min(data.len(), cmp::min(channel.remote_window, channel.remote_max_packet_size) as usize);
+            if length == 0 {
+                channel.pending.push_front((data_type, data));
+                break;
+            }
+            let rest = data.split_off(length);
+            if !rest.is_empty() {
+                channel.pending.push_front((data_type, rest));
+            }
+            channel.remote_window -= length as u32;
+            let remote_id = channel.remote_id;
+            outgoing.push(get_payload(|p| write_channel_data_message(p, remote_id, data_type, &data)));
+        }
+        if !channel.pending.is_empty() {
+            return;
+        }
+        if channel.eof_queued && !channel.sent_eof {
+            channel.sent_eof = true;
+            outgoing.push(get_payload(|p| write_channel_message(p, numbers::SSH_MSG_CHANNEL_EOF, channel.remote_id)));
+        }
+        if channel.close_queued {
+            channel.sent_close = true;
+            outgoing.push(get_payload(|p| write_channel_message(p, numbers::SSH_MSG_CHANNEL_CLOSE, channel.remote_id)));
+        }
+    }
+
+    //removes the channel once CLOSE went both ways
+    fn check_closed(&mut self, id: u32) -> Option<ChannelEvent> {
+        let closed = self.channels.get(&id).map(|c| c.sent_close && c.received_close).unwrap_or(false);
+        if closed {
+            self.channels.remove(&id);
+            return Some(ChannelEvent::Closed { channel: id });
+        }
+        None
+    }
+
+    fn read_recipient(payload: &[u8]) -> Result<u32, Error> {
+        if payload.len() < 5 {
+            return Err(unexpected_message());
+        }
+        (&payload[1..5]).read_u32::<BigEndian>()
+    }
+
+    //channel the other side may still talk to, messages after our CLOSE are ignored
+    fn get_receiving_channel(&mut self, id: u32) -> Result<Option<&mut Channel>, Error> {
+        match self.channels.get_mut(&id) {
+            Some(ref channel) if channel.state != ChannelState::Open || channel.received_close => Err(unexpected_message()),
+            Some(channel) => Ok(if channel.sent_close { None } else { Some(channel) }),
+            None => Err(unknown_channel()),
+        }
+    }
+
+    //handles a connection protocol payload, an error means the connection should be disconnected
+    pub fn handle_payload(&mut self, payload: &[u8]) -> Result<Option<ChannelEvent>, Error> {
+        let number = msgs::get_message_number(payload)?;
+        match number {
+            numbers::SSH_MSG_GLOBAL_REQUEST => {
+                let request = read_global_request_message(&mut &payload[..])?;
+                Ok(Some(ChannelEvent::GlobalRequest { name: request.name, want_reply: request.want_reply, data: request.data }))
+            }
+            numbers::SSH_MSG_REQUEST_SUCCESS | numbers::SSH_MSG_REQUEST_FAILURE => {
+                if self.global_requests_awaiting_reply == 0 {
+                    return Err(unexpected_message());
+                }
+                self.global_requests_awaiting_reply -= 1;
+                Ok(Some(ChannelEvent::GlobalRequestReply { success: number == numbers::SSH_MSG_REQUEST_SUCCESS, data: payload[1..].to_vec() }))
+            }
+            numbers::SSH_MSG_CHANNEL_OPEN => {
+                let open = read_channel_open_message(&mut &payload[..])?;
+                let id = self.new_channel(&open.channel_type, ChannelState::Requested, open.sender_channel);
+                let channel = self.channels.get_mut(&id).unwrap();
+                channel.remote_window = open.initial_window_size;
+                channel.remote_max_packet_size = open.maximum_packet_size;
+                Ok(Some(ChannelEvent::OpenRequested { channel: id, channel_type: open.channel_type, type_data: open.type_data }))
+            }
+            numbers::SSH_MSG_CHANNEL_OPEN_CONFIRMATION => {
+                let confirmation = read_channel_open_confirmation_message(&mut &payload[..])?;
+                let id = confirmation.recipient_channel;
+                match self.channels.get_mut(&id) {
+                    Some(ref mut channel) if channel.state == ChannelState::Opening => {
+                        channel.state = ChannelState::Open;
+                        channel.remote_id = confirmation.sender_channel;
+                        channel.remote_window = confirmation.initial_window_size;
+                        channel.remote_max_packet_size = confirmation.maximum_packet_size;
+                    }
+                    Some(_) => return Err(unexpected_message()),
+                    None => return Err(unknown_channel()),
+                }
+                //anything queued before the confirmation
+                self.flush(id);
+                Ok(Some(ChannelEvent::Opened { channel: id, type_data: confirmation.type_data }))
+            }
+            numbers::SSH_MSG_CHANNEL_OPEN_FAILURE => {
+                let failure = read_channel_open_failure_message(&mut &payload[..])?;
+                let id = failure.recipient_channel;
+                match self.channels.get(&id) {
+                    Some(channel) if channel.state == ChannelState::Opening => {}
+                    Some(_) => return Err(unexpected_message()),
+                    None => return Err(unknown_channel()),
+                }
+                self.channels.remove(&id);
+                Ok(Some(ChannelEvent::OpenFailed { channel: id, reason_code: failure.reason_code, description: failure.description }))
+            }
+            numbers::SSH_MSG_CHANNEL_WINDOW_ADJUST => {
+                let id = ChannelManager::read_recipient(payload)?;
+                let bytes_to_add = (&payload[5..]).read_u32::<BigEndian>()?;
+                match self.get_receiving_channel(id)? {
+                    //window can not grow over 2^32 - 1 bytes
+                    Some(channel) => channel.remote_window = channel.remote_window.saturating_add(bytes_to_add),
+                    None => return Ok(None),
+                }
+                self.flush(id);
+                Ok(Some(ChannelEvent::WindowAdjusted { channel: id }))
+            }
+            numbers::SSH_MSG_CHANNEL_DATA | numbers::SSH_MSG_CHANNEL_EXTENDED_DATA => {
+                let id = ChannelManager::read_recipient(payload)?;
+                let mut stream = &payload[5..];
+                let data_type = if number == numbers::SSH_MSG_CHANNEL_EXTENDED_DATA {
+                    Some(stream.read_u32::<BigEndian>()?)
+                } else {
+                    None
+                };
+                let data = io_helpers::read_string(&mut stream, None)?;
+                let channel = match self.get_receiving_channel(id)? {
+                    Some(channel) => channel,
+                    None => return Ok(None),
+                };
+                if channel.received_eof {
+                    return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_CHANNEL_DATA_AFTER_EOF));
+                }
+                if data.len() > channel.local_window as usize || data.len() > channel.local_max_packet_size as usize {
+                    return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_CHANNEL_WINDOW_EXCEEDED));
+                }
+                channel.local_window -= data.len() as u32;
+                Ok(Some(match data_type {
+                    None => ChannelEvent::Data { channel: id, data },
+                    Some(data_type) => ChannelEvent::ExtendedData { channel: id, data_type, data },
+                }))
+            }
+            numbers::SSH_MSG_CHANNEL_EOF => {
+                let id = ChannelManager::read_recipient(payload)?;
+                match self.get_receiving_channel(id)? {
+                    Some(channel) => channel.received_eof = true,
+                    None => return Ok(None),
+                }
+                Ok(Some(ChannelEvent::Eof { channel: id }))
+            }
+            numbers::SSH_MSG_CHANNEL_CLOSE => {
+                let id = ChannelManager::read_recipient(payload)?;
+                let reply = match self.channels.get_mut(&id) {
+                    Some(ref channel) if channel.state != ChannelState::Open || channel.received_close => return Err(unexpected_message()),
+                    Some(channel) => {
+                        channel.received_close = true;
+                        //data still waiting for window will never be sent
+                        channel.pending.clear();
+                        !channel.sent_close
+                    }
+                    None => return Err(unknown_channel()),
+                };
+                if reply {
+                    let channel = self.channels.get_mut(&id).unwrap();
+                    channel.sent_close = true;
+                    let remote_id = channel.remote_id;
+                    self.outgoing.push(get_payload(|p| write_channel_message(p, numbers::SSH_MSG_CHANNEL_CLOSE, remote_id)));
+                }
+                Ok(self.check_closed(id))
+            }
+            numbers::SSH_MSG_CHANNEL_REQUEST => {
+                let request = read_channel_request_message(&mut &payload[..])?;
+                let id = request.recipient_channel;
+                if self.get_receiving_channel(id)?.is_none() {
+                    return Ok(None);
+                }
+                Ok(Some(ChannelEvent::Request { channel: id, request_type: request.request_type, want_reply: request.want_reply, data: request.data }))
+            }
+            numbers::SSH_MSG_CHANNEL_SUCCESS | numbers::SSH_MSG_CHANNEL_FAILURE => {
+                let id = ChannelManager::read_recipient(payload)?;
+                match self.channels.get_mut(&id) {
+                    Some(ref mut channel) if channel.requests_awaiting_reply > 0 => channel.requests_awaiting_reply -= 1,
+                    Some(_) => return Err(unexpected_message()),
+                    None => return Err(unknown_channel()),
+                }
+                Ok(Some(ChannelEvent::RequestReply { channel: id, success: number == numbers::SSH_MSG_CHANNEL_SUCCESS }))
+            }
+            _ => Err(unexpected_message()),
+        }
+    }
+}
+
+//Local sources (process output, sockets, stdin) are read in threads. After each chunk the
+//reader waits until the connection lets it continue, so a full window stops the reading.
+pub struct ChannelInput {
+    pub channel: u32,
+    //None for SSH_MSG_CHANNEL_DATA, data type code of extended data otherwise
+    pub data_type: Option<u32>,
+    //None at end of file or read error
+    pub data: Option<Vec<u8>>,
+    pub resume: Sender<()>,
+}
+
+pub fn spawn_channel_reader<R, E, F>(mut source: R, channel: u32, data_type: Option<u32>, events: Sender<E>, make_event: F) -> thread::JoinHandle<()>
+    where R: Read + Send + 'static, E: Send + 'static, F: Fn(ChannelInput) -> E + Send + 'static
+{
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; READ_BUFFER_LENGTH];
+        loop {
+            let data = match source.read(&mut buffer) {
+                Ok(0) | Err(_) => None,
+                Ok(n) => Some(buffer[..n].to_vec()),
+            };
+            let end = data.is_none();
+            let (resume, resumed) = mpsc::channel();
+            if events.send(make_event(ChannelInput { channel, data_type, data, resume })).is_err() || end {
+                return;
+            }
+            //the channel is gone when the sender is dropped
+            if resumed.recv().is_err() {
+                return;
+            }
+        }
+    })
+}
+
+//readers waiting for window space
+#[derive(Default)]
+pub struct ReaderThrottle {
+    paused: Vec<(u32, Sender<()>)>,
+}
+
+impl ReaderThrottle {
+    //queues the data and lets the reader continue unless too much is pending
+    pub fn send_input(&mut self, manager: &mut ChannelManager, input: ChannelInput) -> Result<(), Error> {
+        let data = match input.data {
+            Some(ref data) => data,
+            None => return Ok(()),
+        };
+        match input.data_type {
+            None => manager.send_data(input.channel, data)?,
+            Some(data_type) => manager.send_extended_data(input.channel, data_type, data)?,
+        }
+        self.paused.push((input.channel, input.resume));
+        self.resume_readers(manager);
+        Ok(())
+    }
+
+    //to be called after window adjustments, readers of closed channels are dropped
+    pub fn resume_readers(&mut self, manager: &ChannelManager) {
+        self.paused.retain(|&(channel, ref resume)| match manager.get_channel(channel) {
+            Some(c) if c.get_pending_length() < MAX_PENDING_DATA => {
+                let _ = resume.send(());
+                false
+            }
+            Some(_) => true,
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //delivers queued payloads both ways until both sides are quiet, events of each side are collected
+    fn exchange(a: &mut ChannelManager, b: &mut ChannelManager) -> (Vec<ChannelEvent>, Vec<ChannelEvent>) {
+        let mut a_events: Vec<ChannelEvent> = Vec::new();
+        let mut b_events: Vec<ChannelEvent> = Vec::new();
+        loop {
+            let to_b = a.take_outgoing();
+            let to_a = b.take_outgoing();
+            if to_a.is_empty() && to_b.is_empty() {
+                return (a_events, b_events);
+            }
+            for payload in to_b {
+                b_events.extend(b.handle_payload(&payload).unwrap());
+            }
+            for payload in to_a {
+                a_events.extend(a.handle_payload(&payload).unwrap());
+            }
+        }
+    }
+
+    fn open_channel(client: &mut ChannelManager, server: &mut ChannelManager) -> (u32, u32) {
+        let client_id = client.open("session", &[]);
+        let (_, server_events) = exchange(client, server);
+        let server_id = match server_events[0] {
+            ChannelEvent::OpenRequested { channel, ref channel_type, .. } if channel_type == "session" => channel,
+            ref event => panic!("unexpected {:?}", event),
+        };
+        server.accept(server_id, &[]).unwrap();
+        let (client_events, _) = exchange(client, server);
+        assert_eq!(client_events, vec![ChannelEvent::Opened { channel: client_id, type_data: Vec::new() }]);
+        (client_id, server_id)
+    }
+
+    fn received_data(events: &[ChannelEvent]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for event in events {
+            if let ChannelEvent::Data { data: ref chunk, .. } = *event {
+                data.extend_from_slice(chunk);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn channel_opens_transfers_and_closes() {
+        let mut client = ChannelManager::new();
+        let mut server = ChannelManager::new();
+        //ids on both sides are independent
+        server.open("x11", &[]);
+        let (client_id, server_id) = open_channel(&mut client, &mut server);
+        assert_eq!((client_id, server_id), (0, 1));
+
+        client.send_data(client_id, b"hello").unwrap();
+        let (_, server_events) = exchange(&mut client, &mut server);
+        assert_eq!(server_events, vec![ChannelEvent::Data { channel: server_id, data: b"hello".to_vec() }]);
+        server.send_extended_data(server_id, numbers::SSH_EXTENDED_DATA_STDERR, b"oops").unwrap();
+        server.send_eof(server_id).unwrap();
+        server.close(server_id).unwrap();
+        let (client_events, server_events) = exchange(&mut client, &mut server);
+        assert_eq!(server_events, vec![ChannelEvent::Closed { channel: server_id }]);
+        assert_eq!(client_events, vec![
+            ChannelEvent::ExtendedData { channel: client_id, data_type: numbers::SSH_EXTENDED_DATA_STDERR, data: b"oops".to_vec() },
+            ChannelEvent::Eof { channel: client_id },
+            ChannelEvent::Closed { channel: client_id },
+        ]);
+        assert!(client.get_channel(client_id).is_none());
+        assert!(client.send_data(client_id, b"late").is_err());
+    }
+
+    #[test]
+    fn open_can_be_rejected() {
+        let mut client = ChannelManager::new();
+        let mut server = ChannelManager::new();
+        let client_id = client.open("direct-tcpip", b"data");
+        let (_, server_events) = exchange(&mut client, &mut server);
+        let server_id = match server_events[0] {
+            ChannelEvent::OpenRequested { channel, ref type_data, .. } if type_data == b"data" => channel,
+            ref event => panic!("unexpected {:?}", event),
+        };
+        server.reject(server_id, numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED, "no").unwrap();
+        let (client_events, _) = exchange(&mut client, &mut server);
+        assert_eq!(client_events, vec![ChannelEvent::OpenFailed {
+            channel: client_id,
+            reason_code: numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+            description: "no".to_string(),
+        }]);
+        assert!(client.channels.is_empty() && server.channels.is_empty());
+    }
+
+    #[test]
+    fn data_waits_for_window() {
+        let mut client = ChannelManager::new();
+        let mut server = ChannelManager::new();
+        server.window_size = 100;
+        server.max_packet_size = 30;
+        let (client_id, server_id) = open_channel(&mut client, &mut server);
+
+        let data: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        client.send_data(client_id, &data).unwrap();
+        client.send_eof(client_id).unwrap();
+        let (_, server_events) = exchange(&mut client, &mut server);
+        //four packets within the maximum size fill the window, EOF waits for the rest
+        assert_eq!(server_events.len(), 4);
+        assert!(server_events.iter().all(|e| match *e { ChannelEvent::Data { ref data, .. } => data.len() <= 30, _ => false }));
+        assert_eq!(client.get_channel(client_id).unwrap().get_pending_length(), 150);
+        assert_eq!(client.get_channel(client_id).unwrap().remote_window, 0);
+
+        //less than half of the window does not adjust it
+        server.consume(server_id, 40).unwrap();
+        assert!(server.take_outgoing().is_empty());
+        server.consume(server_id, 60).unwrap();
+        let (_, more_events) = exchange(&mut client, &mut server);
+        let mut received = received_data(&server_events);
+        received.extend(received_data(&more_events));
+        assert_eq!(received.len(), 200);
+        server.consume(server_id, 100).unwrap();
+        let (_, last_events) = exchange(&mut client, &mut server);
+        received.extend(received_data(&last_events));
+        assert_eq!(received, data);
+        assert_eq!(last_events.last(), Some(&ChannelEvent::Eof { channel: server_id }));
+        assert!(client.send_data(client_id, b"more").is_err());
+    }
+
+    #[test]
+    fn window_violations_are_errors() {
+        let mut client = ChannelManager::new();
+        let mut server = ChannelManager::new();
+        server.window_size = 10;
+        let (client_id, server_id) = open_channel(&mut client, &mut server);
+
+        let mut payload: Vec<u8> = Vec::new();
+        write_channel_data_message(&mut payload, server_id, None, &[0u8; 11]).unwrap();
+        assert!(server.handle_payload(&payload).is_err());
+
+        let mut payload: Vec<u8> = Vec::new();
+        write_channel_data_message(&mut payload, 7, None, b"x").unwrap();
+        assert!(server.handle_payload(&payload).is_err());
+
+        client.send_eof(client_id).unwrap();
+        exchange(&mut client, &mut server);
+        let mut payload: Vec<u8> = Vec::new();
+        write_channel_data_message(&mut payload, server_id, None, b"x").unwrap();
+        assert!(server.handle_payload(&payload).is_err());
+    }
+
+    #[test]
+    fn close_drops_pending_data() {
+        let mut client = ChannelManager::new();
+        let mut server = ChannelManager::new();
+        server.window_size = 4;
+        let (client_id, server_id) = open_channel(&mut client, &mut server);
+
+        client.send_data(client_id, b"too much data").unwrap();
+        server.close(server_id).unwrap();
+        let (client_events, server_events) = exchange(&mut client, &mut server);
+        assert_eq!(client_events, vec![ChannelEvent::Closed { channel: client_id }]);
+        //data sent before the CLOSE arrived is ignored
+        assert_eq!(server_events, vec![ChannelEvent::Closed { channel: server_id }]);
+    }
+
+    #[test]
+    fn requests_are_replied() {
+        let mut client = ChannelManager::new();
+        let mut server = ChannelManager::new();
+        let (client_id, server_id) = open_channel(&mut client, &mut server);
+
+        client.send_request(client_id, "shell", true, &[]).unwrap();
+        client.send_request(client_id, "env", false, b"LANG").unwrap();
+        let (_, server_events) = exchange(&mut client, &mut server);
+        assert_eq!(server_events, vec![
+            ChannelEvent::Request { channel: server_id, request_type: "shell".to_string(), want_reply: true, data: Vec::new() },
+            ChannelEvent::Request { channel: server_id, request_type: "env".to_string(), want_reply: false, data: b"LANG".to_vec() },
+        ]);
+        server.reply_request(server_id, false).unwrap();
+        let (client_events, _) = exchange(&mut client, &mut server);
+        assert_eq!(client_events, vec![ChannelEvent::RequestReply { channel: client_id, success: false }]);
+
+        //reply nobody asked for
+        server.reply_request(server_id, true).unwrap();
+        let payload = server.take_outgoing().remove(0);
+        assert!(client.handle_payload(&payload).is_err());
+
+        client.send_global_request("tcpip-forward", true, b"port");
+        let (_, server_events) = exchange(&mut client, &mut server);
+        assert_eq!(server_events, vec![ChannelEvent::GlobalRequest { name: "tcpip-forward".to_string(), want_reply: true, data: b"port".to_vec() }]);
+        server.reply_global_request(true, &[0, 0, 0, 22]);
+        let (client_events, _) = exchange(&mut client, &mut server);
+        assert_eq!(client_events, vec![ChannelEvent::GlobalRequestReply { success: true, data: vec![0, 0, 0, 22] }]);
+    }
+}
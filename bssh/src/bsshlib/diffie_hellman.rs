@@ -1,4 +1,20 @@
-use num::bigint::{BigUint, ToBigUint};
+use std::io::{Error, ErrorKind};
+use num::bigint::{BigUint, ToBigInt, ToBigUint};
+use rand_core::{OsRng, RngCore};
+use auth_publickey;
+use certificate;
+use certificate::Certificate;
+use errors;
+use io_helpers;
+use keys::{PrivateKey, PublicKey};
+use mac;
+use signature;
+
+pub const DIFFIE_HELLMAN_GROUP14_SHA1: &str = "diffie-hellman-group14-sha1";
+pub const DIFFIE_HELLMAN_GROUP14_SHA256: &str = "diffie-hellman-group14-sha256";
+
+//both Oakley groups use the generator 2
+const GENERATOR: u32 = 2;
 
 //https://www.ietf.org/rfc/rfc2409.txt
 const OAKLEY_GROUP_2_PRIME_STR : &'static str = 
@@ -34,4 +50,239 @@ pub fn get_oakley_group14_prime() -> BigUint {
 	
 	BigUint::parse_bytes(text.into_bytes().as_slice(), 16).unwrap()
 }
-      
\ No newline at end of file
+      
+
+pub fn get_group_prime(algorithm: &str) -> Option<BigUint> {
+	match algorithm {
+		DIFFIE_HELLMAN_GROUP14_SHA1 | DIFFIE_HELLMAN_GROUP14_SHA256 => Some(get_oakley_group14_prime()),
+		_ => None,
+	}
+}
+
+//RFC 4253 page 9, the first algorithm of the client which the server supports as well
+pub fn negotiate_algorithm(client_algorithms: &[String], server_algorithms: &[String]) -> Option<String> {
+	client_algorithms.iter().find(|a| server_algorithms.contains(a)).cloned()
+}
+
+//random exponent 1 < x < p - 1, extra bytes make the modulo bias negligible
+pub fn generate_private_exponent(p: &BigUint) -> BigUint {
+	let mut bytes = vec![0u8; p.to_bytes_be().len() + 8];
+	OsRng.fill_bytes(&mut bytes);
+	let two = 2.to_biguint().unwrap();
+	BigUint::from_bytes_be(&bytes) % (p - 3.to_biguint().unwrap()) + two
+}
+
+//e = g^x mod p on the client side, f = g^y mod p on the server side
+pub fn get_public_value(p: &BigUint, exponent: &BigUint) -> BigUint {
+	GENERATOR.to_biguint().unwrap().modpow(exponent, p)
+}
+
+//RFC 4253 page 21, values not in range [1, p-1] must not be accepted, 1 and p-1 would give a known secret
+pub fn check_public_value(p: &BigUint, value: &BigUint) -> Result<(), Error> {
+	let one = 1.to_biguint().unwrap();
+	if *value <= one || *value >= p - &one {
+		return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_DH_ERR_PUBLIC_VALUE_OUT_OF_RANGE));
+	}
+	Ok(())
+}
+
+//K = f^x mod p on the client side, K = e^y mod p on the server side
+pub fn get_shared_secret(p: &BigUint, public_value: &BigUint, exponent: &BigUint) -> BigUint {
+	public_value.modpow(exponent, p)
+}
+
+//RFC 4253 page 23, everything the exchange hash H is computed over
+pub struct ExchangeHashInput<'a> {
+	//identification strings without CR LF
+	pub client_version: &'a [u8],
+	pub server_version: &'a [u8],
+	//payloads of SSH_MSG_KEXINIT
+	pub client_kex_init: &'a [u8],
+	pub server_kex_init: &'a [u8],
+	pub host_key: &'a [u8],
+	pub e: &'a BigUint,
+	pub f: &'a BigUint,
+	pub shared_secret: &'a BigUint,
+}
+
+pub fn get_exchange_hash(algorithm: &str, input: &ExchangeHashInput) -> Result<Vec<u8>, Error> {
+	let mut data: Vec<u8> = Vec::new();
+	io_helpers::write_string(&mut data, &input.client_version.to_vec())?;
+	io_helpers::write_string(&mut data, &input.server_version.to_vec())?;
+	io_helpers::write_string(&mut data, &input.client_kex_init.to_vec())?;
+	io_helpers::write_string(&mut data, &input.server_kex_init.to_vec())?;
+	io_helpers::write_string(&mut data, &input.host_key.to_vec())?;
+	io_helpers::write_mpint(&mut data, input.e.to_bigint().unwrap())?;
+	io_helpers::write_mpint(&mut data, input.f.to_bigint().unwrap())?;
+	io_helpers::write_mpint(&mut data, input.shared_secret.to_bigint().unwrap())?;
+
+	let hash = get_hash_function(algorithm).ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_NO_MATCHING_KEX_ALGORITHM))?;
+	Ok(hash(&data))
+}
+
+pub type HashFunction = fn(&[u8]) -> Vec<u8>;
+
+//the hash of the kex algorithm is used for H and for deriving the keys
+pub fn get_hash_function(algorithm: &str) -> Option<HashFunction> {
+	match algorithm {
+		DIFFIE_HELLMAN_GROUP14_SHA1 => Some(mac::sha1),
+		DIFFIE_HELLMAN_GROUP14_SHA256 => Some(mac::sha256),
+		_ => None,
+	}
+}
+
+//a host key of the server and its certificate, if HostCertificate names one for it
+pub struct HostKey {
+	pub key: PrivateKey,
+	pub certificate: Option<Certificate>,
+}
+
+impl HostKey {
+	pub fn new(key: PrivateKey) -> HostKey {
+		HostKey { key, certificate: None }
+	}
+
+	//the certificate is preferred, as by OpenSSH
+	pub fn get_algorithms(&self) -> Vec<String> {
+		let plain = signature::get_signature_algorithms(&self.key.get_public_key());
+		let certified = plain.iter().filter(|_| self.certificate.is_some()).map(|a| format!("{}{}", a, certificate::CERT_SUFFIX));
+		certified.chain(plain.iter().map(|a| a.to_string())).collect()
+	}
+
+	//the certificate is sent instead of the key for the *-cert-v01@openssh.com algorithms
+	pub fn get_blob(&self, host_key_algorithm: &str) -> Vec<u8> {
+		match self.certificate {
+			Some(ref certificate) if certificate::is_certificate_algorithm(host_key_algorithm) => certificate.blob.clone(),
+			_ => self.key.get_public_key().to_blob(),
+		}
+	}
+
+	pub fn sign(&self, host_key_algorithm: &str, exchange_hash: &[u8]) -> Result<Vec<u8>, Error> {
+		signature::sign(&self.key, auth_publickey::get_signature_algorithm(host_key_algorithm), exchange_hash)
+	}
+}
+
+//HostCertificate, the certificate goes to the host key it certifies
+pub fn attach_host_certificate(host_keys: &mut [HostKey], certificate: Certificate) -> Result<(), Error> {
+	if !certificate.is_host_certificate() {
+		return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_CERT_WRONG_TYPE));
+	}
+	let blob = certificate.key.to_blob();
+	let host_key = host_keys.iter_mut().find(|k| k.key.get_public_key().to_blob() == blob)
+		.ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_NO_HOST_KEY_FOR_CERTIFICATE))?;
+	host_key.certificate = Some(certificate);
+	Ok(())
+}
+
+//host key algorithms usable with given keys, in order of the keys
+pub fn get_host_key_algorithms(host_keys: &[HostKey]) -> Vec<String> {
+	host_keys.iter().flat_map(|k| k.get_algorithms()).collect()
+}
+
+//the host key which signs H for the negotiated host key algorithm
+pub fn choose_host_key<'a>(host_keys: &'a [HostKey], host_key_algorithm: &str) -> Option<&'a HostKey> {
+	host_keys.iter().find(|k| k.get_algorithms().iter().any(|a| a == host_key_algorithm))
+}
+
+//the host key is a certificate for the *-cert-v01@openssh.com algorithms, its key signs H then
+pub fn verify_exchange_hash_signature(host_key_algorithm: &str, host_key: &[u8], exchange_hash: &[u8], host_signature: &[u8]) -> Result<(), Error> {
+	let bad_signature = || Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_BAD_HOST_KEY_SIGNATURE);
+	let is_certificate = host_key_algorithm.ends_with(certificate::CERT_SUFFIX);
+	if certificate::is_certificate_blob(host_key) != is_certificate {
+		return Err(bad_signature());
+	}
+	let key: PublicKey = if is_certificate { Certificate::from_blob(host_key)?.key } else { PublicKey::from_blob(host_key)? };
+
+	let signature_algorithm = auth_publickey::get_signature_algorithm(host_key_algorithm);
+	let (algorithm, _) = signature::read_signature(host_signature)?;
+	if algorithm != signature_algorithm || !signature::get_signature_algorithms(&key).contains(&signature_algorithm) ||
+		!signature::verify(&key, exchange_hash, host_signature) {
+		return Err(bad_signature());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use keys;
+
+	#[test]
+	fn both_sides_get_same_shared_secret() {
+		let p = get_oakley_group14_prime();
+		let x = generate_private_exponent(&p);
+		let y = generate_private_exponent(&p);
+		let e = get_public_value(&p, &x);
+		let f = get_public_value(&p, &y);
+		assert!(check_public_value(&p, &e).is_ok());
+		assert!(check_public_value(&p, &f).is_ok());
+		assert_eq!(get_shared_secret(&p, &f, &x), get_shared_secret(&p, &e, &y));
+		assert_eq!(get_public_value(&p, &10.to_biguint().unwrap()), 1024.to_biguint().unwrap());
+	}
+
+	#[test]
+	fn check_public_value_rejects_degenerate_values() {
+		let p = get_oakley_group2_prime();
+		let one = 1.to_biguint().unwrap();
+		assert!(check_public_value(&p, &0.to_biguint().unwrap()).is_err());
+		assert!(check_public_value(&p, &one).is_err());
+		assert!(check_public_value(&p, &(&p - &one)).is_err());
+		assert!(check_public_value(&p, &p).is_err());
+		assert!(check_public_value(&p, &2.to_biguint().unwrap()).is_ok());
+	}
+
+	#[test]
+	fn negotiate_algorithm_prefers_client_order() {
+		let client = vec![DIFFIE_HELLMAN_GROUP14_SHA256.to_string(), DIFFIE_HELLMAN_GROUP14_SHA1.to_string()];
+		let server = vec![DIFFIE_HELLMAN_GROUP14_SHA1.to_string(), DIFFIE_HELLMAN_GROUP14_SHA256.to_string()];
+		assert_eq!(negotiate_algorithm(&client, &server), Some(DIFFIE_HELLMAN_GROUP14_SHA256.to_string()));
+		assert_eq!(negotiate_algorithm(&client[1..], &server[1..]), None);
+		//group1 with its 1024 bit prime is not supported
+		assert_eq!(get_group_prime("diffie-hellman-group1-sha1"), None);
+		assert_eq!(get_group_prime("ecdh-sha2-nistp256"), None);
+	}
+
+	fn get_test_exchange_hash(algorithm: &str, host_key: &[u8]) -> Vec<u8> {
+		let e = 2.to_biguint().unwrap();
+		let f = 3.to_biguint().unwrap();
+		let k = 4.to_biguint().unwrap();
+		get_exchange_hash(algorithm, &ExchangeHashInput {
+			client_version: b"SSH-2.0-client",
+			server_version: b"SSH-2.0-server",
+			client_kex_init: &[20, 1],
+			server_kex_init: &[20, 2],
+			host_key,
+			e: &e,
+			f: &f,
+			shared_secret: &k,
+		}).unwrap()
+	}
+
+	#[test]
+	fn exchange_hash_uses_hash_of_kex_algorithm() {
+		assert_eq!(get_test_exchange_hash(DIFFIE_HELLMAN_GROUP14_SHA1, &[1]).len(), 20);
+		assert_eq!(get_test_exchange_hash(DIFFIE_HELLMAN_GROUP14_SHA256, &[1]).len(), 32);
+		assert_ne!(get_test_exchange_hash(DIFFIE_HELLMAN_GROUP14_SHA256, &[1]), get_test_exchange_hash(DIFFIE_HELLMAN_GROUP14_SHA256, &[2]));
+	}
+
+	#[test]
+	fn exchange_hash_signature_is_verified() {
+		let host_keys = vec![HostKey::new(keys::generate_private_key(keys::SSH_ED25519, None).unwrap()),
+		                     HostKey::new(keys::generate_private_key(keys::ECDSA_SHA2_NISTP256, None).unwrap())];
+		assert_eq!(get_host_key_algorithms(&host_keys), vec![keys::SSH_ED25519, keys::ECDSA_SHA2_NISTP256]);
+
+		let key = choose_host_key(&host_keys, keys::ECDSA_SHA2_NISTP256).unwrap();
+		let blob = key.get_blob(keys::ECDSA_SHA2_NISTP256);
+		let hash = get_test_exchange_hash(DIFFIE_HELLMAN_GROUP14_SHA256, &blob);
+		let host_signature = key.sign(keys::ECDSA_SHA2_NISTP256, &hash).unwrap();
+		assert!(verify_exchange_hash_signature(keys::ECDSA_SHA2_NISTP256, &blob, &hash, &host_signature).is_ok());
+
+		let other_hash = get_test_exchange_hash(DIFFIE_HELLMAN_GROUP14_SHA256, &[1]);
+		assert!(verify_exchange_hash_signature(keys::ECDSA_SHA2_NISTP256, &blob, &other_hash, &host_signature).is_err());
+		assert!(verify_exchange_hash_signature(keys::SSH_ED25519, &blob, &hash, &host_signature).is_err());
+		let other_key = host_keys[0].key.get_public_key().to_blob();
+		assert!(verify_exchange_hash_signature(keys::ECDSA_SHA2_NISTP256, &other_key, &hash, &host_signature).is_err());
+		assert!(choose_host_key(&host_keys, signature::RSA_SHA2_256).is_none());
+	}
+}
@@ -0,0 +1,36 @@
+use std::mem;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::ptr;
+use libc;
+
+//getnameinfo(3) of the address, confirmed by a forward lookup as in OpenSSH since
+//whoever controls the address controls its PTR record, None if either lookup fails
+pub fn get_host_name_by_address(address: &IpAddr) -> Option<String> {
+    let mut buffer = [0u8; libc::NI_MAXHOST as usize];
+    let res = match *address {
+        IpAddr::V4(ref v4) => {
+            let mut socket_address: libc::sockaddr_in = unsafe { mem::zeroed() };
+            socket_address.sin_family = libc::AF_INET as libc::sa_family_t;
+            socket_address.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+            unsafe {
+                libc::getnameinfo(&socket_address as *const libc::sockaddr_in as *const libc::sockaddr, mem::size_of_val(&socket_address) as libc::socklen_t,
+                                  buffer.as_mut_ptr() as *mut libc::c_char, buffer.len() as libc::socklen_t, ptr::null_mut(), 0, libc::NI_NAMEREQD)
+            }
+        }
+        IpAddr::V6(ref v6) => {
+            let mut socket_address: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            socket_address.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            socket_address.sin6_addr.s6_addr = v6.octets();
+            unsafe {
+                libc::getnameinfo(&socket_address as *const libc::sockaddr_in6 as *const libc::sockaddr, mem::size_of_val(&socket_address) as libc::socklen_t,
+                                  buffer.as_mut_ptr() as *mut libc::c_char, buffer.len() as libc::socklen_t, ptr::null_mut(), 0, libc::NI_NAMEREQD)
+            }
+        }
+    };
+    if res != 0 {
+        return None;
+    }
+    let length = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    let name = String::from_utf8(buffer[..length].to_vec()).ok()?.to_lowercase();
+    if (name.as_str(), 0).to_socket_addrs().ok()?.any(|a| a.ip() == *address) { Some(name) } else { None }
+}
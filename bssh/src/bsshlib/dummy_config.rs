@@ -1,18 +1,28 @@
+use certificate;
+use cipher;
 use config;
+use diffie_hellman;
+use keys;
+use mac;
+use signature;
 
 pub struct DummyCommonConfig {}
 
 impl config::AvailableAlgorithms for DummyCommonConfig {
     fn get_available_kex_algorithms(&self) -> Vec<String> {
-        vec!["diffie-hellman-group1-sha1".to_string(), "diffie-hellman-group14-sha1".to_string()]
+        vec![diffie_hellman::DIFFIE_HELLMAN_GROUP14_SHA256.to_string(),
+             diffie_hellman::DIFFIE_HELLMAN_GROUP14_SHA1.to_string()]
     }
 
     fn get_available_server_host_key_algorithms(&self) -> Vec<String> {
-        vec!["ssh-dss".to_string()]
+        //the server offers only the algorithms of its host keys, certificates are preferred as by OpenSSH
+        let plain = [keys::SSH_ED25519, keys::ECDSA_SHA2_NISTP256, signature::RSA_SHA2_512, signature::RSA_SHA2_256];
+        let certified = plain.iter().map(|a| format!("{}{}", a, certificate::CERT_SUFFIX));
+        certified.chain(plain.iter().map(|a| a.to_string())).collect()
     }
 
     fn get_available_encryption_algorithms_client_to_server(&self) -> Vec<String> {
-        vec!["3des-cbc".to_string()]
+        vec![cipher::AES128_CTR.to_string(), cipher::AES256_CTR.to_string()]
     }
 
     fn get_available_encryption_algorithms_server_to_client(&self) -> Vec<String> {
@@ -20,7 +30,7 @@ impl config::AvailableAlgorithms for DummyCommonConfig {
     }
 
     fn get_available_mac_algorithms_client_to_server(&self) -> Vec<String> {
-        vec!["hmac-sha1".to_string()]
+        vec![mac::HMAC_SHA2_256.to_string(), mac::HMAC_SHA1.to_string()]
     }
 
     fn get_available_mac_algorithms_server_to_client(&self) -> Vec<String> {
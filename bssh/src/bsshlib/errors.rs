@@ -13,4 +13,88 @@ pub const BSSH_ERR_EXPECTED_KEX_MSG_INIT			: &'static str = "Error while process
 pub const BSSH_ERR_EXPECTED_ZERO_U32				: &'static str = "Error while processing key exchange packet: expected 0 (u32) at the end of message";
 
 pub const BSSH_DH_ERR_EXPECTED_KEXDH_INIT			: &'static str = "Error in kex exchange: expected KEXDH_INIT.";
-pub const BSSH_DH_ERR_NEGATIVE_E					: &'static str = "Error in kex exchange: e number is negative!";
\ No newline at end of file
+pub const BSSH_DH_ERR_NEGATIVE_E					: &'static str = "Error in kex exchange: e number is negative!";
+
+pub const BSSH_ERR_PEM_NO_BLOCK_FOUND               : &str = "Error while reading PEM: no -----BEGIN ...----- line found.";
+pub const BSSH_ERR_PEM_MALFORMED                    : &str = "Error while reading PEM: malformed block.";
+pub const BSSH_ERR_PEM_UNSUPPORTED_CIPHER           : &str = "Error while reading PEM: unsupported DEK-Info cipher.";
+
+pub const BSSH_ERR_KEY_MALFORMED                    : &str = "Error while reading key: malformed key data.";
+pub const BSSH_ERR_KEY_UNSUPPORTED_TYPE             : &str = "Error while reading key: unsupported key type.";
+pub const BSSH_ERR_KEY_UNSUPPORTED_FORMAT           : &str = "Error while reading key: unsupported key format.";
+pub const BSSH_ERR_KEY_PASSPHRASE_REQUIRED          : &str = "Error while reading key: key is encrypted and passphrase is required.";
+pub const BSSH_ERR_KEY_DECRYPTION_FAILED            : &str = "Error while reading key: decryption failed (incorrect passphrase?).";
+pub const BSSH_ERR_KEY_INVALID_LENGTH               : &str = "Invalid key length.";
+pub const BSSH_ERR_KEY_GENERATION_FAILED            : &str = "Key generation failed.";
+
+pub const BSSH_ERR_PUBLIC_KEY_LINE_MALFORMED        : &str = "Error while reading public key: expected \"<type> <base64 key> [comment]\".";
+pub const BSSH_ERR_PUBLIC_KEY_TYPE_MISMATCH         : &str = "Error while reading public key: key type does not match the encoded key.";
+pub const BSSH_ERR_AUTHORIZED_KEYS_BAD_OPTIONS      : &str = "Error while reading authorized_keys: bad key options.";
+pub const BSSH_ERR_RFC4716_MALFORMED                : &str = "Error while reading RFC 4716 public key: malformed file.";
+pub const BSSH_ERR_KNOWN_HOSTS_BAD_MARKER           : &str = "Error while reading known_hosts: unknown marker.";
+pub const BSSH_ERR_CONFIG_BAD_OPTION                : &str = "Error in configuration: unsupported option or bad value.";
+pub const BSSH_ERR_HOST_KEY_VERIFICATION_FAILED     : &str = "Host key verification failed.";
+pub const BSSH_ERR_EXPECTED_KEXDH_REPLY             : &str = "Error in kex exchange: expected KEXDH_REPLY.";
+pub const BSSH_ERR_SIGNATURE_UNSUPPORTED_ALGORITHM  : &str = "Signature algorithm is not supported or does not match the key.";
+pub const BSSH_ERR_SIGNATURE_MALFORMED              : &str = "Malformed signature.";
+pub const BSSH_ERR_SIGNATURE_FAILED                 : &str = "Signing failed.";
+pub const BSSH_ERR_UNEXPECTED_MESSAGE               : &str = "Unexpected message.";
+pub const BSSH_ERR_STRING_NOT_UTF8                  : &str = "Malformed string: not utf8.";
+pub const BSSH_ERR_NO_MATCHING_KEX_ALGORITHM        : &str = "Unable to negotiate a key exchange method.";
+pub const BSSH_ERR_NO_MATCHING_HOST_KEY_ALGORITHM   : &str = "Unable to negotiate a host key algorithm.";
+pub const BSSH_DH_ERR_PUBLIC_VALUE_OUT_OF_RANGE     : &str = "Error in kex exchange: public value is out of range.";
+pub const BSSH_ERR_BAD_HOST_KEY_SIGNATURE           : &str = "Host key signature of the key exchange is invalid.";
+pub const BSSH_ERR_UNSUPPORTED_CIPHER               : &str = "Unsupported cipher or bad key length.";
+pub const BSSH_ERR_BAD_PACKET_LENGTH                : &str = "Bad packet length or padding.";
+pub const BSSH_ERR_BAD_PACKET_MAC                   : &str = "Corrupted MAC on input.";
+pub const BSSH_ERR_NO_MATCHING_CIPHER               : &str = "Unable to negotiate a cipher.";
+pub const BSSH_ERR_NO_MATCHING_MAC                  : &str = "Unable to negotiate a MAC algorithm.";
+pub const BSSH_ERR_NO_MATCHING_COMPRESSION          : &str = "Unable to negotiate a compression method.";
+pub const BSSH_ERR_TRANSPORT_DIRECTION_SPLIT        : &str = "This half of the split transport does not handle the direction.";
+pub const BSSH_ERR_BAD_CIDR                         : &str = "Invalid address/mask list entry.";
+
+pub const BSSH_ERR_CERT_MALFORMED                   : &str = "Error while reading certificate: malformed certificate.";
+pub const BSSH_ERR_CERT_WRONG_TYPE                  : &str = "Certificate invalid: not a user or host certificate as expected.";
+pub const BSSH_ERR_CERT_NOT_YET_VALID               : &str = "Certificate invalid: not yet valid.";
+pub const BSSH_ERR_CERT_EXPIRED                     : &str = "Certificate invalid: expired.";
+pub const BSSH_ERR_CERT_NO_PRINCIPALS               : &str = "Certificate invalid: certificate lacks principal list.";
+pub const BSSH_ERR_CERT_PRINCIPAL_MISMATCH          : &str = "Certificate invalid: name is not a listed principal.";
+pub const BSSH_ERR_CERT_UNKNOWN_CRITICAL_OPTION     : &str = "Certificate invalid: unsupported critical option.";
+pub const BSSH_ERR_CERT_UNTRUSTED_CA                : &str = "Certificate invalid: signed by untrusted CA.";
+pub const BSSH_ERR_CERT_BAD_SIGNATURE               : &str = "Certificate invalid: bad CA signature.";
+pub const BSSH_ERR_CERT_SOURCE_ADDRESS              : &str = "Certificate invalid: source address not allowed.";
+pub const BSSH_ERR_NO_HOST_KEYS                     : &str = "No host keys available.";
+pub const BSSH_ERR_NO_HOST_KEY_FOR_CERTIFICATE      : &str = "No host key matches the host certificate.";
+pub const BSSH_ERR_SERVICE_NOT_AVAILABLE            : &str = "Service not available.";
+pub const BSSH_ERR_TOO_MANY_AUTH_FAILURES           : &str = "Too many authentication failures.";
+pub const BSSH_ERR_LOGIN_GRACE_TIME_EXCEEDED        : &str = "Timeout before authentication.";
+pub const BSSH_ERR_USER_CHANGED                     : &str = "Change of user or service is not allowed.";
+pub const BSSH_ERR_CRYPT_FAILED                   : &str = "Password hashing failed.";
+pub const BSSH_ERR_KEY_SOURCE_ADDRESS               : &str = "Key not allowed from this address.";
+pub const BSSH_ERR_CONFLICTING_FORCE_COMMANDS       : &str = "Certificate and authorized_keys force different commands.";
+pub const BSSH_ERR_TOO_MANY_PROMPTS                 : &str = "Too many keyboard-interactive prompts.";
+pub const BSSH_ERR_WRONG_NUMBER_OF_RESPONSES        : &str = "Number of responses does not match the prompts.";
+pub const BSSH_ERR_TOTP_SECRETS_MALFORMED           : &str = "Error while reading TOTP secrets: expected \"<user> <base32 secret>\".";
+pub const BSSH_ERR_TOTP_SECRETS_MISSING             : &str = "KbdInteractiveChallenge totp needs TotpSecretsFile.";
+pub const BSSH_ERR_KEYSIGN_BAD_REQUEST              : &str = "Not a hostbased request of this user on this host.";
+pub const BSSH_ERR_KEYSIGN_NO_HOST_KEY              : &str = "No host key matches the hostbased request.";
+pub const BSSH_ERR_KEYSIGN_DISABLED                 : &str = "Not enabled, see EnableSSHKeysign in /etc/ssh/ssh_config.";
+pub const BSSH_ERR_KEYSIGN_FAILED                   : &str = "bssh-keysign failed to sign the hostbased request.";
+pub const BSSH_ERR_AUTH_METHOD_NOT_ENABLED          : &str = "AuthenticationMethods lists a disabled authentication method.";
+pub const BSSH_ERR_AGENT_FAILURE                    : &str = "Agent refused operation.";
+pub const BSSH_ERR_AGENT_MESSAGE_LENGTH             : &str = "Agent message has bad length.";
+pub const BSSH_ERR_AGENT_UNKNOWN_CONSTRAINT         : &str = "Unknown agent key constraint.";
+pub const BSSH_ERR_UNKNOWN_CHANNEL                  : &str = "Message for an unknown channel.";
+pub const BSSH_ERR_CHANNEL_WINDOW_EXCEEDED          : &str = "Channel data exceeds the window or maximum packet size.";
+pub const BSSH_ERR_CHANNEL_DATA_AFTER_EOF           : &str = "Channel data after EOF.";
+pub const BSSH_ERR_CHANNEL_NOT_OPEN                 : &str = "Channel is not open.";
+pub const BSSH_ERR_CANNOT_SWITCH_USER               : &str = "Can not run processes as another user without root privileges.";
+pub const BSSH_ERR_SESSION_ALREADY_STARTED          : &str = "Session already runs a process.";
+pub const BSSH_ERR_INTERRUPTED                      : &str = "Interrupted by signal before the session started.";
+pub const BSSH_ERR_SFTP_BAD_PACKET                  : &str = "Malformed SFTP packet.";
+pub const BSSH_ERR_SFTP_INVALID_HANDLE              : &str = "Invalid SFTP handle.";
+pub const BSSH_ERR_SFTP_TOO_MANY_HANDLES            : &str = "Too many open SFTP handles.";
+pub const BSSH_ERR_UNKNOWN_SUBSYSTEM                : &str = "Unknown subsystem.";
+pub const BSSH_ERR_SFTP_VERSION_NOT_SUPPORTED       : &str = "SFTP server does not support version 3.";
+pub const BSSH_ERR_UNTERMINATED_QUOTE               : &str = "Unterminated quote.";
+pub const BSSH_ERR_BAD_FORWARD_SPECIFICATION        : &str = "Bad forwarding specification.";
@@ -0,0 +1,163 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use md5::Md5;
+use sha2::{Sha256, Digest};
+use keys::PublicKey;
+
+//the visualisation below is the "drunken bishop" from OpenSSH's sshkey.c, so output matches ssh-keygen -lv
+const FIELD_WIDTH: usize = 17;
+const FIELD_HEIGHT: usize = 9;
+const AUGMENTATION: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FingerprintHash {
+    Md5,
+    Sha256,
+}
+
+impl FingerprintHash {
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            FingerprintHash::Md5 => "MD5",
+            FingerprintHash::Sha256 => "SHA256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FingerprintHash> {
+        match name.to_lowercase().as_str() {
+            "md5" => Some(FingerprintHash::Md5),
+            "sha256" => Some(FingerprintHash::Sha256),
+            _ => None,
+        }
+    }
+}
+
+pub fn get_digest(key: &PublicKey, hash: FingerprintHash) -> Vec<u8> {
+    let blob = key.to_blob();
+    match hash {
+        FingerprintHash::Md5 => Md5::digest(&blob).to_vec(),
+        FingerprintHash::Sha256 => Sha256::digest(&blob).to_vec(),
+    }
+}
+
+//"SHA256:<unpadded base64>" or "MD5:<colon separated hex>"
+pub fn get_fingerprint(key: &PublicKey, hash: FingerprintHash) -> String {
+    let digest = get_digest(key, hash);
+    let encoded = match hash {
+        FingerprintHash::Md5 => digest.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(":"),
+        FingerprintHash::Sha256 => STANDARD_NO_PAD.encode(&digest),
+    };
+    format!("{}:{}", hash.get_name(), encoded)
+}
+
+//first line of ssh-keygen -l output: "<bits> <fingerprint> <comment> (<type>)"
+pub fn get_fingerprint_line(key: &PublicKey, comment: &str, hash: FingerprintHash) -> String {
+    format!("{} {} {} ({})",
+            key.get_bits(),
+            get_fingerprint(key, hash),
+            if comment.is_empty() { "no comment" } else { comment },
+            key.get_type_label())
+}
+
+fn framed_title(title: &str) -> String {
+    let mut res = String::from("+");
+    let padding = FIELD_WIDTH.saturating_sub(title.len());
+    res.push_str(&"-".repeat(padding / 2));
+    res.push_str(title);
+    res.push_str(&"-".repeat(padding - padding / 2));
+    res.push('+');
+    res
+}
+
+pub fn get_randomart(key: &PublicKey, hash: FingerprintHash) -> String {
+    let digest = get_digest(key, hash);
+    let max_value = AUGMENTATION.len() - 1;
+    let mut field = [[0usize; FIELD_HEIGHT]; FIELD_WIDTH];
+    let mut x = FIELD_WIDTH / 2;
+    let mut y = FIELD_HEIGHT / 2;
+
+    for byte in digest.iter() {
+        let mut input = *byte;
+        for _ in 0..4 {
+            x = if input & 0x1 != 0 { (x + 1).min(FIELD_WIDTH - 1) } else { x.saturating_sub(1) };
+            y = if input & 0x2 != 0 { (y + 1).min(FIELD_HEIGHT - 1) } else { y.saturating_sub(1) };
+            if field[x][y] < max_value - 2 {
+                field[x][y] += 1;
+            }
+            input >>= 2;
+        }
+    }
+
+    field[FIELD_WIDTH / 2][FIELD_HEIGHT / 2] = max_value - 1;
+    field[x][y] = max_value;
+
+    let mut title = format!("[{} {}]", key.get_type_label(), key.get_bits());
+    if title.len() > FIELD_WIDTH - 1 {
+        title = format!("[{}]", key.get_type_label());
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    lines.push(framed_title(&title));
+    for row in 0..FIELD_HEIGHT {
+        let mut line = String::from("|");
+        for column in field.iter() {
+            line.push(AUGMENTATION[column[row].min(max_value)] as char);
+        }
+        line.push('|');
+        lines.push(line);
+    }
+    lines.push(framed_title(&format!("[{}]", hash.get_name())));
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+
+    //ssh-keygen -t ed25519 -C user@example.com
+    const ED25519_BLOB: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIK3FM+xl4coRYITfMC07c5t0WngvwhzU5Te9htYbXReo";
+
+    fn ed25519_key() -> PublicKey {
+        PublicKey::from_blob(&STANDARD.decode(ED25519_BLOB).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn get_fingerprint_matches_ssh_keygen() {
+        let key = ed25519_key();
+        assert_eq!(get_fingerprint(&key, FingerprintHash::Sha256),
+                   "SHA256:HkqsW1U4TMvI4erIUYYZavjCmUD3cAZV14W9omnT4j8");
+        assert_eq!(get_fingerprint(&key, FingerprintHash::Md5),
+                   "MD5:d7:17:cf:38:44:73:00:64:f1:54:9b:13:1e:30:92:61");
+        assert_eq!(get_fingerprint_line(&key, "user@example.com", FingerprintHash::Sha256),
+                   "256 SHA256:HkqsW1U4TMvI4erIUYYZavjCmUD3cAZV14W9omnT4j8 user@example.com (ED25519)");
+        assert_eq!(get_fingerprint_line(&key, "", FingerprintHash::Md5),
+                   "256 MD5:d7:17:cf:38:44:73:00:64:f1:54:9b:13:1e:30:92:61 no comment (ED25519)");
+    }
+
+    #[test]
+    fn get_randomart_matches_ssh_keygen() {
+        //ssh-keygen -lv
+        let expected = "+--[ED25519 256]--+
+| ..+o+o.... +.   |
+|o..+=o *.o o .   |
+|+.o o.+ * .   .  |
+|+.oo o   o . .   |
+|.+o . o S + .    |
+| o + o + B .     |
+|  o o o + o      |
+|     o   . E     |
+|    .     ...    |
++----[SHA256]-----+";
+        assert_eq!(get_randomart(&ed25519_key(), FingerprintHash::Sha256), expected);
+    }
+
+    #[test]
+    fn fingerprint_hash_from_name_works() {
+        assert_eq!(FingerprintHash::from_name("md5"), Some(FingerprintHash::Md5));
+        assert_eq!(FingerprintHash::from_name("SHA256"), Some(FingerprintHash::Sha256));
+        assert_eq!(FingerprintHash::from_name("sha1"), None);
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use connection;
+use connection::{ChannelInput, ChannelManager};
+use errors;
+use io_helpers;
+use libc;
+
+//RFC 4254 section 7, TCP/IP Port Forwarding
+
+pub const CHANNEL_DIRECT_TCPIP: &str = "direct-tcpip";
+pub const CHANNEL_FORWARDED_TCPIP: &str = "forwarded-tcpip";
+
+pub const REQUEST_TCPIP_FORWARD: &str = "tcpip-forward";
+pub const REQUEST_CANCEL_TCPIP_FORWARD: &str = "cancel-tcpip-forward";
+
+//sshd_config GatewayPorts, who may connect to remote forwardings
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GatewayPorts {
+    //loopback only
+    #[default]
+    No,
+    //all addresses
+    Yes,
+    //the address the client asked for
+    ClientSpecified,
+}
+
+impl GatewayPorts {
+    pub fn from_name(name: &str) -> Option<GatewayPorts> {
+        match name.to_lowercase().as_str() {
+            "no" => Some(GatewayPorts::No),
+            "yes" => Some(GatewayPorts::Yes),
+            "clientspecified" => Some(GatewayPorts::ClientSpecified),
+            _ => None,
+        }
+    }
+}
+
+//RFC 4254 page 16 and 18, type specific data of direct-tcpip and forwarded-tcpip
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcpipChannel {
+    //where the connection goes to, for forwarded-tcpip the listening address and port
+    pub host: String,
+    pub port: u16,
+    //where the connection came from
+    pub originator_address: String,
+    pub originator_port: u16,
+}
+
+fn bad_message() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)
+}
+
+fn bad_specification() -> Error {
+    Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_BAD_FORWARD_SPECIFICATION)
+}
+
+//ports are uint32 on the wire
+fn read_port(stream: &mut dyn Read) -> Result<u16, Error> {
+    u16::try_from(stream.read_u32::<BigEndian>()?).map_err(|_| bad_message())
+}
+
+pub fn write_tcpip_channel(stream: &mut dyn Write, channel: &TcpipChannel) -> Result<(), Error> {
+    io_helpers::write_string(stream, &channel.host.as_bytes().to_vec())?;
+    stream.write_u32::<BigEndian>(channel.port as u32)?;
+    io_helpers::write_string(stream, &channel.originator_address.as_bytes().to_vec())?;
+    stream.write_u32::<BigEndian>(channel.originator_port as u32)?;
+    Ok(())
+}
+
+pub fn read_tcpip_channel(stream: &mut dyn Read) -> Result<TcpipChannel, Error> {
+    let host = io_helpers::read_utf8_string(stream)?;
+    let port = read_port(stream)?;
+    let originator_address = io_helpers::read_utf8_string(stream)?;
+    let originator_port = read_port(stream)?;
+    Ok(TcpipChannel { host, port, originator_address, originator_port })
+}
+
+//RFC 4254 page 18, request specific data of tcpip-forward and cancel-tcpip-forward
+pub fn write_forward_request(stream: &mut dyn Write, address: &str, port: u16) -> Result<(), Error> {
+    io_helpers::write_string(stream, &address.as_bytes().to_vec())?;
+    stream.write_u32::<BigEndian>(port as u32)
+}
+
+pub fn read_forward_request(stream: &mut dyn Read) -> Result<(String, u16), Error> {
+    let address = io_helpers::read_utf8_string(stream)?;
+    let port = read_port(stream)?;
+    Ok((address, port))
+}
+
+//"[listen_host:]listen_port:connect_host:connect_port", as given with -L and -R
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Forward {
+    //None listens on loopback only, "*" or "" on all addresses,
+    //for remote forwardings only if the server allows it
+    pub listen_host: Option<String>,
+    pub listen_port: u16,
+    pub connect_host: String,
+    pub connect_port: u16,
+}
+
+//fields separated by colons, IPv6 addresses are put in brackets
+fn split_fields(spec: &str) -> Option<Vec<String>> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut rest = spec;
+    loop {
+        let (field, next) = match rest.strip_prefix('[') {
+            Some(bracketed) => {
+                let end = bracketed.find(']')?;
+                (&bracketed[..end], &bracketed[end + 1..])
+            }
+            None => rest.split_at(rest.find(':').unwrap_or(rest.len())),
+        };
+        fields.push(field.to_string());
+        match next.strip_prefix(':') {
+            Some(next) => rest = next,
+            None if next.is_empty() => return Some(fields),
+            None => return None,
+        }
+    }
+}
+
+pub fn parse_forward(spec: &str) -> Result<Forward, Error> {
+    let fields = split_fields(spec).ok_or_else(bad_specification)?;
+    let (listen_host, fields) = match fields.len() {
+        3 => (None, &fields[..]),
+        4 => (Some(fields[0].clone()), &fields[1..]),
+        _ => return Err(bad_specification()),
+    };
+    if fields[1].is_empty() {
+        return Err(bad_specification());
+    }
+    let parse_port = |port: &str| port.parse::<u16>().map_err(|_| bad_specification());
+    Ok(Forward {
+        listen_host,
+        listen_port: parse_port(&fields[0])?,
+        connect_host: fields[1].clone(),
+        connect_port: parse_port(&fields[2])?,
+    })
+}
+
+//address to bind a local forwarding to
+pub fn get_listen_address(listen_host: Option<&str>) -> &str {
+    match listen_host {
+        None | Some("localhost") => "127.0.0.1",
+        Some("") | Some("*") => "0.0.0.0",
+        Some(host) => host,
+    }
+}
+
+//address the server binds a remote forwarding to, "" and "*" stand for all addresses
+pub fn get_remote_listen_address(requested: &str, gateway_ports: GatewayPorts) -> &str {
+    match gateway_ports {
+        GatewayPorts::No => "127.0.0.1",
+        GatewayPorts::Yes => "0.0.0.0",
+        GatewayPorts::ClientSpecified => get_listen_address(Some(requested)),
+    }
+}
+
+//accepted connections are sent as events until the receiver is gone or the listener is closed
+pub fn spawn_listener<E, F>(listener: TcpListener, events: Sender<E>, make_event: F) -> thread::JoinHandle<()>
+    where E: Send + 'static, F: Fn(TcpStream) -> E + Send + 'static
+{
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if events.send(make_event(stream)).is_err() {
+                        return;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::InvalidInput => return,
+                Err(_) => {}
+            }
+        }
+    })
+}
+
+//a thread blocked in accept returns, dropping the listener alone does not wake it up
+pub fn close_listener(listener: &TcpListener) {
+    unsafe {
+        libc::shutdown(listener.as_raw_fd(), libc::SHUT_RDWR);
+    }
+}
+
+struct TcpChannel {
+    stream: TcpStream,
+    //channel data for the socket, dropped at EOF of the channel
+    output: Option<Sender<Vec<u8>>>,
+    //the socket reached end of file
+    sent_eof: bool,
+}
+
+impl Drop for TcpChannel {
+    //lets the reader return if the socket is still open
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Read);
+    }
+}
+
+//TCP connections carried by channels, the channel closes once EOF went both ways
+#[derive(Default)]
+pub struct TcpChannels {
+    channels: HashMap<u32, TcpChannel>,
+}
+
+impl TcpChannels {
+    pub fn contains(&self, channel: u32) -> bool {
+        self.channels.contains_key(&channel)
+    }
+
+    //the socket is read and written in threads like the pipes of a process,
+    //make_written reports data taken by the socket so it can be returned to the window
+    pub fn start<E, I, W>(&mut self, stream: TcpStream, channel: u32, events: &Sender<E>, make_input: I, make_written: W) -> Result<(), Error>
+        where E: Send + 'static, I: Fn(ChannelInput) -> E + Send + 'static, W: Fn(u32, usize) -> E + Send + 'static
+    {
+        let mut writer = stream.try_clone()?;
+        connection::spawn_channel_reader(stream.try_clone()?, channel, None, events.clone(), make_input);
+        let (output, received) = mpsc::channel::<Vec<u8>>();
+        let written = events.clone();
+        thread::spawn(move || {
+            for data in received {
+                //a peer which went away still gets the data acknowledged
+                let _ = writer.write_all(&data);
+                if written.send(make_written(channel, data.len())).is_err() {
+                    return;
+                }
+            }
+            let _ = writer.shutdown(Shutdown::Write);
+        });
+        self.channels.insert(channel, TcpChannel { stream, output: Some(output), sent_eof: false });
+        Ok(())
+    }
+
+    pub fn handle_data(&mut self, manager: &mut ChannelManager, channel: u32, data: Vec<u8>) -> Result<(), Error> {
+        let length = data.len();
+        let delivered = match self.channels.get(&channel).and_then(|c| c.output.as_ref()) {
+            Some(output) => output.send(data).is_ok(),
+            None => false,
+        };
+        if !delivered {
+            manager.consume(channel, length)?;
+        }
+        Ok(())
+    }
+
+    //the socket is shut down for writing after the data before EOF
+    pub fn handle_eof(&mut self, manager: &mut ChannelManager, channel: u32) -> Result<(), Error> {
+        if let Some(tcp) = self.channels.get_mut(&channel) {
+            tcp.output = None;
+        }
+        self.check_finished(manager, channel)
+    }
+
+    //end of file or error reading the socket
+    pub fn handle_input_end(&mut self, manager: &mut ChannelManager, channel: u32) -> Result<(), Error> {
+        match self.channels.get_mut(&channel) {
+            Some(tcp) => tcp.sent_eof = true,
+            None => return Ok(()),
+        }
+        manager.send_eof(channel)?;
+        self.check_finished(manager, channel)
+    }
+
+    fn check_finished(&mut self, manager: &mut ChannelManager, channel: u32) -> Result<(), Error> {
+        match self.channels.get(&channel) {
+            Some(tcp) if tcp.sent_eof && tcp.output.is_none() => manager.close(channel),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn handle_closed(&mut self, channel: u32) {
+        self.channels.remove(&channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn tcpip_channel_roundtrips() {
+        let channel = TcpipChannel {
+            host: "db.internal".to_string(),
+            port: 5432,
+            originator_address: "127.0.0.1".to_string(),
+            originator_port: 40000,
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_tcpip_channel(&mut data, &channel).unwrap();
+        assert_eq!(read_tcpip_channel(&mut &data[..]).unwrap(), channel);
+
+        let mut data: Vec<u8> = Vec::new();
+        io_helpers::write_string(&mut data, &b"host".to_vec()).unwrap();
+        data.write_u32::<BigEndian>(70000).unwrap();
+        assert!(read_tcpip_channel(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn forward_specifications_are_parsed() {
+        assert_eq!(parse_forward("8080:db.internal:5432").unwrap(), Forward {
+            listen_host: None,
+            listen_port: 8080,
+            connect_host: "db.internal".to_string(),
+            connect_port: 5432,
+        });
+        let forward = parse_forward("*:8080:[::1]:5432").unwrap();
+        assert_eq!(forward.listen_host.as_deref(), Some("*"));
+        assert_eq!(forward.connect_host, "::1");
+        assert_eq!(parse_forward("[::1]:8080:host:22").unwrap().listen_host.as_deref(), Some("::1"));
+        assert_eq!(parse_forward(":8080:host:22").unwrap().listen_host.as_deref(), Some(""));
+        assert!(parse_forward("8080:host").is_err());
+        assert!(parse_forward("8080::22").is_err());
+        assert!(parse_forward("8080:host:ssh").is_err());
+        assert!(parse_forward("8080:[::1:22").is_err());
+        assert!(parse_forward("8080:[::1]x:22").is_err());
+        assert_eq!(get_listen_address(None), "127.0.0.1");
+        assert_eq!(get_listen_address(Some("*")), "0.0.0.0");
+    }
+
+    #[test]
+    fn remote_listen_address_follows_gateway_ports() {
+        assert_eq!(get_remote_listen_address("", GatewayPorts::No), "127.0.0.1");
+        assert_eq!(get_remote_listen_address("localhost", GatewayPorts::Yes), "0.0.0.0");
+        assert_eq!(get_remote_listen_address("", GatewayPorts::ClientSpecified), "0.0.0.0");
+        assert_eq!(get_remote_listen_address("localhost", GatewayPorts::ClientSpecified), "127.0.0.1");
+        assert_eq!(get_remote_listen_address("192.0.2.1", GatewayPorts::ClientSpecified), "192.0.2.1");
+        assert_eq!(GatewayPorts::from_name("ClientSpecified"), Some(GatewayPorts::ClientSpecified));
+        assert_eq!(GatewayPorts::from_name("maybe"), None);
+
+        let mut data: Vec<u8> = Vec::new();
+        write_forward_request(&mut data, "localhost", 8080).unwrap();
+        assert_eq!(read_forward_request(&mut &data[..]).unwrap(), ("localhost".to_string(), 8080));
+    }
+
+    #[test]
+    fn closed_listener_stops_accepting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closing = listener.try_clone().unwrap();
+        let (events, received) = mpsc::channel();
+        let thread = spawn_listener(listener, events, |stream| stream);
+        close_listener(&closing);
+        thread.join().unwrap();
+        assert!(received.recv().is_err());
+    }
+}
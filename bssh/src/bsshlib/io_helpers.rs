@@ -43,6 +43,13 @@ pub fn read_string(stream: &mut Read, max_length: Option<u32>) -> Result<Vec<u8>
     Ok(body)
 }
 
+pub fn read_utf8_string(stream: &mut dyn Read) -> Result<String, Error> {
+    match String::from_utf8(read_string(stream, None)?) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_STRING_NOT_UTF8)),
+    }
+}
+
 pub fn write_name_list(stream: &mut Write, names: &Vec<String>) -> Result<(), Error> {
     let mut payload: Vec<u8> = Vec::new();
 
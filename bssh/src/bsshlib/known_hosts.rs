@@ -0,0 +1,450 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand_core::{OsRng, RngCore};
+use certificate::Certificate;
+use errors;
+use fingerprint;
+use fingerprint::FingerprintHash;
+use keys::PublicKey;
+use mac;
+use patterns;
+use public_key_file;
+
+//sshd(8) SSH_KNOWN_HOSTS FILE FORMAT: "[marker] hostpatterns keytype base64-key [comment]"
+
+const MARKER_CERT_AUTHORITY: &str = "@cert-authority";
+const MARKER_REVOKED: &str = "@revoked";
+const HASH_MAGIC: &str = "|1|";
+const HASH_SALT_LENGTH: usize = 20; //SHA1 digest length
+pub const DEFAULT_PORT: u16 = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Marker {
+    None,
+    CertAuthority,
+    Revoked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrictHostKeyChecking {
+    Yes,
+    Ask,
+    AcceptNew,
+    No,
+}
+
+impl StrictHostKeyChecking {
+    pub fn from_name(name: &str) -> Option<StrictHostKeyChecking> {
+        match name.to_lowercase().as_str() {
+            "yes" => Some(StrictHostKeyChecking::Yes),
+            "ask" => Some(StrictHostKeyChecking::Ask),
+            "accept-new" => Some(StrictHostKeyChecking::AcceptNew),
+            "no" | "off" => Some(StrictHostKeyChecking::No),
+            _ => None,
+        }
+    }
+}
+
+pub struct KnownHostEntry {
+    pub marker: Marker,
+    pub host_patterns: String,
+    pub key: PublicKey,
+    pub comment: String,
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    Known,
+    NotFound,
+    //(file, line) of entries for this host having a different key of the same type
+    Changed(Vec<(String, usize)>),
+    Revoked,
+}
+
+pub struct KnownHosts {
+    pub entries: Vec<KnownHostEntry>,
+}
+
+//"[host]:port" for non-default ports, as used both in patterns and as hashed input
+pub fn get_host_key_name(host: &str, port: u16) -> String {
+    if port == DEFAULT_PORT {
+        host.to_lowercase()
+    } else {
+        format!("[{}]:{}", host.to_lowercase(), port)
+    }
+}
+
+fn hash_host_name(salt: &[u8], name: &str) -> Vec<u8> {
+    mac::hmac_sha1_with_key(salt, name.as_bytes())
+}
+
+pub fn get_hashed_host_name(name: &str) -> String {
+    let mut salt = [0u8; HASH_SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    format!("{}{}|{}", HASH_MAGIC, STANDARD.encode(salt), STANDARD.encode(hash_host_name(&salt, name)))
+}
+
+fn match_hashed(name: &str, hashed: &str) -> bool {
+    let mut parts = hashed[HASH_MAGIC.len()..].splitn(2, '|');
+    let salt = parts.next().and_then(|s| STANDARD.decode(s).ok());
+    let hash = parts.next().and_then(|s| STANDARD.decode(s).ok());
+    match (salt, hash) {
+        (Some(salt), Some(hash)) => hash_host_name(&salt, name) == hash,
+        _ => false,
+    }
+}
+
+impl KnownHostEntry {
+    pub fn matches_host(&self, host: &str, port: u16) -> bool {
+        let name = get_host_key_name(host, port);
+        if self.host_patterns.starts_with(HASH_MAGIC) {
+            return match_hashed(&name, &self.host_patterns);
+        }
+        patterns::match_pattern_list(&name, &self.host_patterns, true) == Some(true)
+    }
+}
+
+pub fn read_known_host_line(line: &str, file: &str, line_number: usize) -> Result<Option<KnownHostEntry>, Error> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (first, rest) = public_key_file::split_token(line);
+    let (marker, host_patterns, rest) = match first {
+        MARKER_CERT_AUTHORITY | MARKER_REVOKED => {
+            let (host_patterns, rest) = public_key_file::split_token(rest);
+            (if first == MARKER_REVOKED { Marker::Revoked } else { Marker::CertAuthority }, host_patterns, rest)
+        }
+        _ if first.starts_with('@') => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_KNOWN_HOSTS_BAD_MARKER)),
+        _ => (Marker::None, first, rest),
+    };
+
+    if host_patterns.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PUBLIC_KEY_LINE_MALFORMED));
+    }
+
+    let key_line = public_key_file::read_public_key_line(rest)?;
+
+    Ok(Some(KnownHostEntry {
+        marker,
+        host_patterns: host_patterns.to_string(),
+        key: key_line.key,
+        comment: key_line.comment,
+        file: file.to_string(),
+        line: line_number,
+    }))
+}
+
+impl KnownHosts {
+    pub fn new() -> KnownHosts {
+        KnownHosts { entries: Vec::new() }
+    }
+
+    //malformed lines are skipped, same as ssh does
+    pub fn read(&mut self, text: &str, file: &str) {
+        for (i, line) in text.lines().enumerate() {
+            if let Ok(Some(entry)) = read_known_host_line(line, file, i + 1) {
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    //missing files are not an error, there is just nothing known yet
+    pub fn read_file(&mut self, path: &Path) -> Result<(), Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.read(&text, &path.to_string_lossy());
+                Ok(())
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn check_host_key(&self, host: &str, port: u16, key: &PublicKey) -> HostKeyStatus {
+        let mut known = false;
+        let mut changed: Vec<(String, usize)> = Vec::new();
+
+        for entry in self.entries.iter() {
+            if entry.marker == Marker::Revoked && entry.key == *key {
+                //revoked keys are revoked for any host
+                return HostKeyStatus::Revoked;
+            }
+            if entry.marker != Marker::None || !entry.matches_host(host, port) {
+                continue;
+            }
+            if entry.key == *key {
+                known = true;
+            } else if entry.key.get_algorithm_name() == key.get_algorithm_name() {
+                changed.push((entry.file.clone(), entry.line));
+            }
+        }
+
+        if known {
+            HostKeyStatus::Known
+        } else if !changed.is_empty() {
+            HostKeyStatus::Changed(changed)
+        } else {
+            HostKeyStatus::NotFound
+        }
+    }
+
+    pub fn get_cert_authorities(&self, host: &str, port: u16) -> Vec<&PublicKey> {
+        self.entries
+            .iter()
+            .filter(|e| e.marker == Marker::CertAuthority && e.matches_host(host, port))
+            .map(|e| &e.key)
+            .collect()
+    }
+
+    pub fn is_revoked(&self, key: &PublicKey) -> bool {
+        self.entries.iter().any(|e| e.marker == Marker::Revoked && e.key == *key)
+    }
+
+    //Known if certificate is signed by a @cert-authority for this host and valid for it,
+    //NotFound if there is no such authority, in which case the certified key itself should be checked.
+    //Error describes why a certificate from a known authority was not accepted.
+    pub fn check_host_certificate(&self, host: &str, port: u16, certificate: &Certificate, now: u64) -> Result<HostKeyStatus, Error> {
+        if self.is_revoked(&certificate.key) || self.is_revoked(&certificate.signature_key) {
+            return Ok(HostKeyStatus::Revoked);
+        }
+        if !self.get_cert_authorities(host, port).contains(&&certificate.signature_key) {
+            return Ok(HostKeyStatus::NotFound);
+        }
+        if !certificate.verify_signature() {
+            return Err(Error::new(ErrorKind::PermissionDenied, errors::BSSH_ERR_CERT_BAD_SIGNATURE));
+        }
+        certificate.check_authority(true, false, Some(host), now)?;
+        Ok(HostKeyStatus::Known)
+    }
+}
+
+impl Default for KnownHosts {
+    fn default() -> KnownHosts {
+        KnownHosts::new()
+    }
+}
+
+pub fn write_known_host_line(host: &str, port: u16, key: &PublicKey, hash: bool) -> String {
+    let name = get_host_key_name(host, port);
+    let host_patterns = if hash { get_hashed_host_name(&name) } else { name };
+    format!("{} {}", host_patterns, public_key_file::write_public_key_line(key, ""))
+}
+
+pub fn append_known_host(path: &Path, host: &str, port: u16, key: &PublicKey, hash: bool) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{}\n", write_known_host_line(host, port, key, hash)).as_bytes())?;
+    Ok(())
+}
+
+pub enum HostKeyAction {
+    Accept,
+    //key is not known and should be added to user's known_hosts, with optional warning to show
+    Add(Option<String>),
+    //user has to confirm the message first, and then the key is added
+    Ask(String),
+    //connection may continue, but the warning has to be shown
+    Warn(String),
+    Reject(String),
+}
+
+const BANNER_LINE: &str = "@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@";
+
+fn get_changed_key_warning(host_display: &str, key: &PublicKey, offending: &[(String, usize)]) -> String {
+    let mut res = format!("{}\n@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @\n{}\n", BANNER_LINE, BANNER_LINE);
+    res.push_str("IT IS POSSIBLE THAT SOMEONE IS DOING SOMETHING NASTY!\n");
+    res.push_str("Someone could be eavesdropping on you right now (man-in-the-middle attack)!\n");
+    res.push_str("It is also possible that a host key has just been changed.\n");
+    res.push_str(&format!("The fingerprint for the {} key sent by the remote host is\n{}.\n",
+                          key.get_type_label(),
+                          fingerprint::get_fingerprint(key, FingerprintHash::Sha256)));
+    res.push_str("Please contact your system administrator.\n");
+    for &(ref file, line) in offending.iter() {
+        res.push_str(&format!("Offending {} key in {}:{}\n", key.get_type_label(), file, line));
+    }
+    res.push_str(&format!("Host key for {} has changed", host_display));
+    res
+}
+
+pub fn get_host_key_action(status: &HostKeyStatus,
+                           policy: StrictHostKeyChecking,
+                           host_display: &str,
+                           key: &PublicKey)
+                           -> HostKeyAction {
+    match *status {
+        HostKeyStatus::Known => HostKeyAction::Accept,
+        HostKeyStatus::Revoked => {
+            HostKeyAction::Reject(format!("{}\n@       WARNING: REVOKED HOST KEY DETECTED!               @\n{}\nThe {} host key for {} is marked as revoked.\nThis could mean that a stolen key is being used to\nimpersonate this host.\nHost key verification failed.",
+                                          BANNER_LINE, BANNER_LINE, key.get_type_label(), host_display))
+        }
+        HostKeyStatus::Changed(ref offending) => {
+            let warning = get_changed_key_warning(host_display, key, offending);
+            if policy == StrictHostKeyChecking::No {
+                HostKeyAction::Warn(format!("{}.", warning))
+            } else {
+                HostKeyAction::Reject(format!("{} and you have requested strict checking.\nHost key verification failed.", warning))
+            }
+        }
+        HostKeyStatus::NotFound => {
+            let fingerprint = fingerprint::get_fingerprint(key, FingerprintHash::Sha256);
+            match policy {
+                StrictHostKeyChecking::Yes => {
+                    HostKeyAction::Reject(format!("No {} host key is known for {} and you have requested strict checking.\nHost key verification failed.",
+                                                  key.get_type_label(), host_display))
+                }
+                StrictHostKeyChecking::Ask => {
+                    HostKeyAction::Ask(format!("The authenticity of host '{}' can't be established.\n{} key fingerprint is {}.\nAre you sure you want to continue connecting (yes/no/[fingerprint])? ",
+                                               host_display, key.get_type_label(), fingerprint))
+                }
+                StrictHostKeyChecking::AcceptNew | StrictHostKeyChecking::No => {
+                    HostKeyAction::Add(Some(format!("Warning: Permanently added '{}' ({}) to the list of known hosts.",
+                                                    host_display, key.get_type_label())))
+                }
+            }
+        }
+    }
+}
+
+//answer to the Ask prompt, "yes" or the fingerprint of the key itself
+pub fn is_confirmation(answer: &str, key: &PublicKey) -> bool {
+    let answer = answer.trim();
+    answer.eq_ignore_ascii_case("yes") || answer == fingerprint::get_fingerprint(key, FingerprintHash::Sha256)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK3FM+xl4coRYITfMC07c5t0WngvwhzU5Te9htYbXReo";
+    const OTHER_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPsTZQK3o+0CRLDw5dUDWBs77QBSBwkNAiaa+m7LCjpF";
+
+    fn key(line: &str) -> PublicKey {
+        public_key_file::read_public_key_line(line).unwrap().key
+    }
+
+    fn known_hosts(text: &str) -> KnownHosts {
+        let mut known_hosts = KnownHosts::new();
+        known_hosts.read(text, "known_hosts");
+        known_hosts
+    }
+
+    #[test]
+    fn check_host_key_matches_patterns() {
+        let kh = known_hosts(&format!("*.example.com,!evil.example.com {}\n[alt.example.org]:2222 {}\n", KEY, KEY));
+        assert_eq!(kh.check_host_key("www.Example.com", 22, &key(KEY)), HostKeyStatus::Known);
+        assert_eq!(kh.check_host_key("evil.example.com", 22, &key(KEY)), HostKeyStatus::NotFound);
+        assert_eq!(kh.check_host_key("alt.example.org", 2222, &key(KEY)), HostKeyStatus::Known);
+        assert_eq!(kh.check_host_key("alt.example.org", 22, &key(KEY)), HostKeyStatus::NotFound);
+    }
+
+    #[test]
+    fn check_host_key_detects_changed_key() {
+        let kh = known_hosts(&format!("# comment\nhost.example.com {}\n", KEY));
+        assert_eq!(kh.check_host_key("host.example.com", 22, &key(OTHER_KEY)),
+                   HostKeyStatus::Changed(vec![("known_hosts".to_string(), 2)]));
+    }
+
+    #[test]
+    fn check_host_key_matches_hashed_entries() {
+        //ssh-keygen -H on "host.example.com" and "[host.example.com]:2222"
+        let kh = known_hosts(&format!("|1|EECWzImOvj6w1L2L+J8oanjb9Yw=|Z2gLxuD0fa1FxbSNbSzRZXtCFYQ= {}\n|1|EpZblBKpTXgLcVsz3d0xw+PTHyM=|BXlMHZmMnuu0Ud33qaRSw7+YgWQ= {}\n", KEY, OTHER_KEY));
+        assert_eq!(kh.check_host_key("host.example.com", 22, &key(KEY)), HostKeyStatus::Known);
+        assert_eq!(kh.check_host_key("host.example.com", 2222, &key(OTHER_KEY)), HostKeyStatus::Known);
+        assert_eq!(kh.check_host_key("other.example.com", 22, &key(KEY)), HostKeyStatus::NotFound);
+    }
+
+    #[test]
+    fn write_known_host_line_hashes_names() {
+        let line = write_known_host_line("Host.example.com", 2022, &key(KEY), true);
+        assert!(line.starts_with("|1|"));
+        let kh = known_hosts(&line);
+        assert_eq!(kh.check_host_key("host.example.com", 2022, &key(KEY)), HostKeyStatus::Known);
+        assert_eq!(write_known_host_line("host", 22, &key(KEY), false), format!("host {}", KEY));
+    }
+
+    #[test]
+    fn markers_are_honored() {
+        let kh = known_hosts(&format!("@revoked * {}\n@cert-authority *.example.com {}\nhost.example.com {}\n@bogus * {}\n", KEY, OTHER_KEY, KEY, KEY));
+        assert_eq!(kh.entries.len(), 3);
+        assert_eq!(kh.check_host_key("host.example.com", 22, &key(KEY)), HostKeyStatus::Revoked);
+        //cert-authority entry is not a host key
+        assert_eq!(kh.check_host_key("host.example.com", 22, &key(OTHER_KEY)),
+                   HostKeyStatus::Changed(vec![("known_hosts".to_string(), 3)]));
+        assert_eq!(kh.check_host_key("a.example.com", 22, &key(OTHER_KEY)), HostKeyStatus::NotFound);
+        assert_eq!(kh.get_cert_authorities("a.example.com", 22).len(), 1);
+        assert_eq!(kh.get_cert_authorities("a.example.org", 22).len(), 0);
+    }
+
+    #[test]
+    fn check_host_certificate_uses_cert_authorities() {
+        use certificate;
+        //ssh-keygen -s rsaca -h -n host.example.com,*.example.org -V 20200101:20400101
+        let (cert, _) = certificate::read_certificate_line("ecdsa-sha2-nistp256-cert-v01@openssh.com AAAAKGVjZHNhLXNoYTItbmlzdHAyNTYtY2VydC12MDFAb3BlbnNzaC5jb20AAAAgOd9WynFTK2khfKExuJBw4lNUCJhAj5fRcmATr1FOUa8AAAAIbmlzdHAyNTYAAABBBEciOXh2btC38Hh5xv7Jjw/SENSeS5/eA6dlOSx+8Be7btFCBzXmjCWKYvuW247bYLCfa7XvDmnwPamtqSG2pGQAAAAAAAAAAAAAAAIAAAAQaG9zdC5leGFtcGxlLmNvbQAAACUAAAAQaG9zdC5leGFtcGxlLmNvbQAAAA0qLmV4YW1wbGUub3JnAAAAAF4L4QAAAAAAg6p+gAAAAAAAAAAAAAAAAAAAARcAAAAHc3NoLXJzYQAAAAMBAAEAAAEBALC7OKc1KSrNfC8K69YqxkVnCjFCvWCzmVT4dKANUY9CqAFrWu/CSyRitG94JIzElgeTlIdK6FObvuEPN1BQ8wD9FU9/5lYEp+Uu82aAKgtCRpY2z8a1nt1B76bWtOcTvXAkiR8ciQVTv+lY60LVCLFNgyvlm5wwOi6pRQ5ZaOexFfjrmuMogdmJ+QFWbKoYzwCpbuupT+84JyEjHlZ3pcO46fMph8VbOV6thYET79+UD9J4e6zwdSdhhG1MuVU/aWKiCtb59MqgYAuCooN3wUpVXBn17ICoyYE8lEZkrfqNNCURYRbHDP5QcfZFbx/Zd9NvWqN+/1roLoSG2GNOD0EAAAEUAAAADHJzYS1zaGEyLTUxMgAAAQAHBU4LOOZ3ZUkppzXzAU7JuMgekVcSjFBJvlKxmmKPoVNk6ARnhUEtGbEI0kEB6xu4YPQL2D1F4wNRFbdCEeDnPybSKIVYzKwYVu8Z14KN+A/ls/FAsPolq0WtwCW051BJ5cK45nhnC7BR5FKNOydriBpT80GYOvTwbT7Y//yebRUD2aG0iHJxKS7pQA9o5iEhhpOK5CzAY9QByZvfunQlCU1pjXVOXfXS+8SvWpswbc/cAX+F65PgjFwNu931twJSJH1z3/uWHXkZlU2c+TH5LyeUJUcTll6rGDpXaGwBrzVcjbDJO4Sd0JZQhLgUaaetHQh2Em6Cbo1Qno6lBmnZ").unwrap();
+        let ca = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQCwuzinNSkqzXwvCuvWKsZFZwoxQr1gs5lU+HSgDVGPQqgBa1rvwkskYrRveCSMxJYHk5SHSuhTm77hDzdQUPMA/RVPf+ZWBKflLvNmgCoLQkaWNs/GtZ7dQe+m1rTnE71wJIkfHIkFU7/pWOtC1QixTYMr5ZucMDouqUUOWWjnsRX465rjKIHZifkBVmyqGM8AqW7rqU/vOCchIx5Wd6XDuOnzKYfFWzlerYWBE+/flA/SeHus8HUnYYRtTLlVP2liogrW+fTKoGALgqKDd8FKVVwZ9eyAqMmBPJRGZK36jTQlEWEWxwz+UHH2RW8f2XfTb1qjfv9a6C6EhthjTg9B";
+        let now = 1700000000;
+
+        let kh = known_hosts(&format!("@cert-authority *.example.com,*.example.org {}\n", ca));
+        assert_eq!(kh.check_host_certificate("host.example.com", 22, &cert, now).unwrap(), HostKeyStatus::Known);
+        assert_eq!(kh.check_host_certificate("a.example.org", 22, &cert, now).unwrap(), HostKeyStatus::Known);
+        //authority is trusted for this host, but the certificate is not issued for it
+        assert!(kh.check_host_certificate("other.example.com", 22, &cert, now).is_err());
+        assert!(kh.check_host_certificate("host.example.com", 22, &cert, 2300000000).is_err());
+        assert_eq!(kh.check_host_certificate("host.example.net", 22, &cert, now).unwrap(), HostKeyStatus::NotFound);
+
+        let kh = known_hosts(&format!("@cert-authority * {}\n@revoked * {}\n", ca, ca));
+        assert_eq!(kh.check_host_certificate("host.example.com", 22, &cert, now).unwrap(), HostKeyStatus::Revoked);
+    }
+
+    #[test]
+    fn get_host_key_action_follows_policy() {
+        let k = key(KEY);
+        let changed = HostKeyStatus::Changed(vec![("known_hosts".to_string(), 1)]);
+        for policy in [StrictHostKeyChecking::Yes, StrictHostKeyChecking::Ask, StrictHostKeyChecking::AcceptNew].iter() {
+            match get_host_key_action(&changed, *policy, "host", &k) {
+                HostKeyAction::Reject(msg) => assert!(msg.contains("REMOTE HOST IDENTIFICATION HAS CHANGED")),
+                _ => panic!(),
+            }
+        }
+        match get_host_key_action(&changed, StrictHostKeyChecking::No, "host", &k) {
+            HostKeyAction::Warn(_) => {}
+            _ => panic!(),
+        }
+        match get_host_key_action(&HostKeyStatus::NotFound, StrictHostKeyChecking::Yes, "host", &k) {
+            HostKeyAction::Reject(_) => {}
+            _ => panic!(),
+        }
+        match get_host_key_action(&HostKeyStatus::NotFound, StrictHostKeyChecking::Ask, "host", &k) {
+            HostKeyAction::Ask(_) => {}
+            _ => panic!(),
+        }
+        match get_host_key_action(&HostKeyStatus::NotFound, StrictHostKeyChecking::AcceptNew, "host", &k) {
+            HostKeyAction::Add(_) => {}
+            _ => panic!(),
+        }
+        match get_host_key_action(&HostKeyStatus::Revoked, StrictHostKeyChecking::No, "host", &k) {
+            HostKeyAction::Reject(_) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn is_confirmation_accepts_yes_or_fingerprint() {
+        let k = key(KEY);
+        assert!(is_confirmation("yes\n", &k));
+        assert!(is_confirmation("SHA256:HkqsW1U4TMvI4erIUYYZavjCmUD3cAZV14W9omnT4j8", &k));
+        assert!(!is_confirmation("y", &k));
+    }
+}
@@ -2,6 +2,20 @@ extern crate byteorder;
 extern crate rand;
 extern crate num;
 extern crate sha1;
+extern crate md5;
+extern crate sha2;
+extern crate aes;
+extern crate cbc;
+extern crate des;
+extern crate rsa;
+extern crate ed25519_dalek;
+extern crate p256;
+extern crate pkcs8;
+extern crate base64;
+extern crate ctr;
+extern crate bcrypt_pbkdf;
+extern crate rand_core;
+extern crate libc;
 
 pub mod version;
 pub mod numbers;
@@ -13,7 +27,47 @@ pub mod dummy_config; //TODO remove this after implementing proper config parsin
 
 pub mod io_helpers;
 pub mod packet;
+pub mod transport;
+pub mod userauth;
 pub mod diffie_hellman;
+pub mod kex;
 pub mod mac;
+pub mod cipher;
 
+pub mod pem;
+pub mod keys;
+pub mod signature;
+pub mod public_key_file;
+pub mod authorized_keys;
+pub mod fingerprint;
+pub mod openssh_key;
+pub mod rfc4716;
+pub mod certificate;
+pub mod auth_options;
+pub mod auth_password;
+pub mod auth_publickey;
+pub mod auth_keyboard_interactive;
+pub mod auth_hostbased;
+pub mod agent;
+pub mod connection;
+pub mod session;
+pub mod pty;
+pub mod server_session;
+pub mod client_session;
+pub mod signals;
+pub mod sftp;
+pub mod sftp_server;
+pub mod sftp_client;
+pub mod forwarding;
+
+pub mod patterns;
+pub mod known_hosts;
+pub mod ssh_config;
+pub mod sshd_config;
+pub mod passwd;
+pub mod dns;
+
+pub mod terminal;
+
+#[cfg(test)]
 mod mocks;
@@ -1,8 +1,109 @@
 use sha1::{Sha1, Digest};
+use sha2::{Digest as Sha2Digest, Sha256};
 
-//TODO rewrite?
-pub fn hmac_sha1(inp: &Vec<u8>) -> Vec<u8> {
+pub fn sha1(inp: &[u8]) -> Vec<u8> {
   let mut sh = Sha1::default();
   sh.input(inp);
   sh.result().to_vec()
 }
+
+pub fn sha256(inp: &[u8]) -> Vec<u8> {
+  <Sha256 as Sha2Digest>::digest(inp).to_vec()
+}
+
+pub const HMAC_SHA2_256: &str = "hmac-sha2-256";
+pub const HMAC_SHA1: &str = "hmac-sha1";
+
+//RFC 2104, both SHA-1 and SHA-256 work on 64 byte blocks
+fn hmac(hash: fn(&[u8]) -> Vec<u8>, key: &[u8], data: &[u8]) -> Vec<u8> {
+  const BLOCK_SIZE: usize = 64;
+
+  let mut padded_key: Vec<u8> = if key.len() > BLOCK_SIZE { hash(key) } else { key.to_vec() };
+  padded_key.resize(BLOCK_SIZE, 0);
+
+  let mut inner: Vec<u8> = padded_key.iter().map(|b| b ^ 0x36).collect();
+  inner.extend_from_slice(data);
+  let mut outer: Vec<u8> = padded_key.iter().map(|b| b ^ 0x5c).collect();
+  outer.extend_from_slice(&hash(&inner));
+  hash(&outer)
+}
+
+pub fn hmac_sha1_with_key(key: &[u8], data: &[u8]) -> Vec<u8> {
+  hmac(sha1, key, data)
+}
+
+pub fn hmac_sha256_with_key(key: &[u8], data: &[u8]) -> Vec<u8> {
+  hmac(sha256, key, data)
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+//key length equals the digest length for both
+pub fn get_mac_length(algorithm: &str) -> Option<usize> {
+  match algorithm {
+    HMAC_SHA2_256 => Some(32),
+    HMAC_SHA1 => Some(20),
+    _ => None,
+  }
+}
+
+//RFC 4253 page 12, mac = MAC(key, sequence_number || unencrypted_packet)
+pub struct PacketMac {
+  hash: fn(&[u8]) -> Vec<u8>,
+  key: Vec<u8>,
+}
+
+impl PacketMac {
+  pub fn new(algorithm: &str, key: &[u8]) -> Option<PacketMac> {
+    let hash: fn(&[u8]) -> Vec<u8> = match algorithm {
+      HMAC_SHA2_256 => sha256,
+      HMAC_SHA1 => sha1,
+      _ => return None,
+    };
+    Some(PacketMac { hash, key: key[..get_mac_length(algorithm)?].to_vec() })
+  }
+
+  pub fn get_length(&self) -> usize {
+    self.key.len()
+  }
+
+  pub fn compute(&self, sequence_number: u32, packet: &[u8]) -> Vec<u8> {
+    let mut data: Vec<u8> = sequence_number.to_be_bytes().to_vec();
+    data.extend_from_slice(packet);
+    hmac(self.hash, &self.key, &data)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn hmac_sha1_with_key_works() {
+    //RFC 2202 test cases 1 and 6
+    assert_eq!(hmac_sha1_with_key(&[0x0b; 20], b"Hi There"),
+               vec![0xb6, 0x17, 0x31, 0x86, 0x55, 0x05, 0x72, 0x64, 0xe2, 0x8b, 0xc0, 0xb6, 0xfb, 0x37, 0x8c, 0x8e, 0xf1, 0x46, 0xbe, 0x00]);
+    assert_eq!(hmac_sha1_with_key(&[0xaa; 80], b"Test Using Larger Than Block-Size Key - Hash Key First"),
+               vec![0xaa, 0x4a, 0xe5, 0xe1, 0x52, 0x72, 0xd0, 0x0e, 0x95, 0x70, 0x56, 0x37, 0xce, 0x8a, 0x3b, 0x55, 0xed, 0x40, 0x21, 0x12]);
+  }
+
+  #[test]
+  fn hmac_sha256_with_key_works() {
+    //RFC 4231 test case 1
+    assert_eq!(hmac_sha256_with_key(&[0x0b; 20], b"Hi There"),
+               vec![0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b, 0xf1, 0x2b,
+                    0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c, 0x2e, 0x32, 0xcf, 0xf7]);
+  }
+
+  #[test]
+  fn packet_mac_covers_sequence_number() {
+    let mac = PacketMac::new(HMAC_SHA1, &[7; 40]).unwrap();
+    assert_eq!(mac.get_length(), 20);
+    assert_eq!(mac.compute(1, b"packet"), hmac_sha1_with_key(&[7; 20], b"\x00\x00\x00\x01packet"));
+    assert_ne!(mac.compute(1, b"packet"), mac.compute(2, b"packet"));
+    assert!(PacketMac::new("hmac-md5", &[7; 40]).is_none());
+  }
+}
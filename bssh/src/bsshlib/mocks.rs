@@ -1,6 +1,8 @@
 
 
 use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use transport::PayloadStream;
 
 pub struct MockReadStream {
     pub input: Vec<u8>,
@@ -74,3 +76,19 @@ impl Read for MockReadStreamInfitnite {
         panic!();
     }
 }
+
+//payloads passed between threads of one process
+pub struct ChannelPayloadStream {
+    pub sender: Sender<Vec<u8>>,
+    pub receiver: Receiver<Vec<u8>>,
+}
+
+impl PayloadStream for ChannelPayloadStream {
+    fn send_payload(&mut self, payload: &[u8]) -> Result<()> {
+        self.sender.send(payload.to_vec()).map_err(|_| Error::from(ErrorKind::BrokenPipe))
+    }
+
+    fn receive_payload(&mut self) -> Result<Vec<u8>> {
+        self.receiver.recv().map_err(|_| Error::from(ErrorKind::UnexpectedEof))
+    }
+}
@@ -7,6 +7,7 @@ use numbers;
 use io_helpers;
 use num::BigUint;
 use num::bigint::ToBigInt;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 const MAX_BUFFER_LENGTH: usize = 255; //RFC 4253 page 5
 const MAX_COMMENT_LINES: usize = 100; //TODO arbitrary value
@@ -201,6 +202,109 @@ pub fn read_kexdh_init_message(stream : &mut Read) -> Result<BigUint, Error> {
 	Ok(positive_e)
 }
 
+//RFC 4253 page 23
+pub struct KexdhReplyMessage {
+	pub host_key: Vec<u8>,
+	pub f: BigUint,
+	pub signature: Vec<u8>,
+}
+
+pub fn write_kexdh_reply_message(stream : &mut dyn Write, reply : &KexdhReplyMessage) -> Result<(), Error> {
+	stream.write_all(&[numbers::SSH_MSG_KEXDH_REPLY])?;
+	io_helpers::write_string(stream, &reply.host_key)?;
+	io_helpers::write_mpint(stream, reply.f.to_bigint().unwrap())?;
+	io_helpers::write_string(stream, &reply.signature)?;
+	Ok(())
+}
+
+pub fn read_kexdh_reply_message(stream : &mut dyn Read) -> Result<KexdhReplyMessage, Error> {
+	let mut reply_byte : [u8; 1] = [0; 1];
+	stream.read_exact(&mut reply_byte)?;
+
+	if reply_byte[0] != numbers::SSH_MSG_KEXDH_REPLY {
+		return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_EXPECTED_KEXDH_REPLY));
+	}
+
+	let host_key = io_helpers::read_string(stream, None)?;
+	let f = match io_helpers::read_mpint(stream)?.to_biguint() {
+		Some(f) => f,
+		None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_DH_ERR_NEGATIVE_E))
+	};
+	let signature = io_helpers::read_string(stream, None)?;
+
+	Ok(KexdhReplyMessage {
+		host_key,
+		f,
+		signature,
+	})
+}
+
+
+
+//RFC 4253 page 23
+pub struct DisconnectMessage {
+	pub reason_code: u32,
+	pub description: String,
+	pub language_tag: String,
+}
+
+pub fn write_disconnect_message(stream : &mut dyn Write, reason_code : u32, description : &str) -> Result<(), Error> {
+	stream.write_all(&[numbers::SSH_MSG_DISCONNECT])?;
+	stream.write_u32::<BigEndian>(reason_code)?;
+	io_helpers::write_string(stream, &description.as_bytes().to_vec())?;
+	io_helpers::write_string(stream, &Vec::new())?;
+	Ok(())
+}
+
+pub fn read_disconnect_message(stream : &mut dyn Read) -> Result<DisconnectMessage, Error> {
+	read_message_number(stream, numbers::SSH_MSG_DISCONNECT)?;
+	let reason_code = stream.read_u32::<BigEndian>()?;
+	let description = io_helpers::read_utf8_string(stream)?;
+	let language_tag = io_helpers::read_utf8_string(stream)?;
+	Ok(DisconnectMessage {
+		reason_code,
+		description,
+		language_tag,
+	})
+}
+
+//RFC 4253 page 24
+pub fn write_service_request_message(stream : &mut dyn Write, service_name : &str) -> Result<(), Error> {
+	stream.write_all(&[numbers::SSH_MSG_SERVICE_REQUEST])?;
+	io_helpers::write_string(stream, &service_name.as_bytes().to_vec())
+}
+
+pub fn read_service_request_message(stream : &mut dyn Read) -> Result<String, Error> {
+	read_message_number(stream, numbers::SSH_MSG_SERVICE_REQUEST)?;
+	io_helpers::read_utf8_string(stream)
+}
+
+pub fn write_service_accept_message(stream : &mut dyn Write, service_name : &str) -> Result<(), Error> {
+	stream.write_all(&[numbers::SSH_MSG_SERVICE_ACCEPT])?;
+	io_helpers::write_string(stream, &service_name.as_bytes().to_vec())
+}
+
+pub fn read_service_accept_message(stream : &mut dyn Read) -> Result<String, Error> {
+	read_message_number(stream, numbers::SSH_MSG_SERVICE_ACCEPT)?;
+	io_helpers::read_utf8_string(stream)
+}
+
+pub fn read_message_number(stream : &mut dyn Read, expected : u8) -> Result<(), Error> {
+	let mut number : [u8; 1] = [0; 1];
+	stream.read_exact(&mut number)?;
+	if number[0] != expected {
+		return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+	}
+	Ok(())
+}
+
+//message number is the first byte of every payload
+pub fn get_message_number(payload : &[u8]) -> Result<u8, Error> {
+	match payload.first() {
+		Some(number) => Ok(*number),
+		None => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+	}
+}
 
 
 #[cfg(test)]
@@ -298,4 +402,38 @@ mod tests {
 		assert!(intersection.is_complete());
 	}
 
+	#[test]
+	fn reading_writing_kexdh_reply_works() {
+		let mut mws = MockWriteStream::new();
+		let reply = KexdhReplyMessage {
+			host_key : vec![1, 2, 3],
+			f : BigUint::from(0x80u32),
+			signature : vec![4, 5],
+		};
+		assert!(write_kexdh_reply_message(&mut mws, &reply).is_ok());
+
+		let mut mrs = MockReadStream::new(mws.output);
+		let read = read_kexdh_reply_message(&mut mrs).unwrap();
+		assert_eq!(read.host_key, reply.host_key);
+		assert_eq!(read.f, reply.f);
+		assert_eq!(read.signature, reply.signature);
+	}
+
+	#[test]
+	fn reading_writing_service_messages_works() {
+		let mut mws = MockWriteStream::new();
+		write_service_request_message(&mut mws, "ssh-userauth").unwrap();
+		write_service_accept_message(&mut mws, "ssh-userauth").unwrap();
+		write_disconnect_message(&mut mws, numbers::SSH_DISCONNECT_BY_APPLICATION, "bye").unwrap();
+
+		let mut mrs = MockReadStream::new(mws.output);
+		assert_eq!(read_service_request_message(&mut mrs).unwrap(), "ssh-userauth");
+		assert!(read_service_request_message(&mut mrs).is_err());
+		mrs.pos -= 1;
+		assert_eq!(read_service_accept_message(&mut mrs).unwrap(), "ssh-userauth");
+		let disconnect = read_disconnect_message(&mut mrs).unwrap();
+		assert_eq!(disconnect.reason_code, numbers::SSH_DISCONNECT_BY_APPLICATION);
+		assert_eq!(disconnect.description, "bye");
+	}
+
 }
@@ -25,7 +25,39 @@ pub const SSH_MSG_CHANNEL_REQUEST           : u8 =  98;
 pub const SSH_MSG_CHANNEL_SUCCESS           : u8 =  99;
 pub const SSH_MSG_CHANNEL_FAILURE           : u8 = 100;
 
+//RFC 4254 page 7
+pub const SSH_OPEN_ADMINISTRATIVELY_PROHIBITED    : u32 =  1;
+pub const SSH_OPEN_CONNECT_FAILED                 : u32 =  2;
+pub const SSH_OPEN_UNKNOWN_CHANNEL_TYPE           : u32 =  3;
+pub const SSH_OPEN_RESOURCE_SHORTAGE              : u32 =  4;
+
+//RFC 4254 page 8
+pub const SSH_EXTENDED_DATA_STDERR                : u32 =  1;
+
 //https://www.rfc-editor.org/errata_search.php?rfc=4253
 
 pub const SSH_MSG_KEXDH_INIT				: u8 = 30;
-pub const SSH_MSG_KEXDH_REPLY				: u8 = 31;
\ No newline at end of file
+pub const SSH_MSG_KEXDH_REPLY				: u8 = 31;
+
+//RFC 4252 page 6 and 11, method specific messages share numbers 60-79
+pub const SSH_MSG_USERAUTH_PK_OK            : u8 =  60;
+pub const SSH_MSG_USERAUTH_PASSWD_CHANGEREQ : u8 =  60;
+pub const SSH_MSG_USERAUTH_INFO_REQUEST     : u8 =  60;
+pub const SSH_MSG_USERAUTH_INFO_RESPONSE    : u8 =  61;
+
+//RFC 4253 page 23
+pub const SSH_DISCONNECT_HOST_NOT_ALLOWED_TO_CONNECT      : u32 =  1;
+pub const SSH_DISCONNECT_PROTOCOL_ERROR                   : u32 =  2;
+pub const SSH_DISCONNECT_KEY_EXCHANGE_FAILED              : u32 =  3;
+pub const SSH_DISCONNECT_RESERVED                         : u32 =  4;
+pub const SSH_DISCONNECT_MAC_ERROR                        : u32 =  5;
+pub const SSH_DISCONNECT_COMPRESSION_ERROR                : u32 =  6;
+pub const SSH_DISCONNECT_SERVICE_NOT_AVAILABLE            : u32 =  7;
+pub const SSH_DISCONNECT_PROTOCOL_VERSION_NOT_SUPPORTED   : u32 =  8;
+pub const SSH_DISCONNECT_HOST_KEY_NOT_VERIFIABLE          : u32 =  9;
+pub const SSH_DISCONNECT_CONNECTION_LOST                  : u32 = 10;
+pub const SSH_DISCONNECT_BY_APPLICATION                   : u32 = 11;
+pub const SSH_DISCONNECT_TOO_MANY_CONNECTIONS             : u32 = 12;
+pub const SSH_DISCONNECT_AUTH_CANCELLED_BY_USER           : u32 = 13;
+pub const SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE   : u32 = 14;
+pub const SSH_DISCONNECT_ILLEGAL_USER_NAME                : u32 = 15;
@@ -1,84 +1,154 @@
-use std::io::{Cursor, Error, ErrorKind, Read};
-use rand;
+use std::io::{Error, ErrorKind, Read, Write};
+use rand_core::{OsRng, RngCore};
 use errors;
-use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
+use byteorder::{BigEndian, WriteBytesExt, ByteOrder};
+use cipher::PacketCipher;
 use mac;
+use mac::PacketMac;
 
-const MAX_PACKET_LENGTH : usize = 4*1024*1024; //TODO arbitrary value, probably too large
+const MAX_PACKET_LENGTH : usize = 256*1024; //RFC 4253 only requires 35000, as OpenSSH
+//RFC 4253 on random padding, packets are aligned to 8 bytes until there is a cipher
+const PLAIN_BLOCK_SIZE : usize = 8;
+const MIN_PADDING_LENGTH : usize = 4;
 
-pub fn get_packet_from_payload(payload: &mut Vec<u8>, cipher_block_size : Option<u8>, enable_mac : bool, sequence_number : u32) -> Result<Vec<u8>, Error> {
-    let mut result: Vec<u8> = Vec::new();
+//cipher and MAC of one direction, in effect after SSH_MSG_NEWKEYS
+pub struct PacketKeys {
+    pub cipher: PacketCipher,
+    pub mac: PacketMac,
+}
 
-    let packet_length_without_random = 4 + 1 + payload.len(); //TODO this 4+ is not according to protocol, but gets openssh working
-    //TODO this is ad-hoc formula
-    let alignment : u8 = match cipher_block_size {
-    	Some(size) => size,
-    	None => 8 //rfc4253 on random padding
-    };
+fn bad_length() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_BAD_PACKET_LENGTH)
+}
 
-    let min_padding_length: u8 = alignment - ((packet_length_without_random % alignment as usize) as u8);
+//RFC 4253 page 7: packet_length || padding_length || payload || random padding, then the MAC
+//of the unencrypted packet, the whole packet is encrypted
+pub fn write_packet(stream: &mut dyn Write, payload: &[u8], keys: Option<&mut PacketKeys>, sequence_number: u32) -> Result<(), Error> {
+    let block_size = keys.as_ref().map(|k| k.cipher.get_block_size()).unwrap_or(PLAIN_BLOCK_SIZE);
 
-	//RFC 4253 page 8 "there MUST be at least four bytes of padding"
-	let padding_length : u8 = if min_padding_length >= 4 { min_padding_length } else {min_padding_length + alignment};
-	println!("min_padding {}, alignment {}, padding_length {}", min_padding_length, alignment, padding_length);
+    //"there MUST be at least four bytes of padding", the length field is aligned too
+    let mut padding_length = block_size - (4 + 1 + payload.len()) % block_size;
+    if padding_length < MIN_PADDING_LENGTH {
+        padding_length += block_size;
+    }
+    let mut random_padding = vec![0; padding_length];
+    OsRng.fill_bytes(&mut random_padding);
+
+    //packet_length - not including 'mac' or the 'packet_length' itself.
+    let packet_length = 1 + payload.len() + padding_length;
+    let mut packet: Vec<u8> = Vec::with_capacity(4 + packet_length);
+    packet.write_u32::<BigEndian>(packet_length as u32)?;
+    packet.push(padding_length as u8);
+    packet.extend_from_slice(payload);
+    packet.extend_from_slice(&random_padding);
+
+    if let Some(keys) = keys {
+        let packet_mac = keys.mac.compute(sequence_number, &packet);
+        keys.cipher.apply(&mut packet);
+        packet.extend_from_slice(&packet_mac);
+    }
+    stream.write_all(&packet)?;
+    stream.flush()
+}
+
+//the first block holds the length, the rest is read only after it is checked
+pub fn read_packet(stream: &mut dyn Read, keys: Option<&mut PacketKeys>, sequence_number: u32) -> Result<Vec<u8>, Error> {
+    let block_size = keys.as_ref().map(|k| k.cipher.get_block_size()).unwrap_or(PLAIN_BLOCK_SIZE);
 
-    let mut random_padding: Vec<u8> = Vec::new();
-    random_padding.reserve(padding_length as usize);
-    for _ in 0..padding_length {
-        random_padding.push(rand::random::<u8>());
+    let mut packet = vec![0; block_size];
+    stream.read_exact(&mut packet)?;
+    let mut keys = keys;
+    if let Some(ref mut keys) = keys {
+        keys.cipher.apply(&mut packet);
     }
 
-	//packet_length - not including 'mac' or the 'packet_length' itself.
-	let packet_length : usize = 1 + payload.len() + random_padding.len();
-	println!("writing packet_length {}", packet_length);
-    result.write_u32::<BigEndian>(packet_length as u32)?;
-    result.push(padding_length);
-    //println!("writing padding_length {}", padding_length);
-    result.append(payload);
-    result.append(&mut random_padding);
-
-    //TODO hardcoded sha1mac
-	if enable_mac {
-		let mut all : Vec<u8> = Vec::new();
-		all.write_u32::<BigEndian>(sequence_number)?;
-		all.append(result.clone().as_mut());
-		let mut mac = mac::hmac_sha1(&all);
-		result.append(&mut mac);
-	}
-
-    Ok(result)
+    let packet_length = BigEndian::read_u32(&packet[0..4]) as usize;
+    if packet_length > MAX_PACKET_LENGTH || 4 + packet_length < block_size || !(4 + packet_length).is_multiple_of(block_size) {
+        return Err(bad_length());
+    }
+
+    let mut rest = vec![0; 4 + packet_length - block_size];
+    stream.read_exact(&mut rest)?;
+    if let Some(ref mut keys) = keys {
+        keys.cipher.apply(&mut rest);
+    }
+    packet.append(&mut rest);
+
+    if let Some(keys) = keys {
+        let mut received_mac = vec![0; keys.mac.get_length()];
+        stream.read_exact(&mut received_mac)?;
+        if !mac::constant_time_eq(&received_mac, &keys.mac.compute(sequence_number, &packet)) {
+            return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_BAD_PACKET_MAC));
+        }
+    }
+
+    let padding_length = packet[4] as usize;
+    if padding_length < MIN_PADDING_LENGTH || padding_length + 1 > packet_length {
+        return Err(bad_length());
+    }
+    Ok(packet[5..4 + packet_length - padding_length].to_vec())
 }
 
-pub fn read_packet_from_stream(stream : &mut Read, cipher_block_size : Option<u8>) -> Result<Vec<u8>, Error> {
-	let read_to_determine_packet_length : u8 = match cipher_block_size {
-    	Some(size) => size,
-    	None => 4
-    };
-
-	let mut length_buffer : Vec<u8> = Vec::new();
-	length_buffer.resize(read_to_determine_packet_length as usize, 0 as u8);
-	stream.read_exact(&mut length_buffer)?;
-
-	//TODO here some decoding takes place?
-	let packet_length : u32 = Cursor::new(length_buffer[0..4].to_vec()).read_u32::<BigEndian>()?; //TODO this is horrible
-	//println!("reading packet_length = {}", packet_length);
-
-	if packet_length as usize > MAX_PACKET_LENGTH {
-		return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_BUFFER_CAPACITY_EXCEEDED)); //TODO better message?
-	}
-
-	//TODO if there is a remainder of what has been read except the length_buffer, this needs to be transfered here
-
-	//read padding_length
-	let mut padding_length_buf : [u8; 1] = [0; 1];
-	stream.read(&mut padding_length_buf)?;
-	let padding_length : u8 = padding_length_buf[0];
-	//println!("reading padding_length = {}", padding_length);
-
-	let mut buffer : Vec<u8> = Vec::new();
-	buffer.resize((packet_length - 1) as usize, 0); // -1 stands for padding_length which is read before
-	stream.read_exact(&mut buffer)?;
-	//println!("read!");
-	//println!("{:?}", &buffer);
-	Ok(buffer[0..(buffer.len() - (padding_length as usize))].to_vec()) //TODO this is also horrible
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use cipher;
+
+    fn get_keys() -> PacketKeys {
+        PacketKeys {
+            cipher: PacketCipher::new(cipher::AES128_CTR, &[1; 16], &[2; 16]).unwrap(),
+            mac: PacketMac::new(mac::HMAC_SHA2_256, &[3; 32]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn plain_packets_are_aligned_to_8_bytes() {
+        for length in 0..20 {
+            let mut written: Vec<u8> = Vec::new();
+            write_packet(&mut written, &vec![5; length], None, 0).unwrap();
+            assert_eq!(written.len() % 8, 0);
+            assert!(written[4] >= 4);
+            assert_eq!(read_packet(&mut written.as_slice(), None, 0).unwrap(), vec![5; length]);
+        }
+    }
+
+    #[test]
+    fn encrypted_packets_roundtrip() {
+        let (mut sending, mut receiving) = (get_keys(), get_keys());
+        let mut written: Vec<u8> = Vec::new();
+        write_packet(&mut written, b"first", Some(&mut sending), 7).unwrap();
+        write_packet(&mut written, b"second payload", Some(&mut sending), 8).unwrap();
+        assert!(!written.windows(5).any(|w| w == b"first"));
+
+        let mut input = written.as_slice();
+        assert_eq!(read_packet(&mut input, Some(&mut receiving), 7).unwrap(), b"first".to_vec());
+        assert_eq!(read_packet(&mut input, Some(&mut receiving), 8).unwrap(), b"second payload".to_vec());
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn tampered_or_reordered_packets_are_rejected() {
+        let mut written: Vec<u8> = Vec::new();
+        write_packet(&mut written, b"payload", Some(&mut get_keys()), 0).unwrap();
+
+        let err = read_packet(&mut written.as_slice(), Some(&mut get_keys()), 1).unwrap_err();
+        assert_eq!(err.to_string(), errors::BSSH_ERR_BAD_PACKET_MAC);
+
+        let mut tampered = written.clone();
+        tampered[10] ^= 1;
+        let err = read_packet(&mut tampered.as_slice(), Some(&mut get_keys()), 0).unwrap_err();
+        assert_eq!(err.to_string(), errors::BSSH_ERR_BAD_PACKET_MAC);
+    }
+
+    #[test]
+    fn bad_lengths_are_rejected() {
+        let mut too_long: Vec<u8> = vec![0x7f, 0, 0, 0, 4, 0, 0, 0];
+        too_long.resize(16, 0);
+        assert_eq!(read_packet(&mut too_long.as_slice(), None, 0).unwrap_err().to_string(), errors::BSSH_ERR_BAD_PACKET_LENGTH);
+
+        //padding_length larger than the packet
+        let bad_padding: Vec<u8> = vec![0, 0, 0, 12, 200, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(read_packet(&mut bad_padding.as_slice(), None, 0).unwrap_err().to_string(), errors::BSSH_ERR_BAD_PACKET_LENGTH);
+    }
 }
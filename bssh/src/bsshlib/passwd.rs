@@ -0,0 +1,103 @@
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+use libc;
+
+//passwd(5) entry of a local user
+
+pub struct Passwd {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+}
+
+const INITIAL_BUFFER_LENGTH: usize = 1024;
+const MAX_BUFFER_LENGTH: usize = 1024 * 1024;
+
+unsafe fn c_string(s: *const libc::c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    }
+}
+
+//calls getpwnam_r(3) or getpwuid_r(3) like lookup with growing buffer
+fn lookup<F>(mut get: F) -> Option<Passwd>
+    where F: FnMut(&mut libc::passwd, &mut Vec<libc::c_char>, &mut *mut libc::passwd) -> libc::c_int
+{
+    let mut buffer: Vec<libc::c_char> = vec![0; INITIAL_BUFFER_LENGTH];
+
+    loop {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let ret = get(&mut entry, &mut buffer, &mut result);
+        if ret == libc::ERANGE && buffer.len() < MAX_BUFFER_LENGTH {
+            let length = buffer.len() * 2;
+            buffer.resize(length, 0);
+            continue;
+        }
+        if ret != 0 || result.is_null() {
+            return None;
+        }
+        return unsafe {
+            Some(Passwd {
+                name: c_string(entry.pw_name),
+                uid: entry.pw_uid,
+                gid: entry.pw_gid,
+                home: PathBuf::from(c_string(entry.pw_dir)),
+                shell: PathBuf::from(c_string(entry.pw_shell)),
+            })
+        };
+    }
+}
+
+//None if the user does not exist or can not be looked up
+pub fn get_user_by_name(name: &str) -> Option<Passwd> {
+    let c_name = CString::new(name).ok()?;
+    lookup(|entry, buffer, result| unsafe { libc::getpwnam_r(c_name.as_ptr(), entry, buffer.as_mut_ptr(), buffer.len(), result) })
+}
+
+pub fn get_user_by_uid(uid: u32) -> Option<Passwd> {
+    lookup(|entry, buffer, result| unsafe { libc::getpwuid_r(uid, entry, buffer.as_mut_ptr(), buffer.len(), result) })
+}
+
+//group(5) name, None if the group does not exist
+pub fn get_group_name(gid: u32) -> Option<String> {
+    let mut buffer: Vec<libc::c_char> = vec![0; INITIAL_BUFFER_LENGTH];
+    loop {
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::group = std::ptr::null_mut();
+        let ret = unsafe { libc::getgrgid_r(gid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        if ret == libc::ERANGE && buffer.len() < MAX_BUFFER_LENGTH {
+            let length = buffer.len() * 2;
+            buffer.resize(length, 0);
+            continue;
+        }
+        if ret != 0 || result.is_null() {
+            return None;
+        }
+        return Some(unsafe { c_string(entry.gr_name) });
+    }
+}
+
+//user running this process
+pub fn get_current_user() -> Option<Passwd> {
+    get_user_by_uid(unsafe { libc::getuid() })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn get_user_by_name_finds_root() {
+        let root = get_user_by_name("root").unwrap();
+        assert_eq!((root.name.as_str(), root.uid, root.gid), ("root", 0, 0));
+        assert!(get_user_by_name("no such user here").is_none());
+        assert_eq!(get_user_by_uid(0).unwrap().name, "root");
+        assert_eq!(get_group_name(0).unwrap(), "root");
+    }
+}
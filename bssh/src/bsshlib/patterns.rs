@@ -0,0 +1,140 @@
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use errors;
+
+//OpenSSH style patterns (see PATTERNS in ssh_config(5)), "*" matches any sequence and "?" exactly one character
+
+pub fn match_pattern(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    let mut s_pos = 0;
+    let mut p_pos = 0;
+    //position of the last "*" in pattern and of s when we met it, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+
+    while s_pos < s.len() {
+        if p_pos < pattern.len() && (pattern[p_pos] == '?' || pattern[p_pos] == s[s_pos]) {
+            s_pos += 1;
+            p_pos += 1;
+        } else if p_pos < pattern.len() && pattern[p_pos] == '*' {
+            star = Some((p_pos, s_pos));
+            p_pos += 1;
+        } else if let Some((star_p, star_s)) = star {
+            p_pos = star_p + 1;
+            s_pos = star_s + 1;
+            star = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p_pos..].iter().all(|c| *c == '*')
+}
+
+//matches s against comma separated list of patterns, which may be negated with "!".
+//Some(false) if a negated pattern matched (this always wins), Some(true) if a positive one did, None otherwise.
+pub fn match_pattern_list(s: &str, list: &str, ignore_case: bool) -> Option<bool> {
+    let s = if ignore_case { s.to_lowercase() } else { s.to_string() };
+    let mut matched: Option<bool> = None;
+
+    for pattern in list.split(',') {
+        let pattern = pattern.trim();
+        let (negated, pattern) = if let Some(stripped) = pattern.strip_prefix('!') {
+            (true, stripped)
+        } else {
+            (false, pattern)
+        };
+        if pattern.is_empty() {
+            continue;
+        }
+        let pattern = if ignore_case { pattern.to_lowercase() } else { pattern.to_string() };
+
+        if match_pattern(&s, &pattern) {
+            if negated {
+                return Some(false);
+            }
+            matched = Some(true);
+        }
+    }
+
+    matched
+}
+
+fn match_cidr(addr: &IpAddr, cidr: &str) -> Result<bool, Error> {
+    let bad_cidr = || Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_BAD_CIDR);
+    let (network, bits) = match cidr.find('/') {
+        Some(slash) => (&cidr[..slash], Some(cidr[slash + 1..].parse::<u32>().map_err(|_| bad_cidr())?)),
+        None => (cidr, None),
+    };
+    let network: IpAddr = network.parse().map_err(|_| bad_cidr())?;
+
+    let (addr, network, max_bits) = match (*addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+        _ => return Ok(false),
+    };
+    let bits = bits.unwrap_or(max_bits);
+    if bits > max_bits {
+        return Err(bad_cidr());
+    }
+
+    let mask: u128 = if bits == 0 { 0 } else { (!0u128 << (128 - bits)) >> (128 - max_bits) };
+    //host bits set in the network address make the entry invalid, same as in OpenSSH
+    if network & !mask & ((!0u128) >> (128 - max_bits)) != 0 {
+        return Err(bad_cidr());
+    }
+    Ok(addr & mask == network)
+}
+
+//matches address against comma separated list of "address[/bits]" entries, any invalid entry is an error
+pub fn match_cidr_list(addr: &IpAddr, list: &str) -> Result<bool, Error> {
+    let mut matched = false;
+    for cidr in list.split(',') {
+        if match_cidr(addr, cidr.trim())? {
+            matched = true;
+        }
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn match_pattern_works() {
+        assert!(match_pattern("example.com", "example.com"));
+        assert!(match_pattern("example.com", "*.com"));
+        assert!(match_pattern("example.com", "ex*le.c?m"));
+        assert!(match_pattern("a.b.c", "*.*"));
+        assert!(match_pattern("", "*"));
+        assert!(!match_pattern("example.org", "*.com"));
+        assert!(!match_pattern("example.com", "?"));
+        assert!(!match_pattern("abc", "abcd"));
+    }
+
+    #[test]
+    fn match_pattern_list_handles_negation() {
+        assert_eq!(match_pattern_list("host.example.com", "*.example.com,!bad.example.com", false), Some(true));
+        assert_eq!(match_pattern_list("bad.example.com", "*.example.com,!bad.example.com", false), Some(false));
+        assert_eq!(match_pattern_list("other.org", "*.example.com", false), None);
+        assert_eq!(match_pattern_list("HOST", "host", true), Some(true));
+        assert_eq!(match_pattern_list("HOST", "host", false), None);
+    }
+
+    #[test]
+    fn match_cidr_list_works() {
+        let v4: IpAddr = "192.168.1.20".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(match_cidr_list(&v4, "10.0.0.0/8,192.168.1.0/24").unwrap());
+        assert!(match_cidr_list(&v4, "192.168.1.20").unwrap());
+        assert!(match_cidr_list(&v4, "0.0.0.0/0").unwrap());
+        assert!(!match_cidr_list(&v4, "192.168.2.0/24,2001:db8::/32").unwrap());
+        assert!(match_cidr_list(&v6, "192.168.2.0/24,2001:db8::/32").unwrap());
+        assert!(match_cidr_list(&v4, "192.168.1.1/24").is_err());
+        assert!(match_cidr_list(&v4, "192.168.1.0/33").is_err());
+        assert!(match_cidr_list(&v4, "example.com").is_err());
+    }
+}
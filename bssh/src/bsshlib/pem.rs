@@ -0,0 +1,215 @@
+use std::io::{Error, ErrorKind};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use md5::{Md5, Digest};
+use aes::{Aes128, Aes192, Aes256};
+use des::TdesEde3;
+use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+use cbc::cipher::block_padding::Pkcs7;
+use errors;
+
+const PEM_BEGIN: &str = "-----BEGIN ";
+const PEM_END: &str = "-----END ";
+const PEM_DASHES: &str = "-----";
+
+pub struct PemBlock {
+    pub label: String,
+    pub headers: Vec<(String, String)>,
+    pub data: Vec<u8>,
+}
+
+impl PemBlock {
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0 == name).map(|h| h.1.as_str())
+    }
+
+    //RFC 1421 style "Proc-Type: 4,ENCRYPTED", as written by OpenSSL for traditional keys
+    pub fn is_encrypted(&self) -> bool {
+        match self.get_header("Proc-Type") {
+            Some(value) => value.split(',').any(|v| v.trim() == "ENCRYPTED"),
+            None => false,
+        }
+    }
+}
+
+//reads the first PEM block found in text, anything before it is ignored
+pub fn read_pem_block(text: &str) -> Result<PemBlock, Error> {
+    let mut lines = text.lines().map(|l| l.trim());
+
+    let label: String = loop {
+        match lines.next() {
+            Some(line) => {
+                if line.starts_with(PEM_BEGIN) && line.ends_with(PEM_DASHES) && line.len() > PEM_BEGIN.len() + PEM_DASHES.len() {
+                    break line[PEM_BEGIN.len()..line.len() - PEM_DASHES.len()].to_string();
+                }
+            }
+            None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_NO_BLOCK_FOUND)),
+        }
+    };
+
+    let end_line = format!("{}{}{}", PEM_END, label, PEM_DASHES);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut body = String::new();
+    let mut in_headers = true;
+
+    loop {
+        let line = match lines.next() {
+            Some(line) => line,
+            None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_MALFORMED)),
+        };
+
+        if line == end_line {
+            break;
+        }
+
+        if in_headers {
+            if let Some(colon) = line.find(':') {
+                headers.push((line[..colon].trim().to_string(), line[colon + 1..].trim().to_string()));
+                continue;
+            }
+            in_headers = false;
+            if line.is_empty() {
+                continue;
+            }
+        }
+
+        body.push_str(line);
+    }
+
+    let data = match STANDARD.decode(body.as_bytes()) {
+        Ok(data) => data,
+        Err(_) => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_MALFORMED)),
+    };
+
+    Ok(PemBlock {
+        label,
+        headers,
+        data,
+    })
+}
+
+pub fn write_pem_block(label: &str, data: &[u8], line_length: usize) -> String {
+    let encoded = STANDARD.encode(data);
+    let mut res = format!("{}{}{}\n", PEM_BEGIN, label, PEM_DASHES);
+
+    for chunk in encoded.as_bytes().chunks(line_length) {
+        res.push_str(&String::from_utf8_lossy(chunk));
+        res.push('\n');
+    }
+
+    res.push_str(&format!("{}{}{}\n", PEM_END, label, PEM_DASHES));
+    res
+}
+
+//OpenSSL EVP_BytesToKey with MD5 and a single iteration, the salt is first 8 bytes of the iv
+fn evp_bytes_to_key(passphrase: &[u8], salt: &[u8], key_length: usize) -> Vec<u8> {
+    let mut key: Vec<u8> = Vec::new();
+    let mut prev: Vec<u8> = Vec::new();
+
+    while key.len() < key_length {
+        let mut md5 = Md5::new();
+        md5.update(&prev);
+        md5.update(passphrase);
+        md5.update(salt);
+        prev = md5.finalize().to_vec();
+        key.extend_from_slice(&prev);
+    }
+
+    key.truncate(key_length);
+    key
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let mut res: Vec<u8> = Vec::new();
+    for i in (0..text.len()).step_by(2) {
+        match u8::from_str_radix(text.get(i..i + 2)?, 16) {
+            Ok(byte) => res.push(byte),
+            Err(_) => return None,
+        }
+    }
+    Some(res)
+}
+
+fn cbc_decrypt<C: BlockDecryptMut + KeyIvInit>(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let decryptor = match C::new_from_slices(key, iv) {
+        Ok(decryptor) => decryptor,
+        Err(_) => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_MALFORMED)),
+    };
+
+    //a padding error is the only hint we get that passphrase was wrong
+    match decryptor.decrypt_padded_vec_mut::<Pkcs7>(data) {
+        Ok(plain) => Ok(plain),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_KEY_DECRYPTION_FAILED)),
+    }
+}
+
+//decrypts body of traditional (non PKCS#8) encrypted block, according to DEK-Info header
+pub fn decrypt_pem_block(block: &PemBlock, passphrase: &[u8]) -> Result<Vec<u8>, Error> {
+    let dek_info = match block.get_header("DEK-Info") {
+        Some(dek_info) => dek_info,
+        None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_MALFORMED)),
+    };
+
+    let mut parts = dek_info.splitn(2, ',');
+    let cipher = parts.next().unwrap_or("").trim();
+    let iv = match parts.next().and_then(|iv| parse_hex(iv.trim())) {
+        Some(iv) => iv,
+        None => return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_MALFORMED)),
+    };
+
+    if iv.len() < 8 {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_MALFORMED));
+    }
+
+    match cipher {
+        "AES-128-CBC" => cbc_decrypt::<cbc::Decryptor<Aes128>>(&evp_bytes_to_key(passphrase, &iv[..8], 16), &iv, &block.data),
+        "AES-192-CBC" => cbc_decrypt::<cbc::Decryptor<Aes192>>(&evp_bytes_to_key(passphrase, &iv[..8], 24), &iv, &block.data),
+        "AES-256-CBC" => cbc_decrypt::<cbc::Decryptor<Aes256>>(&evp_bytes_to_key(passphrase, &iv[..8], 32), &iv, &block.data),
+        "DES-EDE3-CBC" => cbc_decrypt::<cbc::Decryptor<TdesEde3>>(&evp_bytes_to_key(passphrase, &iv[..8], 24), &iv, &block.data),
+        _ => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PEM_UNSUPPORTED_CIPHER)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn read_pem_block_reads_headers_and_body() {
+        let text = "garbage\n-----BEGIN TEST-----\nProc-Type: 4,ENCRYPTED\nDEK-Info: AES-128-CBC,00\n\naGVsbG8g\nd29ybGQ=\n-----END TEST-----\n";
+        let block = read_pem_block(text).unwrap();
+        assert_eq!(block.label, "TEST");
+        assert_eq!(block.get_header("DEK-Info"), Some("AES-128-CBC,00"));
+        assert!(block.is_encrypted());
+        assert_eq!(block.data, b"hello world".to_vec());
+    }
+
+    #[test]
+    fn read_pem_block_rejects_unterminated_block() {
+        assert!(read_pem_block("-----BEGIN TEST-----\naGVsbG8g\n").is_err());
+        assert!(read_pem_block("-----BEGIN TEST-----\naGVsbG8g\n-----END OTHER-----\n").is_err());
+        assert!(read_pem_block("no pem here").is_err());
+    }
+
+    #[test]
+    fn write_pem_block_roundtrips() {
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let text = write_pem_block("SOME DATA", &data, 64);
+        assert!(text.lines().all(|l| l.len() <= 64 || l.starts_with("-----")));
+        let block = read_pem_block(&text).unwrap();
+        assert_eq!(block.label, "SOME DATA");
+        assert_eq!(block.data, data);
+    }
+
+    #[test]
+    fn evp_bytes_to_key_works() {
+        //openssl enc -aes-128-cbc -pass pass:secret -S 0001020304050607 -P -md md5
+        assert_eq!(evp_bytes_to_key(b"secret", &[0, 1, 2, 3, 4, 5, 6, 7], 16),
+                   parse_hex("035FB8145B73CF111570DC936112BE9C").unwrap());
+    }
+}
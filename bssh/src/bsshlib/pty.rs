@@ -0,0 +1,126 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Error;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use libc;
+use session;
+use terminal;
+use terminal::WindowSize;
+
+//Linux pseudo-terminal (pty(7)), the slave end becomes the controlling terminal of a child process
+pub struct Pty {
+    pub master: File,
+    //kept open until the child is started, so the terminal can be set up before
+    slave: Option<File>,
+    pub slave_path: PathBuf,
+}
+
+fn check(ret: libc::c_int) -> Result<libc::c_int, Error> {
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+impl Pty {
+    pub fn open() -> Result<Pty, Error> {
+        unsafe {
+            let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC))?;
+            let master = File::from_raw_fd(fd);
+            check(libc::grantpt(fd))?;
+            check(libc::unlockpt(fd))?;
+            let mut name = [0 as libc::c_char; 128];
+            let ret = libc::ptsname_r(fd, name.as_mut_ptr(), name.len());
+            if ret != 0 {
+                return Err(Error::from_raw_os_error(ret));
+            }
+            let slave_name = std::ffi::CStr::from_ptr(name.as_ptr()).to_owned();
+            let slave_fd = check(libc::open(slave_name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC))?;
+            Ok(Pty {
+                master,
+                slave: Some(File::from_raw_fd(slave_fd)),
+                slave_path: PathBuf::from(std::ffi::OsStr::from_bytes(slave_name.to_bytes())),
+            })
+        }
+    }
+
+    fn get_setup_fd(&self) -> libc::c_int {
+        match self.slave {
+            Some(ref slave) => slave.as_raw_fd(),
+            None => self.master.as_raw_fd(),
+        }
+    }
+
+    pub fn set_terminal_modes(&self, modes: &[(u8, u32)]) -> Result<(), Error> {
+        let fd = self.get_setup_fd();
+        let mut attributes = terminal::get_attributes(fd)?;
+        session::apply_terminal_modes(&mut attributes, modes);
+        terminal::set_attributes(fd, &attributes)
+    }
+
+    pub fn set_window_size(&self, size: &WindowSize) -> Result<(), Error> {
+        terminal::set_window_size(self.master.as_raw_fd(), size)
+    }
+
+    //the child gets a new session with the slave as its controlling terminal and standard streams
+    pub fn attach(&self, command: &mut Command) -> Result<(), Error> {
+        let slave_path = CString::new(self.slave_path.as_os_str().as_bytes())?;
+        unsafe {
+            command.pre_exec(move || {
+                check(libc::setsid())?;
+                let fd = check(libc::open(slave_path.as_ptr(), libc::O_RDWR))?;
+                check(libc::ioctl(fd, libc::TIOCSCTTY, 0))?;
+                for target in 0..3 {
+                    check(libc::dup2(fd, target))?;
+                }
+                if fd > 2 {
+                    libc::close(fd);
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    //once the child has its own slave descriptor, the master sees end of file (EIO) when the child is gone
+    pub fn close_slave(&mut self) {
+        self.slave = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn pty_runs_child_with_terminal() {
+        let mut pty = Pty::open().unwrap();
+        let size = WindowSize { columns: 100, rows: 30, width_pixels: 0, height_pixels: 0 };
+        pty.set_window_size(&size).unwrap();
+        pty.set_terminal_modes(&[(session::ONLCR, 0)]).unwrap();
+
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg("stty size");
+        pty.attach(&mut command).unwrap();
+        let mut child = command.spawn().unwrap();
+        pty.close_slave();
+
+        let mut output: Vec<u8> = Vec::new();
+        let mut buffer = [0u8; 256];
+        //EIO ends the output
+        while let Ok(n) = pty.master.read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+            output.extend_from_slice(&buffer[..n]);
+        }
+        assert!(child.wait().unwrap().success());
+        assert_eq!(output, b"30 100\n");
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use errors;
+use keys::PublicKey;
+
+const MAX_PUBLIC_KEY_FILE_LENGTH: u64 = 64 * 1024; //TODO arbitrary value
+
+//OpenSSH one-line public key, "<type> <base64 blob> [comment]"
+pub struct PublicKeyLine {
+    pub key: PublicKey,
+    pub comment: String,
+}
+
+fn malformed_line() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PUBLIC_KEY_LINE_MALFORMED)
+}
+
+//splits off the first whitespace separated token, returning (token, rest)
+pub fn split_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(pos) => (&text[..pos], text[pos..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+pub fn read_public_key_line(line: &str) -> Result<PublicKeyLine, Error> {
+    let (key_type, rest) = split_token(line);
+    let (encoded, comment) = split_token(rest);
+
+    if key_type.is_empty() || encoded.is_empty() {
+        return Err(malformed_line());
+    }
+
+    let blob = match STANDARD.decode(encoded) {
+        Ok(blob) => blob,
+        Err(_) => return Err(malformed_line()),
+    };
+
+    let key = PublicKey::from_blob(&blob)?;
+    if key.get_algorithm_name() != key_type {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_PUBLIC_KEY_TYPE_MISMATCH));
+    }
+
+    Ok(PublicKeyLine {
+        key,
+        comment: comment.trim_end().to_string(),
+    })
+}
+
+pub fn write_public_key_line(key: &PublicKey, comment: &str) -> String {
+    let mut res = format!("{} {}", key.get_algorithm_name(), STANDARD.encode(key.to_blob()));
+    if !comment.is_empty() {
+        res.push(' ');
+        res.push_str(comment);
+    }
+    res
+}
+
+//reads the first non-empty, non-comment line of a .pub file
+pub fn load_public_key_file(path: &Path) -> Result<PublicKeyLine, Error> {
+    let mut text = String::new();
+    File::open(path)?.take(MAX_PUBLIC_KEY_FILE_LENGTH).read_to_string(&mut text)?;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        return read_public_key_line(line);
+    }
+
+    Err(malformed_line())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const ED25519_LINE: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIK3FM+xl4coRYITfMC07c5t0WngvwhzU5Te9htYbXReo user@example.com";
+    const ECDSA_LINE: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBCQoplL/MzltOZ0evbzcLlpo09bl1fQgyyHKVupemxgaLujZHYNQoiYCKsHvndTJzNHVlGbocUmwMKA1Vz3lv20=";
+
+    #[test]
+    fn read_public_key_line_works() {
+        let line = read_public_key_line(ED25519_LINE).unwrap();
+        assert_eq!(line.key.get_algorithm_name(), "ssh-ed25519");
+        assert_eq!(line.comment, "user@example.com");
+
+        let line = read_public_key_line(ECDSA_LINE).unwrap();
+        assert_eq!(line.key.get_algorithm_name(), "ecdsa-sha2-nistp256");
+        assert_eq!(line.comment, "");
+    }
+
+    #[test]
+    fn read_public_key_line_keeps_spaces_in_comment() {
+        let line = read_public_key_line(&format!("{} two  words \n", ECDSA_LINE)).unwrap();
+        assert_eq!(line.comment, "two  words");
+    }
+
+    #[test]
+    fn read_public_key_line_rejects_type_mismatch() {
+        let line = ED25519_LINE.replacen("ssh-ed25519", "ssh-rsa", 1);
+        assert!(read_public_key_line(&line).is_err());
+        assert!(read_public_key_line("ssh-ed25519").is_err());
+        assert!(read_public_key_line("ssh-ed25519 !!!notbase64").is_err());
+    }
+
+    #[test]
+    fn write_public_key_line_roundtrips() {
+        for text in [ED25519_LINE, ECDSA_LINE].iter() {
+            let line = read_public_key_line(text).unwrap();
+            assert_eq!(write_public_key_line(&line.key, &line.comment), *text);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::io::{Error, ErrorKind};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use errors;
+use keys::PublicKey;
+
+//RFC 4716, The Secure Shell (SSH) Public Key File Format
+
+const BEGIN_MARKER: &str = "---- BEGIN SSH2 PUBLIC KEY ----";
+const END_MARKER: &str = "---- END SSH2 PUBLIC KEY ----";
+const MAX_LINE_LENGTH: usize = 72; //RFC 4716 page 3
+const BODY_LINE_LENGTH: usize = 70; //same as ssh-keygen -e
+const COMMENT_TAG: &str = "Comment";
+
+pub struct Rfc4716PublicKey {
+    pub key: PublicKey,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Rfc4716PublicKey {
+    //RFC 4716 page 5, "The Comment header SHOULD be enclosed in quotes"
+    pub fn get_comment(&self) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|h| h.0.eq_ignore_ascii_case(COMMENT_TAG))
+            .map(|h| {
+                let value = h.1.as_str();
+                if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    value[1..value.len() - 1].to_string()
+                } else {
+                    value.to_string()
+                }
+            })
+    }
+}
+
+fn malformed() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_RFC4716_MALFORMED)
+}
+
+pub fn read_rfc4716_public_key(text: &str) -> Result<Rfc4716PublicKey, Error> {
+    let mut lines = text.lines().map(|l| l.trim_end());
+
+    loop {
+        match lines.next() {
+            Some(line) if line.trim() == BEGIN_MARKER => break,
+            Some(_) => continue,
+            None => return Err(malformed()),
+        }
+    }
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut body = String::new();
+    let mut continued: Option<String> = None;
+
+    loop {
+        let line = match lines.next() {
+            Some(line) => line,
+            None => return Err(malformed()),
+        };
+
+        if line.trim() == END_MARKER {
+            break;
+        }
+
+        //header lines may be continued with a trailing backslash
+        let header_line = match continued.take() {
+            Some(mut previous) => {
+                previous.push_str(line);
+                previous
+            }
+            None => {
+                if !line.contains(':') {
+                    body.push_str(line.trim());
+                    continue;
+                }
+                line.to_string()
+            }
+        };
+
+        if header_line.ends_with('\\') {
+            continued = Some(header_line[..header_line.len() - 1].to_string());
+            continue;
+        }
+
+        match header_line.find(':') {
+            Some(colon) => headers.push((header_line[..colon].to_string(), header_line[colon + 1..].trim().to_string())),
+            None => return Err(malformed()),
+        }
+    }
+
+    let blob = match STANDARD.decode(body.as_bytes()) {
+        Ok(blob) => blob,
+        Err(_) => return Err(malformed()),
+    };
+
+    Ok(Rfc4716PublicKey {
+        key: PublicKey::from_blob(&blob)?,
+        headers,
+    })
+}
+
+fn write_header(res: &mut String, tag: &str, value: &str) {
+    let line = format!("{}: {}", tag, value);
+    let chars: Vec<char> = line.chars().collect();
+    let mut start = 0;
+
+    while chars.len() - start > MAX_LINE_LENGTH {
+        let end = start + MAX_LINE_LENGTH - 1;
+        res.extend(chars[start..end].iter());
+        res.push_str("\\\n");
+        start = end;
+    }
+    res.extend(chars[start..].iter());
+    res.push('\n');
+}
+
+pub fn write_rfc4716_public_key(key: &PublicKey, comment: &str) -> String {
+    let mut res = String::from(BEGIN_MARKER);
+    res.push('\n');
+
+    if !comment.is_empty() {
+        write_header(&mut res, COMMENT_TAG, &format!("\"{}\"", comment));
+    }
+
+    let encoded = STANDARD.encode(key.to_blob());
+    for chunk in encoded.as_bytes().chunks(BODY_LINE_LENGTH) {
+        res.push_str(&String::from_utf8_lossy(chunk));
+        res.push('\n');
+    }
+
+    res.push_str(END_MARKER);
+    res.push('\n');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use public_key_file;
+
+    //ssh-keygen -e
+    const EXPORTED: &str = "---- BEGIN SSH2 PUBLIC KEY ----
+Comment: \"256-bit ED25519, converted by root@vm from OpenSSH\"
+AAAAC3NzaC1lZDI1NTE5AAAAIPsTZQK3o+0CRLDw5dUDWBs77QBSBwkNAiaa+m7LCjpF
+---- END SSH2 PUBLIC KEY ----
+";
+    const PUBLIC: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPsTZQK3o+0CRLDw5dUDWBs77QBSBwkNAiaa+m7LCjpF";
+
+    #[test]
+    fn read_rfc4716_public_key_reads_ssh_keygen_output() {
+        let imported = read_rfc4716_public_key(EXPORTED).unwrap();
+        assert!(imported.key == public_key_file::read_public_key_line(PUBLIC).unwrap().key);
+        assert_eq!(imported.get_comment(), Some("256-bit ED25519, converted by root@vm from OpenSSH".to_string()));
+    }
+
+    #[test]
+    fn read_rfc4716_public_key_joins_continued_headers() {
+        let text = "---- BEGIN SSH2 PUBLIC KEY ----\nSubject: me\nx-Comment: first \\\nsecond\nAAAAC3NzaC1lZDI1NTE5AAAAIPsTZQK3o+0CRLDw\n5dUDWBs77QBSBwkNAiaa+m7LCjpF\n---- END SSH2 PUBLIC KEY ----\n";
+        let imported = read_rfc4716_public_key(text).unwrap();
+        assert_eq!(imported.headers.len(), 2);
+        assert_eq!(imported.headers[1].1, "first second");
+        assert_eq!(imported.get_comment(), None);
+    }
+
+    #[test]
+    fn write_rfc4716_public_key_roundtrips() {
+        let key = public_key_file::read_public_key_line(PUBLIC).unwrap().key;
+        let comment = "a rather long comment which certainly will not fit into a single header line of 72 characters";
+        let text = write_rfc4716_public_key(&key, comment);
+        assert!(text.lines().all(|l| l.len() <= MAX_LINE_LENGTH));
+
+        let imported = read_rfc4716_public_key(&text).unwrap();
+        assert!(imported.key == key);
+        assert_eq!(imported.get_comment(), Some(comment.to_string()));
+    }
+
+    #[test]
+    fn read_rfc4716_public_key_rejects_unterminated() {
+        assert!(read_rfc4716_public_key("---- BEGIN SSH2 PUBLIC KEY ----\nAAAA\n").is_err());
+        assert!(read_rfc4716_public_key(PUBLIC).is_err());
+    }
+}
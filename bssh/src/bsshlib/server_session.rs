@@ -0,0 +1,1083 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use byteorder::{BigEndian, WriteBytesExt};
+use libc;
+use auth_options;
+use auth_options::AuthOptions;
+use connection;
+use connection::{ChannelEvent, ChannelInput, ChannelManager, ReaderThrottle};
+use errors;
+use forwarding;
+use forwarding::{GatewayPorts, TcpChannels, TcpipChannel};
+use numbers;
+use passwd::Passwd;
+use patterns;
+use pty::Pty;
+use session;
+use transport;
+use transport::PayloadStream;
+
+//Server side of RFC 4254 sessions. Processes are fed and read by threads which report
+//back to the connection through events, the connection itself never blocks on a process.
+
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+const DEFAULT_SHELL: &str = "/bin/sh";
+//command of the built-in SFTP server, as in OpenSSH
+pub const INTERNAL_SFTP: &str = "internal-sftp";
+
+pub enum ServerEvent {
+    Payload(Result<Vec<u8>, Error>),
+    Input(ChannelInput),
+    //bytes written to the process, they can be returned to the window
+    Written { channel: u32, length: usize },
+    Exited { channel: u32, status: ExitStatus },
+    //connection to the destination of a direct-tcpip channel
+    Connected { channel: u32, stream: Result<TcpStream, Error> },
+    //connection to a remote forwarding, by the address the client asked for and the bound port
+    Accepted { address: String, port: u16, stream: TcpStream },
+}
+
+//sshd_config(5) options of the connection protocol
+#[derive(Clone, Default)]
+pub struct SessionConfig {
+    pub accept_env: Vec<String>,
+    //program and arguments serving internal-sftp over standard input and output, bsshd runs
+    //itself again so the server doesn't share the process of the connection; None disables it
+    pub internal_sftp_command: Option<Vec<String>>,
+    //PermitUserEnvironment, environment= options of the key are ignored unless they match
+    pub permit_user_environment: Vec<String>,
+    pub subsystems: Vec<(String, String)>,
+    pub allow_local_forwarding: bool,
+    //None allows any destination
+    pub permit_open: Option<Vec<String>>,
+    pub allow_remote_forwarding: bool,
+    pub gateway_ports: GatewayPorts,
+}
+
+#[derive(Default)]
+struct Session {
+    pty_request: Option<session::PtyRequest>,
+    pty: Option<Pty>,
+    //data received before the process started
+    early_input: Vec<u8>,
+    //variables of accepted env requests
+    environment: Vec<(String, String)>,
+    //standard input of the process, dropped at EOF
+    input: Option<Sender<Vec<u8>>>,
+    received_eof: bool,
+    pid: Option<u32>,
+    //output readers not at end of file yet
+    open_outputs: u32,
+    exit_status: Option<ExitStatus>,
+}
+
+//processes of other users can be started by root only
+fn get_switching_euid(user: &Passwd) -> Result<libc::uid_t, Error> {
+    let euid = unsafe { libc::geteuid() };
+    if euid != 0 && euid != user.uid {
+        return Err(Error::new(ErrorKind::PermissionDenied, errors::BSSH_ERR_CANNOT_SWITCH_USER));
+    }
+    Ok(euid)
+}
+
+//called in the child, between fork and exec
+unsafe fn switch_user(name: &CStr, home: &CStr, uid: libc::uid_t, gid: libc::gid_t, euid: libc::uid_t) -> Result<(), Error> {
+    if euid == 0 && (libc::initgroups(name.as_ptr(), gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0) {
+        return Err(Error::last_os_error());
+    }
+    if libc::chdir(home.as_ptr()) != 0 {
+        libc::chdir(b"/\0".as_ptr() as *const libc::c_char);
+    }
+    Ok(())
+}
+
+//the process runs as the user, in a session of its own, starting in the home directory
+pub fn set_user(command: &mut Command, user: &Passwd) -> Result<(), Error> {
+    let euid = get_switching_euid(user)?;
+    let name = CString::new(user.name.as_bytes())?;
+    let home = CString::new(user.home.as_os_str().as_bytes())?;
+    let (uid, gid) = (user.uid, user.gid);
+    unsafe {
+        command.pre_exec(move || switch_user(&name, &home, uid, gid, euid));
+    }
+    Ok(())
+}
+
+fn get_unstarted_session(sessions: &mut HashMap<u32, Session>, channel: u32) -> Result<&mut Session, Error> {
+    match sessions.get_mut(&channel) {
+        Some(session) if session.pid.is_none() => Ok(session),
+        Some(_) => Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_SESSION_ALREADY_STARTED)),
+        None => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNKNOWN_CHANNEL)),
+    }
+}
+
+//the window is opened again as the process takes its input, the exit is reported once wait returns
+fn watch_process<F>(events: &Sender<ServerEvent>, session: &mut Session, channel: u32, pid: u32, input: Box<dyn Write + Send>, wait: F)
+    where F: FnOnce() -> Option<ExitStatus> + Send + 'static
+{
+    session.pid = Some(pid);
+    let (sender, received) = mpsc::channel::<Vec<u8>>();
+    let written = events.clone();
+    thread::spawn(move || {
+        let mut input = input;
+        for data in received {
+            //a process not reading its input any more still gets the data acknowledged
+            let _ = input.write_all(&data);
+            if written.send(ServerEvent::Written { channel, length: data.len() }).is_err() {
+                return;
+            }
+        }
+    });
+    if !session.early_input.is_empty() {
+        let early_input = std::mem::take(&mut session.early_input);
+        let _ = sender.send(early_input);
+    }
+    if !session.received_eof {
+        session.input = Some(sender);
+    }
+
+    let events = events.clone();
+    thread::spawn(move || {
+        if let Some(status) = wait() {
+            let _ = events.send(ServerEvent::Exited { channel, status });
+        }
+    });
+}
+
+fn get_shell(user: &Passwd) -> &Path {
+    if user.shell.as_os_str().is_empty() {
+        Path::new(DEFAULT_SHELL)
+    } else {
+        &user.shell
+    }
+}
+
+//login shell of the user, or the shell running the command like sshd(8) does
+pub fn get_shell_command(user: &Passwd, command: Option<&str>, term: Option<&str>) -> Command {
+    let shell = get_shell(user);
+    let mut process = Command::new(shell);
+    match command {
+        Some(command) => {
+            process.arg("-c").arg(command);
+        }
+        None => {
+            let name = shell.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            process.arg0(format!("-{}", name));
+        }
+    }
+    process.env_clear()
+        .env("USER", &user.name)
+        .env("LOGNAME", &user.name)
+        .env("HOME", &user.home)
+        .env("SHELL", shell)
+        .env("PATH", DEFAULT_PATH);
+    if let Some(term) = term {
+        process.env("TERM", term);
+    }
+    process
+}
+
+pub struct ServerConnection {
+    pub manager: ChannelManager,
+    pub user: Passwd,
+    pub auth_options: AuthOptions,
+    pub config: SessionConfig,
+    sessions: HashMap<u32, Session>,
+    forwards: TcpChannels,
+    //remote forwardings, by requested address and bound port
+    listeners: HashMap<(String, u16), TcpListener>,
+    //forwarded-tcpip channels waiting to be opened
+    connecting: HashMap<u32, TcpStream>,
+    throttle: ReaderThrottle,
+    events: Sender<ServerEvent>,
+}
+
+impl ServerConnection {
+    pub fn new(user: Passwd, auth_options: AuthOptions, config: SessionConfig, events: Sender<ServerEvent>) -> ServerConnection {
+        ServerConnection {
+            manager: ChannelManager::new(),
+            user,
+            auth_options,
+            config,
+            sessions: HashMap::new(),
+            forwards: TcpChannels::default(),
+            listeners: HashMap::new(),
+            connecting: HashMap::new(),
+            throttle: ReaderThrottle::default(),
+            events,
+        }
+    }
+
+    fn get_session(&mut self, channel: u32) -> Result<&mut Session, Error> {
+        self.sessions.get_mut(&channel).ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNKNOWN_CHANNEL))
+    }
+
+    //services ignore a requested pty, they are no terminal programs
+    fn start_process(&mut self, channel: u32, mut command: Command, terminal: bool) -> Result<(), Error> {
+        let events = self.events.clone();
+        let user = &self.user;
+        let session = get_unstarted_session(&mut self.sessions, channel)?;
+        let (mut child, input): (_, Box<dyn Write + Send>) = match session.pty {
+            Some(ref mut pty) if terminal => {
+                pty.attach(&mut command)?;
+                set_user(&mut command, user)?;
+                let child = command.spawn()?;
+                pty.close_slave();
+                let output = pty.master.try_clone()?;
+                connection::spawn_channel_reader(output, channel, None, events.clone(), ServerEvent::Input);
+                session.open_outputs = 1;
+                (child, Box::new(pty.master.try_clone()?))
+            }
+            _ => {
+                unsafe {
+                    command.pre_exec(|| {
+                        if libc::setsid() < 0 {
+                            return Err(Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+                set_user(&mut command, user)?;
+                command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+                let mut child = command.spawn()?;
+                let stdout = child.stdout.take().unwrap();
+                let stderr = child.stderr.take().unwrap();
+                connection::spawn_channel_reader(stdout, channel, None, events.clone(), ServerEvent::Input);
+                connection::spawn_channel_reader(stderr, channel, Some(numbers::SSH_EXTENDED_DATA_STDERR), events.clone(), ServerEvent::Input);
+                session.open_outputs = 2;
+                let stdin = child.stdin.take().unwrap();
+                (child, Box::new(stdin))
+            }
+        };
+        let pid = child.id();
+        watch_process(&events, session, channel, pid, input, move || child.wait().ok());
+        Ok(())
+    }
+
+    //the built-in server runs directly, not through the shell of the user
+    fn start_internal_sftp(&mut self, channel: u32) -> Result<(), Error> {
+        let program = self.config.internal_sftp_command.clone().filter(|p| !p.is_empty())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, errors::BSSH_ERR_UNKNOWN_SUBSYSTEM))?;
+        let mut command = Command::new(&program[0]);
+        command.args(&program[1..])
+            .env_clear()
+            .env("USER", &self.user.name)
+            .env("LOGNAME", &self.user.name)
+            .env("HOME", &self.user.home)
+            .env("PATH", DEFAULT_PATH);
+        self.start_process(channel, command, false)
+    }
+
+    fn handle_pty_request(&mut self, channel: u32, data: &[u8]) -> Result<(), Error> {
+        if !self.auth_options.permit_pty {
+            return Err(Error::new(ErrorKind::PermissionDenied, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+        }
+        let request = session::read_pty_request(&mut &data[..])?;
+        let session = self.get_session(channel)?;
+        if session.pid.is_some() || session.pty.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_SESSION_ALREADY_STARTED));
+        }
+        let pty = Pty::open()?;
+        pty.set_terminal_modes(&request.modes)?;
+        pty.set_window_size(&request.size)?;
+        session.pty = Some(pty);
+        session.pty_request = Some(request);
+        Ok(())
+    }
+
+    //only variables matching AcceptEnv are taken, and only before the process starts
+    fn handle_env_request(&mut self, channel: u32, data: &[u8]) -> Result<(), Error> {
+        let (name, value) = session::read_env_request(&mut &data[..])?;
+        if !self.config.accept_env.iter().any(|p| patterns::match_pattern(&name, p)) {
+            return Err(Error::new(ErrorKind::PermissionDenied, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+        }
+        let session = self.get_session(channel)?;
+        if session.pid.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_SESSION_ALREADY_STARTED));
+        }
+        session.environment.push((name, value));
+        Ok(())
+    }
+
+    //RFC 4254 page 14, the whole process group gets the signal so children of the shell do too
+    fn handle_signal_request(&mut self, channel: u32, data: &[u8]) -> Result<(), Error> {
+        let name = session::read_signal_request(&mut &data[..])?;
+        let signal = session::get_signal_number(&name).ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE))?;
+        let session = self.get_session(channel)?;
+        match (session.pid, session.exit_status) {
+            (Some(pid), None) => {
+                if unsafe { libc::kill(-(pid as libc::pid_t), signal) } != 0 {
+                    return Err(Error::last_os_error());
+                }
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        }
+    }
+
+    //variables of the client come after the defaults, so they can override e.g. PATH if accepted,
+    //environment= options of the key come last as in OpenSSH
+    fn get_session_command(&mut self, channel: u32, command: Option<&str>) -> Result<Command, Error> {
+        let session = self.sessions.get(&channel).ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNKNOWN_CHANNEL))?;
+        let term = session.pty_request.as_ref().map(|r| r.term.as_str());
+        let mut process = get_shell_command(&self.user, command, term);
+        for (name, value) in session.environment.iter() {
+            process.env(name, value);
+        }
+        for (name, value) in self.auth_options.environment.iter() {
+            if self.config.permit_user_environment.iter().any(|p| patterns::match_pattern(name, p)) {
+                process.env(name, value);
+            }
+        }
+        Ok(process)
+    }
+
+    //a forced command runs instead of any requested one, which is then in SSH_ORIGINAL_COMMAND
+    fn start_command(&mut self, channel: u32, requested: Option<String>) -> Result<(), Error> {
+        let (command, original) = match self.auth_options.force_command.clone() {
+            Some(forced) => (Some(forced), requested),
+            None => (requested, None),
+        };
+        if command.as_deref() == Some(INTERNAL_SFTP) {
+            return self.start_internal_sftp(channel);
+        }
+        let mut process = self.get_session_command(channel, command.as_deref())?;
+        if let Some(original) = original {
+            process.env("SSH_ORIGINAL_COMMAND", original);
+        }
+        self.start_process(channel, process, true)
+    }
+
+    fn handle_exec_request(&mut self, channel: u32, data: &[u8]) -> Result<(), Error> {
+        let requested = session::read_exec_request(&mut &data[..])?;
+        self.start_command(channel, Some(requested))
+    }
+
+    //external subsystems run through the shell of the user, like commands
+    fn handle_subsystem_request(&mut self, channel: u32, data: &[u8]) -> Result<(), Error> {
+        let name = session::read_subsystem_request(&mut &data[..])?;
+        let command = self.config.subsystems.iter().find(|s| s.0 == name).map(|s| s.1.clone())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, errors::BSSH_ERR_UNKNOWN_SUBSYSTEM))?;
+        self.start_command(channel, Some(command))
+    }
+
+    fn handle_request(&mut self, channel: u32, request_type: &str, data: &[u8]) -> Result<(), Error> {
+        match request_type {
+            session::REQUEST_PTY => self.handle_pty_request(channel, data),
+            session::REQUEST_SHELL => self.start_command(channel, None),
+            session::REQUEST_EXEC => self.handle_exec_request(channel, data),
+            session::REQUEST_ENV => self.handle_env_request(channel, data),
+            session::REQUEST_SIGNAL => self.handle_signal_request(channel, data),
+            session::REQUEST_SUBSYSTEM => self.handle_subsystem_request(channel, data),
+            session::REQUEST_WINDOW_CHANGE => {
+                let size = session::read_window_change_request(&mut &data[..])?;
+                match self.get_session(channel)?.pty {
+                    Some(ref pty) => pty.set_window_size(&size),
+                    None => Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+                }
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        }
+    }
+
+    //all output read and the process gone, the channel closes after exit-status or exit-signal
+    fn check_finished(&mut self, channel: u32) -> Result<(), Error> {
+        let status = match self.sessions.get(&channel) {
+            Some(session) if session.open_outputs == 0 => match session.exit_status {
+                Some(status) => status,
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        self.manager.send_eof(channel)?;
+        let mut data: Vec<u8> = Vec::new();
+        match (status.code(), status.signal()) {
+            (Some(code), _) => {
+                session::write_exit_status_request(&mut data, code as u32)?;
+                self.manager.send_request(channel, session::REQUEST_EXIT_STATUS, false, &data)?;
+            }
+            (None, Some(signal)) => {
+                let exit_signal = session::ExitSignal {
+                    signal_name: session::get_signal_name(signal),
+                    core_dumped: status.core_dumped(),
+                    error_message: String::new(),
+                };
+                session::write_exit_signal_request(&mut data, &exit_signal)?;
+                self.manager.send_request(channel, session::REQUEST_EXIT_SIGNAL, false, &data)?;
+            }
+            _ => {}
+        }
+        self.manager.close(channel)
+    }
+
+    //processes of closed sessions get SIGHUP, like when a terminal goes away
+    fn hang_up(session: &Session) {
+        if let (Some(pid), None) = (session.pid, session.exit_status) {
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGHUP);
+            }
+        }
+    }
+
+    //listeners of remote forwardings go away with the sessions
+    pub fn hang_up_all(&mut self) {
+        for session in self.sessions.values() {
+            ServerConnection::hang_up(session);
+        }
+        self.sessions.clear();
+        for listener in self.listeners.values() {
+            forwarding::close_listener(listener);
+        }
+        self.listeners.clear();
+    }
+
+    //the destination has to be allowed by both sshd_config and the key, the channel
+    //is accepted once connected, connecting happens in a thread as it may take a while
+    fn handle_direct_tcpip(&mut self, channel: u32, type_data: &[u8]) -> Result<(), Error> {
+        let request = match forwarding::read_tcpip_channel(&mut &type_data[..]) {
+            Ok(request) => request,
+            Err(_) => return self.manager.reject(channel, numbers::SSH_OPEN_CONNECT_FAILED, "bad request"),
+        };
+        let (host, port) = (request.host, request.port);
+        let permitted = self.config.allow_local_forwarding
+            && self.config.permit_open.as_ref().map(|list| auth_options::match_permit_open(list, &host, port)).unwrap_or(true)
+            && self.auth_options.is_open_permitted(&host, port);
+        if !permitted {
+            return self.manager.reject(channel, numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED, "open failed");
+        }
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let stream = TcpStream::connect((host.as_str(), port));
+            let _ = events.send(ServerEvent::Connected { channel, stream });
+        });
+        Ok(())
+    }
+
+    //RFC 4254 page 18, only root may listen on privileged ports, as in OpenSSH.
+    //Returns data of the reply, the bound port if the client asked for any port.
+    fn handle_tcpip_forward(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (address, port) = forwarding::read_forward_request(&mut &data[..])?;
+        if !self.config.allow_remote_forwarding || !self.auth_options.is_listen_permitted(&address, port)
+            || (port != 0 && port < 1024 && self.user.uid != 0) {
+            return Err(Error::new(ErrorKind::PermissionDenied, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+        }
+        let listen_address = forwarding::get_remote_listen_address(&address, self.config.gateway_ports);
+        let listener = TcpListener::bind((listen_address, port))?;
+        let bound_port = listener.local_addr()?.port();
+        let events = self.events.clone();
+        let requested = address.clone();
+        forwarding::spawn_listener(listener.try_clone()?, events, move |stream| ServerEvent::Accepted { address: requested.clone(), port: bound_port, stream });
+        self.listeners.insert((address, bound_port), listener);
+        let mut reply: Vec<u8> = Vec::new();
+        if port == 0 {
+            reply.write_u32::<BigEndian>(bound_port as u32)?;
+        }
+        Ok(reply)
+    }
+
+    fn handle_cancel_tcpip_forward(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = forwarding::read_forward_request(&mut &data[..])?;
+        let listener = self.listeners.remove(&key).ok_or_else(|| Error::new(ErrorKind::NotFound, errors::BSSH_ERR_UNEXPECTED_MESSAGE))?;
+        forwarding::close_listener(&listener);
+        Ok(Vec::new())
+    }
+
+    fn handle_global_request(&mut self, name: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match name {
+            forwarding::REQUEST_TCPIP_FORWARD => self.handle_tcpip_forward(data),
+            forwarding::REQUEST_CANCEL_TCPIP_FORWARD => self.handle_cancel_tcpip_forward(data),
+            _ => Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        }
+    }
+
+    //connections accepted after the forwarding was cancelled are dropped
+    fn open_forwarded_tcpip(&mut self, address: String, port: u16, stream: TcpStream) -> Result<(), Error> {
+        if !self.listeners.contains_key(&(address.clone(), port)) {
+            return Ok(());
+        }
+        let originator = stream.peer_addr()?;
+        let request = TcpipChannel {
+            host: address,
+            port,
+            originator_address: originator.ip().to_string(),
+            originator_port: originator.port(),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        forwarding::write_tcpip_channel(&mut data, &request)?;
+        let channel = self.manager.open(forwarding::CHANNEL_FORWARDED_TCPIP, &data);
+        self.connecting.insert(channel, stream);
+        Ok(())
+    }
+
+    fn handle_channel_event(&mut self, event: ChannelEvent) -> Result<(), Error> {
+        match event {
+            ChannelEvent::OpenRequested { channel, channel_type, type_data } => match channel_type.as_str() {
+                session::CHANNEL_SESSION => {
+                    self.manager.accept(channel, &[])?;
+                    self.sessions.insert(channel, Session::default());
+                }
+                forwarding::CHANNEL_DIRECT_TCPIP => self.handle_direct_tcpip(channel, &type_data)?,
+                _ => self.manager.reject(channel, numbers::SSH_OPEN_UNKNOWN_CHANNEL_TYPE, "unknown channel type")?,
+            },
+            ChannelEvent::Opened { channel, .. } => {
+                if let Some(stream) = self.connecting.remove(&channel) {
+                    self.forwards.start(stream, channel, &self.events, ServerEvent::Input, |channel, length| ServerEvent::Written { channel, length })?;
+                }
+            }
+            ChannelEvent::OpenFailed { channel, .. } => {
+                self.connecting.remove(&channel);
+            }
+            ChannelEvent::Data { channel, data } if self.forwards.contains(channel) => self.forwards.handle_data(&mut self.manager, channel, data)?,
+            ChannelEvent::Eof { channel } if self.forwards.contains(channel) => self.forwards.handle_eof(&mut self.manager, channel)?,
+            ChannelEvent::Request { channel, request_type, want_reply, data } => {
+                let success = self.handle_request(channel, &request_type, &data).is_ok();
+                if want_reply {
+                    self.manager.reply_request(channel, success)?;
+                }
+            }
+            ChannelEvent::Data { channel, data } => {
+                let session = self.get_session(channel)?;
+                if session.pid.is_none() {
+                    session.early_input.extend_from_slice(&data);
+                    return Ok(());
+                }
+                let delivered = match session.input {
+                    Some(ref input) => input.send(data.clone()).is_ok(),
+                    None => false,
+                };
+                if !delivered {
+                    self.manager.consume(channel, data.len())?;
+                }
+            }
+            //no use for extended data from the client
+            ChannelEvent::ExtendedData { channel, data, .. } => self.manager.consume(channel, data.len())?,
+            ChannelEvent::Eof { channel } => {
+                let session = self.get_session(channel)?;
+                session.received_eof = true;
+                session.input = None;
+            }
+            ChannelEvent::Closed { channel } => {
+                if let Some(session) = self.sessions.remove(&channel) {
+                    ServerConnection::hang_up(&session);
+                }
+                self.forwards.handle_closed(channel);
+            }
+            ChannelEvent::WindowAdjusted { .. } => self.throttle.resume_readers(&self.manager),
+            ChannelEvent::GlobalRequest { name, want_reply, data } => {
+                let reply = self.handle_global_request(&name, &data);
+                if want_reply {
+                    match reply {
+                        Ok(data) => self.manager.reply_global_request(true, &data),
+                        Err(_) => self.manager.reply_global_request(false, &[]),
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_event(&mut self, event: ServerEvent) -> Result<(), Error> {
+        match event {
+            ServerEvent::Payload(payload) => {
+                let payload = payload?;
+                //TODO key re-exchange, other transport messages are ignored for now
+                if !connection::is_connection_message(payload[0]) {
+                    return Ok(());
+                }
+                if let Some(event) = self.manager.handle_payload(&payload)? {
+                    self.handle_channel_event(event)?;
+                }
+            }
+            ServerEvent::Input(input) => {
+                let channel = input.channel;
+                if input.data.is_some() {
+                    //output of a closed channel is dropped
+                    if self.manager.get_channel(channel).is_some() {
+                        self.throttle.send_input(&mut self.manager, input)?;
+                    }
+                    return Ok(());
+                }
+                if self.forwards.contains(channel) {
+                    return self.forwards.handle_input_end(&mut self.manager, channel);
+                }
+                if let Some(session) = self.sessions.get_mut(&channel) {
+                    session.open_outputs = session.open_outputs.saturating_sub(1);
+                }
+                self.check_finished(channel)?;
+            }
+            ServerEvent::Written { channel, length } => {
+                if self.manager.get_channel(channel).is_some() {
+                    self.manager.consume(channel, length)?;
+                }
+            }
+            ServerEvent::Exited { channel, status } => {
+                if let Some(session) = self.sessions.get_mut(&channel) {
+                    session.exit_status = Some(status);
+                }
+                self.check_finished(channel)?;
+            }
+            ServerEvent::Connected { channel, stream: Ok(stream) } => {
+                self.manager.accept(channel, &[])?;
+                self.forwards.start(stream, channel, &self.events, ServerEvent::Input, |channel, length| ServerEvent::Written { channel, length })?;
+            }
+            ServerEvent::Connected { channel, stream: Err(e) } => {
+                self.manager.reject(channel, numbers::SSH_OPEN_CONNECT_FAILED, &e.to_string())?;
+            }
+            ServerEvent::Accepted { address, port, stream } => self.open_forwarded_tcpip(address, port, stream)?,
+        }
+        Ok(())
+    }
+}
+
+//serves the connection protocol for an authenticated user until the client goes away
+pub fn run_server_connection<R, W>(receiver: R, sender: &mut W, user: Passwd, auth_options: AuthOptions, config: SessionConfig) -> Result<(), Error>
+    where R: PayloadStream + Send + 'static, W: PayloadStream
+{
+    let (events, received) = mpsc::channel();
+    transport::spawn_payload_receiver(receiver, events.clone(), ServerEvent::Payload);
+    let mut connection = ServerConnection::new(user, auth_options, config, events);
+    let result = loop {
+        //the connection holds a sender, receiving can not fail
+        let event = received.recv().unwrap();
+        if let Err(e) = connection.handle_event(event).and_then(|_| connection.manager.send_outgoing(sender)) {
+            break match e.kind() {
+                ErrorKind::UnexpectedEof | ErrorKind::ConnectionAborted => Ok(()),
+                _ => Err(e),
+            };
+        }
+    };
+    connection.hang_up_all();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io;
+    use std::io::Read;
+    use std::net::Shutdown;
+    use byteorder::ReadBytesExt;
+    use std::path::PathBuf;
+    use mocks::ChannelPayloadStream;
+    use passwd;
+    use terminal::WindowSize;
+
+    //server for the current user in a thread, returns the client end of the connection
+    pub fn start_server(auth_options: AuthOptions, config: SessionConfig) -> (ChannelPayloadStream, thread::JoinHandle<()>) {
+        let (to_server, server_receiver) = mpsc::channel();
+        let (to_client, client_receiver) = mpsc::channel();
+        let (server_sender, _) = mpsc::channel();
+        let server_in = ChannelPayloadStream { sender: server_sender, receiver: server_receiver };
+        let mut server_out = ChannelPayloadStream { sender: to_client, receiver: mpsc::channel().1 };
+        let mut user = passwd::get_current_user().unwrap();
+        user.shell = PathBuf::from("/bin/sh");
+        user.home = PathBuf::from("/");
+        let handle = thread::spawn(move || run_server_connection(server_in, &mut server_out, user, auth_options, config).unwrap());
+        (ChannelPayloadStream { sender: to_server, receiver: client_receiver }, handle)
+    }
+
+    //sends what the client queued and handles replies until the condition is met
+    pub fn run_until<F: FnMut(&ChannelEvent) -> bool>(stream: &mut ChannelPayloadStream, client: &mut ChannelManager, mut done: F) -> Vec<ChannelEvent> {
+        let mut events: Vec<ChannelEvent> = Vec::new();
+        loop {
+            client.send_outgoing(stream).unwrap();
+            let payload = stream.receive_payload().unwrap();
+            if let Some(event) = client.handle_payload(&payload).unwrap() {
+                if let ChannelEvent::Data { channel, ref data } = event {
+                    client.consume(channel, data.len()).unwrap();
+                }
+                let finished = done(&event);
+                events.push(event);
+                if finished {
+                    return events;
+                }
+            }
+        }
+    }
+
+    pub fn get_output(events: &[ChannelEvent]) -> Vec<u8> {
+        let mut output: Vec<u8> = Vec::new();
+        for event in events {
+            if let ChannelEvent::Data { ref data, .. } = *event {
+                output.extend_from_slice(data);
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn shell_runs_in_pty() {
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), SessionConfig::default());
+        let mut client = ChannelManager::new();
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+
+        let request = session::PtyRequest {
+            term: "vt100".to_string(),
+            size: WindowSize { columns: 80, rows: 24, width_pixels: 0, height_pixels: 0 },
+            modes: vec![(session::ECHO, 0)],
+        };
+        let mut data: Vec<u8> = Vec::new();
+        session::write_pty_request(&mut data, &request).unwrap();
+        client.send_request(channel, session::REQUEST_PTY, true, &data).unwrap();
+        //typed before the shell runs
+        client.send_data(channel, b"stty size; echo $TERM; tty -s && echo tty\n").unwrap();
+        client.send_request(channel, session::REQUEST_SHELL, true, &[]).unwrap();
+        let mut replies = 0;
+        run_until(&mut stream, &mut client, |e| {
+            assert!(match *e { ChannelEvent::RequestReply { success, .. } => success, _ => true });
+            replies += (*e == ChannelEvent::RequestReply { channel, success: true }) as u32;
+            replies == 2
+        });
+
+        let mut output: Vec<u8> = Vec::new();
+        run_until(&mut stream, &mut client, |e| {
+            output.extend(get_output(std::slice::from_ref(e)));
+            String::from_utf8_lossy(&output).contains("tty")
+        });
+        let output = String::from_utf8_lossy(&output).replace('\r', "");
+        assert!(output.contains("24 80\nvt100\ntty"), "{}", output);
+
+        let mut size: Vec<u8> = Vec::new();
+        session::write_window_change_request(&mut size, &WindowSize { columns: 100, rows: 50, width_pixels: 0, height_pixels: 0 }).unwrap();
+        client.send_request(channel, session::REQUEST_WINDOW_CHANGE, false, &size).unwrap();
+        client.send_data(channel, b"stty size; exit\n").unwrap();
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        let output = String::from_utf8_lossy(&get_output(&events)).replace('\r', "");
+        assert!(output.contains("50 100\n"), "{}", output);
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    fn exec(stream: &mut ChannelPayloadStream, client: &mut ChannelManager, command: &str) -> Vec<ChannelEvent> {
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(stream, client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+        let mut data: Vec<u8> = Vec::new();
+        session::write_exec_request(&mut data, command).unwrap();
+        client.send_request(channel, session::REQUEST_EXEC, true, &data).unwrap();
+        client.send_data(channel, b"input").unwrap();
+        client.send_eof(channel).unwrap();
+        run_until(stream, client, |e| *e == ChannelEvent::Closed { channel })
+    }
+
+    fn get_exit_request(events: &[ChannelEvent]) -> (String, Vec<u8>) {
+        events.iter().filter_map(|e| match *e {
+            ChannelEvent::Request { ref request_type, ref data, .. } => Some((request_type.clone(), data.clone())),
+            _ => None,
+        }).next().unwrap()
+    }
+
+    #[test]
+    fn exec_reports_exit_status_and_signal() {
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), SessionConfig::default());
+        let mut client = ChannelManager::new();
+
+        let events = exec(&mut stream, &mut client, "cat; echo error >&2; exit 3");
+        assert_eq!(get_output(&events), b"input");
+        assert!(events.iter().any(|e| match *e {
+            ChannelEvent::ExtendedData { data_type, ref data, .. } => data_type == numbers::SSH_EXTENDED_DATA_STDERR && data == b"error\n",
+            _ => false,
+        }));
+        let (request_type, data) = get_exit_request(&events);
+        assert_eq!(request_type, session::REQUEST_EXIT_STATUS);
+        assert_eq!(session::read_exit_status_request(&mut data.as_slice()).unwrap(), 3);
+
+        let events = exec(&mut stream, &mut client, "kill -TERM $$");
+        let (request_type, data) = get_exit_request(&events);
+        assert_eq!(request_type, session::REQUEST_EXIT_SIGNAL);
+        assert_eq!(session::read_exit_signal_request(&mut data.as_slice()).unwrap().signal_name, "TERM");
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn signal_reaches_process_group() {
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), SessionConfig::default());
+        let mut client = ChannelManager::new();
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+        let mut data: Vec<u8> = Vec::new();
+        session::write_signal_request(&mut data, "TERM").unwrap();
+        //nothing runs yet
+        client.send_request(channel, session::REQUEST_SIGNAL, true, &data).unwrap();
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::RequestReply { channel, success: false });
+
+        //the shell waits for a child of its own, which has to get the signal as well
+        let mut exec: Vec<u8> = Vec::new();
+        session::write_exec_request(&mut exec, "echo started; sleep 30; echo finished").unwrap();
+        client.send_request(channel, session::REQUEST_EXEC, true, &exec).unwrap();
+        run_until(&mut stream, &mut client, |e| get_output(std::slice::from_ref(e)) == b"started\n");
+        client.send_request(channel, session::REQUEST_SIGNAL, true, &data).unwrap();
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        assert!(events.contains(&ChannelEvent::RequestReply { channel, success: true }));
+        assert!(get_output(&events).is_empty());
+        let (request_type, data) = get_exit_request(&events);
+        assert_eq!(request_type, session::REQUEST_EXIT_SIGNAL);
+        assert_eq!(session::read_exit_signal_request(&mut data.as_slice()).unwrap().signal_name, "TERM");
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    fn start_subsystem(stream: &mut ChannelPayloadStream, client: &mut ChannelManager, name: &str, input: &[u8]) -> Vec<ChannelEvent> {
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(stream, client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+        let mut data: Vec<u8> = Vec::new();
+        session::write_subsystem_request(&mut data, name).unwrap();
+        client.send_request(channel, session::REQUEST_SUBSYSTEM, true, &data).unwrap();
+        client.send_data(channel, input).unwrap();
+        client.send_eof(channel).unwrap();
+        run_until(stream, client, |e| *e == ChannelEvent::Closed { channel })
+    }
+
+    #[test]
+    fn subsystems_run_external_or_internal() {
+        let config = SessionConfig {
+            subsystems: vec![("echo".to_string(), "cat".to_string()), ("sftp".to_string(), INTERNAL_SFTP.to_string())],
+            internal_sftp_command: Some(vec!["/bin/echo".to_string(), "serving".to_string()]),
+            ..SessionConfig::default()
+        };
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), config);
+        let mut client = ChannelManager::new();
+
+        let events = start_subsystem(&mut stream, &mut client, "echo", b"hello");
+        assert!(events.iter().any(|e| match *e { ChannelEvent::RequestReply { success, .. } => success, _ => false }));
+        assert_eq!(get_output(&events), b"hello");
+
+        //the internal server is a program of its own, not run through the shell
+        let events = start_subsystem(&mut stream, &mut client, "sftp", b"");
+        assert_eq!(get_output(&events), b"serving\n");
+        let (request_type, data) = get_exit_request(&events);
+        assert_eq!(request_type, session::REQUEST_EXIT_STATUS);
+        assert_eq!(session::read_exit_status_request(&mut data.as_slice()).unwrap(), 0);
+
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+        let mut data: Vec<u8> = Vec::new();
+        session::write_subsystem_request(&mut data, "other").unwrap();
+        client.send_request(channel, session::REQUEST_SUBSYSTEM, true, &data).unwrap();
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::RequestReply { channel, success: false });
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn key_environment_is_filtered_by_permit_user_environment() {
+        let mut auth_options = AuthOptions::unrestricted();
+        auth_options.environment = vec![("LANG".to_string(), "C".to_string()), ("LD_PRELOAD".to_string(), "x".to_string())];
+        let (mut stream, server) = start_server(auth_options, SessionConfig { permit_user_environment: vec!["LANG".to_string()], ..SessionConfig::default() });
+        let mut client = ChannelManager::new();
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+        let mut data: Vec<u8> = Vec::new();
+        session::write_exec_request(&mut data, "echo $LANG $LD_PRELOAD").unwrap();
+        client.send_request(channel, session::REQUEST_EXEC, true, &data).unwrap();
+        client.send_eof(channel).unwrap();
+
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        assert_eq!(get_output(&events), b"C\n");
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn env_is_filtered_by_accept_env() {
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), SessionConfig { accept_env: vec!["LANG".to_string(), "BUILD_*".to_string()], ..SessionConfig::default() });
+        let mut client = ChannelManager::new();
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+
+        for &(name, value) in [("LANG", "C"), ("BUILD_ID", "42"), ("SECRET", "x")].iter() {
+            let mut data: Vec<u8> = Vec::new();
+            session::write_env_request(&mut data, name, value).unwrap();
+            client.send_request(channel, session::REQUEST_ENV, true, &data).unwrap();
+        }
+        let mut data: Vec<u8> = Vec::new();
+        session::write_exec_request(&mut data, "echo $LANG $BUILD_ID $SECRET").unwrap();
+        client.send_request(channel, session::REQUEST_EXEC, true, &data).unwrap();
+        let mut data: Vec<u8> = Vec::new();
+        session::write_env_request(&mut data, "LANG", "late").unwrap();
+        client.send_request(channel, session::REQUEST_ENV, true, &data).unwrap();
+        client.send_eof(channel).unwrap();
+
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        let replies: Vec<bool> = events.iter().filter_map(|e| match *e {
+            ChannelEvent::RequestReply { success, .. } => Some(success),
+            _ => None,
+        }).collect();
+        assert_eq!(replies, vec![true, true, false, true, false]);
+        assert_eq!(get_output(&events), b"C 42\n");
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn pty_can_be_forbidden() {
+        let mut auth_options = AuthOptions::unrestricted();
+        auth_options.permit_pty = false;
+        auth_options.force_command = Some("echo forced $SSH_ORIGINAL_COMMAND".to_string());
+        let (mut stream, server) = start_server(auth_options, SessionConfig::default());
+        let mut client = ChannelManager::new();
+        let channel = client.open(session::CHANNEL_SESSION, &[]);
+        let other = client.open("x11", &[]);
+        run_until(&mut stream, &mut client, |e| matches!(*e, ChannelEvent::OpenFailed { .. }));
+
+        let mut data: Vec<u8> = Vec::new();
+        session::write_pty_request(&mut data, &session::PtyRequest { term: "vt100".to_string(), size: WindowSize::default(), modes: Vec::new() }).unwrap();
+        client.send_request(channel, session::REQUEST_PTY, true, &data).unwrap();
+        client.send_request(channel, session::REQUEST_SHELL, true, &[]).unwrap();
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        assert_eq!(events[0], ChannelEvent::RequestReply { channel, success: false });
+        assert_eq!(events[1], ChannelEvent::RequestReply { channel, success: true });
+        assert_eq!(get_output(&events), b"forced\n");
+        assert!(client.get_channel(other).is_none());
+
+        let events = exec(&mut stream, &mut client, "date");
+        assert_eq!(get_output(&events), b"forced date\n");
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    //sends back what it reads on loopback connections
+    fn start_echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    io::copy(&mut reader, &mut stream).unwrap();
+                    stream.shutdown(Shutdown::Write).unwrap();
+                });
+            }
+        });
+        port
+    }
+
+    fn open_direct_tcpip(client: &mut ChannelManager, host: &str, port: u16) -> u32 {
+        let request = forwarding::TcpipChannel {
+            host: host.to_string(),
+            port,
+            originator_address: "127.0.0.1".to_string(),
+            originator_port: 40000,
+        };
+        let mut data: Vec<u8> = Vec::new();
+        forwarding::write_tcpip_channel(&mut data, &request).unwrap();
+        client.open(forwarding::CHANNEL_DIRECT_TCPIP, &data)
+    }
+
+    fn get_open_failure(stream: &mut ChannelPayloadStream, client: &mut ChannelManager, host: &str, port: u16) -> Option<u32> {
+        open_direct_tcpip(client, host, port);
+        match run_until(stream, client, |e| matches!(*e, ChannelEvent::Opened { .. } | ChannelEvent::OpenFailed { .. })).pop() {
+            Some(ChannelEvent::OpenFailed { reason_code, .. }) => Some(reason_code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn direct_tcpip_connects_to_permitted_destinations() {
+        let port = start_echo_server();
+        let config = SessionConfig { allow_local_forwarding: true, permit_open: Some(vec![format!("127.0.0.1:{}", port)]), ..SessionConfig::default() };
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), config);
+        let mut client = ChannelManager::new();
+        let channel = open_direct_tcpip(&mut client, "127.0.0.1", port);
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Opened { channel, type_data: Vec::new() });
+        client.send_data(channel, b"ping").unwrap();
+        client.send_eof(channel).unwrap();
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        assert_eq!(get_output(&events), b"ping");
+        assert!(events.contains(&ChannelEvent::Eof { channel }));
+
+        assert_eq!(get_open_failure(&mut stream, &mut client, "localhost", port), Some(numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED));
+        let closed_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        assert_eq!(get_open_failure(&mut stream, &mut client, "127.0.0.1", closed_port), Some(numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED));
+        drop(stream);
+        server.join().unwrap();
+
+        let config = SessionConfig { allow_local_forwarding: true, ..SessionConfig::default() };
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), config.clone());
+        assert_eq!(get_open_failure(&mut stream, &mut ChannelManager::new(), "127.0.0.1", closed_port), Some(numbers::SSH_OPEN_CONNECT_FAILED));
+        drop(stream);
+        server.join().unwrap();
+
+        let mut auth_options = AuthOptions::unrestricted();
+        auth_options.permit_open = Some(vec![format!("localhost:{}", port)]);
+        let (mut stream, server) = start_server(auth_options, config);
+        let mut client = ChannelManager::new();
+        assert_eq!(get_open_failure(&mut stream, &mut client, "localhost", port), None);
+        assert_eq!(get_open_failure(&mut stream, &mut client, "127.0.0.1", port), Some(numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED));
+        drop(stream);
+        server.join().unwrap();
+
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), SessionConfig::default());
+        assert_eq!(get_open_failure(&mut stream, &mut ChannelManager::new(), "127.0.0.1", port), Some(numbers::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED));
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    fn send_forward_request(stream: &mut ChannelPayloadStream, client: &mut ChannelManager, name: &str, port: u16) -> Option<Vec<u8>> {
+        let mut data: Vec<u8> = Vec::new();
+        forwarding::write_forward_request(&mut data, "localhost", port).unwrap();
+        client.send_global_request(name, true, &data);
+        match run_until(stream, client, |e| matches!(*e, ChannelEvent::GlobalRequestReply { .. })).pop() {
+            Some(ChannelEvent::GlobalRequestReply { success: true, data }) => Some(data),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tcpip_forward_opens_forwarded_tcpip_channels() {
+        let config = SessionConfig { allow_remote_forwarding: true, ..SessionConfig::default() };
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), config);
+        let mut client = ChannelManager::new();
+        let reply = send_forward_request(&mut stream, &mut client, forwarding::REQUEST_TCPIP_FORWARD, 0).unwrap();
+        let port = (&reply[..]).read_u32::<BigEndian>().unwrap() as u16;
+
+        let mut connection = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        connection.write_all(b"ping").unwrap();
+        connection.shutdown(Shutdown::Write).unwrap();
+        let (channel, type_data) = match run_until(&mut stream, &mut client, |e| matches!(*e, ChannelEvent::OpenRequested { .. })).pop() {
+            Some(ChannelEvent::OpenRequested { channel, channel_type, type_data }) if channel_type == forwarding::CHANNEL_FORWARDED_TCPIP => (channel, type_data),
+            event => panic!("unexpected {:?}", event),
+        };
+        let request = forwarding::read_tcpip_channel(&mut &type_data[..]).unwrap();
+        assert_eq!((request.host.as_str(), request.port, request.originator_address.as_str()), ("localhost", port, "127.0.0.1"));
+        client.accept(channel, &[]).unwrap();
+        let events = run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Eof { channel });
+        assert_eq!(get_output(&events), b"ping");
+        client.send_data(channel, b"pong").unwrap();
+        client.send_eof(channel).unwrap();
+        run_until(&mut stream, &mut client, |e| *e == ChannelEvent::Closed { channel });
+        let mut received: Vec<u8> = Vec::new();
+        connection.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"pong");
+
+        assert_eq!(send_forward_request(&mut stream, &mut client, forwarding::REQUEST_CANCEL_TCPIP_FORWARD, port), Some(Vec::new()));
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+        assert_eq!(send_forward_request(&mut stream, &mut client, forwarding::REQUEST_CANCEL_TCPIP_FORWARD, port), None);
+        drop(stream);
+        server.join().unwrap();
+
+        let (mut stream, server) = start_server(AuthOptions::unrestricted(), SessionConfig::default());
+        assert_eq!(send_forward_request(&mut stream, &mut ChannelManager::new(), forwarding::REQUEST_TCPIP_FORWARD, 0), None);
+        drop(stream);
+        server.join().unwrap();
+    }
+}
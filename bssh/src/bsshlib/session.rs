@@ -0,0 +1,458 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libc;
+use errors;
+use io_helpers;
+use terminal::WindowSize;
+
+//RFC 4254 section 6, Interactive Sessions
+
+pub const CHANNEL_SESSION: &str = "session";
+
+pub const REQUEST_PTY: &str = "pty-req";
+pub const REQUEST_SHELL: &str = "shell";
+pub const REQUEST_WINDOW_CHANGE: &str = "window-change";
+pub const REQUEST_EXEC: &str = "exec";
+pub const REQUEST_EXIT_STATUS: &str = "exit-status";
+pub const REQUEST_EXIT_SIGNAL: &str = "exit-signal";
+pub const REQUEST_ENV: &str = "env";
+pub const REQUEST_SIGNAL: &str = "signal";
+pub const REQUEST_SUBSYSTEM: &str = "subsystem";
+
+//RFC 4254 page 15, signal names without the "SIG" prefix
+const SIGNAL_NAMES: [(libc::c_int, &str); 13] = [
+    (libc::SIGABRT, "ABRT"), (libc::SIGALRM, "ALRM"), (libc::SIGFPE, "FPE"), (libc::SIGHUP, "HUP"),
+    (libc::SIGILL, "ILL"), (libc::SIGINT, "INT"), (libc::SIGKILL, "KILL"), (libc::SIGPIPE, "PIPE"),
+    (libc::SIGQUIT, "QUIT"), (libc::SIGSEGV, "SEGV"), (libc::SIGTERM, "TERM"), (libc::SIGUSR1, "USR1"),
+    (libc::SIGUSR2, "USR2"),
+];
+
+//signals not named by the RFC get a local name, in the "name@domain" format
+pub fn get_signal_name(signal: libc::c_int) -> String {
+    match SIGNAL_NAMES.iter().find(|s| s.0 == signal) {
+        Some(&(_, name)) => name.to_string(),
+        None => format!("SIG{}@bssh", signal),
+    }
+}
+
+pub fn get_signal_number(name: &str) -> Option<libc::c_int> {
+    SIGNAL_NAMES.iter().find(|s| s.1 == name).map(|s| s.0)
+}
+
+//RFC 4254 section 8, Encoding of Terminal Modes
+pub const TTY_OP_END: u8 = 0;
+pub const VINTR: u8 = 1;
+pub const VQUIT: u8 = 2;
+pub const VERASE: u8 = 3;
+pub const VKILL: u8 = 4;
+pub const VEOF: u8 = 5;
+pub const VEOL: u8 = 6;
+pub const VEOL2: u8 = 7;
+pub const VSTART: u8 = 8;
+pub const VSTOP: u8 = 9;
+pub const VSUSP: u8 = 10;
+pub const VREPRINT: u8 = 12;
+pub const VWERASE: u8 = 13;
+pub const VLNEXT: u8 = 14;
+pub const VDISCARD: u8 = 18;
+pub const IGNPAR: u8 = 30;
+pub const PARMRK: u8 = 31;
+pub const INPCK: u8 = 32;
+pub const ISTRIP: u8 = 33;
+pub const INLCR: u8 = 34;
+pub const IGNCR: u8 = 35;
+pub const ICRNL: u8 = 36;
+pub const IXON: u8 = 38;
+pub const IXANY: u8 = 39;
+pub const IXOFF: u8 = 40;
+pub const IMAXBEL: u8 = 41;
+pub const IUTF8: u8 = 42; //RFC 8160
+pub const ISIG: u8 = 50;
+pub const ICANON: u8 = 51;
+pub const ECHO: u8 = 53;
+pub const ECHOE: u8 = 54;
+pub const ECHOK: u8 = 55;
+pub const ECHONL: u8 = 56;
+pub const NOFLSH: u8 = 57;
+pub const TOSTOP: u8 = 58;
+pub const IEXTEN: u8 = 59;
+pub const ECHOCTL: u8 = 60;
+pub const ECHOKE: u8 = 61;
+pub const PENDIN: u8 = 62;
+pub const OPOST: u8 = 70;
+pub const ONLCR: u8 = 72;
+pub const OCRNL: u8 = 73;
+pub const ONOCR: u8 = 74;
+pub const ONLRET: u8 = 75;
+pub const CS7: u8 = 90;
+pub const CS8: u8 = 91;
+pub const PARENB: u8 = 92;
+pub const PARODD: u8 = 93;
+pub const TTY_OP_ISPEED: u8 = 128;
+pub const TTY_OP_OSPEED: u8 = 129;
+
+//opcodes from 160 have arguments of unknown format, they end the parsing
+const FIRST_UNKNOWN_OPCODE: u8 = 160;
+
+//the encoding of a disabled special character, as used by OpenSSH
+const DISABLED_CHARACTER: u32 = 255;
+
+const CHARACTERS: [(u8, usize); 14] = [
+    (VINTR, libc::VINTR), (VQUIT, libc::VQUIT), (VERASE, libc::VERASE), (VKILL, libc::VKILL),
+    (VEOF, libc::VEOF), (VEOL, libc::VEOL), (VEOL2, libc::VEOL2), (VSTART, libc::VSTART),
+    (VSTOP, libc::VSTOP), (VSUSP, libc::VSUSP), (VREPRINT, libc::VREPRINT), (VWERASE, libc::VWERASE),
+    (VLNEXT, libc::VLNEXT), (VDISCARD, libc::VDISCARD),
+];
+
+const INPUT_FLAGS: [(u8, libc::tcflag_t); 12] = [
+    (IGNPAR, libc::IGNPAR), (PARMRK, libc::PARMRK), (INPCK, libc::INPCK), (ISTRIP, libc::ISTRIP),
+    (INLCR, libc::INLCR), (IGNCR, libc::IGNCR), (ICRNL, libc::ICRNL), (IXON, libc::IXON),
+    (IXANY, libc::IXANY), (IXOFF, libc::IXOFF), (IMAXBEL, libc::IMAXBEL), (IUTF8, libc::IUTF8),
+];
+
+const LOCAL_FLAGS: [(u8, libc::tcflag_t); 12] = [
+    (ISIG, libc::ISIG), (ICANON, libc::ICANON), (ECHO, libc::ECHO), (ECHOE, libc::ECHOE),
+    (ECHOK, libc::ECHOK), (ECHONL, libc::ECHONL), (NOFLSH, libc::NOFLSH), (TOSTOP, libc::TOSTOP),
+    (IEXTEN, libc::IEXTEN), (ECHOCTL, libc::ECHOCTL), (ECHOKE, libc::ECHOKE), (PENDIN, libc::PENDIN),
+];
+
+const OUTPUT_FLAGS: [(u8, libc::tcflag_t); 5] = [
+    (OPOST, libc::OPOST), (ONLCR, libc::ONLCR), (OCRNL, libc::OCRNL), (ONOCR, libc::ONOCR), (ONLRET, libc::ONLRET),
+];
+
+const CONTROL_FLAGS: [(u8, libc::tcflag_t); 2] = [
+    (PARENB, libc::PARENB), (PARODD, libc::PARODD),
+];
+
+const SPEEDS: [(libc::speed_t, u32); 18] = [
+    (libc::B50, 50), (libc::B75, 75), (libc::B110, 110), (libc::B134, 134), (libc::B150, 150),
+    (libc::B200, 200), (libc::B300, 300), (libc::B600, 600), (libc::B1200, 1200), (libc::B1800, 1800),
+    (libc::B2400, 2400), (libc::B4800, 4800), (libc::B9600, 9600), (libc::B19200, 19200),
+    (libc::B38400, 38400), (libc::B57600, 57600), (libc::B115200, 115200), (libc::B230400, 230400),
+];
+
+fn speed_to_baud(speed: libc::speed_t) -> u32 {
+    SPEEDS.iter().find(|s| s.0 == speed).map(|s| s.1).unwrap_or(9600)
+}
+
+//the highest known speed not over the baud rate
+fn baud_to_speed(baud: u32) -> libc::speed_t {
+    SPEEDS.iter().take_while(|s| s.1 <= baud).last().map(|s| s.0).unwrap_or(libc::B9600)
+}
+
+//modes of a local terminal, for pty-req
+pub fn get_terminal_modes(attributes: &libc::termios) -> Vec<(u8, u32)> {
+    let mut modes: Vec<(u8, u32)> = Vec::new();
+    for &(opcode, index) in CHARACTERS.iter() {
+        let c = attributes.c_cc[index];
+        modes.push((opcode, if c == 0 { DISABLED_CHARACTER } else { c as u32 }));
+    }
+    let flags = [
+        (&INPUT_FLAGS[..], attributes.c_iflag),
+        (&LOCAL_FLAGS[..], attributes.c_lflag),
+        (&OUTPUT_FLAGS[..], attributes.c_oflag),
+        (&CONTROL_FLAGS[..], attributes.c_cflag),
+    ];
+    for &(table, value) in flags.iter() {
+        for &(opcode, flag) in table.iter() {
+            modes.push((opcode, (value & flag != 0) as u32));
+        }
+    }
+    modes.push((CS7, (attributes.c_cflag & libc::CSIZE == libc::CS7) as u32));
+    modes.push((CS8, (attributes.c_cflag & libc::CSIZE == libc::CS8) as u32));
+    unsafe {
+        modes.push((TTY_OP_ISPEED, speed_to_baud(libc::cfgetispeed(attributes))));
+        modes.push((TTY_OP_OSPEED, speed_to_baud(libc::cfgetospeed(attributes))));
+    }
+    modes
+}
+
+fn set_flag(value: &mut libc::tcflag_t, flag: libc::tcflag_t, on: bool) {
+    if on {
+        *value |= flag;
+    } else {
+        *value &= !flag;
+    }
+}
+
+//modes not supported by this system are ignored
+pub fn apply_terminal_modes(attributes: &mut libc::termios, modes: &[(u8, u32)]) {
+    for &(opcode, argument) in modes.iter() {
+        if let Some(&(_, index)) = CHARACTERS.iter().find(|c| c.0 == opcode) {
+            attributes.c_cc[index] = if argument == DISABLED_CHARACTER { 0 } else { argument as libc::cc_t };
+            continue;
+        }
+        let on = argument != 0;
+        let flags = [
+            (&INPUT_FLAGS[..], &mut attributes.c_iflag),
+            (&LOCAL_FLAGS[..], &mut attributes.c_lflag),
+            (&OUTPUT_FLAGS[..], &mut attributes.c_oflag),
+            (&CONTROL_FLAGS[..], &mut attributes.c_cflag),
+        ];
+        for (table, value) in flags {
+            if let Some(&(_, flag)) = table.iter().find(|f| f.0 == opcode) {
+                set_flag(value, flag, on);
+            }
+        }
+        match opcode {
+            CS7 if on => attributes.c_cflag = (attributes.c_cflag & !libc::CSIZE) | libc::CS7,
+            CS8 if on => attributes.c_cflag = (attributes.c_cflag & !libc::CSIZE) | libc::CS8,
+            TTY_OP_ISPEED => unsafe {
+                libc::cfsetispeed(attributes, baud_to_speed(argument));
+            },
+            TTY_OP_OSPEED => unsafe {
+                libc::cfsetospeed(attributes, baud_to_speed(argument));
+            },
+            _ => {}
+        }
+    }
+}
+
+pub fn encode_terminal_modes(modes: &[(u8, u32)]) -> Vec<u8> {
+    let mut encoded: Vec<u8> = Vec::new();
+    for &(opcode, argument) in modes.iter() {
+        encoded.push(opcode);
+        encoded.write_u32::<BigEndian>(argument).unwrap();
+    }
+    encoded.push(TTY_OP_END);
+    encoded
+}
+
+pub fn decode_terminal_modes(mut encoded: &[u8]) -> Result<Vec<(u8, u32)>, Error> {
+    let mut modes: Vec<(u8, u32)> = Vec::new();
+    //a missing TTY_OP_END is tolerated
+    while !encoded.is_empty() {
+        let opcode = encoded.read_u8()?;
+        if opcode == TTY_OP_END || opcode >= FIRST_UNKNOWN_OPCODE {
+            break;
+        }
+        modes.push((opcode, encoded.read_u32::<BigEndian>()?));
+    }
+    Ok(modes)
+}
+
+fn write_window_size(stream: &mut dyn Write, size: &WindowSize) -> Result<(), Error> {
+    stream.write_u32::<BigEndian>(size.columns)?;
+    stream.write_u32::<BigEndian>(size.rows)?;
+    stream.write_u32::<BigEndian>(size.width_pixels)?;
+    stream.write_u32::<BigEndian>(size.height_pixels)?;
+    Ok(())
+}
+
+fn read_window_size(stream: &mut dyn Read) -> Result<WindowSize, Error> {
+    Ok(WindowSize {
+        columns: stream.read_u32::<BigEndian>()?,
+        rows: stream.read_u32::<BigEndian>()?,
+        width_pixels: stream.read_u32::<BigEndian>()?,
+        height_pixels: stream.read_u32::<BigEndian>()?,
+    })
+}
+
+//RFC 4254 page 11, request specific data of pty-req
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PtyRequest {
+    pub term: String,
+    pub size: WindowSize,
+    pub modes: Vec<(u8, u32)>,
+}
+
+pub fn write_pty_request(stream: &mut dyn Write, request: &PtyRequest) -> Result<(), Error> {
+    io_helpers::write_string(stream, &request.term.as_bytes().to_vec())?;
+    write_window_size(stream, &request.size)?;
+    io_helpers::write_string(stream, &encode_terminal_modes(&request.modes))?;
+    Ok(())
+}
+
+pub fn read_pty_request(stream: &mut dyn Read) -> Result<PtyRequest, Error> {
+    let term = io_helpers::read_utf8_string(stream)?;
+    //TERM ends up in the environment
+    if term.contains('\0') {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+    }
+    let size = read_window_size(stream)?;
+    let modes = decode_terminal_modes(&io_helpers::read_string(stream, None)?)?;
+    Ok(PtyRequest { term, size, modes })
+}
+
+//RFC 4254 page 13, request specific data of window-change
+pub fn write_window_change_request(stream: &mut dyn Write, size: &WindowSize) -> Result<(), Error> {
+    write_window_size(stream, size)
+}
+
+pub fn read_window_change_request(stream: &mut dyn Read) -> Result<WindowSize, Error> {
+    read_window_size(stream)
+}
+
+//RFC 4254 page 13, request specific data of exec
+pub fn write_exec_request(stream: &mut dyn Write, command: &str) -> Result<(), Error> {
+    io_helpers::write_string(stream, &command.as_bytes().to_vec())
+}
+
+//commands are not required to be utf8
+pub fn read_exec_request(stream: &mut dyn Read) -> Result<String, Error> {
+    let command = String::from_utf8_lossy(&io_helpers::read_string(stream, None)?).into_owned();
+    if command.contains('\0') {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+    }
+    Ok(command)
+}
+
+//RFC 4254 page 12, request specific data of env
+pub fn write_env_request(stream: &mut dyn Write, name: &str, value: &str) -> Result<(), Error> {
+    io_helpers::write_string(stream, &name.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &value.as_bytes().to_vec())
+}
+
+//names and values end up in the environment of a process, so "=" in name and NUL anywhere are refused
+pub fn read_env_request(stream: &mut dyn Read) -> Result<(String, String), Error> {
+    let name = io_helpers::read_utf8_string(stream)?;
+    let value = String::from_utf8_lossy(&io_helpers::read_string(stream, None)?).into_owned();
+    if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0') {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE));
+    }
+    Ok((name, value))
+}
+
+//RFC 4254 page 14, request specific data of signal, the name is without "SIG"
+pub fn write_signal_request(stream: &mut dyn Write, signal_name: &str) -> Result<(), Error> {
+    io_helpers::write_string(stream, &signal_name.as_bytes().to_vec())
+}
+
+pub fn read_signal_request(stream: &mut dyn Read) -> Result<String, Error> {
+    io_helpers::read_utf8_string(stream)
+}
+
+//RFC 4254 page 13, request specific data of subsystem
+pub fn write_subsystem_request(stream: &mut dyn Write, name: &str) -> Result<(), Error> {
+    io_helpers::write_string(stream, &name.as_bytes().to_vec())
+}
+
+pub fn read_subsystem_request(stream: &mut dyn Read) -> Result<String, Error> {
+    io_helpers::read_utf8_string(stream)
+}
+
+//RFC 4254 page 15, request specific data of exit-status
+pub fn write_exit_status_request(stream: &mut dyn Write, status: u32) -> Result<(), Error> {
+    stream.write_u32::<BigEndian>(status)
+}
+
+pub fn read_exit_status_request(stream: &mut dyn Read) -> Result<u32, Error> {
+    stream.read_u32::<BigEndian>()
+}
+
+//request specific data of exit-signal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExitSignal {
+    pub signal_name: String,
+    pub core_dumped: bool,
+    pub error_message: String,
+}
+
+pub fn write_exit_signal_request(stream: &mut dyn Write, signal: &ExitSignal) -> Result<(), Error> {
+    io_helpers::write_string(stream, &signal.signal_name.as_bytes().to_vec())?;
+    io_helpers::write_boolean(stream, signal.core_dumped)?;
+    io_helpers::write_string(stream, &signal.error_message.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &Vec::new())?;
+    Ok(())
+}
+
+pub fn read_exit_signal_request(stream: &mut dyn Read) -> Result<ExitSignal, Error> {
+    let signal_name = io_helpers::read_utf8_string(stream)?;
+    let core_dumped = io_helpers::read_boolean(stream)?;
+    let error_message = String::from_utf8_lossy(&io_helpers::read_string(stream, None)?).into_owned();
+    let _language_tag = io_helpers::read_string(stream, None)?;
+    Ok(ExitSignal { signal_name, core_dumped, error_message })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn pty_request_roundtrips() {
+        let request = PtyRequest {
+            term: "xterm-256color".to_string(),
+            size: WindowSize { columns: 80, rows: 24, width_pixels: 640, height_pixels: 480 },
+            modes: vec![(VINTR, 3), (ECHO, 1), (TTY_OP_OSPEED, 38400)],
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_pty_request(&mut data, &request).unwrap();
+        assert_eq!(read_pty_request(&mut data.as_slice()).unwrap(), request);
+
+        let mut data: Vec<u8> = Vec::new();
+        write_window_change_request(&mut data, &request.size).unwrap();
+        assert_eq!(read_window_change_request(&mut data.as_slice()).unwrap(), request.size);
+    }
+
+    #[test]
+    fn exit_requests_roundtrip() {
+        let mut data: Vec<u8> = Vec::new();
+        write_exit_status_request(&mut data, 3).unwrap();
+        assert_eq!(read_exit_status_request(&mut data.as_slice()).unwrap(), 3);
+
+        let signal = ExitSignal { signal_name: get_signal_name(libc::SIGTERM), core_dumped: true, error_message: String::new() };
+        let mut data: Vec<u8> = Vec::new();
+        write_exit_signal_request(&mut data, &signal).unwrap();
+        assert_eq!(read_exit_signal_request(&mut data.as_slice()).unwrap(), signal);
+        assert_eq!(signal.signal_name, "TERM");
+        assert_eq!(get_signal_number("TERM"), Some(libc::SIGTERM));
+        assert_eq!(get_signal_name(libc::SIGBUS), format!("SIG{}@bssh", libc::SIGBUS));
+        assert_eq!(get_signal_number("SIGTERM"), None);
+
+        let mut data: Vec<u8> = Vec::new();
+        write_signal_request(&mut data, "INT").unwrap();
+        assert_eq!(read_signal_request(&mut data.as_slice()).unwrap(), "INT");
+
+        let mut data: Vec<u8> = Vec::new();
+        write_exec_request(&mut data, "ls\0").unwrap();
+        assert!(read_exec_request(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn env_request_checks_name() {
+        let mut data: Vec<u8> = Vec::new();
+        write_env_request(&mut data, "LANG", "C.UTF-8").unwrap();
+        assert_eq!(read_env_request(&mut data.as_slice()).unwrap(), ("LANG".to_string(), "C.UTF-8".to_string()));
+        let mut data: Vec<u8> = Vec::new();
+        write_env_request(&mut data, "A=B", "C").unwrap();
+        assert!(read_env_request(&mut data.as_slice()).is_err());
+        let mut data: Vec<u8> = Vec::new();
+        write_env_request(&mut data, "", "C").unwrap();
+        assert!(read_env_request(&mut data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn terminal_modes_decoding_stops_at_unknown_opcodes() {
+        let mut encoded = encode_terminal_modes(&[(ECHO, 0), (ICANON, 1)]);
+        assert_eq!(encoded.len(), 11);
+        assert_eq!(decode_terminal_modes(&encoded).unwrap(), vec![(ECHO, 0), (ICANON, 1)]);
+        encoded.pop();
+        encoded.extend_from_slice(&[200, 1, 2, 3, ECHO, 0, 0, 0, 1, TTY_OP_END]);
+        assert_eq!(decode_terminal_modes(&encoded).unwrap(), vec![(ECHO, 0), (ICANON, 1)]);
+        //truncated argument
+        assert!(decode_terminal_modes(&[ECHO, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn terminal_modes_apply_to_termios() {
+        let mut attributes: libc::termios = unsafe { std::mem::zeroed() };
+        apply_terminal_modes(&mut attributes, &[(VINTR, 3), (VEOL, 255), (ECHO, 1), (ICRNL, 1), (OPOST, 1), (CS8, 1), (TTY_OP_ISPEED, 38400), (TTY_OP_OSPEED, 40000), (250, 1)]);
+        assert_eq!(attributes.c_cc[libc::VINTR], 3);
+        assert_eq!(attributes.c_lflag, libc::ECHO);
+        assert_eq!(attributes.c_iflag, libc::ICRNL);
+        assert_eq!(attributes.c_cflag & libc::CSIZE, libc::CS8);
+
+        let modes = get_terminal_modes(&attributes);
+        assert!(modes.contains(&(VINTR, 3)));
+        assert!(modes.contains(&(VEOL, 255)));
+        assert!(modes.contains(&(ECHO, 1)));
+        assert!(modes.contains(&(ICANON, 0)));
+        assert!(modes.contains(&(CS8, 1)));
+        assert!(modes.contains(&(TTY_OP_ISPEED, 38400)));
+        assert!(modes.contains(&(TTY_OP_OSPEED, 38400)));
+
+        apply_terminal_modes(&mut attributes, &[(ECHO, 0)]);
+        assert_eq!(attributes.c_lflag, 0);
+    }
+}
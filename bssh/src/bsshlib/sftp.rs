@@ -0,0 +1,355 @@
+use std::fs::Metadata;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use errors;
+use io_helpers;
+
+//SSH File Transfer Protocol version 3 (draft-ietf-secsh-filexfer-02), as spoken by OpenSSH
+
+pub const SFTP_VERSION: u32 = 3;
+
+pub const SSH_FXP_INIT: u8 = 1;
+pub const SSH_FXP_VERSION: u8 = 2;
+pub const SSH_FXP_OPEN: u8 = 3;
+pub const SSH_FXP_CLOSE: u8 = 4;
+pub const SSH_FXP_READ: u8 = 5;
+pub const SSH_FXP_WRITE: u8 = 6;
+pub const SSH_FXP_LSTAT: u8 = 7;
+pub const SSH_FXP_FSTAT: u8 = 8;
+pub const SSH_FXP_SETSTAT: u8 = 9;
+pub const SSH_FXP_FSETSTAT: u8 = 10;
+pub const SSH_FXP_OPENDIR: u8 = 11;
+pub const SSH_FXP_READDIR: u8 = 12;
+pub const SSH_FXP_REMOVE: u8 = 13;
+pub const SSH_FXP_MKDIR: u8 = 14;
+pub const SSH_FXP_RMDIR: u8 = 15;
+pub const SSH_FXP_REALPATH: u8 = 16;
+pub const SSH_FXP_STAT: u8 = 17;
+pub const SSH_FXP_RENAME: u8 = 18;
+pub const SSH_FXP_READLINK: u8 = 19;
+pub const SSH_FXP_SYMLINK: u8 = 20;
+pub const SSH_FXP_STATUS: u8 = 101;
+pub const SSH_FXP_HANDLE: u8 = 102;
+pub const SSH_FXP_DATA: u8 = 103;
+pub const SSH_FXP_NAME: u8 = 104;
+pub const SSH_FXP_ATTRS: u8 = 105;
+pub const SSH_FXP_EXTENDED: u8 = 200;
+pub const SSH_FXP_EXTENDED_REPLY: u8 = 201;
+
+pub const SSH_FXF_READ: u32 = 0x01;
+pub const SSH_FXF_WRITE: u32 = 0x02;
+pub const SSH_FXF_APPEND: u32 = 0x04;
+pub const SSH_FXF_CREAT: u32 = 0x08;
+pub const SSH_FXF_TRUNC: u32 = 0x10;
+pub const SSH_FXF_EXCL: u32 = 0x20;
+
+pub const SSH_FILEXFER_ATTR_SIZE: u32 = 0x01;
+pub const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x02;
+pub const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x04;
+pub const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x08;
+pub const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x8000_0000;
+
+pub const SSH_FX_OK: u32 = 0;
+pub const SSH_FX_EOF: u32 = 1;
+pub const SSH_FX_NO_SUCH_FILE: u32 = 2;
+pub const SSH_FX_PERMISSION_DENIED: u32 = 3;
+pub const SSH_FX_FAILURE: u32 = 4;
+pub const SSH_FX_BAD_MESSAGE: u32 = 5;
+pub const SSH_FX_NO_CONNECTION: u32 = 6;
+pub const SSH_FX_CONNECTION_LOST: u32 = 7;
+pub const SSH_FX_OP_UNSUPPORTED: u32 = 8;
+
+//OpenSSH extensions (PROTOCOL in OpenSSH sources), with their versions
+pub const EXTENSION_POSIX_RENAME: &str = "posix-rename@openssh.com";
+pub const EXTENSION_STATVFS: &str = "statvfs@openssh.com";
+pub const EXTENSION_FSTATVFS: &str = "fstatvfs@openssh.com";
+pub const EXTENSION_HARDLINK: &str = "hardlink@openssh.com";
+pub const EXTENSION_FSYNC: &str = "fsync@openssh.com";
+pub const EXTENSION_LIMITS: &str = "limits@openssh.com";
+pub const EXTENSION_EXPAND_PATH: &str = "expand-path@openssh.com";
+
+//statvfs f_flag bits
+pub const SSH_FXE_STATVFS_ST_RDONLY: u64 = 0x1;
+pub const SSH_FXE_STATVFS_ST_NOSUID: u64 = 0x2;
+
+//same limits as OpenSSH sftp-server
+pub const MAX_PACKET_LENGTH: u32 = 256 * 1024;
+pub const MAX_READ_LENGTH: u32 = MAX_PACKET_LENGTH - 1024;
+
+pub fn get_status_message(code: u32) -> &'static str {
+    match code {
+        SSH_FX_OK => "Success",
+        SSH_FX_EOF => "End of file",
+        SSH_FX_NO_SUCH_FILE => "No such file",
+        SSH_FX_PERMISSION_DENIED => "Permission denied",
+        SSH_FX_BAD_MESSAGE => "Bad message",
+        SSH_FX_NO_CONNECTION => "No connection",
+        SSH_FX_CONNECTION_LOST => "Connection lost",
+        SSH_FX_OP_UNSUPPORTED => "Operation unsupported",
+        _ => "Failure",
+    }
+}
+
+//packets are framed by uint32 length, which is not part of the returned packet
+pub fn write_packet(stream: &mut dyn Write, packet: &[u8]) -> Result<(), Error> {
+    stream.write_u32::<BigEndian>(packet.len() as u32)?;
+    stream.write_all(packet)?;
+    stream.flush()
+}
+
+pub fn read_packet(stream: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    let packet = io_helpers::read_string(stream, Some(MAX_PACKET_LENGTH))?;
+    if packet.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SFTP_BAD_PACKET));
+    }
+    Ok(packet)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub size: Option<u64>,
+    pub uid_gid: Option<(u32, u32)>,
+    //st_mode, the file type included
+    pub permissions: Option<u32>,
+    //atime and mtime, in seconds since the epoch
+    pub times: Option<(u32, u32)>,
+    pub extended: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl FileAttributes {
+    pub fn from_metadata(metadata: &Metadata) -> FileAttributes {
+        FileAttributes {
+            size: Some(metadata.size()),
+            uid_gid: Some((metadata.uid(), metadata.gid())),
+            permissions: Some(metadata.mode()),
+            times: Some((metadata.atime() as u32, metadata.mtime() as u32)),
+            extended: Vec::new(),
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.permissions.map(|p| p & libc::S_IFMT == libc::S_IFDIR).unwrap_or(false)
+    }
+}
+
+pub fn write_attributes(stream: &mut dyn Write, attributes: &FileAttributes) -> Result<(), Error> {
+    let mut flags = 0;
+    if attributes.size.is_some() {
+        flags |= SSH_FILEXFER_ATTR_SIZE;
+    }
+    if attributes.uid_gid.is_some() {
+        flags |= SSH_FILEXFER_ATTR_UIDGID;
+    }
+    if attributes.permissions.is_some() {
+        flags |= SSH_FILEXFER_ATTR_PERMISSIONS;
+    }
+    if attributes.times.is_some() {
+        flags |= SSH_FILEXFER_ATTR_ACMODTIME;
+    }
+    if !attributes.extended.is_empty() {
+        flags |= SSH_FILEXFER_ATTR_EXTENDED;
+    }
+    stream.write_u32::<BigEndian>(flags)?;
+    if let Some(size) = attributes.size {
+        stream.write_u64::<BigEndian>(size)?;
+    }
+    if let Some((uid, gid)) = attributes.uid_gid {
+        stream.write_u32::<BigEndian>(uid)?;
+        stream.write_u32::<BigEndian>(gid)?;
+    }
+    if let Some(permissions) = attributes.permissions {
+        stream.write_u32::<BigEndian>(permissions)?;
+    }
+    if let Some((atime, mtime)) = attributes.times {
+        stream.write_u32::<BigEndian>(atime)?;
+        stream.write_u32::<BigEndian>(mtime)?;
+    }
+    if !attributes.extended.is_empty() {
+        stream.write_u32::<BigEndian>(attributes.extended.len() as u32)?;
+        for (extension_type, data) in attributes.extended.iter() {
+            io_helpers::write_string(stream, extension_type)?;
+            io_helpers::write_string(stream, data)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_attributes(stream: &mut dyn Read) -> Result<FileAttributes, Error> {
+    let flags = stream.read_u32::<BigEndian>()?;
+    let mut attributes = FileAttributes::default();
+    if flags & SSH_FILEXFER_ATTR_SIZE != 0 {
+        attributes.size = Some(stream.read_u64::<BigEndian>()?);
+    }
+    if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+        attributes.uid_gid = Some((stream.read_u32::<BigEndian>()?, stream.read_u32::<BigEndian>()?));
+    }
+    if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+        attributes.permissions = Some(stream.read_u32::<BigEndian>()?);
+    }
+    if flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+        attributes.times = Some((stream.read_u32::<BigEndian>()?, stream.read_u32::<BigEndian>()?));
+    }
+    if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+        let count = stream.read_u32::<BigEndian>()?;
+        for _ in 0..count {
+            let extension_type = io_helpers::read_string(stream, None)?;
+            let data = io_helpers::read_string(stream, None)?;
+            attributes.extended.push((extension_type, data));
+        }
+    }
+    Ok(attributes)
+}
+
+//SSH_FXP_STATUS, the language tag is always empty
+pub fn write_status(stream: &mut dyn Write, id: u32, code: u32, message: &str) -> Result<(), Error> {
+    stream.write_u8(SSH_FXP_STATUS)?;
+    stream.write_u32::<BigEndian>(id)?;
+    stream.write_u32::<BigEndian>(code)?;
+    io_helpers::write_string(stream, &message.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &Vec::new())
+}
+
+//status code and message, after the type and id
+pub fn read_status(stream: &mut dyn Read) -> Result<(u32, String), Error> {
+    let code = stream.read_u32::<BigEndian>()?;
+    let message = String::from_utf8_lossy(&io_helpers::read_string(stream, None)?).into_owned();
+    //some servers leave the language tag out
+    let _language_tag = io_helpers::read_string(stream, None);
+    Ok((code, message))
+}
+
+//entry of SSH_FXP_NAME, names are bytes like the file names of Unix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameEntry {
+    pub filename: Vec<u8>,
+    //"ls -l" like line, for display only
+    pub longname: Vec<u8>,
+    pub attributes: FileAttributes,
+}
+
+pub fn write_names(stream: &mut dyn Write, id: u32, names: &[NameEntry]) -> Result<(), Error> {
+    stream.write_u8(SSH_FXP_NAME)?;
+    stream.write_u32::<BigEndian>(id)?;
+    stream.write_u32::<BigEndian>(names.len() as u32)?;
+    for name in names.iter() {
+        io_helpers::write_string(stream, &name.filename)?;
+        io_helpers::write_string(stream, &name.longname)?;
+        write_attributes(stream, &name.attributes)?;
+    }
+    Ok(())
+}
+
+//entries after the type and id
+pub fn read_names(stream: &mut dyn Read) -> Result<Vec<NameEntry>, Error> {
+    let count = stream.read_u32::<BigEndian>()?;
+    let mut names: Vec<NameEntry> = Vec::new();
+    for _ in 0..count {
+        let filename = io_helpers::read_string(stream, None)?;
+        let longname = io_helpers::read_string(stream, None)?;
+        let attributes = read_attributes(stream)?;
+        names.push(NameEntry { filename, longname, attributes });
+    }
+    Ok(names)
+}
+
+//statvfs@openssh.com reply, fields of statvfs(3)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilesystemStatistics {
+    pub block_size: u64,
+    pub fragment_size: u64,
+    pub blocks: u64,
+    pub blocks_free: u64,
+    pub blocks_available: u64,
+    pub files: u64,
+    pub files_free: u64,
+    pub files_available: u64,
+    pub filesystem_id: u64,
+    pub flags: u64,
+    pub name_max: u64,
+}
+
+pub fn write_filesystem_statistics(stream: &mut dyn Write, statistics: &FilesystemStatistics) -> Result<(), Error> {
+    for value in [statistics.block_size, statistics.fragment_size, statistics.blocks, statistics.blocks_free, statistics.blocks_available,
+                  statistics.files, statistics.files_free, statistics.files_available, statistics.filesystem_id, statistics.flags,
+                  statistics.name_max].iter() {
+        stream.write_u64::<BigEndian>(*value)?;
+    }
+    Ok(())
+}
+
+pub fn read_filesystem_statistics(stream: &mut dyn Read) -> Result<FilesystemStatistics, Error> {
+    Ok(FilesystemStatistics {
+        block_size: stream.read_u64::<BigEndian>()?,
+        fragment_size: stream.read_u64::<BigEndian>()?,
+        blocks: stream.read_u64::<BigEndian>()?,
+        blocks_free: stream.read_u64::<BigEndian>()?,
+        blocks_available: stream.read_u64::<BigEndian>()?,
+        files: stream.read_u64::<BigEndian>()?,
+        files_free: stream.read_u64::<BigEndian>()?,
+        files_available: stream.read_u64::<BigEndian>()?,
+        filesystem_id: stream.read_u64::<BigEndian>()?,
+        flags: stream.read_u64::<BigEndian>()?,
+        name_max: stream.read_u64::<BigEndian>()?,
+    })
+}
+
+//limits@openssh.com reply, 0 means no limit
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_packet_length: u64,
+    pub max_read_length: u64,
+    pub max_write_length: u64,
+    pub max_open_handles: u64,
+}
+
+pub fn write_limits(stream: &mut dyn Write, limits: &Limits) -> Result<(), Error> {
+    stream.write_u64::<BigEndian>(limits.max_packet_length)?;
+    stream.write_u64::<BigEndian>(limits.max_read_length)?;
+    stream.write_u64::<BigEndian>(limits.max_write_length)?;
+    stream.write_u64::<BigEndian>(limits.max_open_handles)
+}
+
+pub fn read_limits(stream: &mut dyn Read) -> Result<Limits, Error> {
+    Ok(Limits {
+        max_packet_length: stream.read_u64::<BigEndian>()?,
+        max_read_length: stream.read_u64::<BigEndian>()?,
+        max_write_length: stream.read_u64::<BigEndian>()?,
+        max_open_handles: stream.read_u64::<BigEndian>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn attributes_roundtrip() {
+        let attributes = FileAttributes {
+            size: Some(1 << 40),
+            uid_gid: None,
+            permissions: Some(libc::S_IFDIR | 0o755),
+            times: Some((1, 2)),
+            extended: vec![(b"a@example.com".to_vec(), b"x".to_vec())],
+        };
+        let mut data: Vec<u8> = Vec::new();
+        write_attributes(&mut data, &attributes).unwrap();
+        assert_eq!(read_attributes(&mut data.as_slice()).unwrap(), attributes);
+        assert!(attributes.is_directory());
+
+        let mut data: Vec<u8> = Vec::new();
+        write_attributes(&mut data, &FileAttributes::default()).unwrap();
+        assert_eq!(data, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn packets_are_limited() {
+        let mut data: Vec<u8> = Vec::new();
+        write_packet(&mut data, &[SSH_FXP_INIT, 0, 0, 0, 3]).unwrap();
+        assert_eq!(read_packet(&mut data.as_slice()).unwrap(), vec![SSH_FXP_INIT, 0, 0, 0, 3]);
+        let data = (MAX_PACKET_LENGTH + 1).to_be_bytes();
+        assert!(read_packet(&mut &data[..]).is_err());
+        assert!(read_packet(&mut &[0u8, 0, 0, 0][..]).is_err());
+    }
+}
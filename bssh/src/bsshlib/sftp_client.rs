@@ -0,0 +1,649 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::env;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use errors;
+use io_helpers;
+use sftp;
+use sftp::{FileAttributes, NameEntry};
+use libc;
+
+//SFTP version 3 client, replies may come in any order so they are matched to the requests by id
+
+//requests in flight per file, as by OpenSSH sftp
+pub const MAX_OUTSTANDING_REQUESTS: usize = 64;
+//read and write length for servers which do not tell their limits
+pub const DEFAULT_TRANSFER_LENGTH: u32 = 32 * 1024;
+//the ssh client runs the subsystem, as for OpenSSH sftp and scp
+pub const SSH_PROGRAM: &str = "bsshc";
+pub const SUBSYSTEM: &str = "sftp";
+
+pub struct SftpClient {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    next_id: u32,
+    pub version: u32,
+    pub extensions: Vec<(String, String)>,
+    pub read_length: u32,
+    pub write_length: u32,
+    //replies which came while another one was awaited
+    replies: HashMap<u32, Vec<u8>>,
+    //requests whose replies are dropped, e.g. reads ahead of a seek
+    ignored: HashSet<u32>,
+}
+
+//a failed request is reported with the message of the server, if it sent one
+fn get_status_error(code: u32, message: &str) -> Error {
+    let kind = match code {
+        sftp::SSH_FX_EOF => ErrorKind::UnexpectedEof,
+        sftp::SSH_FX_NO_SUCH_FILE => ErrorKind::NotFound,
+        sftp::SSH_FX_PERMISSION_DENIED => ErrorKind::PermissionDenied,
+        sftp::SSH_FX_OP_UNSUPPORTED => ErrorKind::Unsupported,
+        sftp::SSH_FX_BAD_MESSAGE => ErrorKind::InvalidData,
+        _ => ErrorKind::Other,
+    };
+    let message = if message.is_empty() { sftp::get_status_message(code) } else { message };
+    Error::new(kind, message.to_string())
+}
+
+//fields of the reply after type and id, SSH_FXP_STATUS is an error unless it was expected and is SSH_FX_OK
+fn get_reply_data(reply: &[u8], packet_type: u8) -> Result<&[u8], Error> {
+    if reply.len() < 5 {
+        return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SFTP_BAD_PACKET));
+    }
+    let data = &reply[5..];
+    match reply[0] {
+        sftp::SSH_FXP_STATUS => {
+            let (code, message) = sftp::read_status(&mut &data[..])?;
+            if code == sftp::SSH_FX_OK && packet_type == sftp::SSH_FXP_STATUS {
+                return Ok(data);
+            }
+            Err(get_status_error(code, &message))
+        }
+        reply_type if reply_type == packet_type => Ok(data),
+        _ => Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SFTP_BAD_PACKET)),
+    }
+}
+
+fn write_path(stream: &mut Vec<u8>, path: &Path) -> Result<(), Error> {
+    io_helpers::write_string(stream, &path.as_os_str().as_bytes().to_vec())
+}
+
+fn read_single_name(data: &[u8]) -> Result<PathBuf, Error> {
+    let names = sftp::read_names(&mut &data[..])?;
+    let name = names.into_iter().next().ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SFTP_BAD_PACKET))?;
+    Ok(PathBuf::from(OsStr::from_bytes(&name.filename)))
+}
+
+impl SftpClient {
+    //sends SSH_FXP_INIT, the transfer lengths follow limits@openssh.com when the server has it
+    pub fn connect(input: Box<dyn Read>, output: Box<dyn Write>) -> Result<SftpClient, Error> {
+        let mut client = SftpClient {
+            input,
+            output,
+            next_id: 0,
+            version: 0,
+            extensions: Vec::new(),
+            read_length: DEFAULT_TRANSFER_LENGTH,
+            write_length: DEFAULT_TRANSFER_LENGTH,
+            replies: HashMap::new(),
+            ignored: HashSet::new(),
+        };
+        let mut init: Vec<u8> = vec![sftp::SSH_FXP_INIT];
+        init.write_u32::<BigEndian>(sftp::SFTP_VERSION)?;
+        sftp::write_packet(&mut client.output, &init)?;
+
+        let version = sftp::read_packet(&mut client.input)?;
+        let mut stream = &version[..];
+        if stream.read_u8()? != sftp::SSH_FXP_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SFTP_BAD_PACKET));
+        }
+        client.version = stream.read_u32::<BigEndian>()?;
+        if client.version < sftp::SFTP_VERSION {
+            return Err(Error::new(ErrorKind::Unsupported, errors::BSSH_ERR_SFTP_VERSION_NOT_SUPPORTED));
+        }
+        while !stream.is_empty() {
+            let name = io_helpers::read_utf8_string(&mut stream)?;
+            let data = io_helpers::read_utf8_string(&mut stream)?;
+            client.extensions.push((name, data));
+        }
+
+        if client.has_extension(sftp::EXTENSION_LIMITS) {
+            let mut data: Vec<u8> = Vec::new();
+            io_helpers::write_string(&mut data, &sftp::EXTENSION_LIMITS.as_bytes().to_vec())?;
+            let reply = client.request(sftp::SSH_FXP_EXTENDED, &data)?;
+            let limits = sftp::read_limits(&mut get_reply_data(&reply, sftp::SSH_FXP_EXTENDED_REPLY)?)?;
+            if limits.max_read_length > 0 {
+                client.read_length = std::cmp::min(limits.max_read_length, sftp::MAX_READ_LENGTH as u64) as u32;
+            }
+            if limits.max_write_length > 0 {
+                client.write_length = std::cmp::min(limits.max_write_length, sftp::MAX_READ_LENGTH as u64) as u32;
+            }
+        }
+        Ok(client)
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|e| e.0 == name)
+    }
+
+    //request fields come after type and id, the reply is awaited separately
+    pub fn send_request(&mut self, packet_type: u8, data: &[u8]) -> Result<u32, Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut packet: Vec<u8> = vec![packet_type];
+        packet.write_u32::<BigEndian>(id)?;
+        packet.extend_from_slice(data);
+        sftp::write_packet(&mut self.output, &packet)?;
+        Ok(id)
+    }
+
+    //replies to other requests are kept until they are awaited
+    pub fn wait_reply(&mut self, id: u32) -> Result<Vec<u8>, Error> {
+        if let Some(reply) = self.replies.remove(&id) {
+            return Ok(reply);
+        }
+        loop {
+            let reply = sftp::read_packet(&mut self.input)?;
+            let reply_id = (&reply[1..]).read_u32::<BigEndian>()?;
+            if reply_id == id {
+                return Ok(reply);
+            }
+            if !self.ignored.remove(&reply_id) {
+                self.replies.insert(reply_id, reply);
+            }
+        }
+    }
+
+    fn ignore_reply(&mut self, id: u32) {
+        if self.replies.remove(&id).is_none() {
+            self.ignored.insert(id);
+        }
+    }
+
+    pub fn request(&mut self, packet_type: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let id = self.send_request(packet_type, data)?;
+        self.wait_reply(id)
+    }
+
+    fn request_status(&mut self, packet_type: u8, data: &[u8]) -> Result<(), Error> {
+        let reply = self.request(packet_type, data)?;
+        get_reply_data(&reply, sftp::SSH_FXP_STATUS)?;
+        Ok(())
+    }
+
+    fn request_path(&mut self, packet_type: u8, path: &Path) -> Result<Vec<u8>, Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        self.request(packet_type, &data)
+    }
+
+    fn request_handle(&mut self, packet_type: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let reply = self.request(packet_type, data)?;
+        io_helpers::read_string(&mut get_reply_data(&reply, sftp::SSH_FXP_HANDLE)?, None)
+    }
+
+    //flags are SSH_FXF_*, the attributes are used for created files
+    pub fn open(&mut self, path: &Path, flags: u32, attributes: &FileAttributes) -> Result<SftpFile<'_>, Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        data.write_u32::<BigEndian>(flags)?;
+        sftp::write_attributes(&mut data, attributes)?;
+        let handle = self.request_handle(sftp::SSH_FXP_OPEN, &data)?;
+        Ok(SftpFile::new(self, handle))
+    }
+
+    fn close_handle(&mut self, handle: &[u8]) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        io_helpers::write_string(&mut data, &handle.to_vec())?;
+        self.request_status(sftp::SSH_FXP_CLOSE, &data)
+    }
+
+    fn get_attributes(reply: &[u8]) -> Result<FileAttributes, Error> {
+        sftp::read_attributes(&mut get_reply_data(reply, sftp::SSH_FXP_ATTRS)?)
+    }
+
+    //follows symbolic links
+    pub fn stat(&mut self, path: &Path) -> Result<FileAttributes, Error> {
+        let reply = self.request_path(sftp::SSH_FXP_STAT, path)?;
+        SftpClient::get_attributes(&reply)
+    }
+
+    pub fn lstat(&mut self, path: &Path) -> Result<FileAttributes, Error> {
+        let reply = self.request_path(sftp::SSH_FXP_LSTAT, path)?;
+        SftpClient::get_attributes(&reply)
+    }
+
+    pub fn setstat(&mut self, path: &Path, attributes: &FileAttributes) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        sftp::write_attributes(&mut data, attributes)?;
+        self.request_status(sftp::SSH_FXP_SETSTAT, &data)
+    }
+
+    //all entries, "." and ".." included when the server lists them
+    pub fn read_dir(&mut self, path: &Path) -> Result<Vec<NameEntry>, Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        let handle = self.request_handle(sftp::SSH_FXP_OPENDIR, &data)?;
+        let mut data: Vec<u8> = Vec::new();
+        io_helpers::write_string(&mut data, &handle)?;
+        let mut entries: Vec<NameEntry> = Vec::new();
+        let result = loop {
+            let reply = match self.request(sftp::SSH_FXP_READDIR, &data) {
+                Ok(reply) => reply,
+                Err(e) => break Err(e),
+            };
+            match get_reply_data(&reply, sftp::SSH_FXP_NAME) {
+                Ok(names) => match sftp::read_names(&mut &names[..]) {
+                    Ok(names) => entries.extend(names),
+                    Err(e) => break Err(e),
+                },
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        let closed = self.close_handle(&handle);
+        result.and(closed)?;
+        Ok(entries)
+    }
+
+    pub fn remove(&mut self, path: &Path) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        self.request_status(sftp::SSH_FXP_REMOVE, &data)
+    }
+
+    pub fn mkdir(&mut self, path: &Path, attributes: &FileAttributes) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        sftp::write_attributes(&mut data, attributes)?;
+        self.request_status(sftp::SSH_FXP_MKDIR, &data)
+    }
+
+    pub fn rmdir(&mut self, path: &Path) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, path)?;
+        self.request_status(sftp::SSH_FXP_RMDIR, &data)
+    }
+
+    //absolute path without "." and ".." components, "." is the initial directory
+    pub fn realpath(&mut self, path: &Path) -> Result<PathBuf, Error> {
+        let reply = self.request_path(sftp::SSH_FXP_REALPATH, path)?;
+        read_single_name(get_reply_data(&reply, sftp::SSH_FXP_NAME)?)
+    }
+
+    //an existing target is replaced when the server has posix-rename@openssh.com, as by OpenSSH sftp
+    pub fn rename(&mut self, old: &Path, new: &Path) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        if self.has_extension(sftp::EXTENSION_POSIX_RENAME) {
+            io_helpers::write_string(&mut data, &sftp::EXTENSION_POSIX_RENAME.as_bytes().to_vec())?;
+            write_path(&mut data, old)?;
+            write_path(&mut data, new)?;
+            return self.request_status(sftp::SSH_FXP_EXTENDED, &data);
+        }
+        write_path(&mut data, old)?;
+        write_path(&mut data, new)?;
+        self.request_status(sftp::SSH_FXP_RENAME, &data)
+    }
+
+    //the target comes first, as OpenSSH servers expect it
+    pub fn symlink(&mut self, target: &Path, link: &Path) -> Result<(), Error> {
+        let mut data: Vec<u8> = Vec::new();
+        write_path(&mut data, target)?;
+        write_path(&mut data, link)?;
+        self.request_status(sftp::SSH_FXP_SYMLINK, &data)
+    }
+
+    pub fn readlink(&mut self, path: &Path) -> Result<PathBuf, Error> {
+        let reply = self.request_path(sftp::SSH_FXP_READLINK, path)?;
+        read_single_name(get_reply_data(&reply, sftp::SSH_FXP_NAME)?)
+    }
+}
+
+//remote file, reads go ahead and writes are acknowledged behind the position,
+//with up to MAX_OUTSTANDING_REQUESTS requests in flight for each
+pub struct SftpFile<'a> {
+    client: &'a mut SftpClient,
+    handle: Vec<u8>,
+    position: u64,
+    //data read ahead, starting at the position
+    buffer: Vec<u8>,
+    //reads following the buffer: id, offset and length
+    reads: VecDeque<(u32, u64, u32)>,
+    //reads to keep in flight, doubled with each complete reply
+    read_window: usize,
+    eof: bool,
+    writes: VecDeque<u32>,
+    closed: bool,
+}
+
+impl<'a> SftpFile<'a> {
+    fn new(client: &'a mut SftpClient, handle: Vec<u8>) -> SftpFile<'a> {
+        SftpFile {
+            client,
+            handle,
+            position: 0,
+            buffer: Vec::new(),
+            reads: VecDeque::new(),
+            read_window: 1,
+            eof: false,
+            writes: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    fn get_handle_data(&self) -> Result<Vec<u8>, Error> {
+        let mut data: Vec<u8> = Vec::new();
+        io_helpers::write_string(&mut data, &self.handle)?;
+        Ok(data)
+    }
+
+    fn cancel_reads(&mut self) {
+        for (id, _, _) in self.reads.drain(..) {
+            self.client.ignore_reply(id);
+        }
+    }
+
+    fn discard_reads(&mut self) {
+        self.cancel_reads();
+        self.buffer.clear();
+        self.eof = false;
+    }
+
+    fn request_reads(&mut self) -> Result<(), Error> {
+        while self.reads.len() < self.read_window {
+            let offset = self.reads.back().map(|r| r.1 + r.2 as u64).unwrap_or(self.position + self.buffer.len() as u64);
+            let length = self.client.read_length;
+            let mut data = self.get_handle_data()?;
+            data.write_u64::<BigEndian>(offset)?;
+            data.write_u32::<BigEndian>(length)?;
+            let id = self.client.send_request(sftp::SSH_FXP_READ, &data)?;
+            self.reads.push_back((id, offset, length));
+        }
+        Ok(())
+    }
+
+    //a short read leaves a gap before the reads which follow, so they are requested again
+    fn receive_read(&mut self) -> Result<(), Error> {
+        let (id, _, length) = match self.reads.pop_front() {
+            Some(read) => read,
+            None => return Ok(()),
+        };
+        let reply = self.client.wait_reply(id)?;
+        match get_reply_data(&reply, sftp::SSH_FXP_DATA) {
+            Ok(mut data) => {
+                self.buffer = io_helpers::read_string(&mut data, Some(length))?;
+                if self.buffer.len() < length as usize {
+                    self.cancel_reads();
+                } else if self.read_window < MAX_OUTSTANDING_REQUESTS {
+                    self.read_window *= 2;
+                }
+                Ok(())
+            }
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                self.discard_reads();
+                self.eof = true;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    //acknowledged writes, the first failure is returned
+    fn finish_writes(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        while let Some(id) = self.writes.pop_front() {
+            let reply = self.client.wait_reply(id);
+            if result.is_ok() {
+                result = reply.and_then(|r| get_reply_data(&r, sftp::SSH_FXP_STATUS).map(|_| ()));
+            }
+        }
+        result
+    }
+
+    pub fn metadata(&mut self) -> Result<FileAttributes, Error> {
+        self.finish_writes()?;
+        let data = self.get_handle_data()?;
+        let reply = self.client.request(sftp::SSH_FXP_FSTAT, &data)?;
+        SftpClient::get_attributes(&reply)
+    }
+
+    pub fn set_metadata(&mut self, attributes: &FileAttributes) -> Result<(), Error> {
+        self.finish_writes()?;
+        let mut data = self.get_handle_data()?;
+        sftp::write_attributes(&mut data, attributes)?;
+        self.client.request_status(sftp::SSH_FXP_FSETSTAT, &data)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.closed = true;
+        self.discard_reads();
+        let written = self.finish_writes();
+        let handle = self.handle.clone();
+        let closed = self.client.close_handle(&handle);
+        written.and(closed)
+    }
+
+    //reports failed writes, which dropping the file would ignore
+    pub fn close(mut self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl<'a> Read for SftpFile<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.finish_writes()?;
+        while self.buffer.is_empty() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.request_reads()?;
+            if let Err(e) = self.receive_read() {
+                self.discard_reads();
+                return Err(e);
+            }
+        }
+        let length = std::cmp::min(buf.len(), self.buffer.len());
+        buf[..length].copy_from_slice(&self.buffer[..length]);
+        self.buffer.drain(..length);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl<'a> Write for SftpFile<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.discard_reads();
+        let length = std::cmp::min(buf.len(), self.client.write_length as usize);
+        let mut data = self.get_handle_data()?;
+        data.write_u64::<BigEndian>(self.position)?;
+        io_helpers::write_string(&mut data, &buf[..length].to_vec())?;
+        let id = self.client.send_request(sftp::SSH_FXP_WRITE, &data)?;
+        self.writes.push_back(id);
+        self.position += length as u64;
+        if self.writes.len() >= MAX_OUTSTANDING_REQUESTS {
+            let id = self.writes.pop_front().unwrap();
+            let reply = self.client.wait_reply(id)?;
+            get_reply_data(&reply, sftp::SSH_FXP_STATUS)?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.finish_writes()
+    }
+}
+
+impl<'a> Seek for SftpFile<'a> {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, Error> {
+        let (base, offset) = match position {
+            SeekFrom::Start(position) => (position, 0),
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => {
+                let size = self.metadata()?.size.ok_or_else(|| Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SFTP_BAD_PACKET))?;
+                (size, offset)
+            }
+        };
+        let position = base.checked_add_signed(offset).ok_or_else(|| Error::from(ErrorKind::InvalidInput))?;
+        if position != self.position {
+            self.discard_reads();
+            self.position = position;
+        }
+        Ok(position)
+    }
+}
+
+impl<'a> Drop for SftpFile<'a> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.finish();
+        }
+    }
+}
+
+//the ssh client installed next to the running program, or the one in PATH
+pub fn get_ssh_program() -> PathBuf {
+    env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(SSH_PROGRAM)))
+        .filter(|program| program.exists())
+        .unwrap_or_else(|| PathBuf::from(SSH_PROGRAM))
+}
+
+//^C is left to the caller, the ssh client only sees its input closing
+pub fn start_ssh(program: &Path, ssh_args: &[String], host: &str) -> Result<(Child, SftpClient), Error> {
+    let mut command = Command::new(program);
+    command.args(ssh_args).arg("-s").arg(host).arg(SUBSYSTEM)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    unsafe {
+        command.pre_exec(|| {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    let input = child.stdout.take().unwrap();
+    let output = child.stdin.take().unwrap();
+    let client = SftpClient::connect(Box::new(input), Box::new(output))?;
+    Ok((child, client))
+}
+
+//remote paths are relative to the working directory of the client, which the server does not know
+pub fn join_path(directory: &Path, path: &Path) -> PathBuf {
+    if path.as_os_str().is_empty() {
+        directory.to_path_buf()
+    } else {
+        directory.join(path)
+    }
+}
+
+//words of an sftp command line, quotes and backslashes work as in the shell
+pub fn split_command_line(line: &str) -> Result<Vec<String>, Error> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                let escaped = chars.next().ok_or_else(|| Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNTERMINATED_QUOTE))?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_UNTERMINATED_QUOTE));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use sftp_server;
+
+    fn start_client() -> (SftpClient, thread::JoinHandle<()>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let mut output = server.try_clone().unwrap();
+            sftp_server::serve(&mut &server, &mut output).unwrap();
+        });
+        let input = client.try_clone().unwrap();
+        (SftpClient::connect(Box::new(input), Box::new(client)).unwrap(), handle)
+    }
+
+    #[test]
+    fn client_pipelines_reads_and_writes() {
+        let directory = env::temp_dir().join(format!("bssh_sftp_client_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let (mut client, server) = start_client();
+        assert!(client.has_extension(sftp::EXTENSION_POSIX_RENAME));
+        assert_eq!(client.read_length, sftp::MAX_READ_LENGTH);
+        client.read_length = 1000;
+        client.write_length = 700;
+
+        client.mkdir(&directory, &FileAttributes::default()).unwrap();
+        let path = directory.join("file");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut file = client.open(&path, sftp::SSH_FXF_WRITE | sftp::SSH_FXF_CREAT | sftp::SSH_FXF_TRUNC, &FileAttributes::default()).unwrap();
+        file.write_all(&content).unwrap();
+        assert_eq!(file.metadata().unwrap().size, Some(content.len() as u64));
+        file.close().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), content);
+
+        let mut file = client.open(&path, sftp::SSH_FXF_READ, &FileAttributes::default()).unwrap();
+        let mut start = [0u8; 10];
+        file.read_exact(&mut start).unwrap();
+        assert_eq!(&start[..], &content[..10]);
+        assert_eq!(file.seek(SeekFrom::End(-50_500)).unwrap(), 49_500);
+        let mut rest: Vec<u8> = Vec::new();
+        file.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &content[49_500..]);
+        assert_eq!(file.read(&mut start).unwrap(), 0);
+        drop(file);
+
+        let link = directory.join("link");
+        client.symlink(Path::new("missing"), &link).unwrap();
+        assert_eq!(client.readlink(&link).unwrap(), PathBuf::from("missing"));
+        assert_eq!(client.lstat(&link).unwrap().permissions.unwrap() & libc::S_IFMT, libc::S_IFLNK);
+        assert_eq!(client.stat(&link).unwrap_err().kind(), ErrorKind::NotFound);
+        client.rename(&link, &path).unwrap();
+        let names: Vec<Vec<u8>> = client.read_dir(&directory).unwrap().into_iter().map(|n| n.filename).collect();
+        assert_eq!(names, vec![b"file".to_vec()]);
+        assert_eq!(client.realpath(&directory.join("../")).unwrap(), fs::canonicalize(env::temp_dir()).unwrap());
+
+        client.remove(&path).unwrap();
+        assert_eq!(client.remove(&path).unwrap_err().kind(), ErrorKind::NotFound);
+        client.rmdir(&directory).unwrap();
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn command_lines_are_split_like_the_shell() {
+        assert_eq!(split_command_line("  put  a\\ b 'c d' \"e\\\"f\" ''").unwrap(), vec!["put", "a b", "c d", "e\"f", ""]);
+        assert!(split_command_line("get 'file").is_err());
+        assert!(split_command_line("get file\\").is_err());
+        assert!(split_command_line("").unwrap().is_empty());
+    }
+}
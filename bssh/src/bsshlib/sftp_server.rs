@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::fs::{DirBuilder, File, Metadata, OpenOptions, Permissions, ReadDir};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libc;
+use errors;
+use io_helpers;
+use passwd;
+use sftp;
+use sftp::{FileAttributes, FilesystemStatistics, Limits, NameEntry};
+
+//SFTP version 3 server, file access is checked by the kernel only, so it has to run as the user
+
+const MAX_OPEN_HANDLES: usize = 512;
+//directory entries in one SSH_FXP_NAME reply
+const READDIR_BATCH_LENGTH: usize = 100;
+//"ls -l" shows the year instead of the time for files older than this, in seconds
+const RECENT_TIME: i64 = 182 * 24 * 60 * 60;
+
+enum Handle {
+    File(File),
+    Directory(ReadDir),
+}
+
+pub struct SftpServer {
+    handles: HashMap<u32, Handle>,
+    next_handle: u32,
+}
+
+fn check(ret: libc::c_int) -> Result<(), Error> {
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn invalid_handle() -> Error {
+    Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_SFTP_INVALID_HANDLE)
+}
+
+fn read_path(stream: &mut &[u8]) -> Result<PathBuf, Error> {
+    let name = io_helpers::read_string(stream, None)?;
+    Ok(PathBuf::from(OsStr::from_bytes(&name)))
+}
+
+fn get_c_path(path: &Path) -> Result<CString, Error> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+//errors of our own (e.g. truncated packets) are bad messages, those of the system map to the closest status
+pub fn get_status_code(e: &Error) -> u32 {
+    match e.kind() {
+        ErrorKind::NotFound => sftp::SSH_FX_NO_SUCH_FILE,
+        ErrorKind::PermissionDenied => sftp::SSH_FX_PERMISSION_DENIED,
+        ErrorKind::Unsupported => sftp::SSH_FX_OP_UNSUPPORTED,
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData if e.raw_os_error().is_none() => sftp::SSH_FX_BAD_MESSAGE,
+        _ => sftp::SSH_FX_FAILURE,
+    }
+}
+
+//permissions as shown by "ls -l", e.g. "drwxr-xr-x"
+pub fn get_mode_string(mode: u32) -> String {
+    let mut string = String::new();
+    string.push(match mode & libc::S_IFMT {
+        libc::S_IFDIR => 'd',
+        libc::S_IFLNK => 'l',
+        libc::S_IFCHR => 'c',
+        libc::S_IFBLK => 'b',
+        libc::S_IFIFO => 'p',
+        libc::S_IFSOCK => 's',
+        _ => '-',
+    });
+    for &(shift, special, special_char) in [(6, libc::S_ISUID, 's'), (3, libc::S_ISGID, 's'), (0, libc::S_ISVTX, 't')].iter() {
+        let bits = (mode >> shift) & 7;
+        string.push(if bits & 4 != 0 { 'r' } else { '-' });
+        string.push(if bits & 2 != 0 { 'w' } else { '-' });
+        string.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    string
+}
+
+fn format_time(time: i64, now: i64) -> String {
+    let format: &[u8] = if (now - time).abs() < RECENT_TIME { b"%b %e %H:%M\0" } else { b"%b %e  %Y\0" };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buffer = [0u8; 64];
+    let length = unsafe {
+        let time = time as libc::time_t;
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), format.as_ptr() as *const libc::c_char, &tm)
+    };
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+//"ls -l" like line of SSH_FXP_NAME entries, as by OpenSSH
+pub fn get_long_name(name: &[u8], metadata: &Metadata, now: i64) -> Vec<u8> {
+    let user = passwd::get_user_by_uid(metadata.uid()).map(|u| u.name).unwrap_or_else(|| metadata.uid().to_string());
+    let group = passwd::get_group_name(metadata.gid()).unwrap_or_else(|| metadata.gid().to_string());
+    let mut line = format!("{} {:>3} {:<8} {:<8} {:>8} {} ", get_mode_string(metadata.mode()), metadata.nlink(), user, group,
+                           metadata.size(), format_time(metadata.mtime(), now)).into_bytes();
+    line.extend_from_slice(name);
+    line
+}
+
+fn set_attributes(path: &Path, attributes: &FileAttributes) -> Result<(), Error> {
+    let c_path = get_c_path(path)?;
+    if let Some(size) = attributes.size {
+        check(unsafe { libc::truncate(c_path.as_ptr(), size as libc::off_t) })?;
+    }
+    if let Some(permissions) = attributes.permissions {
+        fs::set_permissions(path, Permissions::from_mode(permissions & 0o7777))?;
+    }
+    if let Some((atime, mtime)) = attributes.times {
+        let times = [libc::timeval { tv_sec: atime as libc::time_t, tv_usec: 0 }, libc::timeval { tv_sec: mtime as libc::time_t, tv_usec: 0 }];
+        check(unsafe { libc::utimes(c_path.as_ptr(), times.as_ptr()) })?;
+    }
+    if let Some((uid, gid)) = attributes.uid_gid {
+        check(unsafe { libc::chown(c_path.as_ptr(), uid, gid) })?;
+    }
+    Ok(())
+}
+
+fn set_file_attributes(file: &File, attributes: &FileAttributes) -> Result<(), Error> {
+    if let Some(size) = attributes.size {
+        file.set_len(size)?;
+    }
+    if let Some(permissions) = attributes.permissions {
+        file.set_permissions(Permissions::from_mode(permissions & 0o7777))?;
+    }
+    if let Some((atime, mtime)) = attributes.times {
+        let times = [libc::timeval { tv_sec: atime as libc::time_t, tv_usec: 0 }, libc::timeval { tv_sec: mtime as libc::time_t, tv_usec: 0 }];
+        check(unsafe { libc::futimes(file.as_raw_fd(), times.as_ptr()) })?;
+    }
+    if let Some((uid, gid)) = attributes.uid_gid {
+        check(unsafe { libc::fchown(file.as_raw_fd(), uid, gid) })?;
+    }
+    Ok(())
+}
+
+fn get_filesystem_statistics(st: &libc::statvfs) -> FilesystemStatistics {
+    let mut flags = 0;
+    if st.f_flag & libc::ST_RDONLY != 0 {
+        flags |= sftp::SSH_FXE_STATVFS_ST_RDONLY;
+    }
+    if st.f_flag & libc::ST_NOSUID != 0 {
+        flags |= sftp::SSH_FXE_STATVFS_ST_NOSUID;
+    }
+    FilesystemStatistics {
+        block_size: st.f_bsize,
+        fragment_size: st.f_frsize,
+        blocks: st.f_blocks,
+        blocks_free: st.f_bfree,
+        blocks_available: st.f_bavail,
+        files: st.f_files,
+        files_free: st.f_ffree,
+        files_available: st.f_favail,
+        filesystem_id: st.f_fsid,
+        flags,
+        name_max: st.f_namemax,
+    }
+}
+
+//"~" and "~/path" are relative to the home directory of the user
+fn expand_path(path: &Path) -> Result<PathBuf, Error> {
+    let bytes = path.as_os_str().as_bytes();
+    if bytes != b"~" && !bytes.starts_with(b"~/") {
+        return Ok(path.to_path_buf());
+    }
+    let home = passwd::get_current_user().map(|u| u.home).ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+    Ok(home.join(OsStr::from_bytes(&bytes[1..]).to_string_lossy().trim_start_matches('/')))
+}
+
+fn write_ok(reply: &mut Vec<u8>, id: u32) -> Result<(), Error> {
+    sftp::write_status(reply, id, sftp::SSH_FX_OK, sftp::get_status_message(sftp::SSH_FX_OK))
+}
+
+fn write_eof(reply: &mut Vec<u8>, id: u32) -> Result<(), Error> {
+    sftp::write_status(reply, id, sftp::SSH_FX_EOF, sftp::get_status_message(sftp::SSH_FX_EOF))
+}
+
+fn write_attributes_reply(reply: &mut Vec<u8>, id: u32, metadata: &Metadata) -> Result<(), Error> {
+    reply.write_u8(sftp::SSH_FXP_ATTRS)?;
+    reply.write_u32::<BigEndian>(id)?;
+    sftp::write_attributes(reply, &FileAttributes::from_metadata(metadata))
+}
+
+//single name replies (realpath, readlink) carry no attributes
+fn write_path_reply(reply: &mut Vec<u8>, id: u32, path: &Path) -> Result<(), Error> {
+    let name = path.as_os_str().as_bytes().to_vec();
+    sftp::write_names(reply, id, &[NameEntry { filename: name.clone(), longname: name, attributes: FileAttributes::default() }])
+}
+
+fn get_version_packet() -> Vec<u8> {
+    let mut packet: Vec<u8> = vec![sftp::SSH_FXP_VERSION];
+    packet.extend_from_slice(&sftp::SFTP_VERSION.to_be_bytes());
+    for &(name, version) in [(sftp::EXTENSION_POSIX_RENAME, "1"), (sftp::EXTENSION_STATVFS, "2"), (sftp::EXTENSION_FSTATVFS, "2"),
+                             (sftp::EXTENSION_HARDLINK, "1"), (sftp::EXTENSION_FSYNC, "1"), (sftp::EXTENSION_LIMITS, "1"),
+                             (sftp::EXTENSION_EXPAND_PATH, "1")].iter() {
+        //writing to a vector can not fail
+        io_helpers::write_string(&mut packet, &name.as_bytes().to_vec()).unwrap();
+        io_helpers::write_string(&mut packet, &version.as_bytes().to_vec()).unwrap();
+    }
+    packet
+}
+
+impl SftpServer {
+    pub fn new() -> SftpServer {
+        SftpServer { handles: HashMap::new(), next_handle: 0 }
+    }
+
+    fn add_handle(&mut self, handle: Handle) -> Result<Vec<u8>, Error> {
+        if self.handles.len() >= MAX_OPEN_HANDLES {
+            return Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_SFTP_TOO_MANY_HANDLES));
+        }
+        let number = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        self.handles.insert(number, handle);
+        Ok(number.to_string().into_bytes())
+    }
+
+    fn read_handle(stream: &mut &[u8]) -> Result<u32, Error> {
+        let handle = io_helpers::read_string(stream, None)?;
+        String::from_utf8(handle).ok().and_then(|h| h.parse().ok()).ok_or_else(invalid_handle)
+    }
+
+    fn get_file(&mut self, stream: &mut &[u8]) -> Result<&mut File, Error> {
+        match self.handles.get_mut(&SftpServer::read_handle(stream)?) {
+            Some(Handle::File(file)) => Ok(file),
+            _ => Err(invalid_handle()),
+        }
+    }
+
+    fn handle_open(&mut self, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        let path = read_path(stream)?;
+        let flags = stream.read_u32::<BigEndian>()?;
+        let attributes = sftp::read_attributes(stream)?;
+        let mut options = OpenOptions::new();
+        options.read(flags & sftp::SSH_FXF_READ != 0)
+            .write(flags & sftp::SSH_FXF_WRITE != 0)
+            .append(flags & sftp::SSH_FXF_APPEND != 0)
+            .truncate(flags & sftp::SSH_FXF_TRUNC != 0)
+            .mode(attributes.permissions.unwrap_or(0o666) & 0o7777);
+        if flags & sftp::SSH_FXF_CREAT != 0 {
+            if flags & sftp::SSH_FXF_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        let handle = self.add_handle(Handle::File(options.open(&path)?))?;
+        reply.write_u8(sftp::SSH_FXP_HANDLE)?;
+        reply.write_u32::<BigEndian>(id)?;
+        io_helpers::write_string(reply, &handle)
+    }
+
+    fn handle_opendir(&mut self, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        let path = read_path(stream)?;
+        let handle = self.add_handle(Handle::Directory(fs::read_dir(&path)?))?;
+        reply.write_u8(sftp::SSH_FXP_HANDLE)?;
+        reply.write_u32::<BigEndian>(id)?;
+        io_helpers::write_string(reply, &handle)
+    }
+
+    //short reads happen at end of file only, as clients expect
+    fn handle_read(&mut self, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        let file = self.get_file(stream)?;
+        let offset = stream.read_u64::<BigEndian>()?;
+        let length = std::cmp::min(stream.read_u32::<BigEndian>()?, sftp::MAX_READ_LENGTH) as usize;
+        let mut data = vec![0u8; length];
+        let mut read = 0;
+        while read < length {
+            match file.read_at(&mut data[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if read == 0 {
+            return write_eof(reply, id);
+        }
+        data.truncate(read);
+        reply.write_u8(sftp::SSH_FXP_DATA)?;
+        reply.write_u32::<BigEndian>(id)?;
+        io_helpers::write_string(reply, &data)
+    }
+
+    fn handle_write(&mut self, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        let file = self.get_file(stream)?;
+        let offset = stream.read_u64::<BigEndian>()?;
+        let data = io_helpers::read_string(stream, Some(sftp::MAX_PACKET_LENGTH))?;
+        file.write_all_at(&data, offset)?;
+        write_ok(reply, id)
+    }
+
+    fn handle_readdir(&mut self, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        let entries = match self.handles.get_mut(&SftpServer::read_handle(stream)?) {
+            Some(Handle::Directory(entries)) => entries,
+            _ => return Err(invalid_handle()),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        let mut names: Vec<NameEntry> = Vec::new();
+        for entry in entries.by_ref().take(READDIR_BATCH_LENGTH) {
+            let entry = entry?;
+            //entries removed meanwhile are skipped
+            if let Ok(metadata) = entry.path().symlink_metadata() {
+                let filename = entry.file_name().as_bytes().to_vec();
+                let longname = get_long_name(&filename, &metadata, now);
+                names.push(NameEntry { filename, longname, attributes: FileAttributes::from_metadata(&metadata) });
+            }
+        }
+        if names.is_empty() {
+            return write_eof(reply, id);
+        }
+        sftp::write_names(reply, id, &names)
+    }
+
+    fn handle_extended(&mut self, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        let name = io_helpers::read_utf8_string(stream)?;
+        match name.as_str() {
+            sftp::EXTENSION_POSIX_RENAME => {
+                let old = read_path(stream)?;
+                fs::rename(old, read_path(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::EXTENSION_HARDLINK => {
+                let old = read_path(stream)?;
+                fs::hard_link(old, read_path(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::EXTENSION_FSYNC => {
+                self.get_file(stream)?.sync_all()?;
+                write_ok(reply, id)
+            }
+            sftp::EXTENSION_STATVFS | sftp::EXTENSION_FSTATVFS => {
+                let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+                if name == sftp::EXTENSION_STATVFS {
+                    let path = get_c_path(&read_path(stream)?)?;
+                    check(unsafe { libc::statvfs(path.as_ptr(), &mut st) })?;
+                } else {
+                    let fd = self.get_file(stream)?.as_raw_fd();
+                    check(unsafe { libc::fstatvfs(fd, &mut st) })?;
+                }
+                reply.write_u8(sftp::SSH_FXP_EXTENDED_REPLY)?;
+                reply.write_u32::<BigEndian>(id)?;
+                sftp::write_filesystem_statistics(reply, &get_filesystem_statistics(&st))
+            }
+            sftp::EXTENSION_LIMITS => {
+                reply.write_u8(sftp::SSH_FXP_EXTENDED_REPLY)?;
+                reply.write_u32::<BigEndian>(id)?;
+                sftp::write_limits(reply, &Limits {
+                    max_packet_length: sftp::MAX_PACKET_LENGTH as u64,
+                    max_read_length: sftp::MAX_READ_LENGTH as u64,
+                    max_write_length: sftp::MAX_READ_LENGTH as u64,
+                    max_open_handles: MAX_OPEN_HANDLES as u64,
+                })
+            }
+            sftp::EXTENSION_EXPAND_PATH => {
+                let path = fs::canonicalize(expand_path(&read_path(stream)?)?)?;
+                write_path_reply(reply, id, &path)
+            }
+            _ => Err(Error::from(ErrorKind::Unsupported)),
+        }
+    }
+
+    fn handle_request(&mut self, packet_type: u8, id: u32, stream: &mut &[u8], reply: &mut Vec<u8>) -> Result<(), Error> {
+        match packet_type {
+            sftp::SSH_FXP_OPEN => self.handle_open(id, stream, reply),
+            sftp::SSH_FXP_CLOSE => match self.handles.remove(&SftpServer::read_handle(stream)?) {
+                Some(_) => write_ok(reply, id),
+                None => Err(invalid_handle()),
+            },
+            sftp::SSH_FXP_READ => self.handle_read(id, stream, reply),
+            sftp::SSH_FXP_WRITE => self.handle_write(id, stream, reply),
+            sftp::SSH_FXP_LSTAT => write_attributes_reply(reply, id, &read_path(stream)?.symlink_metadata()?),
+            sftp::SSH_FXP_STAT => write_attributes_reply(reply, id, &read_path(stream)?.metadata()?),
+            sftp::SSH_FXP_FSTAT => {
+                let metadata = self.get_file(stream)?.metadata()?;
+                write_attributes_reply(reply, id, &metadata)
+            }
+            sftp::SSH_FXP_SETSTAT => {
+                let path = read_path(stream)?;
+                set_attributes(&path, &sftp::read_attributes(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_FSETSTAT => {
+                let file = self.get_file(stream)?;
+                set_file_attributes(file, &sftp::read_attributes(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_OPENDIR => self.handle_opendir(id, stream, reply),
+            sftp::SSH_FXP_READDIR => self.handle_readdir(id, stream, reply),
+            sftp::SSH_FXP_REMOVE => {
+                fs::remove_file(read_path(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_MKDIR => {
+                let path = read_path(stream)?;
+                let attributes = sftp::read_attributes(stream)?;
+                DirBuilder::new().mode(attributes.permissions.unwrap_or(0o777) & 0o7777).create(path)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_RMDIR => {
+                fs::remove_dir(read_path(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_REALPATH => {
+                let path = read_path(stream)?;
+                let path = if path.as_os_str().is_empty() { PathBuf::from(".") } else { path };
+                write_path_reply(reply, id, &fs::canonicalize(path)?)
+            }
+            //unlike posix-rename, an existing target is not replaced
+            sftp::SSH_FXP_RENAME => {
+                let old = read_path(stream)?;
+                let new = read_path(stream)?;
+                if new.symlink_metadata().is_ok() {
+                    return Err(Error::from(ErrorKind::AlreadyExists));
+                }
+                fs::rename(old, new)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_READLINK => write_path_reply(reply, id, &fs::read_link(read_path(stream)?)?),
+            //the target comes first, as OpenSSH has always done it (the draft says otherwise)
+            sftp::SSH_FXP_SYMLINK => {
+                let target = read_path(stream)?;
+                std::os::unix::fs::symlink(target, read_path(stream)?)?;
+                write_ok(reply, id)
+            }
+            sftp::SSH_FXP_EXTENDED => self.handle_extended(id, stream, reply),
+            _ => Err(Error::from(ErrorKind::Unsupported)),
+        }
+    }
+
+    //reply to one request packet, failures are reported with SSH_FXP_STATUS
+    pub fn handle_packet(&mut self, packet: &[u8]) -> Vec<u8> {
+        let mut stream = packet;
+        let packet_type = stream.read_u8().unwrap_or(0);
+        //the version of the client does not matter, older ones are not supported anyway
+        if packet_type == sftp::SSH_FXP_INIT {
+            return get_version_packet();
+        }
+        let id = stream.read_u32::<BigEndian>().unwrap_or(0);
+        let mut reply: Vec<u8> = Vec::new();
+        if let Err(e) = self.handle_request(packet_type, id, &mut stream, &mut reply) {
+            let code = get_status_code(&e);
+            reply.clear();
+            sftp::write_status(&mut reply, id, code, sftp::get_status_message(code)).unwrap();
+        }
+        reply
+    }
+}
+
+impl Default for SftpServer {
+    fn default() -> SftpServer {
+        SftpServer::new()
+    }
+}
+
+//serves requests until the client closes the input
+pub fn serve(input: &mut dyn Read, output: &mut dyn Write) -> Result<(), Error> {
+    let mut server = SftpServer::new();
+    loop {
+        let packet = match sftp::read_packet(input) {
+            Ok(packet) => packet,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        sftp::write_packet(output, &server.handle_packet(&packet))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+    use std::os::unix::ffi::OsStringExt;
+
+    struct Client {
+        server: SftpServer,
+        id: u32,
+    }
+
+    impl Client {
+        fn request(&mut self, packet_type: u8, fields: &[&[u8]], tail: &[u8]) -> (u8, Vec<u8>) {
+            self.id += 1;
+            let mut packet: Vec<u8> = vec![packet_type];
+            packet.write_u32::<BigEndian>(self.id).unwrap();
+            for field in fields.iter() {
+                io_helpers::write_string(&mut packet, &field.to_vec()).unwrap();
+            }
+            packet.extend_from_slice(tail);
+            let reply = self.server.handle_packet(&packet);
+            let mut stream = &reply[..];
+            let reply_type = stream.read_u8().unwrap();
+            assert_eq!(stream.read_u32::<BigEndian>().unwrap(), self.id);
+            (reply_type, stream.to_vec())
+        }
+
+        fn status(&mut self, packet_type: u8, fields: &[&[u8]], tail: &[u8]) -> u32 {
+            let (reply_type, data) = self.request(packet_type, fields, tail);
+            assert_eq!(reply_type, sftp::SSH_FXP_STATUS);
+            sftp::read_status(&mut &data[..]).unwrap().0
+        }
+
+        fn handle(&mut self, packet_type: u8, fields: &[&[u8]], tail: &[u8]) -> Vec<u8> {
+            let (reply_type, data) = self.request(packet_type, fields, tail);
+            assert_eq!(reply_type, sftp::SSH_FXP_HANDLE);
+            io_helpers::read_string(&mut &data[..], None).unwrap()
+        }
+
+        fn names(&mut self, packet_type: u8, fields: &[&[u8]]) -> Vec<NameEntry> {
+            let (reply_type, data) = self.request(packet_type, fields, &[]);
+            assert_eq!(reply_type, sftp::SSH_FXP_NAME);
+            sftp::read_names(&mut &data[..]).unwrap()
+        }
+    }
+
+    fn open_flags(flags: u32) -> Vec<u8> {
+        let mut tail = flags.to_be_bytes().to_vec();
+        sftp::write_attributes(&mut tail, &FileAttributes { permissions: Some(0o600), ..FileAttributes::default() }).unwrap();
+        tail
+    }
+
+    #[test]
+    fn server_handles_files_and_directories() {
+        let directory = env::temp_dir().join(format!("bssh_sftp_server_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir(&directory).unwrap();
+        let path = |name: &str| directory.join(name).into_os_string().into_vec();
+        let mut client = Client { server: SftpServer::new(), id: 0 };
+
+        let version = client.server.handle_packet(&[sftp::SSH_FXP_INIT, 0, 0, 0, 3]);
+        assert_eq!(&version[..5], &[sftp::SSH_FXP_VERSION, 0, 0, 0, 3]);
+
+        let file = path("file");
+        let handle = client.handle(sftp::SSH_FXP_OPEN, &[&file], &open_flags(sftp::SSH_FXF_WRITE | sftp::SSH_FXF_CREAT | sftp::SSH_FXF_EXCL));
+        let mut tail = 2u64.to_be_bytes().to_vec();
+        io_helpers::write_string(&mut tail, &b"llo".to_vec()).unwrap();
+        assert_eq!(client.status(sftp::SSH_FXP_WRITE, &[&handle], &tail), sftp::SSH_FX_OK);
+        let mut tail = 0u64.to_be_bytes().to_vec();
+        io_helpers::write_string(&mut tail, &b"he".to_vec()).unwrap();
+        assert_eq!(client.status(sftp::SSH_FXP_WRITE, &[&handle], &tail), sftp::SSH_FX_OK);
+        let (reply_type, data) = client.request(sftp::SSH_FXP_FSTAT, &[&handle], &[]);
+        assert_eq!(reply_type, sftp::SSH_FXP_ATTRS);
+        let attributes = sftp::read_attributes(&mut &data[..]).unwrap();
+        assert_eq!((attributes.size, attributes.permissions.map(|p| p & 0o777)), (Some(5), Some(0o600)));
+        assert_eq!(client.status(sftp::SSH_FXP_CLOSE, &[&handle], &[]), sftp::SSH_FX_OK);
+        assert_eq!(client.status(sftp::SSH_FXP_CLOSE, &[&handle], &[]), sftp::SSH_FX_FAILURE);
+        assert_eq!(client.status(sftp::SSH_FXP_OPEN, &[&file], &open_flags(sftp::SSH_FXF_WRITE | sftp::SSH_FXF_CREAT | sftp::SSH_FXF_EXCL)), sftp::SSH_FX_FAILURE);
+
+        let handle = client.handle(sftp::SSH_FXP_OPEN, &[&file], &open_flags(sftp::SSH_FXF_READ));
+        let mut tail = 1u64.to_be_bytes().to_vec();
+        tail.extend_from_slice(&100u32.to_be_bytes());
+        let (reply_type, data) = client.request(sftp::SSH_FXP_READ, &[&handle], &tail);
+        assert_eq!(reply_type, sftp::SSH_FXP_DATA);
+        assert_eq!(io_helpers::read_string(&mut &data[..], None).unwrap(), b"ello");
+        let mut tail = 5u64.to_be_bytes().to_vec();
+        tail.extend_from_slice(&100u32.to_be_bytes());
+        assert_eq!(client.status(sftp::SSH_FXP_READ, &[&handle], &tail), sftp::SSH_FX_EOF);
+        assert_eq!(client.status(sftp::SSH_FXP_CLOSE, &[&handle], &[]), sftp::SSH_FX_OK);
+
+        let mut attributes: Vec<u8> = Vec::new();
+        sftp::write_attributes(&mut attributes, &FileAttributes { size: Some(1), times: Some((1000, 2000)), ..FileAttributes::default() }).unwrap();
+        assert_eq!(client.status(sftp::SSH_FXP_SETSTAT, &[&file], &attributes), sftp::SSH_FX_OK);
+        let metadata = fs::metadata(directory.join("file")).unwrap();
+        assert_eq!((metadata.size(), metadata.mtime()), (1, 2000));
+
+        assert_eq!(client.status(sftp::SSH_FXP_MKDIR, &[&path("dir")], &[0, 0, 0, 0]), sftp::SSH_FX_OK);
+        assert_eq!(client.status(sftp::SSH_FXP_SYMLINK, &[b"file", &path("link")], &[]), sftp::SSH_FX_OK);
+        assert_eq!(client.names(sftp::SSH_FXP_READLINK, &[&path("link")])[0].filename, b"file");
+        let (reply_type, data) = client.request(sftp::SSH_FXP_LSTAT, &[&path("link")], &[]);
+        assert_eq!(reply_type, sftp::SSH_FXP_ATTRS);
+        assert_eq!(sftp::read_attributes(&mut &data[..]).unwrap().permissions.unwrap() & libc::S_IFMT, libc::S_IFLNK);
+        assert_eq!(client.status(sftp::SSH_FXP_RENAME, &[&path("link"), &file], &[]), sftp::SSH_FX_FAILURE);
+        assert_eq!(client.status(sftp::SSH_FXP_RENAME, &[&path("link"), &path("moved")], &[]), sftp::SSH_FX_OK);
+
+        let handle = client.handle(sftp::SSH_FXP_OPENDIR, &[&path("")], &[]);
+        let mut names: Vec<String> = client.names(sftp::SSH_FXP_READDIR, &[&handle]).iter().map(|n| {
+            let longname = String::from_utf8_lossy(&n.longname).into_owned();
+            assert!(longname.ends_with(&*String::from_utf8_lossy(&n.filename)), "{}", longname);
+            longname[..1].to_string() + &String::from_utf8_lossy(&n.filename)
+        }).collect();
+        names.sort();
+        assert_eq!(names, vec!["-file", "ddir", "lmoved"]);
+        assert_eq!(client.status(sftp::SSH_FXP_READDIR, &[&handle], &[]), sftp::SSH_FX_EOF);
+        assert_eq!(client.status(sftp::SSH_FXP_CLOSE, &[&handle], &[]), sftp::SSH_FX_OK);
+
+        let real = client.names(sftp::SSH_FXP_REALPATH, &[&path("dir/../file")]);
+        assert_eq!(real[0].filename, fs::canonicalize(directory.join("file")).unwrap().into_os_string().into_vec());
+        assert_eq!(client.status(sftp::SSH_FXP_EXTENDED, &[sftp::EXTENSION_POSIX_RENAME.as_bytes(), &file, &path("moved")], &[]), sftp::SSH_FX_OK);
+        assert_eq!(client.status(sftp::SSH_FXP_EXTENDED, &[sftp::EXTENSION_HARDLINK.as_bytes(), &path("moved"), &file], &[]), sftp::SSH_FX_OK);
+        assert_eq!(fs::metadata(directory.join("file")).unwrap().nlink(), 2);
+        let (reply_type, data) = client.request(sftp::SSH_FXP_EXTENDED, &[sftp::EXTENSION_STATVFS.as_bytes(), &path("")], &[]);
+        assert_eq!(reply_type, sftp::SSH_FXP_EXTENDED_REPLY);
+        assert!(sftp::read_filesystem_statistics(&mut &data[..]).unwrap().block_size > 0);
+        let (_, data) = client.request(sftp::SSH_FXP_EXTENDED, &[sftp::EXTENSION_LIMITS.as_bytes()], &[]);
+        assert_eq!(sftp::read_limits(&mut &data[..]).unwrap().max_read_length, sftp::MAX_READ_LENGTH as u64);
+        assert_eq!(client.status(sftp::SSH_FXP_EXTENDED, &[b"nothing@example.com"], &[]), sftp::SSH_FX_OP_UNSUPPORTED);
+
+        assert_eq!(client.status(sftp::SSH_FXP_REMOVE, &[&file], &[]), sftp::SSH_FX_OK);
+        assert_eq!(client.status(sftp::SSH_FXP_REMOVE, &[&path("moved")], &[]), sftp::SSH_FX_OK);
+        assert_eq!(client.status(sftp::SSH_FXP_REMOVE, &[&file], &[]), sftp::SSH_FX_NO_SUCH_FILE);
+        assert_eq!(client.status(sftp::SSH_FXP_RMDIR, &[&path("dir")], &[]), sftp::SSH_FX_OK);
+        assert_eq!(client.status(sftp::SSH_FXP_STAT, &[&path("dir")], &[]), sftp::SSH_FX_NO_SUCH_FILE);
+        assert_eq!(client.status(sftp::SSH_FXP_OPEN, &[], &[]), sftp::SSH_FX_BAD_MESSAGE);
+        assert_eq!(client.status(99, &[], &[]), sftp::SSH_FX_OP_UNSUPPORTED);
+        fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn mode_strings_match_ls() {
+        assert_eq!(get_mode_string(libc::S_IFDIR | 0o755), "drwxr-xr-x");
+        assert_eq!(get_mode_string(libc::S_IFREG | 0o4755), "-rwsr-xr-x");
+        assert_eq!(get_mode_string(libc::S_IFDIR | 0o1777), "drwxrwxrwt");
+        assert_eq!(get_mode_string(libc::S_IFREG | 0o2640), "-rw-r-S---");
+    }
+}
@@ -0,0 +1,31 @@
+use std::io::Error;
+use std::thread;
+use libc;
+
+//The signals are blocked and then received by one thread with sigwait(3). Threads inherit the
+//signal mask, so this has to be called before any other thread is started.
+//The handler returns false to stop waiting.
+pub fn spawn_signal_handler<F>(signals: &[libc::c_int], mut handler: F) -> Result<thread::JoinHandle<()>, Error>
+    where F: FnMut(libc::c_int) -> bool + Send + 'static
+{
+    let mut set: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut set);
+        for &signal in signals.iter() {
+            libc::sigaddset(&mut set, signal);
+        }
+        let ret = libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        if ret != 0 {
+            return Err(Error::from_raw_os_error(ret));
+        }
+    }
+    Ok(thread::spawn(move || loop {
+        let mut signal: libc::c_int = 0;
+        if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+            return;
+        }
+        if !handler(signal) {
+            return;
+        }
+    }))
+}
@@ -0,0 +1,179 @@
+use std::io::{Cursor, Error, ErrorKind};
+use num::bigint::{BigInt, Sign};
+use rsa::Pkcs1v15Sign;
+use sha2::{Digest, Sha256, Sha512};
+use ed25519_dalek;
+use ed25519_dalek::{Signer, Verifier};
+use p256;
+use rand_core::OsRng;
+use errors;
+use io_helpers;
+use keys;
+use keys::{PrivateKey, PublicKey};
+use mac;
+
+//RFC 4253 page 15, RFC 8332, RFC 8709 and RFC 5656 page 5: signatures are
+//"string signature format identifier, string signature blob"
+
+pub const RSA_SHA2_256: &str = "rsa-sha2-256";
+pub const RSA_SHA2_512: &str = "rsa-sha2-512";
+
+const NISTP256_SCALAR_LENGTH: usize = 32;
+
+//DigestInfo DER prefix for SHA-1, RFC 8017 page 47
+const SHA1_DIGEST_INFO_PREFIX: [u8; 15] = [0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14];
+
+fn unsupported_algorithm() -> Error {
+    Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_SIGNATURE_UNSUPPORTED_ALGORITHM)
+}
+
+fn malformed_signature() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_SIGNATURE_MALFORMED)
+}
+
+//signature algorithms usable with given key, in order of preference
+pub fn get_signature_algorithms(key: &PublicKey) -> Vec<&'static str> {
+    match *key {
+        PublicKey::Rsa(_) => vec![RSA_SHA2_512, RSA_SHA2_256, keys::SSH_RSA],
+        _ => vec![key.get_algorithm_name()],
+    }
+}
+
+fn get_rsa_scheme_and_hash(algorithm: &str, data: &[u8]) -> Result<(Pkcs1v15Sign, Vec<u8>), Error> {
+    match algorithm {
+        keys::SSH_RSA => {
+            let scheme = Pkcs1v15Sign {
+                hash_len: Some(20),
+                prefix: SHA1_DIGEST_INFO_PREFIX.to_vec().into_boxed_slice(),
+            };
+            Ok((scheme, mac::sha1(data)))
+        }
+        RSA_SHA2_256 => Ok((Pkcs1v15Sign::new::<Sha256>(), Sha256::digest(data).to_vec())),
+        RSA_SHA2_512 => Ok((Pkcs1v15Sign::new::<Sha512>(), Sha512::digest(data).to_vec())),
+        _ => Err(unsupported_algorithm()),
+    }
+}
+
+fn write_nistp256_scalar(stream: &mut Vec<u8>, scalar: &[u8]) {
+    io_helpers::write_mpint(stream, BigInt::from_bytes_be(Sign::Plus, scalar)).unwrap();
+}
+
+fn read_nistp256_scalar(stream: &mut Cursor<&[u8]>) -> Result<[u8; NISTP256_SCALAR_LENGTH], Error> {
+    let bytes = match io_helpers::read_mpint(stream)?.to_bytes_be() {
+        (Sign::Minus, _) => return Err(malformed_signature()),
+        (_, bytes) => bytes,
+    };
+    let bytes: &[u8] = if bytes == [0] { &[] } else { &bytes };
+    if bytes.len() > NISTP256_SCALAR_LENGTH {
+        return Err(malformed_signature());
+    }
+    let mut res = [0u8; NISTP256_SCALAR_LENGTH];
+    res[NISTP256_SCALAR_LENGTH - bytes.len()..].copy_from_slice(bytes);
+    Ok(res)
+}
+
+//returns signature encoded as described above
+pub fn sign(key: &PrivateKey, algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let raw: Vec<u8> = match *key {
+        PrivateKey::Rsa(ref key) => {
+            let (scheme, hash) = get_rsa_scheme_and_hash(algorithm, data)?;
+            match key.sign_with_rng(&mut OsRng, scheme, &hash) {
+                Ok(raw) => raw,
+                Err(_) => return Err(Error::other(errors::BSSH_ERR_SIGNATURE_FAILED)),
+            }
+        }
+        PrivateKey::Ed25519(ref key) if algorithm == keys::SSH_ED25519 => key.sign(data).to_bytes().to_vec(),
+        PrivateKey::EcdsaNistp256(ref key) if algorithm == keys::ECDSA_SHA2_NISTP256 => {
+            let signature: p256::ecdsa::Signature = key.sign(data);
+            let (r, s) = signature.split_bytes();
+            let mut raw: Vec<u8> = Vec::new();
+            write_nistp256_scalar(&mut raw, &r);
+            write_nistp256_scalar(&mut raw, &s);
+            raw
+        }
+        _ => return Err(unsupported_algorithm()),
+    };
+
+    let mut res: Vec<u8> = Vec::new();
+    io_helpers::write_string(&mut res, &algorithm.as_bytes().to_vec())?;
+    io_helpers::write_string(&mut res, &raw)?;
+    Ok(res)
+}
+
+pub fn read_signature(signature: &[u8]) -> Result<(String, Vec<u8>), Error> {
+    let mut stream = Cursor::new(signature);
+    let algorithm = match String::from_utf8(io_helpers::read_string(&mut stream, None)?) {
+        Ok(algorithm) => algorithm,
+        Err(_) => return Err(malformed_signature()),
+    };
+    let raw = io_helpers::read_string(&mut stream, None)?;
+    if stream.position() as usize != signature.len() {
+        return Err(malformed_signature());
+    }
+    Ok((algorithm, raw))
+}
+
+//false on any problem, including signature algorithm not matching the key
+pub fn verify(key: &PublicKey, data: &[u8], signature: &[u8]) -> bool {
+    let (algorithm, raw) = match read_signature(signature) {
+        Ok(res) => res,
+        Err(_) => return false,
+    };
+
+    match *key {
+        PublicKey::Rsa(ref key) => match get_rsa_scheme_and_hash(&algorithm, data) {
+            Ok((scheme, hash)) => key.verify(scheme, &hash, &raw).is_ok(),
+            Err(_) => false,
+        },
+        PublicKey::Ed25519(ref key) if algorithm == keys::SSH_ED25519 => {
+            match ed25519_dalek::Signature::from_slice(&raw) {
+                Ok(signature) => key.verify(data, &signature).is_ok(),
+                Err(_) => false,
+            }
+        }
+        PublicKey::EcdsaNistp256(ref key) if algorithm == keys::ECDSA_SHA2_NISTP256 => {
+            let mut stream = Cursor::new(raw.as_slice());
+            let r = read_nistp256_scalar(&mut stream);
+            let s = read_nistp256_scalar(&mut stream);
+            let signature = match (r, s) {
+                (Ok(r), Ok(s)) if stream.position() as usize == raw.len() => p256::ecdsa::Signature::from_scalars(r, s),
+                _ => return false,
+            };
+            match signature {
+                Ok(signature) => key.verify(data, &signature).is_ok(),
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn sign_verify_roundtrips_for_all_algorithms() {
+        let data = b"exchange hash";
+        for &(key_type, bits) in [(keys::SSH_ED25519, None), (keys::ECDSA_SHA2_NISTP256, None), (keys::SSH_RSA, Some(1024))].iter() {
+            let key = keys::generate_private_key(key_type, bits).unwrap();
+            let public_key = key.get_public_key();
+            for algorithm in get_signature_algorithms(&public_key) {
+                let signature = sign(&key, algorithm, data).unwrap();
+                assert_eq!(read_signature(&signature).unwrap().0, algorithm);
+                assert!(verify(&public_key, data, &signature));
+                assert!(!verify(&public_key, b"other data", &signature));
+            }
+        }
+    }
+
+    #[test]
+    fn sign_rejects_algorithm_not_matching_key() {
+        let key = keys::generate_private_key(keys::SSH_ED25519, None).unwrap();
+        assert!(sign(&key, RSA_SHA2_256, b"data").is_err());
+        let signature = sign(&key, keys::SSH_ED25519, b"data").unwrap();
+        let other = keys::generate_private_key(keys::ECDSA_SHA2_NISTP256, None).unwrap();
+        assert!(!verify(&other.get_public_key(), b"data", &signature));
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use auth_password;
+use auth_publickey;
+use config::{ClientConfig, LogLevel};
+use errors;
+use forwarding;
+use forwarding::Forward;
+use known_hosts::StrictHostKeyChecking;
+use patterns;
+
+//ssh_config(5). As in OpenSSH, for each option the first obtained value is used,
+//so command line options have to be applied before the configuration files.
+
+pub const USER_CONFIG_FILE: &str = ".ssh/config";
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/ssh/ssh_config";
+
+const SUPPORTED_OPTIONS: [&str; 19] = ["port", "user", "stricthostkeychecking", "userknownhostsfile", "globalknownhostsfile", "hashknownhosts",
+                                       "passwordauthentication", "numberofpasswordprompts", "pubkeyauthentication", "identityfile",
+                                       "kbdinteractiveauthentication", "challengeresponseauthentication", "hostbasedauthentication",
+                                       "enablesshkeysign", "loglevel", "sendenv", "setenv", "localforward", "remoteforward"];
+
+pub struct SshConfig {
+    pub home: PathBuf,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub strict_host_key_checking: StrictHostKeyChecking,
+    pub user_known_hosts_files: Vec<PathBuf>,
+    pub global_known_hosts_files: Vec<PathBuf>,
+    pub hash_known_hosts: bool,
+    pub password_authentication: bool,
+    pub number_of_password_prompts: u32,
+    pub pubkey_authentication: bool,
+    pub kbd_interactive_authentication: bool,
+    pub hostbased_authentication: bool,
+    //EnableSSHKeysign, read by bssh-keysign from the system configuration only
+    pub enable_ssh_keysign: bool,
+    pub log_level: LogLevel,
+    //unlike other options, all given identity files are used
+    pub identity_files: Vec<PathBuf>,
+    //SendEnv accumulates too, "-pattern" removes patterns given before
+    pub send_env: Vec<String>,
+    pub set_env: Vec<(String, String)>,
+    //all LocalForward and RemoteForward lines are used as well
+    pub local_forwards: Vec<Forward>,
+    pub remote_forwards: Vec<Forward>,
+    obtained: HashSet<String>,
+}
+
+fn bad_option() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_CONFIG_BAD_OPTION)
+}
+
+//"Keyword value", "Keyword=value" and "Keyword = value" are all allowed
+pub fn split_option(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+    (keyword, rest.trim())
+}
+
+pub fn parse_yes_no(value: &str) -> Result<bool, Error> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(bad_option()),
+    }
+}
+
+impl SshConfig {
+    pub fn new(home: &Path) -> SshConfig {
+        SshConfig {
+            home: home.to_path_buf(),
+            port: None,
+            user: None,
+            strict_host_key_checking: StrictHostKeyChecking::Ask,
+            user_known_hosts_files: vec![home.join(".ssh/known_hosts"), home.join(".ssh/known_hosts2")],
+            global_known_hosts_files: vec![PathBuf::from("/etc/ssh/ssh_known_hosts"), PathBuf::from("/etc/ssh/ssh_known_hosts2")],
+            hash_known_hosts: false,
+            password_authentication: true,
+            number_of_password_prompts: auth_password::DEFAULT_NUMBER_OF_PASSWORD_PROMPTS,
+            pubkey_authentication: true,
+            kbd_interactive_authentication: true,
+            hostbased_authentication: false,
+            enable_ssh_keysign: false,
+            log_level: LogLevel::Info,
+            identity_files: Vec::new(),
+            send_env: Vec::new(),
+            set_env: Vec::new(),
+            local_forwards: Vec::new(),
+            remote_forwards: Vec::new(),
+            obtained: HashSet::new(),
+        }
+    }
+
+    pub fn from_env() -> SshConfig {
+        SshConfig::new(&PathBuf::from(env::var("HOME").unwrap_or_else(|_| "/".to_string())))
+    }
+
+    fn expand_path(&self, path: &str) -> PathBuf {
+        match path.strip_prefix("~/") {
+            Some(rest) => self.home.join(rest),
+            None => PathBuf::from(path),
+        }
+    }
+
+    //applies single "Keyword value" option, as given with -o or in a configuration file
+    pub fn apply_option(&mut self, line: &str) -> Result<(), Error> {
+        let (keyword, value) = split_option(line);
+        let mut keyword = keyword.to_lowercase();
+        if value.is_empty() {
+            return Err(bad_option());
+        }
+        //deprecated alias
+        if keyword == "challengeresponseauthentication" {
+            keyword = "kbdinteractiveauthentication".to_string();
+        }
+        if keyword == "identityfile" {
+            let path = self.expand_path(value);
+            if !self.identity_files.contains(&path) {
+                self.identity_files.push(path);
+            }
+            return Ok(());
+        }
+        if keyword == "sendenv" {
+            for pattern in value.split_whitespace() {
+                match pattern.strip_prefix('-') {
+                    Some(removed) => self.send_env.retain(|p| !patterns::match_pattern(p, removed)),
+                    None if !self.send_env.iter().any(|p| p == pattern) => self.send_env.push(pattern.to_string()),
+                    None => {}
+                }
+            }
+            return Ok(());
+        }
+        //"[bind_address:]port host:hostport", or with colons only as given with -L and -R
+        if keyword == "localforward" || keyword == "remoteforward" {
+            let forward = forwarding::parse_forward(&value.split_whitespace().collect::<Vec<&str>>().join(":")).map_err(|_| bad_option())?;
+            let forwards = if keyword == "localforward" { &mut self.local_forwards } else { &mut self.remote_forwards };
+            if !forwards.contains(&forward) {
+                forwards.push(forward);
+            }
+            return Ok(());
+        }
+        if self.obtained.contains(&keyword) {
+            return Ok(());
+        }
+
+        match keyword.as_str() {
+            "port" => self.port = Some(value.parse().map_err(|_| bad_option())?),
+            "user" => self.user = Some(value.to_string()),
+            "stricthostkeychecking" => {
+                self.strict_host_key_checking = StrictHostKeyChecking::from_name(value).ok_or_else(bad_option)?
+            }
+            "userknownhostsfile" => self.user_known_hosts_files = value.split_whitespace().map(|p| self.expand_path(p)).collect(),
+            "globalknownhostsfile" => self.global_known_hosts_files = value.split_whitespace().map(|p| self.expand_path(p)).collect(),
+            "hashknownhosts" => self.hash_known_hosts = parse_yes_no(value)?,
+            "passwordauthentication" => self.password_authentication = parse_yes_no(value)?,
+            "pubkeyauthentication" => self.pubkey_authentication = parse_yes_no(value)?,
+            "numberofpasswordprompts" => self.number_of_password_prompts = value.parse().map_err(|_| bad_option())?,
+            "kbdinteractiveauthentication" => self.kbd_interactive_authentication = parse_yes_no(value)?,
+            "hostbasedauthentication" => self.hostbased_authentication = parse_yes_no(value)?,
+            "enablesshkeysign" => self.enable_ssh_keysign = parse_yes_no(value)?,
+            "loglevel" => self.log_level = LogLevel::from_name(value).ok_or_else(bad_option)?,
+            "setenv" => {
+                let mut variables: Vec<(String, String)> = Vec::new();
+                for variable in value.split_whitespace() {
+                    match variable.find('=') {
+                        Some(0) | None => return Err(bad_option()),
+                        Some(n) => variables.push((variable[..n].to_string(), variable[n + 1..].to_string())),
+                    }
+                }
+                self.set_env = variables;
+            }
+            _ => return Err(bad_option()),
+        }
+
+        self.obtained.insert(keyword);
+        Ok(())
+    }
+
+    //reads configuration file, only sections whose Host patterns match host are applied.
+    //Options unknown to us are skipped, since the file is most likely shared with OpenSSH.
+    pub fn read(&mut self, text: &str, host: &str) -> Result<(), Error> {
+        let mut active = true;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, value) = split_option(line);
+            if keyword.eq_ignore_ascii_case("host") {
+                let list = value.split_whitespace().collect::<Vec<&str>>().join(",");
+                active = patterns::match_pattern_list(host, &list, true) == Some(true);
+                continue;
+            }
+            if !active {
+                continue;
+            }
+            if SUPPORTED_OPTIONS.contains(&keyword.to_lowercase().as_str()) {
+                self.apply_option(line)?;
+            }
+        }
+        Ok(())
+    }
+
+    //missing files are fine, defaults are used then
+    pub fn read_file(&mut self, path: &Path, host: &str) -> Result<(), Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => self.read(&text, host),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl ClientConfig for SshConfig {
+    fn get_strict_host_key_checking(&self) -> StrictHostKeyChecking {
+        self.strict_host_key_checking
+    }
+    fn get_user_known_hosts_files(&self) -> Vec<PathBuf> {
+        self.user_known_hosts_files.clone()
+    }
+    fn get_global_known_hosts_files(&self) -> Vec<PathBuf> {
+        self.global_known_hosts_files.clone()
+    }
+    fn get_hash_known_hosts(&self) -> bool {
+        self.hash_known_hosts
+    }
+    fn get_password_authentication(&self) -> bool {
+        self.password_authentication
+    }
+    fn get_number_of_password_prompts(&self) -> u32 {
+        self.number_of_password_prompts
+    }
+    fn get_pubkey_authentication(&self) -> bool {
+        self.pubkey_authentication
+    }
+    fn get_kbd_interactive_authentication(&self) -> bool {
+        self.kbd_interactive_authentication
+    }
+    fn get_hostbased_authentication(&self) -> bool {
+        self.hostbased_authentication
+    }
+    fn get_enable_ssh_keysign(&self) -> bool {
+        self.enable_ssh_keysign
+    }
+    fn get_log_level(&self) -> LogLevel {
+        self.log_level
+    }
+    //defaults are used only when no IdentityFile is given
+    fn get_identity_files(&self) -> Vec<PathBuf> {
+        if !self.identity_files.is_empty() {
+            return self.identity_files.clone();
+        }
+        auth_publickey::DEFAULT_IDENTITY_FILES.iter().map(|f| self.home.join(f)).collect()
+    }
+    fn get_send_env(&self) -> Vec<String> {
+        self.send_env.clone()
+    }
+    fn get_set_env(&self) -> Vec<(String, String)> {
+        self.set_env.clone()
+    }
+    fn get_local_forwards(&self) -> Vec<Forward> {
+        self.local_forwards.clone()
+    }
+    fn get_remote_forwards(&self) -> Vec<Forward> {
+        self.remote_forwards.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn split_option_accepts_all_forms() {
+        assert_eq!(split_option("Port 22"), ("Port", "22"));
+        assert_eq!(split_option("Port=22"), ("Port", "22"));
+        assert_eq!(split_option("  Port = 22 "), ("Port", "22"));
+    }
+
+    #[test]
+    fn apply_option_keeps_first_value() {
+        let mut config = SshConfig::new(Path::new("/home/u"));
+        config.apply_option("StrictHostKeyChecking=accept-new").unwrap();
+        config.apply_option("stricthostkeychecking yes").unwrap();
+        config.apply_option("UserKnownHostsFile ~/kh /tmp/kh").unwrap();
+        assert_eq!(config.get_strict_host_key_checking(), StrictHostKeyChecking::AcceptNew);
+        assert_eq!(config.get_user_known_hosts_files(), vec![PathBuf::from("/home/u/kh"), PathBuf::from("/tmp/kh")]);
+        assert!(SshConfig::new(Path::new("/")).apply_option("StrictHostKeyChecking maybe").is_err());
+        assert!(config.apply_option("NoSuchOption yes").is_err());
+        config.apply_option("ChallengeResponseAuthentication no").unwrap();
+        config.apply_option("KbdInteractiveAuthentication yes").unwrap();
+        assert!(!config.get_kbd_interactive_authentication());
+        config.apply_option("LogLevel QUIET").unwrap();
+        assert_eq!(config.get_log_level(), LogLevel::Quiet);
+        assert!(SshConfig::new(Path::new("/")).apply_option("LogLevel everything").is_err());
+    }
+
+    #[test]
+    fn read_applies_matching_host_sections() {
+        let text = "# comment\nHost *.example.com !bad.example.com\n  HashKnownHosts yes\n  Port 2222\n  ForwardAgent yes\nHost *\n  Port 22\n  User someone\n  NumberOfPasswordPrompts 1\n  IdentityFile ~/.ssh/work\n";
+        let mut config = SshConfig::new(Path::new("/home/u"));
+        config.read(text, "www.example.com").unwrap();
+        assert!(config.get_hash_known_hosts());
+        assert_eq!(config.port, Some(2222));
+        assert_eq!(config.user, Some("someone".to_string()));
+        assert_eq!(config.get_number_of_password_prompts(), 1);
+        assert_eq!(config.get_identity_files(), vec![PathBuf::from("/home/u/.ssh/work")]);
+        assert!(config.get_password_authentication());
+        assert!(config.get_kbd_interactive_authentication());
+        assert!(!config.get_hostbased_authentication());
+        assert!(!config.get_enable_ssh_keysign());
+        assert_eq!(config.get_log_level(), LogLevel::Info);
+
+        let mut config = SshConfig::new(Path::new("/home/u"));
+        config.read(text, "bad.example.com").unwrap();
+        assert!(!config.get_hash_known_hosts());
+        assert_eq!(config.port, Some(22));
+        config.apply_option("EnableSSHKeysign yes").unwrap();
+        assert!(config.get_enable_ssh_keysign());
+        config.apply_option("IdentityFile /tmp/id").unwrap();
+        assert_eq!(config.get_identity_files(), vec![PathBuf::from("/home/u/.ssh/work"), PathBuf::from("/tmp/id")]);
+
+        assert!(SshConfig::new(Path::new("/")).read("Port none", "host").is_err());
+    }
+
+    #[test]
+    fn environment_options_are_parsed() {
+        let mut config = SshConfig::new(Path::new("/home/u"));
+        config.read("SendEnv LANG LC_*\nSendEnv BUILD_ID\nSetEnv A=1 B=x=y\nSetEnv C=2\n", "host").unwrap();
+        assert_eq!(config.get_send_env(), vec!["LANG", "LC_*", "BUILD_ID"]);
+        assert_eq!(config.get_set_env(), vec![("A".to_string(), "1".to_string()), ("B".to_string(), "x=y".to_string())]);
+        config.apply_option("SendEnv -LC_*").unwrap();
+        assert_eq!(config.get_send_env(), vec!["LANG", "BUILD_ID"]);
+        assert!(SshConfig::new(Path::new("/")).apply_option("SetEnv NOVALUE").is_err());
+    }
+
+    #[test]
+    fn forwards_accumulate() {
+        let mut config = SshConfig::new(Path::new("/home/u"));
+        config.apply_option("LocalForward 8080:db:5432").unwrap();
+        config.read("LocalForward 127.0.0.1:8081 [::1]:80\nLocalForward 8080 db:5432\n", "host").unwrap();
+        let forwards = config.get_local_forwards();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!((forwards[0].listen_port, forwards[0].connect_host.as_str()), (8080, "db"));
+        assert_eq!(forwards[1].listen_host.as_deref(), Some("127.0.0.1"));
+        assert_eq!((forwards[1].connect_host.as_str(), forwards[1].connect_port), ("::1", 80));
+        assert!(config.apply_option("LocalForward 8080").is_err());
+        config.apply_option("RemoteForward 0 localhost:3000").unwrap();
+        assert_eq!(config.get_remote_forwards()[0].listen_port, 0);
+        assert_eq!(config.get_local_forwards().len(), 2);
+    }
+}
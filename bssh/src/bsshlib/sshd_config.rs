@@ -0,0 +1,540 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use auth_hostbased;
+use auth_keyboard_interactive::ChallengeKind;
+use auth_publickey;
+use authorized_keys;
+use config::ServerConfig;
+use errors;
+use forwarding::GatewayPorts;
+use keys::PublicKey;
+use ssh_config::{parse_yes_no, split_option};
+use userauth;
+
+//sshd_config(5). As in OpenSSH, for each option the first obtained value is used.
+
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/ssh/sshd_config";
+
+const MATCH_KEYWORD: &str = "match";
+
+pub struct SshdConfig {
+    pub port: Option<u16>,
+    pub listen_address: Option<String>,
+    pub trusted_user_ca_keys_file: Option<PathBuf>,
+    pub trusted_user_ca_keys: Vec<PublicKey>,
+    //all HostKey lines are used
+    pub host_key_files: Vec<PathBuf>,
+    //all HostCertificate lines are used, each one certifies one of the host keys
+    pub host_certificate_files: Vec<PathBuf>,
+    pub max_auth_tries: Option<u32>,
+    //seconds, 0 means no limit
+    pub login_grace_time: Option<u64>,
+    pub password_authentication: bool,
+    pub pubkey_authentication: bool,
+    pub kbd_interactive_authentication: bool,
+    //KbdInteractiveChallenge, bssh specific, "password" or "totp"
+    pub kbd_interactive_challenge: ChallengeKind,
+    //TotpSecretsFile, secrets of the users for KbdInteractiveChallenge totp
+    pub totp_secrets_file: Option<PathBuf>,
+    pub hostbased_authentication: bool,
+    pub ignore_rhosts: bool,
+    pub ignore_user_known_hosts: bool,
+    pub hostbased_uses_name_from_packet_only: bool,
+    //UseDNS, look up the client host name for from= patterns
+    pub use_dns: bool,
+    pub authorized_keys_files: Vec<String>,
+    //lists of methods which all have to succeed, empty for "any"
+    pub authentication_methods: Vec<Vec<String>>,
+    pub banner_file: Option<PathBuf>,
+    //all AcceptEnv lines are used
+    pub accept_env: Vec<String>,
+    //PermitUserEnvironment patterns of variables taken from environment= options, empty for "no"
+    pub permit_user_environment: Vec<String>,
+    //name and command line, the first line for a name is used
+    pub subsystems: Vec<(String, String)>,
+    //AllowTcpForwarding yes, all or local
+    pub allow_local_forwarding: bool,
+    //AllowTcpForwarding yes, all or remote
+    pub allow_remote_forwarding: bool,
+    pub gateway_ports: GatewayPorts,
+    //"host:port" destinations of local forwarding, None allows any
+    pub permit_open: Option<Vec<String>>,
+    obtained: HashSet<String>,
+}
+
+fn bad_option() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_CONFIG_BAD_OPTION)
+}
+
+//sshd_config(5) TIME FORMATS, e.g. "90", "10m" or "1h30m", in seconds
+pub fn parse_time(value: &str) -> Result<u64, Error> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let multiplier = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(bad_option()),
+        };
+        let n: u64 = number.parse().map_err(|_| bad_option())?;
+        total = total.checked_add(n * multiplier).ok_or_else(bad_option)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        let n: u64 = number.parse().map_err(|_| bad_option())?;
+        total = total.checked_add(n).ok_or_else(bad_option)?;
+    } else if value.is_empty() {
+        return Err(bad_option());
+    }
+    Ok(total)
+}
+
+//one key per line, in authorized_keys format
+pub fn load_ca_keys_file(path: &Path) -> Result<Vec<PublicKey>, Error> {
+    let text = fs::read_to_string(path)?;
+    Ok(authorized_keys::read_authorized_keys(&text).into_iter().map(|k| k.key).collect())
+}
+
+impl SshdConfig {
+    pub fn new() -> SshdConfig {
+        SshdConfig {
+            port: None,
+            listen_address: None,
+            trusted_user_ca_keys_file: None,
+            trusted_user_ca_keys: Vec::new(),
+            host_key_files: Vec::new(),
+            host_certificate_files: Vec::new(),
+            max_auth_tries: None,
+            login_grace_time: None,
+            password_authentication: true,
+            pubkey_authentication: true,
+            kbd_interactive_authentication: true,
+            kbd_interactive_challenge: ChallengeKind::Password,
+            totp_secrets_file: None,
+            hostbased_authentication: false,
+            ignore_rhosts: true,
+            ignore_user_known_hosts: false,
+            hostbased_uses_name_from_packet_only: false,
+            use_dns: false,
+            authorized_keys_files: auth_publickey::DEFAULT_AUTHORIZED_KEYS_FILES.iter().map(|f| f.to_string()).collect(),
+            authentication_methods: Vec::new(),
+            banner_file: None,
+            accept_env: Vec::new(),
+            permit_user_environment: Vec::new(),
+            subsystems: Vec::new(),
+            allow_local_forwarding: true,
+            allow_remote_forwarding: true,
+            gateway_ports: GatewayPorts::No,
+            permit_open: None,
+            obtained: HashSet::new(),
+        }
+    }
+
+    //applies single "Keyword value" option, unknown keywords are ignored
+    //so the configuration can be shared with OpenSSH sshd
+    pub fn apply_option(&mut self, line: &str) -> Result<(), Error> {
+        let (keyword, value) = split_option(line);
+        let mut keyword = keyword.to_lowercase();
+        if value.is_empty() {
+            return Err(bad_option());
+        }
+        //deprecated alias
+        if keyword == "challengeresponseauthentication" {
+            keyword = "kbdinteractiveauthentication".to_string();
+        }
+        if keyword == "acceptenv" {
+            self.accept_env.extend(value.split_whitespace().map(|p| p.to_string()));
+            return Ok(());
+        }
+        if keyword == "hostkey" {
+            self.host_key_files.push(PathBuf::from(value));
+            return Ok(());
+        }
+        if keyword == "hostcertificate" {
+            self.host_certificate_files.push(PathBuf::from(value));
+            return Ok(());
+        }
+        if keyword == "subsystem" {
+            let (name, command) = split_option(value);
+            if command.is_empty() {
+                return Err(bad_option());
+            }
+            if !self.subsystems.iter().any(|s| s.0 == name) {
+                self.subsystems.push((name.to_string(), command.to_string()));
+            }
+            return Ok(());
+        }
+        if self.obtained.contains(&keyword) {
+            return Ok(());
+        }
+
+        match keyword.as_str() {
+            "port" => self.port = Some(value.parse().map_err(|_| bad_option())?),
+            "listenaddress" => self.listen_address = Some(value.to_string()),
+            "trustedusercakeys" => {
+                //"none" disables the option
+                if !value.eq_ignore_ascii_case("none") {
+                    let path = PathBuf::from(value);
+                    self.trusted_user_ca_keys = load_ca_keys_file(&path)?;
+                    self.trusted_user_ca_keys_file = Some(path);
+                }
+            }
+            "maxauthtries" => self.max_auth_tries = Some(value.parse().map_err(|_| bad_option())?),
+            "logingracetime" => self.login_grace_time = Some(parse_time(value)?),
+            "passwordauthentication" => self.password_authentication = parse_yes_no(value)?,
+            "pubkeyauthentication" => self.pubkey_authentication = parse_yes_no(value)?,
+            "kbdinteractiveauthentication" => self.kbd_interactive_authentication = parse_yes_no(value)?,
+            "kbdinteractivechallenge" => self.kbd_interactive_challenge = ChallengeKind::from_name(value).ok_or_else(bad_option)?,
+            "totpsecretsfile" => self.totp_secrets_file = Some(PathBuf::from(value)),
+            "hostbasedauthentication" => self.hostbased_authentication = parse_yes_no(value)?,
+            "ignorerhosts" => self.ignore_rhosts = parse_yes_no(value)?,
+            "ignoreuserknownhosts" => self.ignore_user_known_hosts = parse_yes_no(value)?,
+            "hostbasedusesnamefrompacketonly" => self.hostbased_uses_name_from_packet_only = parse_yes_no(value)?,
+            "usedns" => self.use_dns = parse_yes_no(value)?,
+            "authorizedkeysfile" => {
+                //"none" means no files
+                self.authorized_keys_files = value.split_whitespace().filter(|f| !f.eq_ignore_ascii_case("none")).map(|f| f.to_string()).collect()
+            }
+            "banner" => {
+                //"none" disables the banner
+                if !value.eq_ignore_ascii_case("none") {
+                    self.banner_file = Some(PathBuf::from(value));
+                }
+            }
+            "authenticationmethods" => {
+                if !value.eq_ignore_ascii_case("any") {
+                    self.authentication_methods = value.split_whitespace().map(|l| l.split(',').map(|m| m.to_string()).collect()).collect()
+                }
+            }
+            "allowtcpforwarding" => {
+                let (local, remote) = match value.to_lowercase().as_str() {
+                    "yes" | "all" => (true, true),
+                    "local" => (true, false),
+                    "remote" => (false, true),
+                    "no" => (false, false),
+                    _ => return Err(bad_option()),
+                };
+                self.allow_local_forwarding = local;
+                self.allow_remote_forwarding = remote;
+            }
+            "permituserenvironment" => {
+                self.permit_user_environment = match value.to_lowercase().as_str() {
+                    "no" => Vec::new(),
+                    "yes" => vec!["*".to_string()],
+                    _ => value.split(',').map(|p| p.to_string()).collect(),
+                }
+            }
+            "gatewayports" => self.gateway_ports = GatewayPorts::from_name(value).ok_or_else(bad_option)?,
+            "permitopen" => {
+                self.permit_open = match value.to_lowercase().as_str() {
+                    "any" => None,
+                    "none" => Some(Vec::new()),
+                    _ if value.split_whitespace().all(|d| d.contains(':')) => Some(value.split_whitespace().map(|d| d.to_string()).collect()),
+                    _ => return Err(bad_option()),
+                }
+            }
+            _ => return Ok(()),
+        }
+
+        self.obtained.insert(keyword);
+        Ok(())
+    }
+
+    pub fn read(&mut self, text: &str) -> Result<(), Error> {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            //TODO conditional blocks are not supported yet, everything after first Match is skipped
+            if split_option(line).0.eq_ignore_ascii_case(MATCH_KEYWORD) {
+                break;
+            }
+            self.apply_option(line)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_file(&mut self, path: &Path) -> Result<(), Error> {
+        let text = fs::read_to_string(path)?;
+        self.read(&text)
+    }
+}
+
+impl Default for SshdConfig {
+    fn default() -> SshdConfig {
+        SshdConfig::new()
+    }
+}
+
+impl ServerConfig for SshdConfig {
+    fn get_host_key_files(&self) -> Vec<PathBuf> {
+        if self.host_key_files.is_empty() {
+            auth_hostbased::DEFAULT_HOST_KEY_FILES.iter().map(PathBuf::from).collect()
+        } else {
+            self.host_key_files.clone()
+        }
+    }
+
+    fn get_host_certificate_files(&self) -> Vec<PathBuf> {
+        self.host_certificate_files.clone()
+    }
+
+    fn get_trusted_user_ca_keys(&self) -> Vec<PublicKey> {
+        self.trusted_user_ca_keys.clone()
+    }
+
+    fn get_max_auth_tries(&self) -> u32 {
+        self.max_auth_tries.unwrap_or(userauth::DEFAULT_MAX_AUTH_TRIES)
+    }
+
+    fn get_login_grace_time(&self) -> Option<Duration> {
+        match self.login_grace_time.unwrap_or(userauth::DEFAULT_LOGIN_GRACE_TIME) {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+
+    fn get_password_authentication(&self) -> bool {
+        self.password_authentication
+    }
+
+    fn get_pubkey_authentication(&self) -> bool {
+        self.pubkey_authentication
+    }
+
+    fn get_kbd_interactive_authentication(&self) -> bool {
+        self.kbd_interactive_authentication
+    }
+
+    fn get_kbd_interactive_challenge(&self) -> ChallengeKind {
+        self.kbd_interactive_challenge
+    }
+
+    fn get_totp_secrets_file(&self) -> Option<PathBuf> {
+        self.totp_secrets_file.clone()
+    }
+
+    fn get_hostbased_authentication(&self) -> bool {
+        self.hostbased_authentication
+    }
+
+    fn get_ignore_rhosts(&self) -> bool {
+        self.ignore_rhosts
+    }
+
+    fn get_ignore_user_known_hosts(&self) -> bool {
+        self.ignore_user_known_hosts
+    }
+
+    fn get_hostbased_uses_name_from_packet_only(&self) -> bool {
+        self.hostbased_uses_name_from_packet_only
+    }
+
+    fn get_use_dns(&self) -> bool {
+        self.use_dns
+    }
+
+    fn get_authorized_keys_files(&self) -> Vec<String> {
+        self.authorized_keys_files.clone()
+    }
+
+    fn get_authentication_methods(&self) -> Vec<Vec<String>> {
+        self.authentication_methods.clone()
+    }
+
+    fn get_banner_file(&self) -> Option<PathBuf> {
+        self.banner_file.clone()
+    }
+
+    fn get_accept_env(&self) -> Vec<String> {
+        self.accept_env.clone()
+    }
+
+    fn get_permit_user_environment(&self) -> Vec<String> {
+        self.permit_user_environment.clone()
+    }
+
+    fn get_subsystems(&self) -> Vec<(String, String)> {
+        self.subsystems.clone()
+    }
+
+    fn get_allow_local_forwarding(&self) -> bool {
+        self.allow_local_forwarding
+    }
+
+    fn get_permit_open(&self) -> Option<Vec<String>> {
+        self.permit_open.clone()
+    }
+
+    fn get_allow_remote_forwarding(&self) -> bool {
+        self.allow_remote_forwarding
+    }
+
+    fn get_gateway_ports(&self) -> GatewayPorts {
+        self.gateway_ports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn read_handles_trusted_user_ca_keys() {
+        let path = env::temp_dir().join(format!("bssh_trusted_ca_keys_{}", std::process::id()));
+        fs::write(&path, "# CA\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAII7fJlUP11Kv8cdL/3YQfA8WsCeHVNiWdLBcc+GbB/xM ca\n").unwrap();
+
+        let mut config = SshdConfig::new();
+        let res = config.read(&format!("Port 2222\nSyslogFacility AUTH\nTrustedUserCAKeys {}\nPort 22\nMatch User x\nListenAddress ::1\n", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        res.unwrap();
+        assert_eq!(config.port, Some(2222));
+        assert_eq!(config.listen_address, None);
+        assert_eq!(config.get_trusted_user_ca_keys().len(), 1);
+        assert!(SshdConfig::new().read("TrustedUserCAKeys /nonexistent/file").is_err());
+        assert!(SshdConfig::new().read("Port x").is_err());
+    }
+
+    #[test]
+    fn read_handles_authentication_limits() {
+        let config = SshdConfig::new();
+        assert_eq!(config.get_max_auth_tries(), 6);
+        assert_eq!(config.get_login_grace_time(), Some(Duration::from_secs(120)));
+
+        let mut config = SshdConfig::new();
+        config.read("MaxAuthTries 3\nLoginGraceTime 1h30m\nPasswordAuthentication no\n").unwrap();
+        assert!(!config.get_password_authentication());
+        assert_eq!(config.get_max_auth_tries(), 3);
+        assert_eq!(config.get_login_grace_time(), Some(Duration::from_secs(5400)));
+
+        let mut config = SshdConfig::new();
+        config.read("LoginGraceTime 0").unwrap();
+        assert_eq!(config.get_login_grace_time(), None);
+
+        let mut config = SshdConfig::new();
+        assert!(config.get_kbd_interactive_authentication());
+        config.read("ChallengeResponseAuthentication no
+KbdInteractiveAuthentication yes").unwrap();
+        assert!(!config.get_kbd_interactive_authentication());
+        assert_eq!(config.get_kbd_interactive_challenge(), ChallengeKind::Password);
+        config.read("KbdInteractiveChallenge totp\nTotpSecretsFile /etc/bssh/totp_secrets").unwrap();
+        assert_eq!(config.get_kbd_interactive_challenge(), ChallengeKind::Totp);
+        assert_eq!(config.get_totp_secrets_file(), Some(PathBuf::from("/etc/bssh/totp_secrets")));
+        assert!(SshdConfig::new().read("KbdInteractiveChallenge pam").is_err());
+
+        let mut config = SshdConfig::new();
+        assert!(!config.get_hostbased_authentication() && config.get_ignore_rhosts());
+        config.read("HostbasedAuthentication yes\nIgnoreRhosts no\nIgnoreUserKnownHosts yes").unwrap();
+        assert!(config.get_hostbased_authentication() && !config.get_ignore_rhosts() && config.get_ignore_user_known_hosts());
+        assert!(!config.get_hostbased_uses_name_from_packet_only());
+        config.read("HostbasedUsesNameFromPacketOnly yes").unwrap();
+        assert!(config.get_hostbased_uses_name_from_packet_only());
+        assert!(!config.get_use_dns());
+        config.read("UseDNS yes").unwrap();
+        assert!(config.get_use_dns());
+
+        assert_eq!(parse_time("90").unwrap(), 90);
+        assert_eq!(parse_time("2m10").unwrap(), 130);
+        assert!(parse_time("1x").is_err());
+        assert!(parse_time("m").is_err());
+    }
+
+    #[test]
+    fn read_handles_host_keys() {
+        let mut config = SshdConfig::new();
+        assert_eq!(config.get_host_key_files(), vec![PathBuf::from("/etc/ssh/ssh_host_ecdsa_key"),
+                                                     PathBuf::from("/etc/ssh/ssh_host_ed25519_key"),
+                                                     PathBuf::from("/etc/ssh/ssh_host_rsa_key")]);
+
+        config.read("HostKey /etc/bssh/host_ed25519_key\nHostKey /etc/bssh/host_rsa_key").unwrap();
+        assert_eq!(config.get_host_key_files(), vec![PathBuf::from("/etc/bssh/host_ed25519_key"), PathBuf::from("/etc/bssh/host_rsa_key")]);
+        assert!(config.get_host_certificate_files().is_empty());
+        config.read("HostCertificate /etc/bssh/host_ed25519_key-cert.pub\nHostCertificate /etc/bssh/host_rsa_key-cert.pub").unwrap();
+        assert_eq!(config.get_host_certificate_files(), vec![PathBuf::from("/etc/bssh/host_ed25519_key-cert.pub"),
+                                                             PathBuf::from("/etc/bssh/host_rsa_key-cert.pub")]);
+    }
+
+    #[test]
+    fn read_handles_authorized_keys_files() {
+        let config = SshdConfig::new();
+        assert_eq!(config.get_authorized_keys_files(), vec![".ssh/authorized_keys", ".ssh/authorized_keys2"]);
+        assert!(config.get_pubkey_authentication());
+
+        let mut config = SshdConfig::new();
+        config.read("AuthorizedKeysFile\t.ssh/authorized_keys /etc/keys/%u\nPubkeyAuthentication no").unwrap();
+        assert_eq!(config.get_authorized_keys_files(), vec![".ssh/authorized_keys", "/etc/keys/%u"]);
+        assert!(!config.get_pubkey_authentication());
+
+        let mut config = SshdConfig::new();
+        config.read("AuthorizedKeysFile none").unwrap();
+        assert!(config.get_authorized_keys_files().is_empty());
+        assert!(config.get_authentication_methods().is_empty());
+
+        let mut config = SshdConfig::new();
+        config.read("AuthenticationMethods publickey,keyboard-interactive publickey,password").unwrap();
+        assert_eq!(config.get_authentication_methods(), vec![vec!["publickey", "keyboard-interactive"], vec!["publickey", "password"]]);
+
+        let mut config = SshdConfig::new();
+        assert_eq!(config.get_banner_file(), None);
+        config.read("Banner /etc/issue.net").unwrap();
+        assert_eq!(config.get_banner_file(), Some(PathBuf::from("/etc/issue.net")));
+        let mut config = SshdConfig::new();
+        config.read("Banner none").unwrap();
+        assert_eq!(config.get_banner_file(), None);
+
+        let mut config = SshdConfig::new();
+        assert!(config.get_accept_env().is_empty());
+        config.read("AcceptEnv LANG LC_*\nAcceptEnv BUILD_ID").unwrap();
+        assert_eq!(config.get_accept_env(), vec!["LANG", "LC_*", "BUILD_ID"]);
+
+        let mut config = SshdConfig::new();
+        assert!(config.get_permit_user_environment().is_empty());
+        config.read("PermitUserEnvironment LANG,LC_*").unwrap();
+        assert_eq!(config.get_permit_user_environment(), vec!["LANG", "LC_*"]);
+        let mut config = SshdConfig::new();
+        config.read("PermitUserEnvironment yes").unwrap();
+        assert_eq!(config.get_permit_user_environment(), vec!["*"]);
+
+        let mut config = SshdConfig::new();
+        config.read("Subsystem sftp /usr/local/libexec/sftp-server -l INFO\nSubsystem sftp internal-sftp\nSubsystem other internal-sftp").unwrap();
+        assert_eq!(config.get_subsystems(), vec![("sftp".to_string(), "/usr/local/libexec/sftp-server -l INFO".to_string()),
+                                                 ("other".to_string(), "internal-sftp".to_string())]);
+        assert!(SshdConfig::new().read("Subsystem sftp").is_err());
+    }
+
+    #[test]
+    fn read_handles_forwarding_options() {
+        let config = SshdConfig::new();
+        assert!(config.get_allow_local_forwarding() && config.get_allow_remote_forwarding());
+        assert_eq!(config.get_permit_open(), None);
+        assert_eq!(config.get_gateway_ports(), GatewayPorts::No);
+
+        let mut config = SshdConfig::new();
+        config.read("AllowTcpForwarding remote\nPermitOpen db:5432 [::1]:*\nPermitOpen any\nGatewayPorts clientspecified").unwrap();
+        assert!(!config.get_allow_local_forwarding() && config.get_allow_remote_forwarding());
+        assert_eq!(config.get_gateway_ports(), GatewayPorts::ClientSpecified);
+        assert_eq!(config.get_permit_open(), Some(vec!["db:5432".to_string(), "[::1]:*".to_string()]));
+
+        let mut config = SshdConfig::new();
+        config.read("AllowTcpForwarding local\nPermitOpen none").unwrap();
+        assert!(config.get_allow_local_forwarding() && !config.get_allow_remote_forwarding());
+        assert_eq!(config.get_permit_open(), Some(Vec::new()));
+        assert!(SshdConfig::new().read("AllowTcpForwarding maybe").is_err());
+        assert!(SshdConfig::new().read("PermitOpen db").is_err());
+        assert!(SshdConfig::new().read("GatewayPorts all").is_err());
+    }
+}
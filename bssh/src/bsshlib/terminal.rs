@@ -0,0 +1,184 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, Write};
+use std::os::unix::io::AsRawFd;
+use libc;
+
+const TTY_PATH: &str = "/dev/tty";
+
+//restores terminal attributes when dropped, so an error or panic does not leave echo disabled
+struct EchoGuard {
+    fd: libc::c_int,
+    original: libc::termios,
+}
+
+impl EchoGuard {
+    fn disable_echo(fd: libc::c_int) -> Result<EchoGuard, Error> {
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut original) != 0 {
+                return Err(Error::last_os_error());
+            }
+            let mut modified = original;
+            modified.c_lflag &= !(libc::ECHO | libc::ECHONL);
+            modified.c_lflag |= libc::ICANON;
+            if libc::tcsetattr(fd, libc::TCSAFLUSH, &modified) != 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(EchoGuard { fd, original })
+        }
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSAFLUSH, &self.original);
+        }
+    }
+}
+
+fn open_tty() -> Result<File, Error> {
+    OpenOptions::new().read(true).write(true).open(TTY_PATH)
+}
+
+//prints prompt on the controlling terminal and reads one line from it
+pub fn read_line_from_tty(prompt: &str, echo: bool) -> Result<String, Error> {
+    let mut tty = open_tty()?;
+    tty.write_all(prompt.as_bytes())?;
+    tty.flush()?;
+
+    let mut line = String::new();
+    {
+        let _guard = if echo { None } else { Some(EchoGuard::disable_echo(tty.as_raw_fd())?) };
+        BufReader::new(&tty).read_line(&mut line)?;
+    }
+
+    if !echo {
+        tty.write_all(b"\n")?;
+    }
+
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+pub fn read_passphrase(prompt: &str) -> Result<String, Error> {
+    read_line_from_tty(prompt, false)
+}
+
+//puts the terminal in raw mode like ssh(1) does for interactive sessions, restored when dropped
+pub struct RawModeGuard {
+    fd: libc::c_int,
+    original: libc::termios,
+}
+
+impl RawModeGuard {
+    pub fn enter(fd: libc::c_int) -> Result<RawModeGuard, Error> {
+        let original = get_attributes(fd)?;
+        let mut raw = original;
+        raw.c_iflag |= libc::IGNPAR;
+        raw.c_iflag &= !(libc::ISTRIP | libc::INLCR | libc::IGNCR | libc::ICRNL | libc::IXON | libc::IXANY | libc::IXOFF);
+        raw.c_lflag &= !(libc::ISIG | libc::ICANON | libc::ECHO | libc::ECHOE | libc::ECHOK | libc::ECHONL | libc::IEXTEN);
+        raw.c_oflag &= !libc::OPOST;
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        set_attributes(fd, &raw)?;
+        Ok(RawModeGuard { fd, original })
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = set_attributes(self.fd, &self.original);
+    }
+}
+
+pub fn is_terminal(fd: libc::c_int) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+pub fn get_attributes(fd: libc::c_int) -> Result<libc::termios, Error> {
+    unsafe {
+        let mut attributes: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut attributes) != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(attributes)
+    }
+}
+
+pub fn set_attributes(fd: libc::c_int, attributes: &libc::termios) -> Result<(), Error> {
+    if unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, attributes) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WindowSize {
+    pub columns: u32,
+    pub rows: u32,
+    pub width_pixels: u32,
+    pub height_pixels: u32,
+}
+
+pub fn get_window_size(fd: libc::c_int) -> Result<WindowSize, Error> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(WindowSize {
+        columns: size.ws_col as u32,
+        rows: size.ws_row as u32,
+        width_pixels: size.ws_xpixel as u32,
+        height_pixels: size.ws_ypixel as u32,
+    })
+}
+
+//sizes over 65535 do not fit the kernel structure and are clamped
+pub fn set_window_size(fd: libc::c_int, size: &WindowSize) -> Result<(), Error> {
+    let clamp = |value: u32| if value > u16::MAX as u32 { u16::MAX } else { value as u16 };
+    let size = libc::winsize {
+        ws_col: clamp(size.columns),
+        ws_row: clamp(size.rows),
+        ws_xpixel: clamp(size.width_pixels),
+        ws_ypixel: clamp(size.height_pixels),
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+//escapes control characters as octal, like vis(3) VIS_SAFE|VIS_OCTAL, so text from the server
+//can not move the cursor, change the title or otherwise act on the terminal
+pub fn sanitize(text: &str) -> String {
+    let mut res = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' | '\r' | '\t' => res.push(c),
+            c if c.is_control() => {
+                let mut buffer = [0u8; 4];
+                for b in c.encode_utf8(&mut buffer).bytes() {
+                    res.push_str(&format!("\\{:03o}", b));
+                }
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn sanitize_escapes_control_characters() {
+        assert_eq!(sanitize("Welcome\r\n\tto bssh\n"), "Welcome\r\n\tto bssh\n");
+        assert_eq!(sanitize("\x1b]0;title\x07ok"), "\\033]0;title\\007ok");
+        assert_eq!(sanitize("del\x7f c1\u{9b}2J žluťoučký"), "del\\177 c1\\302\\2332J žluťoučký");
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::Sender;
+use std::thread;
+use errors;
+use msgs;
+use numbers;
+use packet;
+use packet::PacketKeys;
+
+//Layers above the transport (RFC 4252 authentication, RFC 4254 connection) only ever
+//exchange payloads, this is what they are given to talk to the other side.
+pub trait PayloadStream {
+    fn send_payload(&mut self, payload: &[u8]) -> Result<(), Error>;
+    //SSH_MSG_IGNORE and SSH_MSG_DEBUG are never returned, SSH_MSG_DISCONNECT ends in an error
+    fn receive_payload(&mut self) -> Result<Vec<u8>, Error>;
+}
+
+//sequence numbers count all packets of the direction since the start, keys are in effect after SSH_MSG_NEWKEYS
+#[derive(Default)]
+pub struct PacketState {
+    pub sequence_number: u32,
+    pub keys: Option<PacketKeys>,
+}
+
+impl PacketState {
+    pub fn new() -> PacketState {
+        PacketState { sequence_number: 0, keys: None }
+    }
+}
+
+//RFC 4253 binary packet protocol over a stream, the key exchange sets the keys and
+//the session identifier, a half of a split stream handles only one direction
+pub struct TransportStream<S: Read + Write> {
+    pub stream: S,
+    pub session_id: Vec<u8>,
+    sending: Option<PacketState>,
+    receiving: Option<PacketState>,
+}
+
+impl<S: Read + Write> TransportStream<S> {
+    pub fn new(stream: S) -> TransportStream<S> {
+        TransportStream {
+            stream,
+            session_id: Vec::new(),
+            sending: Some(PacketState::new()),
+            receiving: Some(PacketState::new()),
+        }
+    }
+
+    pub fn set_send_keys(&mut self, keys: PacketKeys) -> Result<(), Error> {
+        get_state(&mut self.sending)?.keys = Some(keys);
+        Ok(())
+    }
+
+    pub fn set_receive_keys(&mut self, keys: PacketKeys) -> Result<(), Error> {
+        get_state(&mut self.receiving)?.keys = Some(keys);
+        Ok(())
+    }
+
+    //true when every direction this stream handles is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        [&self.sending, &self.receiving].iter().all(|state| state.as_ref().is_none_or(|s| s.keys.is_some()))
+    }
+}
+
+impl TransportStream<TcpStream> {
+    //the receiving half is for a thread waiting for the peer, the sending half stays with the caller
+    pub fn split(self) -> Result<(TransportStream<TcpStream>, TransportStream<TcpStream>), Error> {
+        let receiving = TransportStream {
+            stream: self.stream.try_clone()?,
+            session_id: self.session_id.clone(),
+            sending: None,
+            receiving: self.receiving,
+        };
+        let sending = TransportStream {
+            stream: self.stream,
+            session_id: self.session_id,
+            sending: self.sending,
+            receiving: None,
+        };
+        Ok((receiving, sending))
+    }
+}
+
+fn get_state(state: &mut Option<PacketState>) -> Result<&mut PacketState, Error> {
+    state.as_mut().ok_or_else(|| Error::other(errors::BSSH_ERR_TRANSPORT_DIRECTION_SPLIT))
+}
+
+//turns SSH_MSG_DISCONNECT into an error, returns payload of other messages unless it should be skipped
+pub fn filter_transport_message(payload: Vec<u8>) -> Result<Option<Vec<u8>>, Error> {
+    match msgs::get_message_number(&payload)? {
+        numbers::SSH_MSG_IGNORE | numbers::SSH_MSG_DEBUG => Ok(None),
+        numbers::SSH_MSG_DISCONNECT => {
+            let disconnect = msgs::read_disconnect_message(&mut payload.as_slice())?;
+            Err(Error::new(ErrorKind::ConnectionAborted, format!("Disconnected: {}", disconnect.description)))
+        }
+        _ => Ok(Some(payload)),
+    }
+}
+
+impl<S: Read + Write> PayloadStream for TransportStream<S> {
+    fn send_payload(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let state = get_state(&mut self.sending)?;
+        packet::write_packet(&mut self.stream, payload, state.keys.as_mut(), state.sequence_number)?;
+        state.sequence_number = state.sequence_number.wrapping_add(1);
+        Ok(())
+    }
+
+    fn receive_payload(&mut self) -> Result<Vec<u8>, Error> {
+        let state = get_state(&mut self.receiving)?;
+        loop {
+            let payload = packet::read_packet(&mut self.stream, state.keys.as_mut(), state.sequence_number)?;
+            state.sequence_number = state.sequence_number.wrapping_add(1);
+            if let Some(payload) = filter_transport_message(payload)? {
+                return Ok(payload);
+            }
+        }
+    }
+}
+
+//receives payloads in a thread so the connection can also wait for other events,
+//ends after the first error or when nobody listens anymore
+pub fn spawn_payload_receiver<P, E, F>(mut stream: P, events: Sender<E>, make_event: F) -> thread::JoinHandle<()>
+    where P: PayloadStream + Send + 'static, E: Send + 'static, F: Fn(Result<Vec<u8>, Error>) -> E + Send + 'static
+{
+    thread::spawn(move || loop {
+        let received = stream.receive_payload();
+        let failed = received.is_err();
+        if events.send(make_event(received)).is_err() || failed {
+            return;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Cursor;
+    use cipher;
+    use cipher::PacketCipher;
+    use mac;
+    use mac::PacketMac;
+
+    fn get_keys() -> PacketKeys {
+        PacketKeys {
+            cipher: PacketCipher::new(cipher::AES256_CTR, &[1; 32], &[2; 16]).unwrap(),
+            mac: PacketMac::new(mac::HMAC_SHA1, &[3; 20]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn transport_stream_roundtrips_and_skips_ignore() {
+        let mut sender = TransportStream::new(Cursor::new(Vec::new()));
+        sender.send_payload(&[numbers::SSH_MSG_NEWKEYS]).unwrap();
+        assert!(!sender.is_encrypted());
+        sender.set_send_keys(get_keys()).unwrap();
+        sender.send_payload(&[numbers::SSH_MSG_IGNORE, 0, 0, 0, 0]).unwrap();
+        sender.send_payload(&[numbers::SSH_MSG_SERVICE_REQUEST, 1, 2, 3]).unwrap();
+        let mut disconnect: Vec<u8> = Vec::new();
+        msgs::write_disconnect_message(&mut disconnect, numbers::SSH_DISCONNECT_BY_APPLICATION, "bye").unwrap();
+        sender.send_payload(&disconnect).unwrap();
+        assert_eq!(sender.sending.as_ref().unwrap().sequence_number, 4);
+
+        let mut receiver = TransportStream::new(Cursor::new(sender.stream.into_inner()));
+        assert_eq!(receiver.receive_payload().unwrap(), vec![numbers::SSH_MSG_NEWKEYS]);
+        receiver.set_receive_keys(get_keys()).unwrap();
+        assert_eq!(receiver.receive_payload().unwrap(), vec![numbers::SSH_MSG_SERVICE_REQUEST, 1, 2, 3]);
+        let err = receiver.receive_payload().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+        assert_eq!(receiver.receiving.as_ref().unwrap().sequence_number, 4);
+    }
+}
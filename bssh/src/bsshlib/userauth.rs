@@ -0,0 +1,843 @@
+use std::io::{Cursor, Error, ErrorKind, Read, Write};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use auth_options::AuthOptions;
+use errors;
+use io_helpers;
+use msgs;
+use numbers;
+use transport::PayloadStream;
+
+//RFC 4252, The Secure Shell (SSH) Authentication Protocol
+
+pub const SERVICE_USERAUTH: &str = "ssh-userauth";
+pub const SERVICE_CONNECTION: &str = "ssh-connection";
+pub const METHOD_NONE: &str = "none";
+
+pub const DEFAULT_MAX_AUTH_TRIES: u32 = 6; //same as OpenSSH
+pub const DEFAULT_LOGIN_GRACE_TIME: u64 = 120; //seconds, same as OpenSSH
+
+fn unexpected_message() -> Error {
+    Error::new(ErrorKind::InvalidData, errors::BSSH_ERR_UNEXPECTED_MESSAGE)
+}
+
+//RFC 4252 page 4
+pub struct UserauthRequest {
+    pub user: String,
+    pub service: String,
+    pub method: String,
+    //rest of the message, interpreted by the method
+    pub method_data: Vec<u8>,
+}
+
+pub fn write_userauth_request_message(stream: &mut dyn Write, request: &UserauthRequest) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_USERAUTH_REQUEST])?;
+    io_helpers::write_string(stream, &request.user.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.service.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &request.method.as_bytes().to_vec())?;
+    stream.write_all(&request.method_data)?;
+    Ok(())
+}
+
+pub fn read_userauth_request_message(stream: &mut dyn Read) -> Result<UserauthRequest, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_USERAUTH_REQUEST)?;
+    let user = io_helpers::read_utf8_string(stream)?;
+    let service = io_helpers::read_utf8_string(stream)?;
+    let method = io_helpers::read_utf8_string(stream)?;
+    let mut method_data: Vec<u8> = Vec::new();
+    stream.read_to_end(&mut method_data)?;
+    Ok(UserauthRequest {
+        user,
+        service,
+        method,
+        method_data,
+    })
+}
+
+//RFC 4252 page 5
+pub struct UserauthFailure {
+    pub methods: Vec<String>,
+    pub partial_success: bool,
+}
+
+pub fn write_userauth_failure_message(stream: &mut dyn Write, failure: &UserauthFailure) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_USERAUTH_FAILURE])?;
+    io_helpers::write_name_list(stream, &failure.methods)?;
+    io_helpers::write_boolean(stream, failure.partial_success)?;
+    Ok(())
+}
+
+pub fn read_userauth_failure_message(stream: &mut dyn Read) -> Result<UserauthFailure, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_USERAUTH_FAILURE)?;
+    let methods = io_helpers::read_name_list(stream, None)?;
+    let partial_success = io_helpers::read_boolean(stream)?;
+    Ok(UserauthFailure {
+        methods,
+        partial_success,
+    })
+}
+
+//RFC 4252 page 6
+pub fn write_userauth_banner_message(stream: &mut dyn Write, message: &str) -> Result<(), Error> {
+    stream.write_all(&[numbers::SSH_MSG_USERAUTH_BANNER])?;
+    io_helpers::write_string(stream, &message.as_bytes().to_vec())?;
+    io_helpers::write_string(stream, &Vec::new())?;
+    Ok(())
+}
+
+pub fn read_userauth_banner_message(stream: &mut dyn Read) -> Result<String, Error> {
+    msgs::read_message_number(stream, numbers::SSH_MSG_USERAUTH_BANNER)?;
+    let message = io_helpers::read_utf8_string(stream)?;
+    let _language_tag = io_helpers::read_string(stream, None)?;
+    Ok(message)
+}
+
+fn get_payload<F: FnOnce(&mut Vec<u8>) -> Result<(), Error>>(write: F) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    //writing to Vec can not fail
+    write(&mut payload).unwrap();
+    payload
+}
+
+pub struct ClientAuthContext {
+    pub user: String,
+    pub service: String,
+    pub session_id: Vec<u8>,
+}
+
+impl ClientAuthContext {
+    //USERAUTH_REQUEST payload for this user and service
+    pub fn get_request_payload(&self, method: &str, method_data: Vec<u8>) -> Vec<u8> {
+        let request = UserauthRequest {
+            user: self.user.clone(),
+            service: self.service.clone(),
+            method: method.to_string(),
+            method_data,
+        };
+        get_payload(|p| write_userauth_request_message(p, &request))
+    }
+}
+
+pub trait ClientAuthMethod {
+    fn get_name(&self) -> &'static str;
+    //payload of the next USERAUTH_REQUEST to try, None once the method has nothing more to try
+    fn next_request(&mut self, context: &ClientAuthContext) -> Result<Option<Vec<u8>>, Error>;
+    //method specific messages (numbers 60 to 79), returns payloads to send back
+    fn handle_message(&mut self, _context: &ClientAuthContext, _payload: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        Err(unexpected_message())
+    }
+    //methods sending secrets in clear, like passwords, need an encrypted transport
+    fn requires_confidentiality(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuthState {
+    Initial,
+    RequestingService,
+    Authenticating,
+    Succeeded,
+    Failed,
+}
+
+pub struct UserauthClient {
+    pub context: ClientAuthContext,
+    pub state: ClientAuthState,
+    methods: Vec<Box<dyn ClientAuthMethod>>,
+    exhausted: Vec<bool>,
+    current: Option<usize>,
+    //methods that can continue, as last sent by the server
+    pub allowed_methods: Vec<String>,
+    pub partial_success: bool,
+    pub banners: Vec<String>,
+    //set once the transport is encrypted, methods requiring confidentiality are skipped until then
+    pub confidential: bool,
+}
+
+impl UserauthClient {
+    //methods are tried in the given order of preference
+    pub fn new(user: &str, session_id: &[u8], methods: Vec<Box<dyn ClientAuthMethod>>) -> UserauthClient {
+        UserauthClient {
+            context: ClientAuthContext {
+                user: user.to_string(),
+                service: SERVICE_CONNECTION.to_string(),
+                session_id: session_id.to_vec(),
+            },
+            state: ClientAuthState::Initial,
+            exhausted: vec![false; methods.len()],
+            methods,
+            current: None,
+            allowed_methods: Vec::new(),
+            partial_success: false,
+            banners: Vec::new(),
+            confidential: false,
+        }
+    }
+
+    pub fn start(&mut self) -> Vec<Vec<u8>> {
+        self.state = ClientAuthState::RequestingService;
+        vec![get_payload(|p| msgs::write_service_request_message(p, SERVICE_USERAUTH))]
+    }
+
+    fn try_method(&mut self, index: usize) -> Result<Option<Vec<u8>>, Error> {
+        if self.exhausted[index] || !self.allowed_methods.iter().any(|m| m == self.methods[index].get_name()) ||
+            (self.methods[index].requires_confidentiality() && !self.confidential) {
+            return Ok(None);
+        }
+        match self.methods[index].next_request(&self.context)? {
+            Some(payload) => {
+                self.current = Some(index);
+                Ok(Some(payload))
+            }
+            None => {
+                self.exhausted[index] = true;
+                Ok(None)
+            }
+        }
+    }
+
+    //current method is continued while it has something to try, then the others in order of preference
+    fn next_method_request(&mut self) -> Result<Vec<Vec<u8>>, Error> {
+        let order: Vec<usize> = self.current.into_iter().chain(0..self.methods.len()).collect();
+        for index in order {
+            if let Some(payload) = self.try_method(index)? {
+                return Ok(vec![payload]);
+            }
+        }
+        self.state = ClientAuthState::Failed;
+        Err(Error::new(ErrorKind::PermissionDenied, format!("Permission denied ({}).", self.allowed_methods.join(","))))
+    }
+
+    pub fn handle_payload(&mut self, payload: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let number = msgs::get_message_number(payload)?;
+        let mut stream = Cursor::new(payload);
+
+        match (self.state, number) {
+            (ClientAuthState::RequestingService, numbers::SSH_MSG_SERVICE_ACCEPT) => {
+                if msgs::read_service_accept_message(&mut stream)? != SERVICE_USERAUTH {
+                    return Err(unexpected_message());
+                }
+                self.state = ClientAuthState::Authenticating;
+                //RFC 4252 page 7, "none" request returns the list of methods that can continue
+                Ok(vec![self.context.get_request_payload(METHOD_NONE, Vec::new())])
+            }
+            (ClientAuthState::Authenticating, numbers::SSH_MSG_USERAUTH_SUCCESS) => {
+                self.state = ClientAuthState::Succeeded;
+                Ok(Vec::new())
+            }
+            (ClientAuthState::Authenticating, numbers::SSH_MSG_USERAUTH_FAILURE) => {
+                let failure = read_userauth_failure_message(&mut stream)?;
+                self.allowed_methods = failure.methods;
+                self.partial_success = failure.partial_success;
+                self.next_method_request()
+            }
+            //RFC 4252 page 6, banner may be sent any time before authentication succeeds
+            (ClientAuthState::RequestingService, numbers::SSH_MSG_USERAUTH_BANNER) |
+            (ClientAuthState::Authenticating, numbers::SSH_MSG_USERAUTH_BANNER) => {
+                self.banners.push(read_userauth_banner_message(&mut stream)?);
+                Ok(Vec::new())
+            }
+            (ClientAuthState::Authenticating, 60..=79) => {
+                match self.current {
+                    Some(index) => self.methods[index].handle_message(&self.context, payload),
+                    None => Err(unexpected_message()),
+                }
+            }
+            _ => Err(unexpected_message()),
+        }
+    }
+}
+
+//drives client until authentication succeeds, banners are passed to show_banner as they arrive
+pub fn run_client(stream: &mut dyn PayloadStream, client: &mut UserauthClient, show_banner: &mut dyn FnMut(&str)) -> Result<(), Error> {
+    for payload in client.start() {
+        stream.send_payload(&payload)?;
+    }
+    while client.state != ClientAuthState::Succeeded {
+        let payload = stream.receive_payload()?;
+        let shown = client.banners.len();
+        let responses = client.handle_payload(&payload)?;
+        for banner in client.banners[shown..].iter() {
+            show_banner(banner);
+        }
+        for response in responses {
+            stream.send_payload(&response)?;
+        }
+    }
+    Ok(())
+}
+
+pub struct ServerAuthContext {
+    pub user: String,
+    pub service: String,
+    pub session_id: Vec<u8>,
+    pub client_address: Option<IpAddr>,
+    //name of the client address confirmed by a forward lookup, only looked up with UseDNS yes
+    pub client_host_name: Option<String>,
+}
+
+pub enum ServerMethodResult {
+    Success(AuthOptions),
+    Failure,
+    //method needs another round trip, payloads are sent as they are
+    Continue(Vec<Vec<u8>>),
+}
+
+pub trait ServerAuthMethod {
+    fn get_name(&self) -> &'static str;
+    fn handle_request(&mut self, context: &ServerAuthContext, method_data: &[u8]) -> Result<ServerMethodResult, Error>;
+    //method specific messages (numbers 60 to 79)
+    fn handle_message(&mut self, _context: &ServerAuthContext, _payload: &[u8]) -> Result<ServerMethodResult, Error> {
+        Err(unexpected_message())
+    }
+    //methods receiving secrets in clear, like passwords, need an encrypted transport
+    fn requires_confidentiality(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerAuthState {
+    WaitingForServiceRequest,
+    Authenticating,
+    Succeeded,
+    Disconnected,
+}
+
+//AuthenticationMethods lists have to name only enabled methods
+pub fn check_authentication_methods(lists: &[Vec<String>], enabled: &[&str]) -> Result<(), Error> {
+    for list in lists.iter() {
+        if list.is_empty() || list.iter().any(|m| !enabled.contains(&m.as_str())) {
+            return Err(Error::new(ErrorKind::InvalidInput, errors::BSSH_ERR_AUTH_METHOD_NOT_ENABLED));
+        }
+    }
+    Ok(())
+}
+
+pub struct UserauthServer {
+    pub context: ServerAuthContext,
+    pub state: ServerAuthState,
+    methods: Vec<Box<dyn ServerAuthMethod>>,
+    current: Option<usize>,
+    //AuthenticationMethods, empty when any single method is enough
+    pub authentication_methods: Vec<Vec<String>>,
+    //methods still needed by each list, completed methods are removed from the front
+    remaining_methods: Vec<Vec<String>>,
+    //options of the methods completed so far
+    partial_options: Option<AuthOptions>,
+    pub max_auth_tries: u32,
+    pub login_grace_time: Option<Duration>,
+    started: Instant,
+    //failed attempts of the connection, the user can't change so they can't be spread over users
+    pub failures: u32,
+    pub auth_options: Option<AuthOptions>,
+    pub disconnect_reason: Option<String>,
+    //sent once, before the response to the first authentication request
+    pub banner: Option<String>,
+    //set once the transport is encrypted, methods requiring confidentiality are refused until then
+    pub confidential: bool,
+}
+
+impl UserauthServer {
+    pub fn new(session_id: &[u8],
+               client_address: Option<IpAddr>,
+               methods: Vec<Box<dyn ServerAuthMethod>>,
+               max_auth_tries: u32,
+               login_grace_time: Option<Duration>,
+               started: Instant)
+               -> UserauthServer {
+        UserauthServer {
+            context: ServerAuthContext {
+                user: String::new(),
+                service: String::new(),
+                session_id: session_id.to_vec(),
+                client_address,
+                client_host_name: None,
+            },
+            state: ServerAuthState::WaitingForServiceRequest,
+            methods,
+            current: None,
+            authentication_methods: Vec::new(),
+            remaining_methods: Vec::new(),
+            partial_options: None,
+            max_auth_tries,
+            login_grace_time,
+            started,
+            failures: 0,
+            auth_options: None,
+            disconnect_reason: None,
+            banner: None,
+            confidential: false,
+        }
+    }
+
+    //requires the methods of any of the lists to succeed one after another, as OpenSSH AuthenticationMethods
+    pub fn set_authentication_methods(&mut self, lists: Vec<Vec<String>>) -> Result<(), Error> {
+        let enabled: Vec<&str> = self.methods.iter().map(|m| m.get_name()).collect();
+        check_authentication_methods(&lists, &enabled)?;
+        self.remaining_methods = lists.clone();
+        self.authentication_methods = lists;
+        Ok(())
+    }
+
+    pub fn get_authenticated_user(&self) -> Option<&str> {
+        if self.state == ServerAuthState::Succeeded { Some(&self.context.user) } else { None }
+    }
+
+    pub fn is_grace_time_exceeded(&self, now: Instant) -> bool {
+        match self.login_grace_time {
+            Some(grace) => now.duration_since(self.started) >= grace,
+            None => false,
+        }
+    }
+
+    fn is_method_usable(&self, method: &str) -> bool {
+        self.confidential || !self.methods.iter().any(|m| m.get_name() == method && m.requires_confidentiality())
+    }
+
+    //methods which can continue, sent in USERAUTH_FAILURE
+    pub fn get_allowed_methods(&self) -> Vec<String> {
+        if self.authentication_methods.is_empty() {
+            return self.methods.iter().map(|m| m.get_name().to_string()).filter(|m| self.is_method_usable(m)).collect();
+        }
+        let mut allowed: Vec<String> = Vec::new();
+        for method in self.remaining_methods.iter().filter_map(|list| list.first()) {
+            if !allowed.contains(method) && self.is_method_usable(method) {
+                allowed.push(method.clone());
+            }
+        }
+        allowed
+    }
+
+    fn is_method_allowed(&self, method: &str) -> bool {
+        self.get_allowed_methods().iter().any(|m| m == method)
+    }
+
+    //progress of the lists is lost when the options of the methods can't be combined
+    fn reset_partial_success(&mut self) {
+        self.remaining_methods = self.authentication_methods.clone();
+        self.partial_options = None;
+    }
+
+    fn disconnect(&mut self, reason_code: u32, description: &str) -> Vec<Vec<u8>> {
+        self.state = ServerAuthState::Disconnected;
+        self.disconnect_reason = Some(description.to_string());
+        vec![get_payload(|p| msgs::write_disconnect_message(p, reason_code, description))]
+    }
+
+    fn failure(&mut self, counted: bool) -> Vec<Vec<u8>> {
+        self.current = None;
+        if counted {
+            self.failures += 1;
+            if self.failures >= self.max_auth_tries {
+                return self.disconnect(numbers::SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE, errors::BSSH_ERR_TOO_MANY_AUTH_FAILURES);
+            }
+        }
+        self.failure_message(false)
+    }
+
+    fn failure_message(&self, partial_success: bool) -> Vec<Vec<u8>> {
+        let failure = UserauthFailure {
+            methods: self.get_allowed_methods(),
+            partial_success,
+        };
+        vec![get_payload(|p| write_userauth_failure_message(p, &failure))]
+    }
+
+    //RFC 4252 page 6, method succeeded but more are needed
+    fn method_succeeded(&mut self, options: AuthOptions) -> Vec<Vec<u8>> {
+        let method = self.current.take().map(|index| self.methods[index].get_name());
+        let options = match self.partial_options.take() {
+            //restrictions of all methods apply
+            Some(previous) => match previous.merge(&options) {
+                Ok(merged) => merged,
+                Err(_) => {
+                    self.reset_partial_success();
+                    return self.failure(true);
+                }
+            },
+            None => options,
+        };
+        if !self.authentication_methods.is_empty() {
+            for list in self.remaining_methods.iter_mut() {
+                if list.first().map(|m| Some(m.as_str()) == method).unwrap_or(false) {
+                    list.remove(0);
+                }
+            }
+            if !self.remaining_methods.iter().any(|list| list.is_empty()) {
+                self.partial_options = Some(options);
+                return self.failure_message(true);
+            }
+        }
+        self.state = ServerAuthState::Succeeded;
+        self.auth_options = Some(options);
+        vec![vec![numbers::SSH_MSG_USERAUTH_SUCCESS]]
+    }
+
+    fn handle_method_result(&mut self, result: ServerMethodResult) -> Vec<Vec<u8>> {
+        match result {
+            ServerMethodResult::Success(options) => self.method_succeeded(options),
+            ServerMethodResult::Failure => self.failure(true),
+            ServerMethodResult::Continue(payloads) => payloads,
+        }
+    }
+
+    fn handle_request(&mut self, request: UserauthRequest) -> Result<Vec<Vec<u8>>, Error> {
+        if request.service != SERVICE_CONNECTION {
+            return Ok(self.disconnect(numbers::SSH_DISCONNECT_SERVICE_NOT_AVAILABLE, errors::BSSH_ERR_SERVICE_NOT_AVAILABLE));
+        }
+        //RFC 4252 page 5 allows user or service to change, but like OpenSSH the
+        //connection is dropped instead, so failures and partial success stay with one user
+        if !self.context.user.is_empty() && (request.user != self.context.user || request.service != self.context.service) {
+            return Ok(self.disconnect(numbers::SSH_DISCONNECT_PROTOCOL_ERROR, errors::BSSH_ERR_USER_CHANGED));
+        }
+        self.context.user = request.user;
+        self.context.service = request.service;
+        self.current = None;
+
+        let method = request.method;
+        if method == METHOD_NONE {
+            return Ok(self.failure(false));
+        }
+        let index = match self.methods.iter().position(|m| m.get_name() == method) {
+            Some(index) if self.is_method_allowed(&method) => index,
+            _ => return Ok(self.failure(true)),
+        };
+        self.current = Some(index);
+        let result = self.methods[index].handle_request(&self.context, &request.method_data)?;
+        Ok(self.handle_method_result(result))
+    }
+
+    pub fn handle_payload(&mut self, payload: &[u8], now: Instant) -> Result<Vec<Vec<u8>>, Error> {
+        if self.is_grace_time_exceeded(now) {
+            return Ok(self.disconnect(numbers::SSH_DISCONNECT_BY_APPLICATION, errors::BSSH_ERR_LOGIN_GRACE_TIME_EXCEEDED));
+        }
+
+        let number = msgs::get_message_number(payload)?;
+        let mut stream = Cursor::new(payload);
+
+        match (self.state, number) {
+            (ServerAuthState::WaitingForServiceRequest, numbers::SSH_MSG_SERVICE_REQUEST) => {
+                let service = msgs::read_service_request_message(&mut stream)?;
+                if service != SERVICE_USERAUTH {
+                    return Ok(self.disconnect(numbers::SSH_DISCONNECT_SERVICE_NOT_AVAILABLE, errors::BSSH_ERR_SERVICE_NOT_AVAILABLE));
+                }
+                self.state = ServerAuthState::Authenticating;
+                Ok(vec![get_payload(|p| msgs::write_service_accept_message(p, SERVICE_USERAUTH))])
+            }
+            (ServerAuthState::Authenticating, numbers::SSH_MSG_USERAUTH_REQUEST) => {
+                let request = read_userauth_request_message(&mut stream)?;
+                let mut payloads: Vec<Vec<u8>> = Vec::new();
+                if let Some(banner) = self.banner.take() {
+                    payloads.push(get_payload(|p| write_userauth_banner_message(p, &banner)));
+                }
+                payloads.extend(self.handle_request(request)?);
+                Ok(payloads)
+            }
+            (ServerAuthState::Authenticating, 60..=79) => {
+                let index = match self.current {
+                    Some(index) => index,
+                    None => return Err(unexpected_message()),
+                };
+                let result = self.methods[index].handle_message(&self.context, payload)?;
+                Ok(self.handle_method_result(result))
+            }
+            //RFC 4252 page 6, requests after success are silently ignored
+            (ServerAuthState::Succeeded, numbers::SSH_MSG_USERAUTH_REQUEST) => Ok(Vec::new()),
+            _ => Ok(self.disconnect(numbers::SSH_DISCONNECT_PROTOCOL_ERROR, errors::BSSH_ERR_UNEXPECTED_MESSAGE)),
+        }
+    }
+}
+
+//drives server until authentication succeeds, errors if the client is disconnected
+pub fn run_server(stream: &mut dyn PayloadStream, server: &mut UserauthServer) -> Result<(), Error> {
+    while server.state != ServerAuthState::Succeeded {
+        let payload = stream.receive_payload()?;
+        for response in server.handle_payload(&payload, Instant::now())? {
+            stream.send_payload(&response)?;
+        }
+        if let Some(ref reason) = server.disconnect_reason {
+            return Err(Error::new(ErrorKind::PermissionDenied, reason.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    //accepts only the given secret, sent as method data
+    struct SecretMethod {
+        name: &'static str,
+        secret: Vec<u8>,
+        options: AuthOptions,
+    }
+
+    impl SecretMethod {
+        fn new(name: &'static str, secret: &[u8]) -> SecretMethod {
+            SecretMethod { name, secret: secret.to_vec(), options: AuthOptions::unrestricted() }
+        }
+    }
+
+    impl ServerAuthMethod for SecretMethod {
+        fn get_name(&self) -> &'static str {
+            self.name
+        }
+        fn handle_request(&mut self, _context: &ServerAuthContext, method_data: &[u8]) -> Result<ServerMethodResult, Error> {
+            if method_data == self.secret.as_slice() {
+                Ok(ServerMethodResult::Success(self.options.clone()))
+            } else {
+                Ok(ServerMethodResult::Failure)
+            }
+        }
+        //"clear" stands for methods like password
+        fn requires_confidentiality(&self) -> bool {
+            self.name == "clear"
+        }
+    }
+
+    //tries given secrets one by one
+    struct SecretsClientMethod {
+        name: &'static str,
+        secrets: Vec<Vec<u8>>,
+    }
+
+    impl ClientAuthMethod for SecretsClientMethod {
+        fn get_name(&self) -> &'static str {
+            self.name
+        }
+        fn next_request(&mut self, context: &ClientAuthContext) -> Result<Option<Vec<u8>>, Error> {
+            if self.secrets.is_empty() {
+                return Ok(None);
+            }
+            let secret = self.secrets.remove(0);
+            Ok(Some(context.get_request_payload(self.get_name(), secret)))
+        }
+        fn requires_confidentiality(&self) -> bool {
+            self.name == "clear"
+        }
+    }
+
+    fn server(max_auth_tries: u32) -> UserauthServer {
+        UserauthServer::new(b"session",
+                            None,
+                            vec![Box::new(SecretMethod::new("secret", b"good"))],
+                            max_auth_tries,
+                            Some(Duration::from_secs(DEFAULT_LOGIN_GRACE_TIME)),
+                            Instant::now())
+    }
+
+    fn client(secrets: &[&[u8]]) -> UserauthClient {
+        UserauthClient::new("user", b"session", vec![Box::new(SecretsClientMethod { name: "secret", secrets: secrets.iter().map(|s| s.to_vec()).collect() })])
+    }
+
+    //passes payloads between client and server until neither has anything more to say
+    fn exchange(client: &mut UserauthClient, server: &mut UserauthServer) -> Result<(), Error> {
+        let mut to_server = client.start();
+        while !to_server.is_empty() {
+            let mut to_client: Vec<Vec<u8>> = Vec::new();
+            for payload in to_server.iter() {
+                to_client.extend(server.handle_payload(payload, Instant::now())?);
+            }
+            to_server = Vec::new();
+            for payload in to_client.iter() {
+                to_server.extend(client.handle_payload(payload)?);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reading_writing_userauth_messages_works() {
+        let request = UserauthRequest {
+            user: "user".to_string(),
+            service: SERVICE_CONNECTION.to_string(),
+            method: "password".to_string(),
+            method_data: vec![0, 1, 2],
+        };
+        let payload = get_payload(|p| write_userauth_request_message(p, &request));
+        let read = read_userauth_request_message(&mut Cursor::new(payload)).unwrap();
+        assert_eq!((read.user.as_str(), read.service.as_str(), read.method.as_str()), ("user", SERVICE_CONNECTION, "password"));
+        assert_eq!(read.method_data, vec![0, 1, 2]);
+
+        let failure = UserauthFailure {
+            methods: vec!["publickey".to_string(), "password".to_string()],
+            partial_success: true,
+        };
+        let payload = get_payload(|p| write_userauth_failure_message(p, &failure));
+        let read = read_userauth_failure_message(&mut Cursor::new(payload)).unwrap();
+        assert_eq!(read.methods, failure.methods);
+        assert!(read.partial_success);
+    }
+
+    #[test]
+    fn client_and_server_authenticate() {
+        let mut client = client(&[b"bad", b"good"]);
+        let mut server = server(DEFAULT_MAX_AUTH_TRIES);
+        exchange(&mut client, &mut server).unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+        //"none" is not counted
+        assert_eq!(server.failures, 1);
+    }
+
+    #[test]
+    fn server_rejects_change_of_user() {
+        let mut server = server(DEFAULT_MAX_AUTH_TRIES);
+        server.state = ServerAuthState::Authenticating;
+        let mut request = UserauthRequest {
+            user: "user".to_string(),
+            service: SERVICE_CONNECTION.to_string(),
+            method: "secret".to_string(),
+            method_data: b"bad".to_vec(),
+        };
+        let response = server.handle_payload(&get_payload(|p| write_userauth_request_message(p, &request)), Instant::now()).unwrap();
+        assert_eq!(response[0][0], numbers::SSH_MSG_USERAUTH_FAILURE);
+        request.user = "other".to_string();
+        let response = server.handle_payload(&get_payload(|p| write_userauth_request_message(p, &request)), Instant::now()).unwrap();
+        assert_eq!(response[0][0], numbers::SSH_MSG_DISCONNECT);
+        assert_eq!(server.disconnect_reason, Some(errors::BSSH_ERR_USER_CHANGED.to_string()));
+        assert_eq!(server.failures, 1);
+    }
+
+    #[test]
+    fn client_fails_when_methods_are_exhausted() {
+        let mut client = client(&[b"bad"]);
+        let mut server = server(DEFAULT_MAX_AUTH_TRIES);
+        let err = exchange(&mut client, &mut server).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "Permission denied (secret).");
+        assert_eq!(client.state, ClientAuthState::Failed);
+    }
+
+    #[test]
+    fn server_enforces_max_auth_tries() {
+        let mut client = client(&[b"1", b"2", b"3", b"good"]);
+        let mut server = server(3);
+        //DISCONNECT is handled by the transport, so it is unexpected for the client
+        assert!(exchange(&mut client, &mut server).is_err());
+        assert_eq!(server.state, ServerAuthState::Disconnected);
+        assert_eq!(server.disconnect_reason, Some(errors::BSSH_ERR_TOO_MANY_AUTH_FAILURES.to_string()));
+        assert_ne!(client.state, ClientAuthState::Succeeded);
+    }
+
+    #[test]
+    fn server_requires_authentication_methods_lists() {
+        let mut restricted = SecretMethod::new("key", b"k");
+        restricted.options = AuthOptions::restricted();
+        let methods: Vec<Box<dyn ServerAuthMethod>> = vec![Box::new(restricted), Box::new(SecretMethod::new("otp", b"o")), Box::new(SecretMethod::new("secret", b"good"))];
+        let mut server = UserauthServer::new(b"session", None, methods, DEFAULT_MAX_AUTH_TRIES, None, Instant::now());
+        assert!(server.set_authentication_methods(vec![vec!["key".to_string(), "nope".to_string()]]).is_err());
+        server.set_authentication_methods(vec![vec!["key".to_string(), "otp".to_string()], vec!["secret".to_string(), "key".to_string()]]).unwrap();
+        assert_eq!(server.get_allowed_methods(), vec!["key", "secret"]);
+
+        //"otp" first is not allowed, then "key" succeeds partially
+        let otp = SecretsClientMethod { name: "otp", secrets: vec![b"o".to_vec(), b"o".to_vec()] };
+        let key = SecretsClientMethod { name: "key", secrets: vec![b"k".to_vec()] };
+        let mut client = UserauthClient::new("user", b"session", vec![Box::new(otp), Box::new(key)]);
+        client.allowed_methods = vec!["otp".to_string()];
+        let mut to_server = vec![client.try_method(0).unwrap().unwrap()];
+        client.state = ClientAuthState::Authenticating;
+        server.state = ServerAuthState::Authenticating;
+        let response = server.handle_payload(&to_server[0], Instant::now()).unwrap();
+        let failure = read_userauth_failure_message(&mut Cursor::new(&response[0])).unwrap();
+        assert!(!failure.partial_success);
+        assert_eq!(server.failures, 1);
+
+        to_server = client.handle_payload(&response[0]).unwrap();
+        let response = server.handle_payload(&to_server[0], Instant::now()).unwrap();
+        let failure = read_userauth_failure_message(&mut Cursor::new(&response[0])).unwrap();
+        assert!(failure.partial_success);
+        assert_eq!(failure.methods, vec!["otp", "secret"]);
+        assert_eq!(server.state, ServerAuthState::Authenticating);
+
+        to_server = client.handle_payload(&response[0]).unwrap();
+        let response = server.handle_payload(&to_server[0], Instant::now()).unwrap();
+        assert_eq!(response, vec![vec![numbers::SSH_MSG_USERAUTH_SUCCESS]]);
+        assert!(client.partial_success);
+        client.handle_payload(&response[0]).unwrap();
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+        //restrictions of the first factor are kept
+        assert!(!server.auth_options.unwrap().permit_pty);
+    }
+
+    #[test]
+    fn server_sends_banner_once() {
+        let mut client = client(&[b"bad", b"good"]);
+        let mut server = server(DEFAULT_MAX_AUTH_TRIES);
+        server.banner = Some("Authorized use only\r\n".to_string());
+        exchange(&mut client, &mut server).unwrap();
+        assert_eq!(client.banners, vec!["Authorized use only\r\n"]);
+        assert_eq!(client.state, ClientAuthState::Succeeded);
+    }
+
+    #[test]
+    fn server_enforces_login_grace_time() {
+        let started = Instant::now();
+        let mut server = UserauthServer::new(b"session", None, Vec::new(), DEFAULT_MAX_AUTH_TRIES, Some(Duration::from_secs(10)), started);
+        let request = get_payload(|p| msgs::write_service_request_message(p, SERVICE_USERAUTH));
+        assert_eq!(server.handle_payload(&request, started + Duration::from_secs(5)).unwrap()[0][0], numbers::SSH_MSG_SERVICE_ACCEPT);
+        let response = server.handle_payload(&request, started + Duration::from_secs(10)).unwrap();
+        assert_eq!(response[0][0], numbers::SSH_MSG_DISCONNECT);
+        assert_eq!(server.state, ServerAuthState::Disconnected);
+    }
+
+    #[test]
+    fn server_rejects_unknown_service() {
+        let mut server = server(DEFAULT_MAX_AUTH_TRIES);
+        let request = get_payload(|p| msgs::write_service_request_message(p, "ssh-unknown"));
+        let response = server.handle_payload(&request, Instant::now()).unwrap();
+        let disconnect = msgs::read_disconnect_message(&mut Cursor::new(&response[0])).unwrap();
+        assert_eq!(disconnect.reason_code, numbers::SSH_DISCONNECT_SERVICE_NOT_AVAILABLE);
+    }
+
+    #[test]
+    fn secrets_in_clear_need_confidential_transport() {
+        let clear_server = || UserauthServer::new(b"session",
+                                                  None,
+                                                  vec![Box::new(SecretMethod::new("secret", b"good")), Box::new(SecretMethod::new("clear", b"good"))],
+                                                  DEFAULT_MAX_AUTH_TRIES,
+                                                  None,
+                                                  Instant::now());
+        let clear_client = || UserauthClient::new("user", b"session", vec![Box::new(SecretsClientMethod { name: "clear", secrets: vec![b"good".to_vec()] })]);
+
+        let mut server = clear_server();
+        assert_eq!(server.get_allowed_methods(), vec!["secret"]);
+        let mut client = clear_client();
+        client.allowed_methods = vec!["clear".to_string()];
+        assert!(client.next_method_request().is_err());
+
+        //a request sent anyway is refused
+        server.state = ServerAuthState::Authenticating;
+        let request = UserauthRequest {
+            user: "user".to_string(),
+            service: SERVICE_CONNECTION.to_string(),
+            method: "clear".to_string(),
+            method_data: b"good".to_vec(),
+        };
+        let response = server.handle_payload(&get_payload(|p| write_userauth_request_message(p, &request)), Instant::now()).unwrap();
+        assert_eq!(response[0][0], numbers::SSH_MSG_USERAUTH_FAILURE);
+        assert_eq!(server.get_authenticated_user(), None);
+
+        let mut server = clear_server();
+        let mut client = clear_client();
+        server.confidential = true;
+        client.confidential = true;
+        exchange(&mut client, &mut server).unwrap();
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+
+        //as AuthenticationMethods publickey,password over an encrypted transport
+        let mut server = clear_server();
+        server.set_authentication_methods(vec![vec!["secret".to_string(), "clear".to_string()]]).unwrap();
+        let mut client = UserauthClient::new("user", b"session", vec![Box::new(SecretsClientMethod { name: "secret", secrets: vec![b"good".to_vec()] }),
+                                                                      Box::new(SecretsClientMethod { name: "clear", secrets: vec![b"good".to_vec()] })]);
+        server.confidential = true;
+        client.confidential = true;
+        exchange(&mut client, &mut server).unwrap();
+        assert_eq!(server.get_authenticated_user(), Some("user"));
+        assert_eq!(server.state, ServerAuthState::Succeeded);
+    }
+}